@@ -1,7 +1,9 @@
 use candid::{CandidType, Decode, Deserialize, Encode};
 use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
-use ic_stable_structures::{BoundedStorable, DefaultMemoryImpl, StableBTreeMap, Storable};
-use std::{borrow::Cow, cell::RefCell};
+use ic_stable_structures::storable::Blob;
+use ic_stable_structures::{BoundedStorable, DefaultMemoryImpl, StableBTreeMap, StableCell, Storable};
+use futures::future::join_all;
+use std::{borrow::Cow, cell::RefCell, time::Duration};
 
 #[derive(CandidType, Deserialize)]
 struct Proposal {
@@ -10,17 +12,170 @@ struct Proposal {
     reject: u32,
     pass: u32,
     is_active: bool,
+    // The votes are kept in VOTES, so the voters are not bounded by MAX_VALUE_SIZE. This and
+    // the ballots below are only filled in by get_proposal, or in proposals saved before,
+    // until their votes are moved on upgrade.
     voted: Vec<candid::Principal>,
     owner: candid::Principal,
+    // When voting closes, in nanoseconds since the epoch. Open until ended when not set.
+    ends_at: Option<u64>,
+    // How much each vote weighs, one per principal when not set
+    weighting: Option<Weighting>,
+    // The weight of the votes for each choice; approve, reject and pass count the voters
+    weights: Option<Tally>,
+    // The current choice of each voter. Voters from before ballots were kept have none.
+    ballots: Option<Vec<Ballot>>,
+    // What it takes to approve the proposal, the defaults when not set
+    thresholds: Option<Thresholds>,
+    // The only principals who may vote, anyone when not set
+    eligible_voters: Option<Vec<candid::Principal>>,
+    // The options of a poll, voted on with vote_option instead of approve, reject or pass
+    options: Option<Vec<PollOption>>,
+    option_ballots: Option<Vec<OptionBallot>>,
+    // Whether the poll is voted on by ranking its options, with vote_ranking
+    ranked: Option<bool>,
+    rankings: Option<Vec<RankedBallot>>,
+    // With a ledger weighting, the balances of the eligible voters when the proposal was created
+    ledger_balances: Option<Vec<(candid::Principal, u128)>>,
+}
+
+#[derive(CandidType, Deserialize)]
+struct PollOption {
+    label: String,
+    votes: u32,
+    weight: u128,
+}
+
+#[derive(CandidType, Deserialize)]
+struct OptionBallot {
+    voter: candid::Principal,
+    option: u32,
+    weight: u128,
+}
+
+#[derive(CandidType, Deserialize)]
+struct RankedBallot {
+    voter: candid::Principal,
+    // Option indexes, most preferred first. Options left out are not ranked.
+    ranking: Vec<u32>,
+    weight: u128,
+}
+
+#[derive(CandidType)]
+struct IrvRound {
+    // The weight of the ballots counting for each option still in the running, by option index
+    tallies: Vec<(u32, u128)>,
+    eliminated: Vec<u32>,
+}
+
+#[derive(CandidType)]
+struct IrvResult {
+    // None when no ballot ranks any option
+    winner: Option<u32>,
+    rounds: Vec<IrvRound>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Copy)]
+struct Thresholds {
+    // The least weight cast, pass votes included, for the vote to count
+    quorum: u128,
+    // The share of the approve and reject weight that must approve, above which the
+    // proposal is approved. At exactly this share, it is tied.
+    approval_percent: u8,
+}
+
+impl Default for Thresholds {
+    fn default() -> Self {
+        Thresholds { quorum: 1, approval_percent: 50 }
+    }
+}
+
+#[derive(CandidType)]
+enum Outcome {
+    Approved,
+    Rejected,
+    Tied,
+    NoQuorum,
+}
+
+#[derive(CandidType)]
+struct VoteResults {
+    // The weights of the choices, and how many voted
+    approve: u128,
+    reject: u128,
+    pass: u128,
+    voters: u64,
+    // Of the weight cast
+    approve_percent: f64,
+    reject_percent: f64,
+    pass_percent: f64,
+    // Of the weight of the weight map, unknown for other weightings
+    turnout_percent: Option<f64>,
+    // The outcome if voting ended now
+    outcome: Outcome,
+    // Whether voting ended
+    is_final: bool,
+}
+
+#[derive(CandidType, Deserialize, Clone)]
+struct Ballot {
+    voter: candid::Principal,
+    choice: VoteTypes,
+    // Read when the vote was first cast, and kept when it is changed
+    weight: u128,
+}
+
+// A voter's vote on a proposal, by the kind of the proposal
+#[derive(CandidType, Deserialize)]
+enum Vote {
+    // Cast before ballots were kept, it cannot be changed or retracted
+    Unrecorded,
+    Choice(Ballot),
+    Option(OptionBallot),
+    Ranking(RankedBallot),
 }
 
 #[derive(CandidType, Deserialize)]
 struct CreateProposal {
     description: String,
     is_active: bool,
+    // Only read on creation
+    ends_at: Option<u64>,
+    weighting: Option<Weighting>,
+    thresholds: Option<Thresholds>,
+    // Only read on creation; the owner changes the list with add_eligible_voter and
+    // remove_eligible_voter
+    eligible_voters: Option<Vec<candid::Principal>>,
+    // The labels of the options of a poll
+    options: Option<Vec<String>>,
+    // Whether the poll is ranked choice, requires options
+    ranked: Option<bool>,
 }
 
-#[derive(CandidType, Deserialize)]
+#[derive(CandidType, Deserialize, Clone)]
+enum Weighting {
+    OnePerPrincipal,
+    // The voter's balance on an ICRC-1 ledger when the proposal was created. Holders
+    // cannot be listed, so it needs eligible voters, whose balances are read then.
+    Ledger(candid::Principal),
+    // The weights of the principals allowed to vote
+    WeightMap(Vec<(candid::Principal, u128)>),
+}
+
+#[derive(CandidType, Deserialize, Default)]
+struct Tally {
+    approve: u128,
+    reject: u128,
+    pass: u128,
+}
+
+#[derive(CandidType)]
+struct Account {
+    owner: candid::Principal,
+    subaccount: Option<Vec<u8>>,
+}
+
+#[derive(CandidType, Deserialize, Clone)]
 enum VoteTypes {
     Approve,
     Reject,
@@ -35,13 +190,107 @@ enum VoteError {
     NoProposal,
     UpdateError,
     VoteFailed,
+    AlreadyExists,
+    InvalidDeadline,
+    NoVotingPower,
+    LedgerUnavailable,
+    TooManyWeights,
+    NotVoted,
+    InvalidThresholds,
+    NotEligible,
+    TooManyVoters,
+    ProposalActive,
+    InvalidOptions,
+    InvalidOption,
+    InvalidRanking,
+    InvalidWeighting,
 }
 
 type Memory = VirtualMemory<DefaultMemoryImpl>;
-const MAX_VALUE_SIZE: u32 = 100;
+const MAX_VALUE_SIZE: u32 = 16 * 1024;
+// Keeps proposals with a weight map within MAX_VALUE_SIZE
+const MAX_WEIGHTS: usize = 100;
+const MAX_ELIGIBLE_VOTERS: usize = 100;
+const MAX_OPTIONS: usize = 20;
+const MAX_OPTION_LABEL_LEN: usize = 50;
+// The bound of the first proposal map, too small for more than a few voters
+const LEGACY_MAX_VALUE_SIZE: u32 = 100;
+// Fits the ranking of all MAX_OPTIONS options
+const MAX_VOTE_SIZE: u32 = 256;
+
+// The key of a proposal and the bytes of a voter
+type VoteKey = (u64, Blob<29>);
+
+fn vote_key(key: u64, voter: &candid::Principal) -> VoteKey {
+    (key, Blob::try_from(voter.as_slice()).expect("principals are at most 29 bytes"))
+}
+
+impl Proposal {
+    // Proposals from before the weights were kept had one vote per principal
+    fn weights_mut(&mut self) -> &mut Tally {
+        let counts = Tally {
+            approve: self.approve as u128,
+            reject: self.reject as u128,
+            pass: self.pass as u128,
+        };
+        self.weights.get_or_insert(counts)
+    }
+
+    fn count(&mut self, choice: &VoteTypes, weight: u128) {
+        match choice {
+            VoteTypes::Approve => {
+                self.weights_mut().approve += weight;
+                self.approve += 1;
+            }
+            VoteTypes::Reject => {
+                self.weights_mut().reject += weight;
+                self.reject += 1;
+            }
+            VoteTypes::Pass => {
+                self.weights_mut().pass += weight;
+                self.pass += 1;
+            }
+        }
+    }
+
+    fn uncount(&mut self, choice: &VoteTypes, weight: u128) {
+        match choice {
+            VoteTypes::Approve => {
+                self.weights_mut().approve -= weight;
+                self.approve -= 1;
+            }
+            VoteTypes::Reject => {
+                self.weights_mut().reject -= weight;
+                self.reject -= 1;
+            }
+            VoteTypes::Pass => {
+                self.weights_mut().pass -= weight;
+                self.pass -= 1;
+            }
+        }
+    }
+
+    fn outcome(&self, weights: &Tally) -> Outcome {
+        let thresholds = self.thresholds.unwrap_or_default();
+        if weights.approve + weights.reject + weights.pass < thresholds.quorum.max(1) {
+            return Outcome::NoQuorum;
+        }
+        let approving = weights.approve * 100;
+        let needed = (weights.approve + weights.reject) * thresholds.approval_percent as u128;
+        match approving.cmp(&needed) {
+            std::cmp::Ordering::Greater => Outcome::Approved,
+            std::cmp::Ordering::Equal => Outcome::Tied,
+            std::cmp::Ordering::Less => Outcome::Rejected,
+        }
+    }
+
+    fn is_ranked(&self) -> bool {
+        self.ranked == Some(true)
+    }
+}
 
 impl Storable for Proposal {
-    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
         Cow::Owned(Encode!(self).unwrap())
     }
 
@@ -55,20 +304,192 @@ impl BoundedStorable for Proposal {
     const IS_FIXED_SIZE: bool = false;
 }
 
+impl Storable for Vote {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for Vote {
+    const MAX_SIZE: u32 = MAX_VOTE_SIZE;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// A proposal of the first map. A map's bound cannot be raised, so the proposals are
+// moved to a new map on upgrade.
+struct LegacyProposal(Proposal);
+
+impl Storable for LegacyProposal {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        self.0.to_bytes()
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        LegacyProposal(Proposal::from_bytes(bytes))
+    }
+}
+
+impl BoundedStorable for LegacyProposal {
+    const MAX_SIZE: u32 = LEGACY_MAX_VALUE_SIZE;
+    const IS_FIXED_SIZE: bool = false;
+}
+
 thread_local! {
     static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> =
         RefCell::new(MemoryManager::init(DefaultMemoryImpl::default()));
 
-    static PROPOSAL_MAP: RefCell<StableBTreeMap<u64, Proposal, Memory>> = RefCell::new(
+    static LEGACY_PROPOSAL_MAP: RefCell<StableBTreeMap<u64, LegacyProposal, Memory>> = RefCell::new(
         StableBTreeMap::init(
             MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(0))),
         )
     );
+
+    static PROPOSAL_MAP: RefCell<StableBTreeMap<u64, Proposal, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(2))),
+        )
+    );
+
+    // The key of the next proposal, kept in stable memory so keys are never given twice
+    static NEXT_KEY: RefCell<StableCell<u64, Memory>> = RefCell::new(
+        StableCell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(1))), 0)
+            .expect("failed to initialize the proposal key counter")
+    );
+
+    static VOTES: RefCell<StableBTreeMap<VoteKey, Vote, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(3))),
+        )
+    );
+}
+
+// The votes on a proposal, by voter
+fn votes_of(key: u64) -> Vec<(candid::Principal, Vote)> {
+    VOTES.with(|votes| {
+        votes
+            .borrow()
+            .range((key, Blob::default())..)
+            .take_while(|((proposal, _), _)| *proposal == key)
+            .map(|((_, voter), vote)| (candid::Principal::from_slice(voter.as_slice()), vote))
+            .collect()
+    })
 }
 
+fn save_vote(key: u64, voter: candid::Principal, vote: Vote) {
+    VOTES.with(|votes| votes.borrow_mut().insert(vote_key(key, &voter), vote));
+}
+
+// Move the voters and ballots saved in a proposal to VOTES
+fn move_votes(key: u64, proposal: &mut Proposal) {
+    let ballots = proposal.ballots.take().unwrap_or_default();
+    let option_ballots = proposal.option_ballots.take().unwrap_or_default();
+    let rankings = proposal.rankings.take().unwrap_or_default();
+    for voter in std::mem::take(&mut proposal.voted) {
+        save_vote(key, voter, Vote::Unrecorded);
+    }
+    for ballot in ballots {
+        save_vote(key, ballot.voter, Vote::Choice(ballot));
+    }
+    for ballot in option_ballots {
+        save_vote(key, ballot.voter, Vote::Option(ballot));
+    }
+    for ballot in rankings {
+        save_vote(key, ballot.voter, Vote::Ranking(ballot));
+    }
+}
+
+// Move the votes of the proposals saved with their votes inside to VOTES
+fn migrate_votes() {
+    let proposals: Vec<(u64, Proposal)> = PROPOSAL_MAP.with(|p| {
+        p.borrow()
+            .iter()
+            .filter(|(_, proposal)| {
+                !proposal.voted.is_empty()
+                    || proposal.ballots.is_some()
+                    || proposal.option_ballots.is_some()
+                    || proposal.rankings.is_some()
+            })
+            .collect()
+    });
+    for (key, mut proposal) in proposals {
+        move_votes(key, &mut proposal);
+        PROPOSAL_MAP.with(|p| p.borrow_mut().insert(key, proposal));
+    }
+}
+
+// Hand out the next unused key. Keys taken through create_proposal_with_key are skipped.
+fn next_key() -> u64 {
+    NEXT_KEY.with(|n| {
+        let mut key = *n.borrow().get();
+        while PROPOSAL_MAP.with(|p| p.borrow().contains_key(&key)) {
+            key += 1;
+        }
+        n.borrow_mut().set(key + 1).expect("failed to save the proposal key counter");
+        key
+    })
+}
+
+// Move the proposals of the first map to the current one and their votes out of them,
+// and set the deadline timers again, as timers do not survive upgrades
+#[ic_cdk_macros::post_upgrade]
+fn post_upgrade() {
+    let legacy: Vec<(u64, LegacyProposal)> = LEGACY_PROPOSAL_MAP.with(|p| p.borrow().iter().collect());
+    for (key, proposal) in legacy {
+        PROPOSAL_MAP.with(|p| p.borrow_mut().insert(key, proposal.0));
+        LEGACY_PROPOSAL_MAP.with(|p| p.borrow_mut().remove(&key));
+    }
+    migrate_votes();
+
+    let deadlines: Vec<(u64, u64)> = PROPOSAL_MAP.with(|p| {
+        p.borrow().iter()
+            .filter(|(_, proposal)| proposal.is_active)
+            .filter_map(|(key, proposal)| proposal.ends_at.map(|ends_at| (key, ends_at)))
+            .collect()
+    });
+    for (key, ends_at) in deadlines {
+        schedule_close(key, ends_at);
+    }
+}
+
+// Deactivate a proposal once its deadline passes
+fn schedule_close(key: u64, ends_at: u64) {
+    let delay = Duration::from_nanos(ends_at.saturating_sub(ic_cdk::api::time()));
+    ic_cdk_timers::set_timer(delay, move || {
+        PROPOSAL_MAP.with(|p| {
+            let Some(mut proposal) = p.borrow().get(&key) else {
+                return;
+            };
+            // The deadline may be gone if the proposal was replaced meanwhile
+            if proposal.ends_at.is_some_and(|ends_at| ends_at <= ic_cdk::api::time()) {
+                proposal.is_active = false;
+                p.borrow_mut().insert(key, proposal);
+            }
+        })
+    });
+}
+
+fn is_past_deadline(proposal: &Proposal) -> bool {
+    proposal.ends_at.is_some_and(|ends_at| ic_cdk::api::time() >= ends_at)
+}
+
+// The proposal with its voters and ballots
 #[ic_cdk_macros::query]
 fn get_proposal(key: u64) -> Option<Proposal> {
-    PROPOSAL_MAP.with(|p| p.borrow().get(&key))
+    let mut proposal = PROPOSAL_MAP.with(|p| p.borrow().get(&key))?;
+    for (voter, vote) in votes_of(key) {
+        proposal.voted.push(voter);
+        match vote {
+            Vote::Unrecorded => {}
+            Vote::Choice(ballot) => proposal.ballots.get_or_insert_with(Vec::new).push(ballot),
+            Vote::Option(ballot) => proposal.option_ballots.get_or_insert_with(Vec::new).push(ballot),
+            Vote::Ranking(ballot) => proposal.rankings.get_or_insert_with(Vec::new).push(ballot),
+        }
+    }
+    Some(proposal)
 }
 
 #[ic_cdk_macros::query]
@@ -76,18 +497,118 @@ fn get_proposal_count() -> u64 {
     PROPOSAL_MAP.with(|p| p.borrow().len())
 }
 
-#[ic_cdk_macros::update]
-fn create_proposal(key: u64, proposal: CreateProposal) -> Option<Proposal> {
-    let value = Proposal {
+fn new_proposal(
+    proposal: CreateProposal,
+    owner: candid::Principal,
+    ledger_balances: Option<Vec<(candid::Principal, u128)>>,
+) -> Proposal {
+    Proposal {
         description: proposal.description,
         approve: 0u32,
         reject: 0u32,
         pass: 0u32,
         is_active: proposal.is_active,
         voted: vec![],
-        owner: ic_cdk::caller(),
+        owner,
+        ends_at: proposal.ends_at,
+        weighting: proposal.weighting,
+        weights: Some(Tally::default()),
+        ballots: None,
+        thresholds: proposal.thresholds,
+        eligible_voters: proposal.eligible_voters,
+        options: proposal.options.map(|labels| {
+            labels.into_iter().map(|label| PollOption { label, votes: 0, weight: 0 }).collect()
+        }),
+        option_ballots: None,
+        ranked: proposal.ranked,
+        rankings: None,
+        ledger_balances,
+    }
+}
+
+fn validate_new(proposal: &CreateProposal) -> Result<(), VoteError> {
+    if proposal.ends_at.is_some_and(|ends_at| ends_at <= ic_cdk::api::time()) {
+        return Err(VoteError::InvalidDeadline);
+    }
+    match &proposal.weighting {
+        Some(Weighting::WeightMap(weights)) if weights.len() > MAX_WEIGHTS => return Err(VoteError::TooManyWeights),
+        Some(Weighting::Ledger(_)) if proposal.eligible_voters.is_none() => return Err(VoteError::InvalidWeighting),
+        _ => {}
+    }
+    if proposal.thresholds.is_some_and(|thresholds| thresholds.approval_percent > 100) {
+        return Err(VoteError::InvalidThresholds);
+    }
+    if proposal.eligible_voters.as_ref().is_some_and(|voters| voters.len() > MAX_ELIGIBLE_VOTERS) {
+        return Err(VoteError::TooManyVoters);
+    }
+    if let Some(labels) = &proposal.options {
+        let valid_label = |label: &String| !label.is_empty() && label.len() <= MAX_OPTION_LABEL_LEN;
+        if !(2..=MAX_OPTIONS).contains(&labels.len()) || !labels.iter().all(valid_label) {
+            return Err(VoteError::InvalidOptions);
+        }
+    }
+    if proposal.ranked == Some(true) && proposal.options.is_none() {
+        return Err(VoteError::InvalidOptions);
+    }
+    Ok(())
+}
+
+async fn balance_of(ledger: candid::Principal, voter: candid::Principal) -> Result<u128, VoteError> {
+    let account = Account { owner: voter, subaccount: None };
+    let (balance,): (candid::Nat,) = ic_cdk::call(ledger, "icrc1_balance_of", (account,))
+        .await
+        .map_err(|_| VoteError::LedgerUnavailable)?;
+    u128::try_from(&balance.0).map_err(|_| VoteError::LedgerUnavailable)
+}
+
+// The ledger balances of the voters, read concurrently
+async fn ledger_balances(
+    ledger: candid::Principal,
+    voters: &[candid::Principal],
+) -> Result<Vec<(candid::Principal, u128)>, VoteError> {
+    let balances = join_all(voters.iter().map(|voter| balance_of(ledger, *voter))).await;
+    voters.iter().copied().zip(balances).map(|(voter, balance)| Ok((voter, balance?))).collect()
+}
+
+// Save a new proposal, closing it at its deadline if it has one. With a ledger weighting,
+// the balances of the eligible voters are read first, so tokens moved after a vote cannot
+// vote again.
+async fn insert_new(key: u64, proposal: CreateProposal) -> Result<(), VoteError> {
+    validate_new(&proposal)?;
+    let owner = ic_cdk::caller();
+    let balances = match (&proposal.weighting, &proposal.eligible_voters) {
+        (Some(Weighting::Ledger(ledger)), Some(voters)) => Some(ledger_balances(*ledger, voters).await?),
+        _ => None,
     };
-    PROPOSAL_MAP.with(|p| p.borrow_mut().insert(key, value))
+    // The key may have been taken while the ledger replied
+    if PROPOSAL_MAP.with(|p| p.borrow().contains_key(&key)) {
+        return Err(VoteError::AlreadyExists);
+    }
+
+    let proposal = new_proposal(proposal, owner, balances);
+    if let Some(ends_at) = proposal.ends_at {
+        schedule_close(key, ends_at);
+    }
+    PROPOSAL_MAP.with(|p| p.borrow_mut().insert(key, proposal));
+    Ok(())
+}
+
+// Create a proposal under a new key, which is returned
+#[ic_cdk_macros::update]
+async fn create_proposal(proposal: CreateProposal) -> Result<u64, VoteError> {
+    let key = next_key();
+    insert_new(key, proposal).await?;
+    Ok(key)
+}
+
+// Deprecated: create_proposal picks the key. Kept for callers choosing their own keys,
+// which can no longer overwrite an existing proposal.
+#[ic_cdk_macros::update]
+async fn create_proposal_with_key(key: u64, proposal: CreateProposal) -> Result<(), VoteError> {
+    if PROPOSAL_MAP.with(|p| p.borrow().contains_key(&key)) {
+        return Err(VoteError::AlreadyExists);
+    }
+    insert_new(key, proposal).await
 }
 
 #[ic_cdk_macros::update]
@@ -110,6 +631,17 @@ fn edit_proposal(key: u64, proposal: CreateProposal) -> Result<(), VoteError> {
             is_active: proposal.is_active,
             voted: old_proposal.voted,
             owner: ic_cdk::caller(),
+            ends_at: old_proposal.ends_at,
+            weighting: old_proposal.weighting,
+            weights: old_proposal.weights,
+            ballots: old_proposal.ballots,
+            thresholds: old_proposal.thresholds,
+            eligible_voters: old_proposal.eligible_voters,
+            options: old_proposal.options,
+            option_ballots: old_proposal.option_ballots,
+            ranked: old_proposal.ranked,
+            rankings: old_proposal.rankings,
+            ledger_balances: old_proposal.ledger_balances,
         };
 
         let res = p.borrow_mut().insert(key, value);
@@ -123,7 +655,7 @@ fn edit_proposal(key: u64, proposal: CreateProposal) -> Result<(), VoteError> {
 #[ic_cdk_macros::update]
 fn end_proposal(key: u64) -> Result<(), VoteError> {
     PROPOSAL_MAP.with(|p| {
-        let mut proposal = p.borrow_mut().get(&key).unwrap();
+        let mut proposal = p.borrow().get(&key).ok_or(VoteError::NoProposal)?;
         if ic_cdk::caller() != proposal.owner {
             return Err(VoteError::Unauthorized);
         }
@@ -136,26 +668,458 @@ fn end_proposal(key: u64) -> Result<(), VoteError> {
     })
 }
 
-#[ic_cdk_macros::update]
-fn vote(key: u64, choice: VoteTypes) -> Result<(), VoteError> {
+// The weight of a voter's vote on a proposal. Proposals weighted by a ledger before its
+// balances were kept have none, so they cannot be voted on.
+fn weight_of(proposal: &Proposal, voter: candid::Principal) -> u128 {
+    let weight_in = |weights: &[(candid::Principal, u128)]| {
+        weights.iter().find(|(principal, _)| *principal == voter).map_or(0, |(_, weight)| *weight)
+    };
+    match &proposal.weighting {
+        None | Some(Weighting::OnePerPrincipal) => 1,
+        Some(Weighting::Ledger(_)) => weight_in(proposal.ledger_balances.as_deref().unwrap_or_default()),
+        Some(Weighting::WeightMap(weights)) => weight_in(weights),
+    }
+}
+
+fn check_can_vote(key: u64, proposal: &Proposal, voter: &candid::Principal) -> Result<(), VoteError> {
+    if VOTES.with(|votes| votes.borrow().contains_key(&vote_key(key, voter))) {
+        return Err(VoteError::AlreadyVoted);
+    } else if !proposal.is_active || is_past_deadline(proposal) {
+        return Err(VoteError::ProposalNotActive);
+    } else if proposal.eligible_voters.as_ref().is_some_and(|voters| !voters.contains(voter)) {
+        return Err(VoteError::NotEligible);
+    }
+    Ok(())
+}
+
+// Change the eligible voters of an inactive proposal of the caller
+fn update_eligible_voters(key: u64, update: impl FnOnce(&mut Vec<candid::Principal>)) -> Result<(), VoteError> {
     PROPOSAL_MAP.with(|p| {
-        let mut proposal = p.borrow_mut().get(&key).unwrap();
-        let caller = ic_cdk::caller();
-        if proposal.voted.contains(&caller) {
-            return Err(VoteError::AlreadyVoted);
-        } else if !proposal.is_active {
-            return Err(VoteError::ProposalNotActive);
+        let mut proposal = p.borrow().get(&key).ok_or(VoteError::NoProposal)?;
+        if ic_cdk::caller() != proposal.owner {
+            return Err(VoteError::Unauthorized);
         }
-        match choice {
-            VoteTypes::Approve => proposal.approve += 1,
-            VoteTypes::Reject => proposal.reject += 1,
-            VoteTypes::Pass => proposal.pass += 1,
+        if proposal.is_active {
+            return Err(VoteError::ProposalActive);
+        }
+
+        let voters = proposal.eligible_voters.get_or_insert_with(Vec::new);
+        update(voters);
+        if voters.len() > MAX_ELIGIBLE_VOTERS {
+            return Err(VoteError::TooManyVoters);
         }
-        proposal.voted.push(caller);
+        p.borrow_mut().insert(key, proposal);
+        Ok(())
+    })
+}
+
+// Adding a voter to a proposal open to anyone opens it to that voter only
+#[ic_cdk_macros::update]
+fn add_eligible_voter(key: u64, voter: candid::Principal) -> Result<(), VoteError> {
+    update_eligible_voters(key, |voters| {
+        if !voters.contains(&voter) {
+            voters.push(voter);
+        }
+    })
+}
+
+#[ic_cdk_macros::update]
+fn remove_eligible_voter(key: u64, voter: candid::Principal) -> Result<(), VoteError> {
+    update_eligible_voters(key, |voters| voters.retain(|eligible| *eligible != voter))
+}
+
+// The weight of the caller's vote on a proposal they may vote on
+fn caller_weight(key: u64, check: impl Fn(&Proposal) -> Result<(), VoteError>) -> Result<u128, VoteError> {
+    let caller = ic_cdk::caller();
+    let weight = PROPOSAL_MAP.with(|p| {
+        let proposal = p.borrow().get(&key).ok_or(VoteError::NoProposal)?;
+        check_can_vote(key, &proposal, &caller)?;
+        check(&proposal)?;
+        Ok(weight_of(&proposal, caller))
+    })?;
+    if weight == 0 {
+        return Err(VoteError::NoVotingPower);
+    }
+    Ok(weight)
+}
+
+// Polls are voted on with vote_option, or vote_ranking when ranked
+fn require_not_poll(proposal: &Proposal) -> Result<(), VoteError> {
+    match proposal.options {
+        Some(_) => Err(VoteError::InvalidOption),
+        None => Ok(()),
+    }
+}
+
+fn require_option(proposal: &Proposal, option_index: u32) -> Result<(), VoteError> {
+    match &proposal.options {
+        Some(options) if !proposal.is_ranked() && (option_index as usize) < options.len() => Ok(()),
+        _ => Err(VoteError::InvalidOption),
+    }
+}
+
+// A ranking lists distinct options of a ranked poll, at least one
+fn require_ranking(proposal: &Proposal, ranking: &[u32]) -> Result<(), VoteError> {
+    let options = match &proposal.options {
+        Some(options) if proposal.is_ranked() => options.len(),
+        _ => return Err(VoteError::InvalidOption),
+    };
+    let in_range = ranking.iter().all(|option| (*option as usize) < options);
+    let distinct = ranking.iter().enumerate().all(|(i, option)| !ranking[..i].contains(option));
+    if ranking.is_empty() || !in_range || !distinct {
+        return Err(VoteError::InvalidRanking);
+    }
+    Ok(())
+}
+
+#[ic_cdk_macros::update]
+fn vote(key: u64, choice: VoteTypes) -> Result<(), VoteError> {
+    let caller = ic_cdk::caller();
+    let weight = caller_weight(key, require_not_poll)?;
+
+    PROPOSAL_MAP.with(|p| {
+        let mut proposal = p.borrow().get(&key).ok_or(VoteError::NoProposal)?;
+        proposal.count(&choice, weight);
         let res = p.borrow_mut().insert(key, proposal);
         match res {
-            Some(_) => Ok(()),
+            Some(_) => {
+                save_vote(key, caller, Vote::Choice(Ballot { voter: caller, choice, weight }));
+                Ok(())
+            }
             None => Err(VoteError::VoteFailed),
         }
     })
 }
+
+// Vote for one of the options of a poll
+#[ic_cdk_macros::update]
+fn vote_option(key: u64, option_index: u32) -> Result<(), VoteError> {
+    let caller = ic_cdk::caller();
+    let weight = caller_weight(key, |proposal| require_option(proposal, option_index))?;
+
+    PROPOSAL_MAP.with(|p| {
+        let mut proposal = p.borrow().get(&key).ok_or(VoteError::NoProposal)?;
+        let option = &mut proposal.options.as_mut().unwrap()[option_index as usize];
+        option.votes += 1;
+        option.weight += weight;
+        p.borrow_mut().insert(key, proposal);
+        save_vote(key, caller, Vote::Option(OptionBallot { voter: caller, option: option_index, weight }));
+        Ok(())
+    })
+}
+
+// Rank the options of a ranked poll, most preferred first
+#[ic_cdk_macros::update]
+fn vote_ranking(key: u64, ranking: Vec<u32>) -> Result<(), VoteError> {
+    let caller = ic_cdk::caller();
+    let weight = caller_weight(key, |proposal| require_ranking(proposal, &ranking))?;
+    save_vote(key, caller, Vote::Ranking(RankedBallot { voter: caller, ranking, weight }));
+    Ok(())
+}
+
+// Take the caller's vote back out of the tally of an active proposal, to change or
+// retract it. Votes cast before ballots were kept cannot be taken back.
+fn take_vote(key: u64, proposal: &mut Proposal, voter: &candid::Principal) -> Result<Vote, VoteError> {
+    if !proposal.is_active || is_past_deadline(proposal) {
+        return Err(VoteError::ProposalNotActive);
+    }
+    let vote_key = vote_key(key, voter);
+    let vote = VOTES.with(|votes| votes.borrow().get(&vote_key)).ok_or(VoteError::NotVoted)?;
+    match &vote {
+        Vote::Unrecorded => return Err(VoteError::NotVoted),
+        Vote::Choice(ballot) => proposal.uncount(&ballot.choice, ballot.weight),
+        Vote::Option(ballot) => {
+            let option = &mut proposal.options.as_mut().unwrap()[ballot.option as usize];
+            option.votes -= 1;
+            option.weight -= ballot.weight;
+        }
+        Vote::Ranking(_) => {}
+    }
+    VOTES.with(|votes| votes.borrow_mut().remove(&vote_key));
+    Ok(vote)
+}
+
+// Polls' votes are changed by retracting them and voting again
+#[ic_cdk_macros::update]
+fn change_vote(key: u64, new_choice: VoteTypes) -> Result<(), VoteError> {
+    PROPOSAL_MAP.with(|p| {
+        let mut proposal = p.borrow().get(&key).ok_or(VoteError::NoProposal)?;
+        require_not_poll(&proposal)?;
+        let caller = ic_cdk::caller();
+        let Vote::Choice(ballot) = take_vote(key, &mut proposal, &caller)? else {
+            return Err(VoteError::NotVoted);
+        };
+
+        proposal.count(&new_choice, ballot.weight);
+        p.borrow_mut().insert(key, proposal);
+        save_vote(key, caller, Vote::Choice(Ballot { choice: new_choice, ..ballot }));
+        Ok(())
+    })
+}
+
+// The caller may vote again afterwards
+#[ic_cdk_macros::update]
+fn retract_vote(key: u64) -> Result<(), VoteError> {
+    PROPOSAL_MAP.with(|p| {
+        let mut proposal = p.borrow().get(&key).ok_or(VoteError::NoProposal)?;
+        take_vote(key, &mut proposal, &ic_cdk::caller())?;
+        p.borrow_mut().insert(key, proposal);
+        Ok(())
+    })
+}
+
+fn percent_of(part: u128, whole: u128) -> f64 {
+    if whole == 0 {
+        return 0.0;
+    }
+    part as f64 * 100.0 / whole as f64
+}
+
+// The results of a proposal, and its outcome by its thresholds if voting ended now
+#[ic_cdk_macros::query]
+fn get_results(key: u64) -> Option<VoteResults> {
+    let mut proposal = PROPOSAL_MAP.with(|p| p.borrow().get(&key))?;
+    let weights = std::mem::take(proposal.weights_mut());
+    let cast = weights.approve + weights.reject + weights.pass;
+    let eligible = match &proposal.weighting {
+        Some(Weighting::WeightMap(map)) => Some(map.iter().map(|(_, weight)| *weight).sum::<u128>()),
+        _ => None,
+    };
+
+    Some(VoteResults {
+        approve: weights.approve,
+        reject: weights.reject,
+        pass: weights.pass,
+        voters: votes_of(key).len() as u64,
+        approve_percent: percent_of(weights.approve, cast),
+        reject_percent: percent_of(weights.reject, cast),
+        pass_percent: percent_of(weights.pass, cast),
+        turnout_percent: eligible.map(|eligible| percent_of(cast, eligible)),
+        outcome: proposal.outcome(&weights),
+        is_final: !proposal.is_active || is_past_deadline(&proposal),
+    })
+}
+
+// Run instant-runoff on the rankings of a ranked poll. Each round, every ballot counts
+// for its most preferred option still in the running. An option with more than half of
+// the counted weight wins; otherwise the option with the least weight is eliminated.
+// Options tied for least weight are told apart by their first round weight, then the
+// lowest index goes first. There are at most as many rounds as options.
+#[ic_cdk_macros::query]
+fn tally_irv(key: u64) -> Result<IrvResult, VoteError> {
+    let proposal = PROPOSAL_MAP.with(|p| p.borrow().get(&key)).ok_or(VoteError::NoProposal)?;
+    let options = match &proposal.options {
+        Some(options) if proposal.is_ranked() => options.len() as u32,
+        _ => return Err(VoteError::InvalidOption),
+    };
+    let rankings: Vec<RankedBallot> = votes_of(key)
+        .into_iter()
+        .filter_map(|(_, vote)| match vote {
+            Vote::Ranking(ballot) => Some(ballot),
+            _ => None,
+        })
+        .collect();
+
+    let mut running: Vec<u32> = (0..options).collect();
+    let mut rounds: Vec<IrvRound> = vec![];
+    loop {
+        let mut tallies: Vec<(u32, u128)> = running.iter().map(|option| (*option, 0)).collect();
+        for ballot in &rankings {
+            let first = ballot.ranking.iter().find(|option| running.contains(option));
+            if let Some(tally) = first.and_then(|first| tallies.iter_mut().find(|(option, _)| option == first)) {
+                tally.1 += ballot.weight;
+            }
+        }
+        let counted: u128 = tallies.iter().map(|(_, weight)| weight).sum();
+        if counted == 0 {
+            rounds.push(IrvRound { tallies, eliminated: vec![] });
+            return Ok(IrvResult { winner: None, rounds });
+        }
+        if let Some((winner, _)) = tallies.iter().find(|(_, weight)| *weight > counted / 2) {
+            let winner = Some(*winner);
+            rounds.push(IrvRound { tallies, eliminated: vec![] });
+            return Ok(IrvResult { winner, rounds });
+        }
+
+        let first_round = rounds.first().map_or(&tallies, |round| &round.tallies);
+        let first_weight = |option: u32| {
+            first_round.iter().find(|(first, _)| *first == option).map_or(0, |(_, weight)| *weight)
+        };
+        let (eliminated, _) = *tallies
+            .iter()
+            .min_by_key(|(option, weight)| (*weight, first_weight(*option), *option))
+            .unwrap();
+        running.retain(|option| *option != eliminated);
+        rounds.push(IrvRound { tallies, eliminated: vec![eliminated] });
+    }
+}
+
+// The seconds left to vote on a proposal, zero once its deadline passed. None for a
+// proposal without a deadline.
+#[ic_cdk_macros::query]
+fn get_time_remaining(key: u64) -> Option<u64> {
+    let ends_at = PROPOSAL_MAP.with(|p| p.borrow().get(&key))?.ends_at?;
+    Some(ends_at.saturating_sub(ic_cdk::api::time()) / 1_000_000_000)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn voter(n: u8) -> candid::Principal {
+        candid::Principal::from_slice(&[n; 29])
+    }
+
+    fn proposal(options: Option<Vec<String>>, ranked: Option<bool>) -> Proposal {
+        Proposal {
+            description: "proposal".to_string(),
+            approve: 0,
+            reject: 0,
+            pass: 0,
+            is_active: true,
+            voted: vec![],
+            owner: candid::Principal::anonymous(),
+            ends_at: None,
+            weighting: None,
+            weights: None,
+            ballots: None,
+            thresholds: None,
+            eligible_voters: None,
+            options: options.map(|labels| {
+                labels.into_iter().map(|label| PollOption { label, votes: 0, weight: 0 }).collect()
+            }),
+            option_ballots: None,
+            ranked,
+            rankings: None,
+            ledger_balances: None,
+        }
+    }
+
+    #[test]
+    fn ledger_weights_are_the_balances_at_creation() {
+        let mut proposal = proposal(None, None);
+        proposal.weighting = Some(Weighting::Ledger(candid::Principal::anonymous()));
+        // Created before the balances were kept
+        assert_eq!(weight_of(&proposal, voter(1)), 0);
+
+        proposal.ledger_balances = Some(vec![(voter(1), 40), (voter(2), 2)]);
+        assert_eq!(weight_of(&proposal, voter(1)), 40);
+        assert_eq!(weight_of(&proposal, voter(3)), 0);
+    }
+
+    #[test]
+    fn ledger_balances_of_all_eligible_voters_fit_a_proposal() {
+        let voters: Vec<candid::Principal> = (0..MAX_ELIGIBLE_VOTERS as u8).map(voter).collect();
+        let mut proposal = proposal(Some(vec!["x".repeat(MAX_OPTION_LABEL_LEN); MAX_OPTIONS]), None);
+        proposal.weighting = Some(Weighting::Ledger(candid::Principal::anonymous()));
+        proposal.ledger_balances = Some(voters.iter().map(|voter| (*voter, u128::MAX)).collect());
+        proposal.eligible_voters = Some(voters);
+        assert!(proposal.to_bytes().len() <= MAX_VALUE_SIZE as usize);
+    }
+
+    #[test]
+    fn a_ranking_of_all_options_fits_a_vote() {
+        let ranking = (0..MAX_OPTIONS as u32).rev().collect();
+        let vote = Vote::Ranking(RankedBallot { voter: voter(255), ranking, weight: u128::MAX });
+        assert!(vote.to_bytes().len() <= MAX_VOTE_SIZE as usize);
+    }
+
+    #[test]
+    fn votes_are_not_bounded_by_the_proposal() {
+        PROPOSAL_MAP.with(|p| p.borrow_mut().insert(1, proposal(None, None)));
+        for n in 0..=255 {
+            save_vote(1, voter(n), Vote::Choice(Ballot { voter: voter(n), choice: VoteTypes::Approve, weight: 1 }));
+        }
+        save_vote(2, voter(0), Vote::Unrecorded);
+
+        let proposal = get_proposal(1).unwrap();
+        assert_eq!(proposal.voted.len(), 256);
+        assert_eq!(proposal.ballots.unwrap().len(), 256);
+        assert_eq!(votes_of(2).len(), 1);
+    }
+
+    #[test]
+    fn votes_saved_inside_proposals_move_out_on_upgrade() {
+        let mut poll = proposal(Some(vec!["a".to_string(), "b".to_string()]), Some(true));
+        poll.voted = vec![voter(1), voter(2)];
+        poll.rankings = Some(vec![RankedBallot { voter: voter(2), ranking: vec![1, 0], weight: 5 }]);
+        PROPOSAL_MAP.with(|p| p.borrow_mut().insert(3, poll));
+
+        migrate_votes();
+
+        let saved = PROPOSAL_MAP.with(|p| p.borrow().get(&3)).unwrap();
+        assert!(saved.voted.is_empty() && saved.rankings.is_none());
+        let votes = votes_of(3);
+        assert!(matches!(votes.as_slice(), [(first, Vote::Unrecorded), (second, Vote::Ranking(ballot))]
+            if *first == voter(1) && *second == voter(2) && ballot.ranking == vec![1, 0]));
+        let result = tally_irv(3).ok().unwrap();
+        assert_eq!(result.winner, Some(1));
+    }
+
+    fn outcome_of(thresholds: Option<Thresholds>, approve: u128, reject: u128, pass: u128) -> Outcome {
+        let proposal = Proposal { thresholds, ..proposal(None, None) };
+        proposal.outcome(&Tally { approve, reject, pass })
+    }
+
+    #[test]
+    fn outcomes_follow_the_thresholds() {
+        assert!(matches!(outcome_of(None, 0, 0, 0), Outcome::NoQuorum));
+        assert!(matches!(outcome_of(None, 2, 1, 0), Outcome::Approved));
+        assert!(matches!(outcome_of(None, 1, 1, 5), Outcome::Tied));
+        assert!(matches!(outcome_of(None, 1, 2, 0), Outcome::Rejected));
+        // Pass votes count for the quorum only
+        let thresholds = Some(Thresholds { quorum: 10, approval_percent: 60 });
+        assert!(matches!(outcome_of(thresholds, 6, 3, 0), Outcome::NoQuorum));
+        assert!(matches!(outcome_of(thresholds, 6, 4, 1), Outcome::Tied));
+        assert!(matches!(outcome_of(thresholds, 7, 4, 0), Outcome::Approved));
+        assert!(matches!(outcome_of(thresholds, 5, 4, 1), Outcome::Rejected));
+    }
+
+    #[test]
+    fn proposals_from_before_weights_count_one_per_voter() {
+        let mut proposal = Proposal { approve: 2, reject: 1, ..proposal(None, None) };
+        proposal.count(&VoteTypes::Approve, 5);
+        proposal.uncount(&VoteTypes::Reject, 1);
+        let weights = proposal.weights.as_ref().unwrap();
+        assert_eq!((weights.approve, weights.reject, weights.pass), (7, 0, 0));
+        assert_eq!((proposal.approve, proposal.reject), (3, 0));
+    }
+
+    #[test]
+    fn instant_runoff_eliminates_one_option_per_round() {
+        let labels = ["a", "b", "c"].iter().map(|label| label.to_string()).collect();
+        PROPOSAL_MAP.with(|p| p.borrow_mut().insert(4, proposal(Some(labels), Some(true))));
+        let rankings: [(&[u32], u128); 4] = [(&[0], 4), (&[1, 2], 3), (&[2, 1], 2), (&[2, 0], 1)];
+        for (n, (ranking, weight)) in rankings.into_iter().enumerate() {
+            let ballot = RankedBallot { voter: voter(n as u8), ranking: ranking.to_vec(), weight };
+            save_vote(4, voter(n as u8), Vote::Ranking(ballot));
+        }
+
+        // a 4, b 3, c 3: b and c tie last with the same first round weight, so b, the
+        // lowest index, is out and its ballots move to c, which wins over a
+        let result = tally_irv(4).ok().unwrap();
+        assert_eq!(result.winner, Some(2));
+        assert_eq!(result.rounds.len(), 2);
+        assert_eq!(result.rounds[0].tallies, vec![(0, 4), (1, 3), (2, 3)]);
+        assert_eq!(result.rounds[0].eliminated, vec![1]);
+        assert_eq!(result.rounds[1].tallies, vec![(0, 4), (2, 6)]);
+    }
+
+    #[test]
+    fn instant_runoff_ties_go_by_the_first_round() {
+        let labels = ["a", "b", "c", "d"].iter().map(|label| label.to_string()).collect();
+        PROPOSAL_MAP.with(|p| p.borrow_mut().insert(5, proposal(Some(labels), Some(true))));
+        let rankings: [(&[u32], u128); 4] = [(&[0], 5), (&[1], 3), (&[2], 2), (&[3, 2], 1)];
+        for (n, (ranking, weight)) in rankings.into_iter().enumerate() {
+            let ballot = RankedBallot { voter: voter(n as u8), ranking: ranking.to_vec(), weight };
+            save_vote(5, voter(n as u8), Vote::Ranking(ballot));
+        }
+
+        // d is out first and c ties b at 3, but had less in the first round
+        let result = tally_irv(5).ok().unwrap();
+        assert_eq!(result.rounds[0].eliminated, vec![3]);
+        assert_eq!(result.rounds[1].tallies, vec![(0, 5), (1, 3), (2, 3)]);
+        assert_eq!(result.rounds[1].eliminated, vec![2]);
+        assert_eq!(result.winner, Some(0));
+    }
+}
@@ -0,0 +1,78 @@
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{StableCell, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+use crate::{EventError, Memory, MEMORY_MANAGER};
+
+// The principal allowed to call admin-only endpoints
+#[derive(CandidType, Deserialize, Clone, Default)]
+struct Admin {
+    principal: Option<Principal>,
+}
+
+impl Storable for Admin {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+thread_local! {
+    static ADMIN: RefCell<StableCell<Admin, Memory>> = RefCell::new(
+        StableCell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(7))),
+            Admin::default(),
+        ).expect("failed to initialize the admin cell")
+    );
+}
+
+pub fn get() -> Option<Principal> {
+    ADMIN.with(|admin| admin.borrow().get().principal)
+}
+
+fn set(principal: Principal) {
+    ADMIN.with(|admin| {
+        admin
+            .borrow_mut()
+            .set(Admin { principal: Some(principal) })
+            .expect("failed to store the admin")
+    });
+}
+
+// Make the given principal the admin unless one is already set.
+// Called on install and upgrade, so whoever deploys the canister becomes its first admin.
+pub fn init_if_unset(principal: Principal) {
+    if get().is_none() {
+        set(principal);
+    }
+}
+
+// Make the admin of an exported state the admin
+pub fn restore(principal: Principal) {
+    set(principal);
+}
+
+pub fn require_admin() -> Result<(), EventError> {
+    if get() == Some(ic_cdk::caller()) {
+        Ok(())
+    } else {
+        Err(EventError::Unauthorized)
+    }
+}
+
+// Hand the admin role over to another principal
+#[ic_cdk::update]
+fn set_admin(principal: Principal) -> Result<(), EventError> {
+    require_admin()?;
+    set(principal);
+    Ok(())
+}
+
+#[ic_cdk::query]
+fn get_admin() -> Option<Principal> {
+    get()
+}
@@ -0,0 +1,478 @@
+use ic_cdk::api::call::RejectionCode;
+use ic_cdk::api::management_canister::http_request::{
+    http_request, CanisterHttpRequestArgument, HttpMethod,
+};
+
+use candid::{CandidType, Decode, Deserialize, Encode};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::collections::VecDeque;
+use std::time::Duration;
+use std::{borrow::Cow, cell::RefCell};
+
+use crate::types::{Event, EventError, EventStatus, Participant, Visibility, MAX_ADDRESS_LEN, MAX_NAME_LEN};
+use crate::{admin, archive, dates, external_ids, natural_keys, participants, reminders, search};
+use crate::{store_event, take_event_id, Memory, EVENTS_MAP, MEMORY_MANAGER};
+
+// The source registered on install, so a fresh canister behaves like before
+pub const DEFAULT_SOURCE_URL: &str = "https://654c93da77200d6ba8590738.mockapi.io/events";
+
+// How the response body of a source is laid out
+#[derive(CandidType, Deserialize, Clone, Copy)]
+pub enum ParserKind {
+    JsonArray,     // `[{ "name": .., "date": .. }, ..]`
+    JsonDataField, // `{ "data": [{ "name": .., "date": .. }, ..] }`
+}
+
+// An external API that events are fetched from
+#[derive(CandidType, Deserialize, Clone)]
+pub struct ApiSource {
+    pub url: String,
+    pub parser_kind: ParserKind,
+}
+
+impl Storable for ApiSource {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+const MAX_URL_LEN: usize = 400;
+// Ids of the API longer than this are skipped, the event would not fit its storage
+const MAX_API_ID_LEN: usize = 100;
+
+impl BoundedStorable for ApiSource {
+    const MAX_SIZE: u32 = 500;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+thread_local! {
+    static API_SOURCES: RefCell<StableBTreeMap<u64, ApiSource, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(8))),
+        )
+    );
+}
+
+// An event as returned by the external events API, with a string date
+#[derive(Deserialize)]
+struct ApiEvent {
+    #[serde(default)]
+    id: Option<String>,
+    name: String,
+    date: String,
+    #[serde(default)]
+    participants: Vec<Participant>,
+}
+
+#[derive(Deserialize)]
+struct ApiEventList {
+    data: Vec<ApiEvent>,
+}
+
+impl ApiEvent {
+    // `source_id` namespaces the API's own ids, which are only unique within a source
+    fn into_event(self, source_id: u64) -> Option<(Event, Vec<Participant>)> {
+        let start_time = dates::parse_iso_date(&self.date)?;
+        if self.name.is_empty() || self.name.len() > MAX_NAME_LEN {
+            return None;
+        }
+        if self.id.as_ref().is_some_and(|id| id.len() > MAX_API_ID_LEN) {
+            return None;
+        }
+        let participants: Vec<Participant> = self
+            .participants
+            .into_iter()
+            .filter(|p| !p.address.is_empty() && p.address.len() <= MAX_ADDRESS_LEN)
+            .collect();
+        let event = Event {
+            external_id: self.id.map(|id| format!("{}:{}", source_id, id)),
+            name: self.name,
+            start_time,
+            end_time: start_time, // the API has no end date
+            participant_count: participants.len() as u32,
+            maybe_count: 0,
+            declined_count: 0,
+            max_participants: None,
+            status: EventStatus::Published,
+            series_id: None,
+            parent_id: None,
+            tags: Vec::new(),
+            category: None,
+            organizer: ic_cdk::id(),
+            co_hosts: Vec::new(),
+            ticket_price: None,
+            visibility: Visibility::Public,
+            timezone: None,
+            location: None,
+            venue: None,
+        };
+        Some((event, participants))
+    }
+}
+
+impl ParserKind {
+    fn parse(self, body: &[u8]) -> Result<Vec<ApiEvent>, serde_json::Error> {
+        match self {
+            ParserKind::JsonArray => serde_json::from_slice(body),
+            ParserKind::JsonDataField => serde_json::from_slice::<ApiEventList>(body).map(|list| list.data),
+        }
+    }
+}
+
+fn insert_source(source: ApiSource) -> u64 {
+    API_SOURCES.with(|sources| {
+        let mut sources = sources.borrow_mut();
+        let id = sources.iter().last().map(|(id, _)| id + 1).unwrap_or(0);
+        sources.insert(id, source);
+        id
+    })
+}
+
+// Register the default source on a fresh install
+pub fn init_default_source() {
+    if API_SOURCES.with(|sources| sources.borrow().is_empty()) {
+        insert_source(ApiSource {
+            url: DEFAULT_SOURCE_URL.to_string(),
+            parser_kind: ParserKind::JsonArray,
+        });
+    }
+}
+
+#[ic_cdk::update]
+fn add_api_source(url: String, parser_kind: ParserKind) -> Result<u64, EventError> {
+    admin::require_admin()?;
+    if !url.starts_with("https://") || url.len() > MAX_URL_LEN {
+        return Err(EventError::InvalidUrl);
+    }
+    Ok(insert_source(ApiSource { url, parser_kind }))
+}
+
+#[ic_cdk::update]
+fn remove_api_source(source_id: u64) -> Result<(), EventError> {
+    admin::require_admin()?;
+    API_SOURCES
+        .with(|sources| sources.borrow_mut().remove(&source_id))
+        .map(|_| ())
+        .ok_or(EventError::NoSuchSource)
+}
+
+#[ic_cdk::query]
+fn list_api_sources() -> Result<Vec<(u64, ApiSource)>, EventError> {
+    admin::require_admin()?;
+    Ok(API_SOURCES.with(|sources| sources.borrow().iter().collect()))
+}
+
+pub fn sources_len() -> u64 {
+    API_SOURCES.with(|sources| sources.borrow().len())
+}
+
+// Sources ordered by id, from the `skip`-th one
+pub fn all_sources(skip: u64, take: u64) -> Vec<(u64, ApiSource)> {
+    API_SOURCES.with(|sources| sources.borrow().iter().skip(skip as usize).take(take as usize).collect())
+}
+
+pub fn restore_source(source_id: u64, source: ApiSource) {
+    API_SOURCES.with(|sources| sources.borrow_mut().insert(source_id, source));
+}
+
+// Outcome of merging fetched events into the stored ones
+#[derive(CandidType, Deserialize, Clone, Default)]
+pub struct ImportSummary {
+    pub added: u64,
+    pub updated: u64,
+    pub skipped: u64,
+}
+
+// A page that could not be fetched
+#[derive(CandidType, Deserialize, Clone)]
+pub struct FetchFailure {
+    pub source_id: u64,
+    pub page: u32,
+    pub attempt: u32,
+    pub error: String,
+    pub retry_scheduled: bool, // transient errors are retried with exponential backoff
+}
+
+// What a fetch (or a scheduled retry) did
+#[derive(CandidType, Deserialize, Clone, Default)]
+pub struct FetchReport {
+    pub started_at: u64,
+    pub pages_fetched: u32,
+    pub summary: ImportSummary,
+    pub failures: Vec<FetchFailure>,
+}
+
+// Sources are read `PAGE_LIMIT` events at a time, up to `MAX_PAGES` pages per fetch
+const PAGE_LIMIT: u32 = 50;
+const MAX_PAGES: u32 = 20;
+// Upper bound on the size of a single page response
+const MAX_RESPONSE_BYTES: u64 = 200_000;
+// A page is tried at most this many times, waiting BASE_RETRY_DELAY_SECS * 2^(attempt - 1) in between
+const MAX_ATTEMPTS: u32 = 4;
+const BASE_RETRY_DELAY_SECS: u64 = 5;
+// Number of reports kept for `get_fetch_reports`
+const MAX_REPORTS: usize = 10;
+
+thread_local! {
+    static FETCH_REPORTS: RefCell<VecDeque<FetchReport>> = RefCell::default();
+}
+
+enum FetchError {
+    Transient(String), // worth retrying: system rejections, rate limits and server errors
+    Permanent(String),
+}
+
+fn page_url(url: &str, page: u32) -> String {
+    let separator = if url.contains('?') { '&' } else { '?' };
+    format!("{}{}page={}&limit={}", url, separator, page, PAGE_LIMIT)
+}
+
+// Fetch a single page of a source
+async fn fetch_page(source_id: u64, source: &ApiSource, page: u32) -> Result<Vec<(Event, Vec<Participant>)>, FetchError> {
+    // Setup the HTTP request arguments
+    let request = CanisterHttpRequestArgument {
+        url: page_url(&source.url, page),
+        method: HttpMethod::GET,
+        body: None,
+        max_response_bytes: Some(MAX_RESPONSE_BYTES),
+        transform: None,
+        headers: vec![],
+    };
+
+    // Make the HTTPS request and wait for the response
+    match http_request(request).await {
+        Ok((response,)) => {
+            if response.status == 200 {
+                let api_events = source.parser_kind.parse(&response.body).map_err(|err| {
+                    FetchError::Permanent(format!("Failed to parse JSON response: {}", err))
+                })?;
+                // Events whose date cannot be parsed are skipped
+                Ok(api_events.into_iter().filter_map(|event| event.into_event(source_id)).collect())
+            } else {
+                let message = format!("HTTP request failed with status code: {}", response.status);
+                if response.status == 429 || response.status >= 500u32 {
+                    Err(FetchError::Transient(message))
+                } else {
+                    Err(FetchError::Permanent(message))
+                }
+            }
+        }
+        Err((code, message)) => {
+            let message = format!(
+                "The http_request resulted in an error. Code: {:?}, Message: {}",
+                code, message
+            );
+            match code {
+                RejectionCode::SysTransient => Err(FetchError::Transient(message)),
+                _ => Err(FetchError::Permanent(message)),
+            }
+        }
+    }
+}
+
+// Fetch and merge the pages of a source starting at `first_page`, until a page is not full.
+// A page failing with a transient error schedules a retry that continues from that page.
+async fn fetch_source(source_id: u64, source: &ApiSource, first_page: u32, attempt: u32, report: &mut FetchReport) {
+    for page in first_page..first_page + MAX_PAGES {
+        match fetch_page(source_id, source, page).await {
+            Ok(events) => {
+                let is_last_page = (events.len() as u32) < PAGE_LIMIT;
+                merge_events(events, &mut report.summary);
+                report.pages_fetched += 1;
+                if is_last_page {
+                    return;
+                }
+            }
+            Err(err) => {
+                let (error, retry_scheduled) = match err {
+                    FetchError::Transient(error) if attempt < MAX_ATTEMPTS => {
+                        schedule_retry(source_id, page, attempt + 1);
+                        (error, true)
+                    }
+                    FetchError::Transient(error) | FetchError::Permanent(error) => (error, false),
+                };
+                report.failures.push(FetchFailure {
+                    source_id,
+                    page,
+                    attempt,
+                    error,
+                    retry_scheduled,
+                });
+                return;
+            }
+        }
+    }
+}
+
+// Retry a page of a source after an exponentially growing delay, in the background
+fn schedule_retry(source_id: u64, page: u32, attempt: u32) {
+    let delay = Duration::from_secs(BASE_RETRY_DELAY_SECS << (attempt - 2));
+    ic_cdk_timers::set_timer(delay, move || {
+        ic_cdk::spawn(async move {
+            // The source may have been removed in the meantime
+            let Some(source) = API_SOURCES.with(|sources| sources.borrow().get(&source_id)) else {
+                return;
+            };
+            let mut report = FetchReport {
+                started_at: ic_cdk::api::time(),
+                ..Default::default()
+            };
+            fetch_source(source_id, &source, page, attempt, &mut report).await;
+            record_report(report);
+        })
+    });
+}
+
+fn record_report(report: FetchReport) {
+    FETCH_REPORTS.with(|reports| {
+        let mut reports = reports.borrow_mut();
+        if reports.len() == MAX_REPORTS {
+            reports.pop_front();
+        }
+        reports.push_back(report);
+    });
+}
+
+// Find the stored event an imported one corresponds to: by external id if it has one,
+// otherwise by its natural (name, start_time) key. Events imported with another external
+// id are not matched by their natural key, so they are not taken over by another source.
+fn find_existing(events_map: &StableBTreeMap<u64, Event, Memory>, imported: &Event) -> Option<(u64, Event)> {
+    if let Some(found) = imported
+        .external_id
+        .as_ref()
+        .and_then(|external_id| external_ids::find(events_map, external_id))
+        .and_then(|event_id| events_map.get(&event_id).map(|event| (event_id, event)))
+    {
+        return Some(found);
+    }
+    natural_keys::find(events_map, &imported.name, imported.start_time)
+        .and_then(|event_id| events_map.get(&event_id).map(|event| (event_id, event)))
+        .filter(|(_, event)| imported.external_id.is_none() || event.external_id.is_none())
+}
+
+// Upsert imported events: new ones are added with the API's participants, known ones get
+// the API's name and times while their local state (participants, status, tags, ...) is kept
+fn merge_events(events: Vec<(Event, Vec<Participant>)>, summary: &mut ImportSummary) {
+    EVENTS_MAP.with(|events_map_ref| {
+        let mut events_map = events_map_ref.borrow_mut();
+        for (imported, imported_participants) in events {
+            // Events archived here have ended long ago and are not imported again
+            if archive::contains(&imported) {
+                summary.skipped += 1;
+                continue;
+            }
+            match find_existing(&events_map, &imported) {
+                Some((event_id, mut event)) => {
+                    if event.name == imported.name
+                        && event.start_time == imported.start_time
+                        && event.end_time == imported.end_time
+                        && event.external_id == imported.external_id
+                    {
+                        summary.skipped += 1;
+                        continue;
+                    }
+                    if event.start_time != imported.start_time {
+                        dates::unindex_event(event_id, event.start_time);
+                        dates::index_event(event_id, imported.start_time);
+                        reminders::schedule(event_id, imported.start_time);
+                    }
+                    if event.name != imported.name {
+                        search::unindex_event(event_id, &event.name);
+                        search::index_event(event_id, &imported.name);
+                    }
+                    event.name = imported.name;
+                    event.start_time = imported.start_time;
+                    event.end_time = imported.end_time;
+                    event.external_id = imported.external_id;
+                    store_event(&mut events_map, event_id, event);
+                    summary.updated += 1;
+                }
+                None => {
+                    let event_id = take_event_id();
+                    for participant in imported_participants {
+                        participants::insert(event_id, participant);
+                    }
+                    dates::index_event(event_id, imported.start_time);
+                    search::index_event(event_id, &imported.name);
+                    reminders::schedule(event_id, imported.start_time);
+                    store_event(&mut events_map, event_id, imported);
+                    summary.added += 1;
+                }
+            }
+        }
+    });
+}
+
+// Update method to make HTTPS outcalls to every registered source and merge the fetched
+// events. Outcalls are paid for by the canister, so only the admin may trigger them.
+#[ic_cdk::update]
+async fn get_events_from_api() -> Result<FetchReport, EventError> {
+    admin::require_admin()?;
+    let sources: Vec<(u64, ApiSource)> = API_SOURCES.with(|sources| sources.borrow().iter().collect());
+    let mut report = FetchReport {
+        started_at: ic_cdk::api::time(),
+        ..Default::default()
+    };
+
+    for (source_id, source) in &sources {
+        fetch_source(*source_id, source, 1, 1, &mut report).await;
+    }
+    record_report(report.clone());
+    Ok(report)
+}
+
+// Query the reports of the latest fetches and background retries, oldest first
+#[ic_cdk::query]
+fn get_fetch_reports() -> Vec<FetchReport> {
+    FETCH_REPORTS.with(|reports| reports.borrow().iter().cloned().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn insert(event_id: u64, event: &Event) {
+        natural_keys::on_event_stored(event_id, None, event);
+        external_ids::on_event_stored(event_id, None, event);
+        EVENTS_MAP.with(|events_map| events_map.borrow_mut().insert(event_id, event.clone()));
+    }
+
+    fn imported(name: &str, start_time: u64, external_id: &str) -> Event {
+        let mut event = Event::for_test(name, start_time);
+        event.external_id = Some(external_id.to_string());
+        event
+    }
+
+    fn find_stored(imported: &Event) -> Option<u64> {
+        EVENTS_MAP.with(|events_map| find_existing(&events_map.borrow(), imported).map(|(event_id, _)| event_id))
+    }
+
+    #[test]
+    fn imported_events_fall_back_to_their_natural_key() {
+        insert(1, &Event::for_test("Meetup", 10));
+        insert(2, &imported("Talk", 20, "1:9"));
+
+        // A local event is matched by name and start time
+        assert_eq!(find_stored(&imported("Meetup", 10, "0:1")), Some(1));
+        // An event of another source is only matched by its own external id
+        assert_eq!(find_stored(&imported("Talk", 21, "1:9")), Some(2));
+        assert_eq!(find_stored(&imported("Talk", 20, "0:2")), None);
+        assert_eq!(find_stored(&Event::for_test("Talk", 20)), Some(2));
+    }
+
+    #[test]
+    fn events_with_an_over_long_id_are_skipped() {
+        let api_event = ApiEvent {
+            id: Some("x".repeat(MAX_API_ID_LEN + 1)),
+            name: "Meetup".to_string(),
+            date: "2024-05-01".to_string(),
+            participants: Vec::new(),
+        };
+        assert!(api_event.into_event(0).is_none());
+    }
+}
@@ -0,0 +1,191 @@
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+use std::time::Duration;
+
+use crate::dates::{self, NANOS_PER_DAY};
+use crate::types::{Event, EventError};
+use crate::{admin, external_ids, natural_keys};
+use crate::{index_event, remove_event, store_event, Memory, EVENTS_MAP, MEMORY_MANAGER, PAGE_SIZE};
+
+// Events that ended more than this many days ago are moved to the archive
+const ARCHIVE_AFTER_DAYS: u64 = 90;
+const ARCHIVE_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+// Events archived per run, so a run never runs out of instructions
+const MAX_ARCHIVED_PER_RUN: usize = 100;
+
+thread_local! {
+    // Archived events keep their id, and their participants stay in the participants map
+    static ARCHIVED_EVENTS_MAP: RefCell<StableBTreeMap<u64, Event, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(49))),
+        )
+    );
+
+    // Ids of the events restored by the admin, which are not archived again
+    static RESTORED: RefCell<StableBTreeMap<u64, (), Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(50))),
+        )
+    );
+
+    // Archived events keyed by (hash of their import key, event_id), so imports skip them
+    // without a scan. Hashes may collide, so the events found under a hash are compared
+    // with the imported one before they count as a match.
+    static ARCHIVED_KEYS: RefCell<StableBTreeMap<(u64, u64), (), Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(54))),
+        )
+    );
+}
+
+// Imports match events on their external id, or on their name and start time without one
+fn import_key(event: &Event) -> u64 {
+    match &event.external_id {
+        Some(external_id) => external_ids::hash(external_id),
+        None => natural_keys::hash(&event.name, event.start_time),
+    }
+}
+
+fn insert(event_id: u64, event: Event) {
+    ARCHIVED_KEYS.with(|keys| keys.borrow_mut().insert((import_key(&event), event_id), ()));
+    ARCHIVED_EVENTS_MAP.with(|archive| archive.borrow_mut().insert(event_id, event));
+}
+
+fn take(event_id: u64) -> Option<Event> {
+    let event = ARCHIVED_EVENTS_MAP.with(|archive| archive.borrow_mut().remove(&event_id))?;
+    ARCHIVED_KEYS.with(|keys| keys.borrow_mut().remove(&(import_key(&event), event_id)));
+    Some(event)
+}
+
+// Index the events archived before the index existed, once
+pub fn backfill_keys() {
+    if ARCHIVED_KEYS.with(|keys| !keys.borrow().is_empty()) {
+        return;
+    }
+    ARCHIVED_EVENTS_MAP.with(|archive| {
+        ARCHIVED_KEYS.with(|keys| {
+            let mut keys = keys.borrow_mut();
+            for (event_id, event) in archive.borrow().iter() {
+                keys.insert((import_key(&event), event_id), ());
+            }
+        })
+    });
+}
+
+pub fn len() -> u64 {
+    ARCHIVED_EVENTS_MAP.with(|archive| archive.borrow().len())
+}
+
+pub fn highest_id() -> Option<u64> {
+    ARCHIVED_EVENTS_MAP.with(|archive| archive.borrow().last_key_value().map(|(event_id, _)| event_id))
+}
+
+pub fn is_archived(event_id: u64) -> bool {
+    ARCHIVED_EVENTS_MAP.with(|archive| archive.borrow().contains_key(&event_id))
+}
+
+// Archived events ordered by id, from the `skip`-th one
+pub fn all(skip: u64, take: u64) -> Vec<(u64, Event)> {
+    ARCHIVED_EVENTS_MAP.with(|archive| {
+        archive
+            .borrow()
+            .iter()
+            .skip(skip as usize)
+            .take(take as usize)
+            .collect()
+    })
+}
+
+pub fn restore(event_id: u64, event: Event) {
+    insert(event_id, event);
+}
+
+// Whether an imported event was archived, matched on its external id or name and start time
+pub fn contains(imported: &Event) -> bool {
+    let key = import_key(imported);
+    let event_ids: Vec<u64> = ARCHIVED_KEYS.with(|keys| {
+        keys.borrow().range((key, 0)..=(key, u64::MAX)).map(|((_, event_id), _)| event_id).collect()
+    });
+    ARCHIVED_EVENTS_MAP.with(|archive| {
+        let archive = archive.borrow();
+        event_ids.iter().filter_map(|event_id| archive.get(event_id)).any(|event| match &imported.external_id {
+            Some(external_id) => event.external_id.as_ref() == Some(external_id),
+            None => event.name == imported.name && event.start_time == imported.start_time,
+        })
+    })
+}
+
+// Timers do not survive upgrades, so this is called from init and post_upgrade
+pub fn start() {
+    ic_cdk_timers::set_timer_interval(ARCHIVE_INTERVAL, archive_ended_events);
+}
+
+// Move the events that ended more than ARCHIVE_AFTER_DAYS ago out of EVENTS_MAP. Events
+// end after they start, so only those that started before the cutoff are looked at.
+fn archive_ended_events() {
+    let Some(cutoff) = ic_cdk::api::time().checked_sub(ARCHIVE_AFTER_DAYS * NANOS_PER_DAY) else {
+        return;
+    };
+    EVENTS_MAP.with(|events_map_ref| {
+        let mut events_map = events_map_ref.borrow_mut();
+        let ended: Vec<u64> = dates::events_starting_between(0, cutoff)
+            .into_iter()
+            .filter(|event_id| !RESTORED.with(|restored| restored.borrow().contains_key(event_id)))
+            .filter(|event_id| events_map.get(event_id).is_some_and(|event| event.end_time < cutoff))
+            .take(MAX_ARCHIVED_PER_RUN)
+            .collect();
+        for event_id in ended {
+            if let Some(event) = remove_event(&mut events_map, event_id) {
+                insert(event_id, event);
+            }
+        }
+    });
+}
+
+// Query a page of the archived public events, by id
+#[ic_cdk::query]
+fn get_archived_events(page: u64) -> Vec<(u64, Event)> {
+    ARCHIVED_EVENTS_MAP.with(|archive| {
+        archive
+            .borrow()
+            .iter()
+            .filter(|(_, event)| event.is_public())
+            .skip((page * PAGE_SIZE) as usize)
+            .take(PAGE_SIZE as usize)
+            .collect()
+    })
+}
+
+// Move an archived event back to EVENTS_MAP under its id. It is not archived again.
+#[ic_cdk::update]
+fn restore_archived(event_id: u64) -> Result<(), EventError> {
+    admin::require_admin()?;
+    let event = take(event_id).ok_or(EventError::NoSuchEvent)?;
+    RESTORED.with(|restored| restored.borrow_mut().insert(event_id, ()));
+    index_event(event_id, &event);
+    EVENTS_MAP.with(|events_map| store_event(&mut events_map.borrow_mut(), event_id, event));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn imports_match_archived_events_on_their_external_id_or_natural_key() {
+        let mut imported = Event::for_test("Meetup", 1);
+        imported.external_id = Some("0:42".to_string());
+        restore(1, imported.clone());
+        restore(2, Event::for_test("Workshop", 2));
+
+        assert!(contains(&imported));
+        assert!(contains(&Event::for_test("Workshop", 2)));
+        assert!(!contains(&Event::for_test("Meetup", 1)));
+        imported.external_id = Some("0:43".to_string());
+        assert!(!contains(&imported));
+
+        take(2);
+        assert!(!contains(&Event::for_test("Workshop", 2)));
+    }
+}
@@ -0,0 +1,260 @@
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::time::Duration;
+use std::{borrow::Cow, cell::Cell, cell::RefCell};
+
+use crate::types::{EventError, EventStatus};
+use crate::{checkin, require_host, tickets, Memory, EVENTS_MAP, MEMORY_MANAGER};
+
+// Proofs minted per timer run, so a large event does not run out of cycles in one message
+const MINTS_PER_RUN: usize = 20;
+
+#[derive(CandidType, Deserialize, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct MintKey {
+    event_id: u64,
+    address: String,
+}
+
+impl Storable for MintKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for MintKey {
+    const MAX_SIZE: u32 = 160;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+#[derive(CandidType, Deserialize, Clone)]
+pub enum MintState {
+    Pending,
+    Minted(u64),
+    // Failed mints are only tried again when the organizer mints the proofs again
+    Failed(EventError),
+}
+
+#[derive(CandidType, Deserialize, Clone)]
+pub struct AttendanceMint {
+    pub state: MintState,
+    pub attempts: u32,
+}
+
+impl Storable for AttendanceMint {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+// The encoding carries the type of EventError, with all of its variants
+impl BoundedStorable for AttendanceMint {
+    const MAX_SIZE: u32 = 1024;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct MintFailure {
+    pub address: String,
+    pub attempts: u32,
+    pub error: EventError,
+}
+
+#[derive(CandidType, Deserialize, Default)]
+pub struct AttendanceMintReport {
+    pub minted: u64,
+    pub pending: u64,
+    pub failed: u64,
+    pub tokens: Vec<(String, u64)>,
+    pub failures: Vec<MintFailure>,
+}
+
+thread_local! {
+    static ATTENDANCE_MINTS: RefCell<StableBTreeMap<MintKey, AttendanceMint, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(47))),
+        )
+    );
+
+    // Mints waiting for a timer run
+    static PENDING: RefCell<StableBTreeMap<MintKey, (), Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(48))),
+        )
+    );
+
+    static SCHEDULED: Cell<bool> = const { Cell::new(false) };
+}
+
+fn mints_range(event_id: u64) -> std::ops::Range<MintKey> {
+    MintKey { event_id, address: String::new() }..MintKey { event_id: event_id + 1, address: String::new() }
+}
+
+fn schedule() {
+    if SCHEDULED.with(|scheduled| scheduled.replace(true)) {
+        return;
+    }
+    ic_cdk_timers::set_timer(Duration::ZERO, || {
+        SCHEDULED.with(|scheduled| scheduled.set(false));
+        ic_cdk::spawn(process());
+    });
+}
+
+// Mint the next batch of pending proofs, one call to the nft-container at a time
+async fn process() {
+    // Taken off the queue first, so a run started meanwhile does not mint them twice
+    let batch: Vec<MintKey> = PENDING.with(|pending| {
+        let mut pending = pending.borrow_mut();
+        let batch: Vec<MintKey> = pending.iter().take(MINTS_PER_RUN).map(|(key, _)| key).collect();
+        for key in &batch {
+            pending.remove(key);
+        }
+        batch
+    });
+
+    for key in batch {
+        let Some(mut mint) = ATTENDANCE_MINTS.with(|mints| mints.borrow().get(&key)) else {
+            continue;
+        };
+        let result = match (
+            EVENTS_MAP.with(|events_map| events_map.borrow().get(&key.event_id)),
+            Principal::from_text(&key.address),
+        ) {
+            (Some(event), Ok(owner)) => tickets::mint_attendance_proof(key.event_id, &event, owner).await,
+            (None, _) => Err(EventError::NoSuchEvent),
+            (_, Err(_)) => Err(EventError::InvalidAddress),
+        };
+        mint.attempts += 1;
+        mint.state = match result {
+            Ok(token_id) => MintState::Minted(token_id),
+            Err(error) => MintState::Failed(error),
+        };
+        ATTENDANCE_MINTS.with(|mints| mints.borrow_mut().insert(key, mint));
+    }
+
+    if PENDING.with(|pending| !pending.borrow().is_empty()) {
+        schedule();
+    }
+}
+
+// Timers do not survive upgrades, so the pending mints are scheduled again. Mints that
+// were in flight during the upgrade stay Pending until the organizer mints again.
+pub fn reschedule() {
+    if PENDING.with(|pending| !pending.borrow().is_empty()) {
+        schedule();
+    }
+}
+
+pub fn len() -> u64 {
+    ATTENDANCE_MINTS.with(|mints| mints.borrow().len())
+}
+
+// Mints ordered by (event_id, address), from the `skip`-th one, and whether each waits
+// for a timer run
+pub fn all(skip: u64, take: u64) -> Vec<(u64, String, AttendanceMint, bool)> {
+    ATTENDANCE_MINTS.with(|mints| {
+        mints
+            .borrow()
+            .iter()
+            .skip(skip as usize)
+            .take(take as usize)
+            .map(|(key, mint)| {
+                let queued = PENDING.with(|pending| pending.borrow().contains_key(&key));
+                (key.event_id, key.address, mint, queued)
+            })
+            .collect()
+    })
+}
+
+// Keep a mint without scheduling it, which reschedule does
+pub fn restore(event_id: u64, address: String, mint: AttendanceMint, queued: bool) {
+    let key = MintKey { event_id, address };
+    if queued {
+        PENDING.with(|pending| pending.borrow_mut().insert(key.clone(), ()));
+    }
+    ATTENDANCE_MINTS.with(|mints| mints.borrow_mut().insert(key, mint));
+}
+
+// Mint a non-transferable proof of attendance to every participant who checked in to a
+// completed event. Proofs are minted in batches by a timer; calling this again queues
+// the proofs that failed or were never minted. Returns the number of proofs queued.
+#[ic_cdk::update]
+fn mint_attendance_proofs(event_id: u64) -> Result<u64, EventError> {
+    let event = EVENTS_MAP
+        .with(|events_map| events_map.borrow().get(&event_id))
+        .ok_or(EventError::NoSuchEvent)?;
+    if event.organizer != ic_cdk::caller() {
+        return Err(EventError::Unauthorized);
+    }
+    if event.status != EventStatus::Completed {
+        return Err(EventError::EventNotCompleted);
+    }
+    tickets::canister().ok_or(EventError::NoTicketCanister)?;
+
+    let mut queued = 0;
+    for check_in in checkin::checkins_of(event_id) {
+        let key = MintKey { event_id, address: check_in.address };
+        let previous = ATTENDANCE_MINTS.with(|mints| mints.borrow().get(&key));
+        if previous.as_ref().is_some_and(|mint| matches!(mint.state, MintState::Minted(_))) {
+            continue;
+        }
+        let attempts = previous.map(|mint| mint.attempts).unwrap_or(0);
+        ATTENDANCE_MINTS.with(|mints| {
+            mints.borrow_mut().insert(key.clone(), AttendanceMint { state: MintState::Pending, attempts })
+        });
+        PENDING.with(|pending| pending.borrow_mut().insert(key, ()));
+        queued += 1;
+    }
+    if queued > 0 {
+        schedule();
+    }
+    Ok(queued)
+}
+
+// Query the progress of the proofs of an event, with the tokens minted and the failures
+#[ic_cdk::query]
+fn get_attendance_mint_report(event_id: u64) -> Result<AttendanceMintReport, EventError> {
+    let event = EVENTS_MAP
+        .with(|events_map| events_map.borrow().get(&event_id))
+        .ok_or(EventError::NoSuchEvent)?;
+    require_host(&event)?;
+
+    let mut report = AttendanceMintReport::default();
+    ATTENDANCE_MINTS.with(|mints| {
+        for (key, mint) in mints.borrow().range(mints_range(event_id)) {
+            match mint.state {
+                MintState::Pending => report.pending += 1,
+                MintState::Minted(token_id) => {
+                    report.minted += 1;
+                    report.tokens.push((key.address, token_id));
+                }
+                MintState::Failed(error) => {
+                    report.failed += 1;
+                    report.failures.push(MintFailure { address: key.address, attempts: mint.attempts, error });
+                }
+            }
+        }
+    });
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_mint_state_fits_the_bound() {
+        for state in [MintState::Pending, MintState::Minted(u64::MAX), MintState::Failed(EventError::InvalidAddress)] {
+            let mint = AttendanceMint { state, attempts: u32::MAX };
+            assert!(mint.to_bytes().len() <= AttendanceMint::MAX_SIZE as usize);
+        }
+    }
+}
@@ -0,0 +1,183 @@
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{StableBTreeMap, StableLog, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+use crate::types::{Event, EventError, Participant, RsvpStatus};
+use crate::{admin, Memory, MEMORY_MANAGER, PAGE_SIZE};
+
+// Fields derived from the participants, which get entries of their own
+const DERIVED_FIELDS: [&str; 3] = ["participant_count", "maybe_count", "declined_count"];
+
+#[derive(CandidType, Deserialize, Clone, Copy)]
+pub enum AuditAction {
+    Create,
+    Update,
+    // A participant answered or changed their RSVP
+    Join,
+    // A participant was removed
+    Cancel,
+    Delete,
+}
+
+#[derive(CandidType, Deserialize, Clone)]
+pub struct AuditEntry {
+    pub event_id: u64,
+    pub caller: Principal,
+    pub timestamp: u64,
+    pub action: AuditAction,
+    // What changed, e.g. `status: "Draft" -> "Published"`
+    pub changes: Vec<String>,
+}
+
+impl Storable for AuditEntry {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+thread_local! {
+    // Entries are only ever appended
+    static AUDIT_LOG: RefCell<StableLog<AuditEntry, Memory, Memory>> = RefCell::new(
+        StableLog::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(26))),
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(27))),
+        ).expect("failed to initialize the audit log")
+    );
+
+    // Log indexes keyed by (event_id, index), so the history of an event is a contiguous range
+    static EVENT_HISTORY: RefCell<StableBTreeMap<(u64, u64), (), Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(28))),
+        )
+    );
+}
+
+fn append(event_id: u64, action: AuditAction, changes: Vec<String>) {
+    restore(AuditEntry {
+        event_id,
+        caller: ic_cdk::caller(),
+        timestamp: ic_cdk::api::time(),
+        action,
+        changes,
+    });
+}
+
+// Append an entry as it is, e.g. one exported by another canister
+pub fn restore(entry: AuditEntry) {
+    let index = AUDIT_LOG
+        .with(|log| log.borrow().append(&entry))
+        .expect("failed to append to the audit log");
+    EVENT_HISTORY.with(|history| history.borrow_mut().insert((entry.event_id, index), ()));
+}
+
+pub fn len() -> u64 {
+    AUDIT_LOG.with(|log| log.borrow().len())
+}
+
+// Entries of the log in the order they were appended, from the `skip`-th one
+pub fn all(skip: u64, take: u64) -> Vec<AuditEntry> {
+    AUDIT_LOG.with(|log| {
+        let log = log.borrow();
+        (skip..skip.saturating_add(take)).map_while(|index| log.get(index)).collect()
+    })
+}
+
+// The fields that differ between two versions of an event
+fn diff(previous: &Event, event: &Event) -> Vec<String> {
+    let (Ok(serde_json::Value::Object(previous)), Ok(serde_json::Value::Object(event))) =
+        (serde_json::to_value(previous), serde_json::to_value(event))
+    else {
+        return Vec::new();
+    };
+    event
+        .iter()
+        .filter(|(field, _)| !DERIVED_FIELDS.contains(&field.as_str()))
+        .filter_map(|(field, value)| {
+            let old = previous.get(field).unwrap_or(&serde_json::Value::Null);
+            (old != value).then(|| format!("{}: {} -> {}", field, old, value))
+        })
+        .collect()
+}
+
+fn rsvp_name(rsvp: RsvpStatus) -> &'static str {
+    match rsvp {
+        RsvpStatus::Going => "Going",
+        RsvpStatus::Maybe => "Maybe",
+        RsvpStatus::Declined => "Declined",
+    }
+}
+
+// Called with the previous and new value whenever an event is stored
+pub fn on_event_stored(event_id: u64, previous: Option<&Event>, event: &Event) {
+    match previous {
+        None => append(event_id, AuditAction::Create, vec![format!("name: {}", event.name)]),
+        Some(previous) => {
+            let changes = diff(previous, event);
+            // Writes that only update the participant counts are covered by Join and Cancel
+            if !changes.is_empty() {
+                append(event_id, AuditAction::Update, changes);
+            }
+        }
+    }
+}
+
+// Called whenever a participant is stored
+pub fn on_participant_stored(event_id: u64, previous: Option<&Participant>, participant: &Participant) {
+    let change = match previous {
+        Some(previous) if previous.rsvp == participant.rsvp => return,
+        Some(previous) => format!(
+            "{}: {} -> {}",
+            participant.address,
+            rsvp_name(previous.rsvp),
+            rsvp_name(participant.rsvp)
+        ),
+        None => format!("{}: {}", participant.address, rsvp_name(participant.rsvp)),
+    };
+    append(event_id, AuditAction::Join, vec![change]);
+}
+
+// Called whenever a participant is removed
+pub fn on_participant_removed(event_id: u64, address: &str) {
+    append(event_id, AuditAction::Cancel, vec![address.to_string()]);
+}
+
+pub fn record_update(event_id: u64, change: String) {
+    append(event_id, AuditAction::Update, vec![change]);
+}
+
+pub fn record_delete(event_id: u64, what: String) {
+    append(event_id, AuditAction::Delete, vec![what]);
+}
+
+// Query the whole audit log in the order of the changes
+#[ic_cdk::query]
+fn get_audit_log(page: u64) -> Result<Vec<AuditEntry>, EventError> {
+    admin::require_admin()?;
+    Ok(AUDIT_LOG.with(|log| {
+        let log = log.borrow();
+        (page * PAGE_SIZE..(page + 1) * PAGE_SIZE)
+            .map_while(|index| log.get(index))
+            .collect()
+    }))
+}
+
+// Query every change made to an event, oldest first
+#[ic_cdk::query]
+fn get_event_history(event_id: u64) -> Vec<AuditEntry> {
+    let indexes: Vec<u64> = EVENT_HISTORY.with(|history| {
+        history
+            .borrow()
+            .range((event_id, 0)..=(event_id, u64::MAX))
+            .map(|((_, index), _)| index)
+            .collect()
+    });
+    AUDIT_LOG.with(|log| {
+        let log = log.borrow();
+        indexes.into_iter().filter_map(|index| log.get(index)).collect()
+    })
+}
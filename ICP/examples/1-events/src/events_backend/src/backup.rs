@@ -0,0 +1,442 @@
+use candid::{CandidType, Deserialize, Principal};
+
+use crate::api::{self, ApiSource};
+use crate::attendance::{self, AttendanceMint};
+use crate::audit::{self, AuditEntry};
+use crate::checkin::{CheckIn, CheckinCode};
+use crate::comments::Comment;
+use crate::images::ImageInfo;
+use crate::notifications::{self, Notification};
+use crate::payments::{self, Payment};
+use crate::profiles::ParticipantProfile;
+use crate::refunds::{self, Refund};
+use crate::sync::{self, SyncStatus};
+use crate::types::{Event, EventError, Participant};
+use crate::webhooks::{self, Webhook, WebhookDelivery};
+use crate::{admin, archive, certification, checkin, comments, images, invites, participants, profiles};
+use crate::{promotions, reminders, reserve_event_id, restore_event, sessions, tickets, waitlist, EVENTS_MAP};
+
+// Every item but image chunks is bounded, so a chunk stays well under the response size
+// limit. Image chunks are up to 64 KiB each, so they come last, one per export chunk.
+const ITEMS_PER_CHUNK: u64 = 500;
+
+// A record of the primary state. Secondary indexes, statistics and the certified
+// tree are derived from it, so they are rebuilt on import rather than exported.
+#[derive(CandidType, Deserialize)]
+pub enum StateItem {
+    AuditEntry { entry: AuditEntry },
+    Event { event_id: u64, event: Box<Event> },
+    ArchivedEvent { event_id: u64, event: Box<Event> },
+    Session { parent_id: u64, event_id: u64 },
+    Participant { event_id: u64, participant: Participant },
+    WaitlistEntry { event_id: u64, address: String },
+    Payment { event_id: u64, address: String, payment: Payment },
+    Refund { event_id: u64, address: String, refund: Refund },
+    Promotion { event_id: u64, address: String, deadline: u64 },
+    Invite { event_id: u64, principal: String },
+    CheckinCode { event_id: u64, code: CheckinCode },
+    CheckIn { event_id: u64, checkin: CheckIn },
+    AttendanceMint { event_id: u64, address: String, mint: AttendanceMint, queued: bool },
+    Comment { event_id: u64, comment_id: u64, comment: Comment },
+    Profile { event_id: u64, principal: Principal, profile: ParticipantProfile },
+    Image { event_id: u64, info: ImageInfo },
+    ImageChunk { event_id: u64, chunk_index: u32, bytes: Vec<u8> },
+    Notification { recipient: String, seq: u64, notification: Notification },
+    ApiSource { source_id: u64, source: ApiSource },
+    Webhook { event_id: u64, webhook: Webhook },
+    WebhookDelivery { event_id: u64, delivery: WebhookDelivery },
+    PushEndpoint { url: String },
+    SyncStatus { event_id: u64, status: SyncStatus },
+    TicketCanister { canister: Principal },
+    Admin { principal: Principal },
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct StateChunk {
+    pub chunk_index: u64,
+    pub total_chunks: u64,
+    // Number of items of the whole export; it changes if the state is written to
+    // while the export runs
+    pub total_items: u64,
+    pub items: Vec<StateItem>,
+}
+
+// The number of items of a section of the export, and a page of them from the `skip`-th one
+type Section = (u64, fn(u64, u64) -> Vec<StateItem>);
+
+// Sections come in a fixed order, so entries of the waitlists keep their order. The audit
+// log comes first, so the entries imported events and participants append follow it.
+// Webhooks come after the participants, whose import would otherwise be delivered to them.
+fn sections() -> [Section; 24] {
+    [
+        (audit::len(), |skip, take| {
+            audit::all(skip, take).into_iter().map(|entry| StateItem::AuditEntry { entry }).collect()
+        }),
+        (EVENTS_MAP.with(|events_map| events_map.borrow().len()), |skip, take| {
+            EVENTS_MAP.with(|events_map| {
+                events_map
+                    .borrow()
+                    .iter()
+                    .skip(skip as usize)
+                    .take(take as usize)
+                    .map(|(event_id, event)| StateItem::Event { event_id, event: Box::new(event) })
+                    .collect()
+            })
+        }),
+        (archive::len(), |skip, take| {
+            archive::all(skip, take)
+                .into_iter()
+                .map(|(event_id, event)| StateItem::ArchivedEvent { event_id, event: Box::new(event) })
+                .collect()
+        }),
+        (sessions::len(), |skip, take| {
+            sessions::all(skip, take)
+                .into_iter()
+                .map(|(parent_id, event_id)| StateItem::Session { parent_id, event_id })
+                .collect()
+        }),
+        (participants::len(), |skip, take| {
+            participants::all(skip, take)
+                .into_iter()
+                .map(|(event_id, participant)| StateItem::Participant { event_id, participant })
+                .collect()
+        }),
+        (waitlist::len(), |skip, take| {
+            waitlist::all(skip, take)
+                .into_iter()
+                .map(|(event_id, address)| StateItem::WaitlistEntry { event_id, address })
+                .collect()
+        }),
+        (payments::len(), |skip, take| {
+            payments::all(skip, take)
+                .into_iter()
+                .map(|(event_id, address, payment)| StateItem::Payment { event_id, address, payment })
+                .collect()
+        }),
+        (refunds::len(), |skip, take| {
+            refunds::all(skip, take)
+                .into_iter()
+                .map(|(event_id, address, refund)| StateItem::Refund { event_id, address, refund })
+                .collect()
+        }),
+        (promotions::len(), |skip, take| {
+            promotions::all(skip, take)
+                .into_iter()
+                .map(|(event_id, address, deadline)| StateItem::Promotion { event_id, address, deadline })
+                .collect()
+        }),
+        (invites::len(), |skip, take| {
+            invites::all(skip, take)
+                .into_iter()
+                .map(|(event_id, principal)| StateItem::Invite { event_id, principal })
+                .collect()
+        }),
+        (checkin::codes_len(), |skip, take| {
+            checkin::all_codes(skip, take)
+                .into_iter()
+                .map(|(event_id, code)| StateItem::CheckinCode { event_id, code })
+                .collect()
+        }),
+        (checkin::len(), |skip, take| {
+            checkin::all(skip, take)
+                .into_iter()
+                .map(|(event_id, checkin)| StateItem::CheckIn { event_id, checkin })
+                .collect()
+        }),
+        (attendance::len(), |skip, take| {
+            attendance::all(skip, take)
+                .into_iter()
+                .map(|(event_id, address, mint, queued)| StateItem::AttendanceMint { event_id, address, mint, queued })
+                .collect()
+        }),
+        (comments::len(), |skip, take| {
+            comments::all(skip, take)
+                .into_iter()
+                .map(|((event_id, comment_id), comment)| StateItem::Comment { event_id, comment_id, comment })
+                .collect()
+        }),
+        (profiles::len(), |skip, take| {
+            profiles::all(skip, take)
+                .into_iter()
+                .map(|(event_id, principal, profile)| StateItem::Profile { event_id, principal, profile })
+                .collect()
+        }),
+        (images::len(), |skip, take| {
+            images::all(skip, take)
+                .into_iter()
+                .map(|(event_id, info)| StateItem::Image { event_id, info })
+                .collect()
+        }),
+        (notifications::len(), |skip, take| {
+            notifications::all(skip, take)
+                .into_iter()
+                .map(|(recipient, seq, notification)| StateItem::Notification { recipient, seq, notification })
+                .collect()
+        }),
+        (api::sources_len(), |skip, take| {
+            api::all_sources(skip, take)
+                .into_iter()
+                .map(|(source_id, source)| StateItem::ApiSource { source_id, source })
+                .collect()
+        }),
+        (webhooks::len(), |skip, take| {
+            webhooks::all(skip, take)
+                .into_iter()
+                .map(|(event_id, webhook)| StateItem::Webhook { event_id, webhook })
+                .collect()
+        }),
+        (webhooks::deliveries_len(), |skip, take| {
+            webhooks::all_deliveries(skip, take)
+                .into_iter()
+                .map(|(event_id, delivery)| StateItem::WebhookDelivery { event_id, delivery })
+                .collect()
+        }),
+        (sync::get_push_endpoint().is_some() as u64, |skip, take| {
+            sync::get_push_endpoint()
+                .into_iter()
+                .skip(skip as usize)
+                .take(take as usize)
+                .map(|url| StateItem::PushEndpoint { url })
+                .collect()
+        }),
+        (sync::statuses_len(), |skip, take| {
+            sync::all_statuses(skip, take)
+                .into_iter()
+                .map(|(event_id, status)| StateItem::SyncStatus { event_id, status })
+                .collect()
+        }),
+        (tickets::canister().is_some() as u64, |skip, take| {
+            tickets::canister()
+                .into_iter()
+                .skip(skip as usize)
+                .take(take as usize)
+                .map(|canister| StateItem::TicketCanister { canister })
+                .collect()
+        }),
+        (admin::get().is_some() as u64, |skip, take| {
+            admin::get()
+                .into_iter()
+                .skip(skip as usize)
+                .take(take as usize)
+                .map(|principal| StateItem::Admin { principal })
+                .collect()
+        }),
+    ]
+}
+
+// Export a chunk of the state. Items come in a fixed order: the audit log, events,
+// archived events, sessions, participants, waitlist entries, payments, refunds,
+// promotions, invites, check-in codes, check-ins, attendance mints, comments, profiles,
+// images, notifications, API sources, webhooks and their deliveries, the push endpoint,
+// sync statuses, the ticket canister and the admin, then the chunks of the images.
+#[ic_cdk::query]
+fn export_state(chunk_index: u64) -> Result<StateChunk, EventError> {
+    admin::require_admin()?;
+
+    let sections = sections();
+    let bounded_items: u64 = sections.iter().map(|(len, _)| len).sum();
+    let bounded_chunks = bounded_items.div_ceil(ITEMS_PER_CHUNK);
+    let image_chunks = images::chunks_len();
+
+    let mut items = Vec::new();
+    if chunk_index >= bounded_chunks {
+        if let Some((event_id, index, bytes)) = images::nth_chunk(chunk_index - bounded_chunks) {
+            items.push(StateItem::ImageChunk { event_id, chunk_index: index, bytes });
+        }
+    } else {
+        let mut skip = chunk_index * ITEMS_PER_CHUNK;
+        for (len, page) in sections {
+            let take = ITEMS_PER_CHUNK - items.len() as u64;
+            if take == 0 {
+                break;
+            }
+            if skip >= len {
+                skip -= len;
+                continue;
+            }
+            items.extend(page(skip, take));
+            skip = 0;
+        }
+    }
+
+    Ok(StateChunk {
+        chunk_index,
+        total_chunks: bounded_chunks + image_chunks,
+        total_items: bounded_items + image_chunks,
+        items,
+    })
+}
+
+// Import a chunk exported by export_state, e.g. into a new canister. Chunks must be
+// imported in order. A chunk with an event id that is already taken is rejected as a
+// whole with AlreadyExists. Imported events keep their ids, and new events get ids
+// above them. The admin of the export becomes the admin, so the chunks after it must be
+// imported by them.
+#[ic_cdk::update]
+fn import_state(chunk: StateChunk) -> Result<(), EventError> {
+    admin::require_admin()?;
+
+    let taken = EVENTS_MAP.with(|events_map| {
+        let events_map = events_map.borrow();
+        chunk.items.iter().any(|item| match item {
+            StateItem::Event { event_id, .. } | StateItem::ArchivedEvent { event_id, .. } => {
+                events_map.contains_key(event_id) || archive::is_archived(*event_id)
+            }
+            _ => false,
+        })
+    });
+    if taken {
+        return Err(EventError::AlreadyExists);
+    }
+
+    let scheduled = chunk.items.iter().any(|item| {
+        matches!(
+            item,
+            StateItem::Refund { .. }
+                | StateItem::Promotion { .. }
+                | StateItem::AttendanceMint { .. }
+                | StateItem::WebhookDelivery { .. }
+        )
+    });
+    let events: Vec<(u64, u64)> = chunk
+        .items
+        .iter()
+        .filter_map(|item| match item {
+            StateItem::Event { event_id, event } => Some((*event_id, event.start_time)),
+            _ => None,
+        })
+        .collect();
+    for item in chunk.items {
+        import_item(item);
+    }
+    if !events.is_empty() {
+        certification::certify_restored();
+        for (event_id, start_time) in events {
+            reminders::schedule(event_id, start_time);
+        }
+    }
+    // Imported refunds, deadlines, mints and deliveries are only kept by import_item
+    if scheduled {
+        refunds::reschedule();
+        promotions::reschedule_all();
+        attendance::reschedule();
+        webhooks::reschedule();
+    }
+    Ok(())
+}
+
+fn import_item(item: StateItem) {
+    match item {
+        StateItem::AuditEntry { entry } => audit::restore(entry),
+        StateItem::Event { event_id, event } => {
+            EVENTS_MAP.with(|events_map_ref| restore_event(&mut events_map_ref.borrow_mut(), event_id, *event))
+        }
+        StateItem::ArchivedEvent { event_id, event } => {
+            reserve_event_id(event_id);
+            archive::restore(event_id, *event);
+        }
+        StateItem::Session { parent_id, event_id } => sessions::index_event(event_id, parent_id),
+        StateItem::Participant { event_id, participant } => participants::insert(event_id, participant),
+        StateItem::WaitlistEntry { event_id, address } => {
+            waitlist::push(event_id, address);
+        }
+        StateItem::Payment { event_id, address, payment } => payments::restore(event_id, &address, payment),
+        StateItem::Refund { event_id, address, refund } => refunds::restore(event_id, address, refund),
+        StateItem::Promotion { event_id, address, deadline } => promotions::restore(event_id, address, deadline),
+        StateItem::Invite { event_id, principal } => invites::restore(event_id, principal),
+        StateItem::CheckinCode { event_id, code } => checkin::restore_code(event_id, code),
+        StateItem::CheckIn { event_id, checkin } => checkin::restore(event_id, checkin),
+        StateItem::AttendanceMint { event_id, address, mint, queued } => {
+            attendance::restore(event_id, address, mint, queued)
+        }
+        StateItem::Comment { event_id, comment_id, comment } => comments::restore(event_id, comment_id, comment),
+        StateItem::Profile { event_id, principal, profile } => profiles::set(event_id, principal, profile),
+        StateItem::Image { event_id, info } => images::restore(event_id, info),
+        StateItem::ImageChunk { event_id, chunk_index, bytes } => images::restore_chunk(event_id, chunk_index, bytes),
+        StateItem::Notification { recipient, seq, notification } => {
+            notifications::restore(recipient, seq, notification)
+        }
+        StateItem::ApiSource { source_id, source } => api::restore_source(source_id, source),
+        StateItem::Webhook { event_id, webhook } => webhooks::restore(event_id, webhook),
+        StateItem::WebhookDelivery { event_id, delivery } => webhooks::restore_delivery(event_id, delivery),
+        StateItem::PushEndpoint { url } => sync::restore_push_endpoint(url),
+        StateItem::SyncStatus { event_id, status } => sync::restore_status(event_id, status),
+        StateItem::TicketCanister { canister } => tickets::restore_canister(canister),
+        StateItem::Admin { principal } => admin::restore(principal),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audit::AuditAction;
+    use crate::sync::SyncState;
+    use crate::{listings, next_event_id};
+    use candid::{Encode, Nat};
+
+    // Every item of the export, in the order export_state pages them
+    fn export() -> Vec<StateItem> {
+        sections().into_iter().flat_map(|(len, page)| page(0, len)).collect()
+    }
+
+    #[test]
+    fn an_import_exports_the_same_items() {
+        let exported = std::thread::spawn(|| {
+            audit::restore(AuditEntry {
+                event_id: 3,
+                caller: Principal::anonymous(),
+                timestamp: 5,
+                action: AuditAction::Create,
+                changes: vec!["name: \"Meetup\"".to_string()],
+            });
+            EVENTS_MAP.with(|events_map| events_map.borrow_mut().insert(3, Event::for_test("Meetup", 1)));
+            let payment = Payment { amount: 100, ledger: Principal::anonymous(), block_index: Nat::from(7u64) };
+            let refund = Refund {
+                payment,
+                amount: Some(90),
+                attempts: 2,
+                next_attempt: None,
+                created_at: Some(5),
+                unconfirmed: Some(true),
+            };
+            refunds::restore(3, "aaaaa-aa".to_string(), refund);
+            sync::restore_status(
+                3,
+                SyncStatus {
+                    state: SyncState::Failed,
+                    version: 3,
+                    pushed_version: Some(2),
+                    failed_attempts: 1,
+                    last_error: Some("HTTP request failed with status code: 500".to_string()),
+                    last_pushed_at: Some(5),
+                },
+            );
+            admin::restore(Principal::from_slice(&[1]));
+            export()
+        })
+        .join()
+        .unwrap();
+        let expected = Encode!(&exported).unwrap();
+
+        let imported = std::thread::spawn(move || {
+            for item in exported {
+                import_item(item);
+            }
+            export()
+        })
+        .join()
+        .unwrap();
+        assert_eq!(Encode!(&imported).unwrap(), expected);
+    }
+
+    #[test]
+    fn imported_events_are_indexed_without_new_audit_entries_or_syncs() {
+        import_item(StateItem::Event { event_id: 7, event: Box::new(Event::for_test("Meetup", 1)) });
+
+        assert_eq!(audit::len(), 0);
+        assert_eq!(sync::statuses_len(), 0);
+        assert_eq!(next_event_id(), 8);
+        let page = listings::list(listings::SortBy::RecentlyCreated, None);
+        assert_eq!(page.events.iter().map(|(event_id, _)| *event_id).collect::<Vec<_>>(), vec![7]);
+    }
+}
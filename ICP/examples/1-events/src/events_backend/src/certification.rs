@@ -0,0 +1,100 @@
+use candid::{CandidType, Deserialize, Encode};
+use ic_certified_map::{AsHashTree, Hash, RbTree};
+use serde::Serialize;
+use serde_cbor::Serializer;
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+
+use crate::types::Event;
+use crate::EVENTS_MAP;
+
+// Label of the event subtree in the certified data
+const LABEL: &[u8] = b"events";
+
+// An event with the proof that it is the value currently stored under its id
+#[derive(CandidType, Deserialize)]
+pub struct CertifiedEvent {
+    // None with a witness proving that no event has this id
+    pub event: Option<Event>,
+    // The subnet's certificate of the canister's certified data
+    pub certificate: Vec<u8>,
+    // CBOR-encoded hash tree witnessing the event hash under `events/<id>`
+    pub witness: Vec<u8>,
+}
+
+// Hashes of all events by big-endian id. The tree lives on the heap and is rebuilt from
+// EVENTS_MAP after an upgrade.
+thread_local! {
+    static EVENT_HASHES: RefCell<RbTree<[u8; 8], Hash>> = const { RefCell::new(RbTree::new()) };
+}
+
+// The hash certified for an event: sha256 of its candid encoding
+pub fn hash_of(event: &Event) -> Hash {
+    Sha256::digest(Encode!(event).unwrap()).into()
+}
+
+fn update_certified_data(hashes: &RbTree<[u8; 8], Hash>) {
+    ic_cdk::api::set_certified_data(&ic_certified_map::labeled_hash(LABEL, &hashes.root_hash()));
+}
+
+// Record the new value of an event and certify it
+pub fn certify(event_id: u64, event: &Event) {
+    EVENT_HASHES.with(|hashes| {
+        let mut hashes = hashes.borrow_mut();
+        hashes.insert(event_id.to_be_bytes(), hash_of(event));
+        update_certified_data(&hashes);
+    });
+}
+
+// Record the value of an imported event without certifying it yet; import_state
+// certifies the whole chunk at once with `certify_restored`
+pub fn restore(event_id: u64, event: &Event) {
+    EVENT_HASHES.with(|hashes| hashes.borrow_mut().insert(event_id.to_be_bytes(), hash_of(event)));
+}
+
+pub fn certify_restored() {
+    EVENT_HASHES.with(|hashes| update_certified_data(&hashes.borrow()));
+}
+
+pub fn uncertify(event_id: u64) {
+    EVENT_HASHES.with(|hashes| {
+        let mut hashes = hashes.borrow_mut();
+        hashes.delete(&event_id.to_be_bytes());
+        update_certified_data(&hashes);
+    });
+}
+
+// Rebuild the tree from the stored events
+pub fn certify_all() {
+    EVENT_HASHES.with(|hashes| {
+        let mut hashes = hashes.borrow_mut();
+        *hashes = RbTree::new();
+        EVENTS_MAP.with(|events_map| {
+            for (event_id, event) in events_map.borrow().iter() {
+                hashes.insert(event_id.to_be_bytes(), hash_of(&event));
+            }
+        });
+        update_certified_data(&hashes);
+    });
+}
+
+fn witness(event_id: u64) -> Vec<u8> {
+    EVENT_HASHES.with(|hashes| {
+        let hashes = hashes.borrow();
+        let tree = ic_certified_map::labeled(LABEL, hashes.witness(&event_id.to_be_bytes()));
+        let mut data = vec![];
+        let mut serializer = Serializer::new(&mut data);
+        serializer.self_describe().unwrap();
+        tree.serialize(&mut serializer).unwrap();
+        data
+    })
+}
+
+// Query an event together with a certificate, so agents can verify the response
+// without an update call. Only available in non-replicated queries.
+#[ic_cdk::query]
+fn get_certified_event(event_id: u64) -> Option<CertifiedEvent> {
+    let certificate = ic_cdk::api::data_certificate()?;
+    let event = EVENTS_MAP.with(|events_map| events_map.borrow().get(&event_id));
+    Some(CertifiedEvent { event, certificate, witness: witness(event_id) })
+}
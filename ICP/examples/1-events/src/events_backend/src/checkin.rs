@@ -0,0 +1,226 @@
+use candid::{CandidType, Decode, Deserialize, Encode};
+use ic_cdk::api::management_canister::main::raw_rand;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+use crate::types::{EventError, RsvpStatus};
+use crate::{can_see_participants, participants, require_host, tickets, Memory, EVENTS_MAP, MEMORY_MANAGER};
+
+// Number of random bytes in a check-in code, shown as hex
+const CODE_BYTES: usize = 6;
+
+// The code participants enter to check in, handed out by the hosts at the venue
+#[derive(CandidType, Deserialize, Clone)]
+pub struct CheckinCode {
+    code: String,
+    created_at: u64,
+}
+
+impl Storable for CheckinCode {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for CheckinCode {
+    const MAX_SIZE: u32 = 100;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// Check-ins are keyed by (event_id, address), like participants
+#[derive(CandidType, Deserialize, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct CheckinKey {
+    event_id: u64,
+    address: String,
+}
+
+impl Storable for CheckinKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for CheckinKey {
+    const MAX_SIZE: u32 = 160;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+#[derive(CandidType, Deserialize, Clone)]
+pub struct CheckIn {
+    pub address: String,
+    pub checked_in_at: u64,
+}
+
+// Going participants of an event, and those of them who checked in
+#[derive(CandidType, Deserialize, Clone)]
+pub struct Attendance {
+    pub registered: Vec<String>,
+    pub checked_in: Vec<CheckIn>,
+}
+
+thread_local! {
+    static CHECKIN_CODES: RefCell<StableBTreeMap<u64, CheckinCode, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(12))),
+        )
+    );
+
+    // Time of each check-in, in nanoseconds since the epoch
+    static CHECKINS: RefCell<StableBTreeMap<CheckinKey, u64, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(13))),
+        )
+    );
+}
+
+fn key(event_id: u64, address: &str) -> CheckinKey {
+    CheckinKey { event_id, address: address.to_string() }
+}
+
+pub fn is_checked_in(event_id: u64, address: &str) -> bool {
+    CHECKINS.with(|checkins| checkins.borrow().contains_key(&key(event_id, address)))
+}
+
+pub fn checkins_of(event_id: u64) -> Vec<CheckIn> {
+    CHECKINS.with(|checkins| {
+        checkins
+            .borrow()
+            .range(key(event_id, "")..key(event_id + 1, ""))
+            .map(|(key, checked_in_at)| CheckIn { address: key.address, checked_in_at })
+            .collect()
+    })
+}
+
+pub fn codes_len() -> u64 {
+    CHECKIN_CODES.with(|codes| codes.borrow().len())
+}
+
+// Check-in codes ordered by event id, from the `skip`-th one
+pub fn all_codes(skip: u64, take: u64) -> Vec<(u64, CheckinCode)> {
+    CHECKIN_CODES.with(|codes| {
+        codes
+            .borrow()
+            .iter()
+            .skip(skip as usize)
+            .take(take as usize)
+            .collect()
+    })
+}
+
+pub fn restore_code(event_id: u64, code: CheckinCode) {
+    CHECKIN_CODES.with(|codes| codes.borrow_mut().insert(event_id, code));
+}
+
+pub fn len() -> u64 {
+    CHECKINS.with(|checkins| checkins.borrow().len())
+}
+
+// Check-ins of all events ordered by (event_id, address), from the `skip`-th one
+pub fn all(skip: u64, take: u64) -> Vec<(u64, CheckIn)> {
+    CHECKINS.with(|checkins| {
+        checkins
+            .borrow()
+            .iter()
+            .skip(skip as usize)
+            .take(take as usize)
+            .map(|(key, checked_in_at)| (key.event_id, CheckIn { address: key.address, checked_in_at }))
+            .collect()
+    })
+}
+
+pub fn restore(event_id: u64, checkin: CheckIn) {
+    CHECKINS.with(|checkins| {
+        checkins
+            .borrow_mut()
+            .insert(key(event_id, &checkin.address), checkin.checked_in_at)
+    });
+}
+
+// Generate a new random check-in code for an event, replacing the previous one
+#[ic_cdk::update]
+async fn generate_checkin_code(event_id: u64) -> Result<String, EventError> {
+    let event = EVENTS_MAP
+        .with(|events_map| events_map.borrow().get(&event_id))
+        .ok_or(EventError::NoSuchEvent)?;
+    require_host(&event)?;
+
+    let (bytes,) = raw_rand().await.map_err(|_| EventError::RandomnessUnavailable)?;
+    let code: String = bytes
+        .iter()
+        .take(CODE_BYTES)
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+
+    CHECKIN_CODES.with(|codes| {
+        codes.borrow_mut().insert(
+            event_id,
+            CheckinCode { code: code.clone(), created_at: ic_cdk::api::time() },
+        )
+    });
+    Ok(code)
+}
+
+// Check the caller in to an event they are going to, using the code of its hosts.
+// Participants holding a ticket must still own it.
+#[ic_cdk::update]
+async fn check_in(event_id: u64, code: String) -> Result<(), EventError> {
+    let event = EVENTS_MAP
+        .with(|events_map| events_map.borrow().get(&event_id))
+        .ok_or(EventError::NoSuchEvent)?;
+    if !event.status.is_open() {
+        return Err(EventError::EventNotOpen);
+    }
+
+    let expected = CHECKIN_CODES.with(|codes| codes.borrow().get(&event_id));
+    match expected {
+        Some(expected) if expected.code == code.trim().to_lowercase() => {}
+        _ => return Err(EventError::InvalidCheckinCode),
+    }
+
+    let caller = ic_cdk::caller().to_text();
+    let participant = match participants::get(event_id, &caller) {
+        Some(participant) if participant.rsvp == RsvpStatus::Going => participant,
+        _ => return Err(EventError::NotRegistered),
+    };
+    if is_checked_in(event_id, &caller) {
+        return Err(EventError::AlreadyCheckedIn);
+    }
+
+    tickets::verify_owner(&participant, ic_cdk::caller()).await?;
+    // Another check-in of the caller may have completed during the call
+    if is_checked_in(event_id, &caller) {
+        return Err(EventError::AlreadyCheckedIn);
+    }
+
+    CHECKINS.with(|checkins| {
+        checkins
+            .borrow_mut()
+            .insert(key(event_id, &caller), ic_cdk::api::time())
+    });
+    Ok(())
+}
+
+// Query the registered (Going) and checked-in participants of an event, None for private
+// events of other hosts
+#[ic_cdk::query]
+fn get_attendance(event_id: u64) -> Option<Attendance> {
+    EVENTS_MAP
+        .with(|events_map| events_map.borrow().get(&event_id))
+        .filter(can_see_participants)?;
+
+    let registered = participants::of_event(event_id)
+        .into_iter()
+        .filter(|participant| participant.rsvp == RsvpStatus::Going)
+        .map(|participant| participant.address)
+        .collect();
+    Some(Attendance { registered, checked_in: checkins_of(event_id) })
+}
@@ -0,0 +1,118 @@
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+use crate::types::EventError;
+use crate::{audit, require_host, Memory, EVENTS_MAP, MEMORY_MANAGER, PAGE_SIZE};
+
+pub const MAX_COMMENT_LEN: usize = 500;
+
+#[derive(CandidType, Deserialize, Clone)]
+pub struct Comment {
+    pub author: Principal,
+    pub text: String,
+    pub created_at: u64,
+}
+
+impl Storable for Comment {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for Comment {
+    const MAX_SIZE: u32 = 600;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// Comments are keyed by (event_id, comment_id), so the thread of an event is a
+// contiguous range ordered by posting time
+thread_local! {
+    static COMMENTS_MAP: RefCell<StableBTreeMap<(u64, u64), Comment, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(16))),
+        )
+    );
+}
+
+pub fn len() -> u64 {
+    COMMENTS_MAP.with(|comments| comments.borrow().len())
+}
+
+// Comments of all events ordered by (event_id, comment_id), from the `skip`-th one
+pub fn all(skip: u64, take: u64) -> Vec<((u64, u64), Comment)> {
+    COMMENTS_MAP.with(|comments| {
+        comments
+            .borrow()
+            .iter()
+            .skip(skip as usize)
+            .take(take as usize)
+            .collect()
+    })
+}
+
+pub fn restore(event_id: u64, comment_id: u64, comment: Comment) {
+    COMMENTS_MAP.with(|comments| comments.borrow_mut().insert((event_id, comment_id), comment));
+}
+
+// Post a comment to the discussion of an event, returns the comment id
+#[ic_cdk::update]
+fn post_comment(event_id: u64, text: String) -> Result<u64, EventError> {
+    if !EVENTS_MAP.with(|events_map| events_map.borrow().contains_key(&event_id)) {
+        return Err(EventError::NoSuchEvent);
+    }
+    let text = text.trim().to_string();
+    if text.is_empty() || text.len() > MAX_COMMENT_LEN {
+        return Err(EventError::InvalidComment);
+    }
+
+    COMMENTS_MAP.with(|comments| {
+        let mut comments = comments.borrow_mut();
+        // Next id after the newest comment of the event
+        let comment_id = comments
+            .range((event_id, 0)..=(event_id, u64::MAX))
+            .last()
+            .map(|((_, comment_id), _)| comment_id + 1)
+            .unwrap_or(0);
+        let comment = Comment { author: ic_cdk::caller(), text, created_at: ic_cdk::api::time() };
+        comments.insert((event_id, comment_id), comment);
+        Ok(comment_id)
+    })
+}
+
+// Query a page of the comments of an event, oldest first
+#[ic_cdk::query]
+fn get_comments(event_id: u64, page: u64) -> Vec<(u64, Comment)> {
+    COMMENTS_MAP.with(|comments| {
+        comments
+            .borrow()
+            .range((event_id, 0)..=(event_id, u64::MAX))
+            .skip((page * PAGE_SIZE) as usize)
+            .take(PAGE_SIZE as usize)
+            .map(|((_, comment_id), comment)| (comment_id, comment))
+            .collect()
+    })
+}
+
+// Delete a comment; allowed to its author and to the hosts of the event
+#[ic_cdk::update]
+fn delete_comment(event_id: u64, comment_id: u64) -> Result<(), EventError> {
+    let comment = COMMENTS_MAP
+        .with(|comments| comments.borrow().get(&(event_id, comment_id)))
+        .ok_or(EventError::NoSuchComment)?;
+    if comment.author != ic_cdk::caller() {
+        let event = EVENTS_MAP
+            .with(|events_map| events_map.borrow().get(&event_id))
+            .ok_or(EventError::NoSuchEvent)?;
+        require_host(&event)?;
+    }
+
+    COMMENTS_MAP.with(|comments| comments.borrow_mut().remove(&(event_id, comment_id)));
+    audit::record_delete(event_id, format!("comment {}", comment_id));
+    Ok(())
+}
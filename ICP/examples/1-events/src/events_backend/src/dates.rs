@@ -0,0 +1,159 @@
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+
+use crate::{Memory, MEMORY_MANAGER};
+
+pub const NANOS_PER_SECOND: u64 = 1_000_000_000;
+pub const NANOS_PER_DAY: u64 = 24 * 60 * 60 * NANOS_PER_SECOND;
+
+// Events ordered by (start_time, event_id), so time ranges can be read without a full scan
+thread_local! {
+    static START_TIME_INDEX: RefCell<StableBTreeMap<(u64, u64), (), Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(5))),
+        )
+    );
+}
+
+pub fn index_event(event_id: u64, start_time: u64) {
+    START_TIME_INDEX.with(|index| index.borrow_mut().insert((start_time, event_id), ()));
+}
+
+pub fn unindex_event(event_id: u64, start_time: u64) {
+    START_TIME_INDEX.with(|index| index.borrow_mut().remove(&(start_time, event_id)));
+}
+
+// Ids of the events starting in [from, to], ordered by start time
+pub fn events_starting_between(from: u64, to: u64) -> Vec<u64> {
+    if from > to {
+        return Vec::new();
+    }
+    START_TIME_INDEX.with(|index| {
+        index
+            .borrow()
+            .range((from, 0)..=(to, u64::MAX))
+            .map(|((_, event_id), _)| event_id)
+            .collect()
+    })
+}
+
+// Run `f` over the index of all events ordered by start time
+pub fn with_start_time_index<R>(f: impl FnOnce(&StableBTreeMap<(u64, u64), (), Memory>) -> R) -> R {
+    START_TIME_INDEX.with(|index| f(&index.borrow()))
+}
+
+// Number of events starting in [from, to], read from the index without loading the events
+pub fn count_starting_between(from: u64, to: u64) -> u64 {
+    if from > to {
+        return 0;
+    }
+    START_TIME_INDEX.with(|index| index.borrow().range((from, 0)..=(to, u64::MAX)).count() as u64)
+}
+
+// Parse `YYYY-MM-DD` optionally followed by `THH:MM` or `THH:MM:SS` (UTC) into
+// nanoseconds since the epoch. Used for events imported with string dates.
+pub fn parse_iso_date(date: &str) -> Option<u64> {
+    let (ymd, time) = match date.split_once('T') {
+        Some((ymd, time)) => (ymd, Some(time)),
+        None => (date, None),
+    };
+
+    let mut parts = ymd.split('-');
+    let year: i64 = parts.next()?.parse().ok()?;
+    let month: u32 = parts.next()?.parse().ok()?;
+    let day: u32 = parts.next()?.parse().ok()?;
+    if parts.next().is_some() || !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    let mut seconds = 0;
+    if let Some(time) = time {
+        // Ignore fractional seconds and a trailing `Z`
+        let time = time.trim_end_matches('Z');
+        let time = time.split('.').next()?;
+        let mut parts = time.split(':');
+        let hours: u64 = parts.next()?.parse().ok()?;
+        let minutes: u64 = parts.next()?.parse().ok()?;
+        let secs: u64 = parts.next().map(|s| s.parse().ok()).unwrap_or(Some(0))?;
+        if hours > 23 || minutes > 59 || secs > 59 {
+            return None;
+        }
+        seconds = hours * 3600 + minutes * 60 + secs;
+    }
+
+    let days = days_from_civil(year, month, day);
+    if days < 0 {
+        return None;
+    }
+    Some(days as u64 * NANOS_PER_DAY + seconds * NANOS_PER_SECOND)
+}
+
+// Days since 1970-01-01 for a proleptic Gregorian date
+pub fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = if year >= 0 { year } else { year - 399 } / 400;
+    let yoe = year - era * 400;
+    let mp = (month as i64 + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+
+// Proleptic Gregorian (year, month, day) for days since 1970-01-01
+pub fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = if z >= 0 { z } else { z - 146096 } / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+// Format nanoseconds since the epoch as a UTC date-time in the iCalendar
+// `YYYYMMDDTHHMMSSZ` form
+pub fn format_ical(time: u64) -> String {
+    let (year, month, day) = civil_from_days((time / NANOS_PER_DAY) as i64);
+    let seconds = (time % NANOS_PER_DAY) / NANOS_PER_SECOND;
+    format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn iso_dates_are_parsed_as_utc() {
+        assert_eq!(parse_iso_date("1970-01-02"), Some(NANOS_PER_DAY));
+        assert_eq!(
+            parse_iso_date("2024-02-29T12:30:15.250Z"),
+            Some(days_from_civil(2024, 2, 29) as u64 * NANOS_PER_DAY + (12 * 3600 + 30 * 60 + 15) * NANOS_PER_SECOND)
+        );
+        assert_eq!(parse_iso_date("2024-02-29T12:30"), parse_iso_date("2024-02-29T12:30:00"));
+        for invalid in ["2024-13-01", "2024-01-32", "2024-01-01T24:00", "1969-12-31", "2024-01", "May 1st"] {
+            assert_eq!(parse_iso_date(invalid), None, "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn civil_dates_round_trip() {
+        for days in [-719468, -1, 0, 59, 60, 11016, 19782, 2932896] {
+            let (year, month, day) = civil_from_days(days);
+            assert_eq!(days_from_civil(year, month, day), days);
+        }
+        assert_eq!(civil_from_days(19782), (2024, 2, 29));
+    }
+}
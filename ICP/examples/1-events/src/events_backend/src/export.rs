@@ -0,0 +1,114 @@
+use candid::{CandidType, Deserialize};
+use serde::Serialize;
+use std::collections::HashMap;
+
+use crate::types::{EventError, RsvpStatus};
+use crate::{checkin, participants, waitlist, EVENTS_MAP};
+
+// Every row is bounded by the address length, so a chunk stays well under the response
+// size limit
+const ROWS_PER_CHUNK: u64 = 500;
+const CSV_HEADER: &str = "address,rsvp,joined_at,checked_in,checked_in_at\n";
+
+#[derive(CandidType, Deserialize, Clone, Copy)]
+pub enum ExportFormat {
+    Csv,
+    Json,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct ParticipantExport {
+    pub chunk_index: u64,
+    pub total_chunks: u64,
+    pub data: String,
+}
+
+// A row of the export. Times are in nanoseconds since the epoch.
+#[derive(Serialize)]
+struct ExportRow {
+    address: String,
+    // Going, Maybe, Declined or Waitlisted
+    rsvp: &'static str,
+    joined_at: Option<u64>,
+    checked_in: bool,
+    checked_in_at: Option<u64>,
+}
+
+fn rsvp_label(rsvp: RsvpStatus) -> &'static str {
+    match rsvp {
+        RsvpStatus::Going => "Going",
+        RsvpStatus::Maybe => "Maybe",
+        RsvpStatus::Declined => "Declined",
+    }
+}
+
+// Quote a CSV field if it holds a separator, a quote or a line break
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn csv_row(row: &ExportRow) -> String {
+    let time = |time: Option<u64>| time.map(|time| time.to_string()).unwrap_or_default();
+    format!(
+        "{},{},{},{},{}\n",
+        csv_field(&row.address),
+        row.rsvp,
+        time(row.joined_at),
+        row.checked_in,
+        time(row.checked_in_at)
+    )
+}
+
+// Export a chunk of the participants of an event and its waitlist, ordered by address,
+// waitlisted participants last. CSV chunks can be concatenated, as only the first one has
+// the header; JSON chunks are arrays of rows.
+#[ic_cdk::query]
+fn export_participants(event_id: u64, format: ExportFormat, chunk_index: u64) -> Result<ParticipantExport, EventError> {
+    let event = EVENTS_MAP
+        .with(|events_map| events_map.borrow().get(&event_id))
+        .ok_or(EventError::NoSuchEvent)?;
+    if event.organizer != ic_cdk::caller() {
+        return Err(EventError::Unauthorized);
+    }
+
+    let checkins: HashMap<String, u64> = checkin::checkins_of(event_id)
+        .into_iter()
+        .map(|check_in| (check_in.address, check_in.checked_in_at))
+        .collect();
+    let mut answers: Vec<(String, &'static str)> = participants::of_event(event_id)
+        .into_iter()
+        .map(|participant| (participant.address, rsvp_label(participant.rsvp)))
+        .collect();
+    answers.extend(waitlist::addresses(event_id).into_iter().map(|address| (address, "Waitlisted")));
+
+    let total_rows = answers.len() as u64;
+    let rows: Vec<ExportRow> = answers
+        .into_iter()
+        .skip((chunk_index * ROWS_PER_CHUNK) as usize)
+        .take(ROWS_PER_CHUNK as usize)
+        .map(|(address, rsvp)| ExportRow {
+            joined_at: participants::joined_at(event_id, &address),
+            checked_in: checkins.contains_key(&address),
+            checked_in_at: checkins.get(&address).copied(),
+            address,
+            rsvp,
+        })
+        .collect();
+
+    let data = match format {
+        ExportFormat::Csv => {
+            let header = if chunk_index == 0 { CSV_HEADER } else { "" };
+            header.to_string() + &rows.iter().map(csv_row).collect::<String>()
+        }
+        ExportFormat::Json => serde_json::to_string(&rows).unwrap(),
+    };
+    Ok(ParticipantExport {
+        chunk_index,
+        total_chunks: total_rows.div_ceil(ROWS_PER_CHUNK).max(1),
+        data,
+    })
+}
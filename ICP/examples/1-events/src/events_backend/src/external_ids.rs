@@ -0,0 +1,108 @@
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::StableBTreeMap;
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+
+use crate::types::Event;
+use crate::{Memory, EVENTS_MAP, MEMORY_MANAGER};
+
+// Imported events keyed by (hash of external id, event_id), so imports find the events
+// they update without a scan. Hashes may collide, so the events found under a hash are
+// compared with the external id before they count as a match.
+thread_local! {
+    static EXTERNAL_IDS: RefCell<StableBTreeMap<(u64, u64), (), Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(53))),
+        )
+    );
+}
+
+pub fn hash(external_id: &str) -> u64 {
+    let digest = Sha256::digest(external_id.as_bytes());
+    u64::from_be_bytes(digest[..8].try_into().unwrap())
+}
+
+// Called with the previous and new value whenever an event is stored
+pub fn on_event_stored(event_id: u64, previous: Option<&Event>, event: &Event) {
+    let previous_id = previous.and_then(|previous| previous.external_id.as_ref());
+    if previous_id == event.external_id.as_ref() {
+        return;
+    }
+    EXTERNAL_IDS.with(|index| {
+        let mut index = index.borrow_mut();
+        if let Some(previous_id) = previous_id {
+            index.remove(&(hash(previous_id), event_id));
+        }
+        if let Some(external_id) = &event.external_id {
+            index.insert((hash(external_id), event_id), ());
+        }
+    });
+}
+
+// Called when an event is moved out of the events map
+pub fn on_event_removed(event_id: u64, event: &Event) {
+    if let Some(external_id) = &event.external_id {
+        EXTERNAL_IDS.with(|index| index.borrow_mut().remove(&(hash(external_id), event_id)));
+    }
+}
+
+// Index the events stored before the index existed, once
+pub fn backfill() {
+    if EXTERNAL_IDS.with(|index| !index.borrow().is_empty()) {
+        return;
+    }
+    EVENTS_MAP.with(|events_map| {
+        for (event_id, event) in events_map.borrow().iter() {
+            on_event_stored(event_id, None, &event);
+        }
+    });
+}
+
+// Id of the event with this external id, if one is stored
+pub fn find(events_map: &StableBTreeMap<u64, Event, Memory>, external_id: &str) -> Option<u64> {
+    let key = hash(external_id);
+    EXTERNAL_IDS.with(|index| {
+        index
+            .borrow()
+            .range((key, 0)..=(key, u64::MAX))
+            .map(|((_, event_id), _)| event_id)
+            .find(|event_id| {
+                events_map
+                    .get(event_id)
+                    .is_some_and(|event| event.external_id.as_deref() == Some(external_id))
+            })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store(event_id: u64, previous: Option<&Event>, event: &Event) {
+        on_event_stored(event_id, previous, event);
+        EVENTS_MAP.with(|events_map| events_map.borrow_mut().insert(event_id, event.clone()));
+    }
+
+    fn find_stored(external_id: &str) -> Option<u64> {
+        EVENTS_MAP.with(|events_map| find(&events_map.borrow(), external_id))
+    }
+
+    #[test]
+    fn events_are_found_by_their_current_external_id() {
+        let mut event = Event::for_test("Meetup", 1);
+        event.external_id = Some("0:42".to_string());
+        store(5, None, &event);
+        store(6, None, &Event::for_test("Meetup", 1));
+        assert_eq!(find_stored("0:42"), Some(5));
+        assert_eq!(find_stored("0:43"), None);
+
+        let mut moved = event.clone();
+        moved.external_id = Some("1:42".to_string());
+        store(5, Some(&event), &moved);
+        assert_eq!(find_stored("0:42"), None);
+        assert_eq!(find_stored("1:42"), Some(5));
+
+        on_event_removed(5, &moved);
+        assert_eq!(find_stored("1:42"), None);
+    }
+}
@@ -0,0 +1,144 @@
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+
+use crate::types::{Event, EventError, Location};
+use crate::{Memory, EVENTS_MAP, MEMORY_MANAGER, PAGE_SIZE};
+
+// Bits of the geohashes in the index: 15 for the latitude and 15 for the longitude,
+// cells of about 600 m by 1.2 km at the equator
+const GEOHASH_BITS: u32 = 30;
+const EARTH_RADIUS_KM: f64 = 6371.0;
+const KM_PER_DEGREE: f64 = 111.32;
+
+// Events keyed by (geohash, event_id). A geohash interleaves longitude and latitude
+// bits, so the events of a coarser cell are a contiguous range of the index.
+thread_local! {
+    static GEO_INDEX: RefCell<StableBTreeMap<(u64, u64), (), Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(32))),
+        )
+    );
+}
+
+// Geohash of a point with the given number of bits, starting with a longitude bit
+fn geohash(location: &Location, bits: u32) -> u64 {
+    let (mut lat_range, mut lon_range) = ((-90.0, 90.0), (-180.0, 180.0));
+    let mut hash = 0;
+    for bit in 0..bits {
+        let (range, value) = if bit % 2 == 0 {
+            (&mut lon_range, location.longitude)
+        } else {
+            (&mut lat_range, location.latitude)
+        };
+        let middle = (range.0 + range.1) / 2.0;
+        hash <<= 1;
+        if value >= middle {
+            hash |= 1;
+            range.0 = middle;
+        } else {
+            range.1 = middle;
+        }
+    }
+    hash
+}
+
+// Height and width in degrees of the cells of a geohash precision
+fn cell_size(bits: u32) -> (f64, f64) {
+    let lat_bits = bits / 2;
+    let lon_bits = bits - lat_bits;
+    (180.0 / (1u64 << lat_bits) as f64, 360.0 / (1u64 << lon_bits) as f64)
+}
+
+// Great-circle distance in km
+fn distance_km(from: &Location, to: &Location) -> f64 {
+    let (lat1, lat2) = (from.latitude.to_radians(), to.latitude.to_radians());
+    let d_lat = lat2 - lat1;
+    let d_lon = (to.longitude - from.longitude).to_radians();
+    let a = (d_lat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (d_lon / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_KM * a.sqrt().asin()
+}
+
+pub fn index_event(event_id: u64, location: &Location) {
+    let hash = geohash(location, GEOHASH_BITS);
+    GEO_INDEX.with(|index| index.borrow_mut().insert((hash, event_id), ()));
+}
+
+pub fn unindex_event(event_id: u64, location: &Location) {
+    let hash = geohash(location, GEOHASH_BITS);
+    GEO_INDEX.with(|index| index.borrow_mut().remove(&(hash, event_id)));
+}
+
+// Ids of the events in the cell around `center` and its 8 neighbours, using the finest
+// precision whose cells are at least `radius_km` wide, so the whole circle is covered
+fn candidates(center: &Location, radius_km: f64) -> Vec<u64> {
+    // Cells are narrowest at the latitude of the circle that is furthest from the equator
+    let max_latitude = (center.latitude.abs() + radius_km / KM_PER_DEGREE).min(90.0);
+    let bits = (0..=GEOHASH_BITS)
+        .rev()
+        .step_by(2)
+        .find(|bits| {
+            let (height, width) = cell_size(*bits);
+            let width_km = width * KM_PER_DEGREE * max_latitude.to_radians().cos();
+            height * KM_PER_DEGREE >= radius_km && width_km >= radius_km
+        })
+        .unwrap_or(0);
+    let (height, width) = cell_size(bits);
+
+    let mut cells = Vec::new();
+    for d_lat in [-1.0, 0.0, 1.0] {
+        for d_lon in [-1.0, 0.0, 1.0] {
+            let point = Location {
+                latitude: (center.latitude + d_lat * height).clamp(-90.0, 90.0),
+                // Wrap around the antimeridian
+                longitude: (center.longitude + d_lon * width + 180.0).rem_euclid(360.0) - 180.0,
+            };
+            cells.push(geohash(&point, bits));
+        }
+    }
+    cells.sort_unstable();
+    cells.dedup();
+
+    let shift = GEOHASH_BITS - bits;
+    GEO_INDEX.with(|index| {
+        let index = index.borrow();
+        cells
+            .into_iter()
+            .flat_map(|cell| {
+                index
+                    .range((cell << shift, 0)..((cell + 1) << shift, 0))
+                    .map(|((_, event_id), _)| event_id)
+                    .collect::<Vec<u64>>()
+            })
+            .collect()
+    })
+}
+
+// Query the public events within `radius_km` of a point, nearest first
+#[ic_cdk::query]
+fn get_events_near(latitude: f64, longitude: f64, radius_km: f64, page: u64) -> Result<Vec<(u64, Event)>, EventError> {
+    let center = Location { latitude, longitude };
+    if !center.is_valid() || !radius_km.is_finite() || radius_km <= 0.0 {
+        return Err(EventError::InvalidLocation);
+    }
+
+    let mut events: Vec<(f64, u64, Event)> = EVENTS_MAP.with(|events_map| {
+        let events = events_map.borrow();
+        candidates(&center, radius_km)
+            .into_iter()
+            .filter_map(|event_id| events.get(&event_id).map(|event| (event_id, event)))
+            .filter(|(_, event)| event.is_public())
+            .filter_map(|(event_id, event)| {
+                let distance = distance_km(&center, event.location.as_ref()?);
+                (distance <= radius_km).then_some((distance, event_id, event))
+            })
+            .collect()
+    });
+    events.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)));
+    Ok(events
+        .into_iter()
+        .skip((page * PAGE_SIZE) as usize)
+        .take(PAGE_SIZE as usize)
+        .map(|(_, event_id, event)| (event_id, event))
+        .collect())
+}
@@ -0,0 +1,272 @@
+use candid::{CandidType, Decode, Deserialize, Encode};
+use serde::Serialize;
+
+use crate::dates;
+use crate::listings::{self, ListCursor, SortBy};
+use crate::types::{Event, EventStatus, Participant};
+use crate::{images, participants, EVENTS_MAP, PAGE_SIZE};
+
+#[derive(CandidType, Deserialize)]
+struct HttpRequest {
+    method: String,
+    url: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+#[derive(CandidType)]
+struct HttpResponse {
+    status_code: u16,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl HttpResponse {
+    fn ok(content_type: &str, body: Vec<u8>) -> Self {
+        HttpResponse {
+            status_code: 200,
+            headers: vec![("Content-Type".to_string(), content_type.to_string())],
+            body,
+        }
+    }
+
+    fn error(status_code: u16, message: &str) -> Self {
+        HttpResponse {
+            status_code,
+            headers: vec![("Content-Type".to_string(), "text/plain; charset=utf-8".to_string())],
+            body: message.as_bytes().to_vec(),
+        }
+    }
+
+    fn json(value: &impl Serialize) -> Self {
+        match serde_json::to_vec(value) {
+            Ok(body) => HttpResponse::ok("application/json", body)
+                .with_header("Access-Control-Allow-Origin", "*".to_string()),
+            Err(_) => HttpResponse::error(500, "Failed to serialize the response"),
+        }
+    }
+
+    fn with_header(mut self, name: &str, value: String) -> Self {
+        self.headers.push((name.to_string(), value));
+        self
+    }
+}
+
+// Escape a text value as required by RFC 5545
+fn escape_ical(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace('\n', "\\n")
+        .replace('\r', "")
+}
+
+// Content lines longer than 75 octets are folded onto continuation lines starting with a space
+fn fold_ical_line(line: &str) -> String {
+    let mut folded = String::new();
+    let mut line_len = 0;
+    for c in line.chars() {
+        if line_len + c.len_utf8() > 75 {
+            folded.push_str("\r\n ");
+            line_len = 1;
+        }
+        folded.push(c);
+        line_len += c.len_utf8();
+    }
+    folded.push_str("\r\n");
+    folded
+}
+
+// An iCalendar file with a single VEVENT for the event
+fn ical_of(event_id: u64, event: &Event) -> String {
+    let status = match event.status {
+        EventStatus::Draft => "TENTATIVE",
+        EventStatus::Cancelled => "CANCELLED",
+        EventStatus::Published | EventStatus::Full | EventStatus::Completed => "CONFIRMED",
+    };
+    let mut lines = vec![
+        "BEGIN:VCALENDAR".to_string(),
+        "VERSION:2.0".to_string(),
+        "PRODID:-//ICP Hacker's Den//events_backend//EN".to_string(),
+        "CALSCALE:GREGORIAN".to_string(),
+        "BEGIN:VEVENT".to_string(),
+        format!("UID:event-{}@{}", event_id, ic_cdk::id().to_text()),
+        format!("DTSTAMP:{}", dates::format_ical(ic_cdk::api::time())),
+        format!("DTSTART:{}", dates::format_ical(event.start_time)),
+        format!("DTEND:{}", dates::format_ical(event.end_time)),
+        format!("SUMMARY:{}", escape_ical(&event.name)),
+        format!("STATUS:{}", status),
+    ];
+    if let Some(category) = &event.category {
+        lines.push(format!("CATEGORIES:{}", escape_ical(category)));
+    }
+    lines.push("END:VEVENT".to_string());
+    lines.push("END:VCALENDAR".to_string());
+    lines.iter().map(|line| fold_ical_line(line)).collect()
+}
+
+// HTTP requests are anonymous, so private events are answered like missing ones
+fn public_event(event_id: u64) -> Option<Event> {
+    EVENTS_MAP
+        .with(|events_map| events_map.borrow().get(&event_id))
+        .filter(|event| event.is_public())
+}
+
+// An event in JSON responses, with its id next to its fields
+#[derive(Serialize)]
+struct EventJson {
+    id: u64,
+    #[serde(flatten)]
+    event: Event,
+}
+
+// Value of a `name=value` parameter of the query string
+fn query_param<'a>(url: &'a str, name: &str) -> Option<&'a str> {
+    let (_, query) = url.split_once('?')?;
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+}
+
+// Value of the `sort` parameter of GET /events
+fn parse_sort(sort: &str) -> Option<SortBy> {
+    match sort {
+        "date" => Some(SortBy::DateAscending),
+        "-date" => Some(SortBy::DateDescending),
+        "name" => Some(SortBy::Name),
+        "participants" => Some(SortBy::ParticipantCount),
+        "recent" => Some(SortBy::RecentlyCreated),
+        _ => None,
+    }
+}
+
+// Listing cursors travel as the hex of their Candid encoding
+fn encode_cursor(cursor: &ListCursor) -> String {
+    Encode!(cursor).unwrap().iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn decode_cursor(hex: &str) -> Option<ListCursor> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return None;
+    }
+    let bytes = (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+    Decode!(&bytes, ListCursor).ok()
+}
+
+// GET /events?sort=S&after=C, a page of the public events in the given order after the
+// cursor C of the previous page, which is returned in the X-Next-Cursor header
+fn sorted_events_response(sort_by: SortBy, after: Option<ListCursor>) -> HttpResponse {
+    let page = listings::list(sort_by, after);
+    let events: Vec<EventJson> = page.events.into_iter().map(|(id, event)| EventJson { id, event }).collect();
+    let response = HttpResponse::json(&events);
+    match page.next {
+        Some(next) => response.with_header("X-Next-Cursor", encode_cursor(&next)),
+        None => response,
+    }
+}
+
+// GET /events?page=N, the public events in the order of their ids
+fn events_response(page: u64) -> HttpResponse {
+    let events: Vec<EventJson> = EVENTS_MAP.with(|events_map| {
+        events_map
+            .borrow()
+            .iter()
+            .filter(|(_, event)| event.is_public())
+            .skip((page * PAGE_SIZE) as usize)
+            .take(PAGE_SIZE as usize)
+            .map(|(id, event)| EventJson { id, event })
+            .collect()
+    });
+    HttpResponse::json(&events)
+}
+
+// GET /events/{id}
+fn event_response(event_id: u64) -> HttpResponse {
+    match public_event(event_id) {
+        Some(event) => HttpResponse::json(&EventJson { id: event_id, event }),
+        None => HttpResponse::error(404, "No such event"),
+    }
+}
+
+// GET /events/{id}/participants, with any RSVP status
+fn participants_response(event_id: u64) -> HttpResponse {
+    if public_event(event_id).is_none() {
+        return HttpResponse::error(404, "No such event");
+    }
+    let participants: Vec<Participant> = participants::of_event(event_id);
+    HttpResponse::json(&participants)
+}
+
+// GET /events/{id}/image, the banner image with its own content type
+fn image_response(event_id: u64) -> HttpResponse {
+    if public_event(event_id).is_none() {
+        return HttpResponse::error(404, "No such event");
+    }
+    match images::get(event_id) {
+        Some((info, bytes)) => HttpResponse::ok(&info.content_type, bytes)
+            .with_header("X-Content-Type-Options", "nosniff".to_string())
+            .with_header(
+                "ETag",
+                format!("\"{}\"", info.sha256.iter().map(|byte| format!("{:02x}", byte)).collect::<String>()),
+            ),
+        None => HttpResponse::error(404, "No image"),
+    }
+}
+
+// GET /events/{id}.ics
+fn ical_response(event_id: &str) -> HttpResponse {
+    let Some((event_id, event)) = event_id
+        .parse::<u64>()
+        .ok()
+        .and_then(|id| public_event(id).map(|event| (id, event)))
+    else {
+        return HttpResponse::error(404, "No such event");
+    };
+    HttpResponse::ok("text/calendar; charset=utf-8", ical_of(event_id, &event).into_bytes()).with_header(
+        "Content-Disposition",
+        format!("attachment; filename=\"event-{}.ics\"", event_id),
+    )
+}
+
+// Serve event data over plain HTTP(S) through the boundary nodes
+#[ic_cdk::query]
+fn http_request(request: HttpRequest) -> HttpResponse {
+    if request.method != "GET" {
+        return HttpResponse::error(405, "Method not allowed");
+    }
+    let path = request.url.split('?').next().unwrap_or("/");
+
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    match segments.as_slice() {
+        ["events"] => match query_param(&request.url, "sort").map(parse_sort) {
+            Some(None) => HttpResponse::error(400, "Unknown sort order"),
+            Some(Some(sort_by)) => match query_param(&request.url, "after").map(decode_cursor) {
+                Some(None) => HttpResponse::error(400, "Invalid cursor"),
+                after => sorted_events_response(sort_by, after.flatten()),
+            },
+            None => {
+                let page = query_param(&request.url, "page").and_then(|page| page.parse().ok()).unwrap_or(0);
+                events_response(page)
+            }
+        },
+        ["events", id] if id.ends_with(".ics") => ical_response(id.trim_end_matches(".ics")),
+        ["events", id] => match id.parse() {
+            Ok(event_id) => event_response(event_id),
+            Err(_) => HttpResponse::error(404, "No such event"),
+        },
+        ["events", id, "participants"] => match id.parse() {
+            Ok(event_id) => participants_response(event_id),
+            Err(_) => HttpResponse::error(404, "No such event"),
+        },
+        ["events", id, "image"] => match id.parse() {
+            Ok(event_id) => image_response(event_id),
+            Err(_) => HttpResponse::error(404, "No such event"),
+        },
+        _ => HttpResponse::error(404, "Not found"),
+    }
+}
@@ -0,0 +1,104 @@
+use candid::{CandidType, Decode, Deserialize, Encode};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+use crate::dates::NANOS_PER_DAY;
+use crate::{Memory, MEMORY_MANAGER};
+
+pub const MAX_KEY_LEN: usize = 64;
+// How long a key is remembered after the event was created
+const KEY_TTL: u64 = NANOS_PER_DAY;
+
+// Keys are scoped to the caller, so clients cannot collide with each other's keys
+#[derive(CandidType, Deserialize, Clone, PartialEq, Eq, PartialOrd, Ord, Default)]
+struct IdempotencyKey {
+    caller: String,
+    key: String,
+}
+
+impl Storable for IdempotencyKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for IdempotencyKey {
+    const MAX_SIZE: u32 = 200;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// The event created with a key, and when
+#[derive(CandidType, Deserialize, Clone)]
+struct Entry {
+    event_id: u64,
+    created_at: u64,
+}
+
+impl Storable for Entry {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for Entry {
+    const MAX_SIZE: u32 = 50;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+thread_local! {
+    static KEYS: RefCell<StableBTreeMap<IdempotencyKey, Entry, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(24))),
+        )
+    );
+
+    // The keys ordered by (created_at, key), so expired ones are at the front
+    static KEYS_BY_TIME: RefCell<StableBTreeMap<(u64, IdempotencyKey), (), Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(25))),
+        )
+    );
+}
+
+fn key_of(key: &str) -> IdempotencyKey {
+    IdempotencyKey { caller: ic_cdk::caller().to_text(), key: key.to_string() }
+}
+
+// Forget the keys older than the TTL
+fn prune(now: u64) {
+    let expired: Vec<(u64, IdempotencyKey)> = KEYS_BY_TIME.with(|keys| {
+        keys.borrow()
+            .iter()
+            .take_while(|((created_at, _), _)| created_at + KEY_TTL <= now)
+            .map(|(key, _)| key)
+            .collect()
+    });
+    for (created_at, key) in expired {
+        KEYS_BY_TIME.with(|keys| keys.borrow_mut().remove(&(created_at, key.clone())));
+        KEYS.with(|keys| keys.borrow_mut().remove(&key));
+    }
+}
+
+// The event the caller created with this key, if it has not expired
+pub fn lookup(key: &str) -> Option<u64> {
+    let now = ic_cdk::api::time();
+    prune(now);
+    KEYS.with(|keys| keys.borrow().get(&key_of(key))).map(|entry| entry.event_id)
+}
+
+// Remember the event the caller created with this key
+pub fn record(key: &str, event_id: u64) {
+    let now = ic_cdk::api::time();
+    let key = key_of(key);
+    KEYS_BY_TIME.with(|keys| keys.borrow_mut().insert((now, key.clone()), ()));
+    KEYS.with(|keys| keys.borrow_mut().insert(key, Entry { event_id, created_at: now }));
+}
@@ -0,0 +1,214 @@
+use candid::{CandidType, Decode, Deserialize, Encode};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use sha2::{Digest, Sha256};
+use std::{borrow::Cow, cell::RefCell};
+
+use crate::types::EventError;
+use crate::{audit, require_host, Memory, EVENTS_MAP, MEMORY_MANAGER};
+
+pub const CHUNK_SIZE: usize = 64 * 1024;
+// Images are served in a single HTTP response, which must stay under the response size limit
+pub const MAX_IMAGE_SIZE: usize = 2 * 1024 * 1024;
+const MAX_CHUNKS: u32 = (MAX_IMAGE_SIZE / CHUNK_SIZE) as u32;
+// SVG is not accepted since it can carry scripts
+const CONTENT_TYPES: [&str; 4] = ["image/png", "image/jpeg", "image/gif", "image/webp"];
+
+// Chunks are stored as raw bytes rather than candid, to keep uploads and serving cheap
+#[derive(Clone)]
+struct Chunk(Vec<u8>);
+
+impl Storable for Chunk {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Borrowed(&self.0)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Chunk(bytes.into_owned())
+    }
+}
+
+impl BoundedStorable for Chunk {
+    const MAX_SIZE: u32 = CHUNK_SIZE as u32;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+#[derive(CandidType, Deserialize, Clone)]
+pub struct ImageInfo {
+    pub content_type: String,
+    pub sha256: Vec<u8>,
+    pub size: u64,
+    pub chunk_count: u32,
+}
+
+impl Storable for ImageInfo {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for ImageInfo {
+    const MAX_SIZE: u32 = 120;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// Chunks are keyed by (event_id, chunk_index), so the chunks of an image are a contiguous range
+thread_local! {
+    // Chunks uploaded but not committed yet
+    static UPLOADS: RefCell<StableBTreeMap<(u64, u32), Chunk, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(29))),
+        )
+    );
+
+    // Chunks of the committed image of each event
+    static IMAGE_CHUNKS: RefCell<StableBTreeMap<(u64, u32), Chunk, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(30))),
+        )
+    );
+
+    static IMAGES: RefCell<StableBTreeMap<u64, ImageInfo, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(31))),
+        )
+    );
+}
+
+fn require_event_host(event_id: u64) -> Result<(), EventError> {
+    let event = EVENTS_MAP
+        .with(|events_map| events_map.borrow().get(&event_id))
+        .ok_or(EventError::NoSuchEvent)?;
+    require_host(&event)
+}
+
+fn chunks_of(map: &StableBTreeMap<(u64, u32), Chunk, Memory>, event_id: u64) -> Vec<(u32, Chunk)> {
+    map.range((event_id, 0)..=(event_id, u32::MAX))
+        .map(|((_, index), chunk)| (index, chunk))
+        .collect()
+}
+
+fn clear(map: &mut StableBTreeMap<(u64, u32), Chunk, Memory>, event_id: u64) {
+    for (index, _) in chunks_of(map, event_id) {
+        map.remove(&(event_id, index));
+    }
+}
+
+pub fn len() -> u64 {
+    IMAGES.with(|images| images.borrow().len())
+}
+
+// Images of all events ordered by event id, from the `skip`-th one
+pub fn all(skip: u64, take: u64) -> Vec<(u64, ImageInfo)> {
+    IMAGES.with(|images| {
+        images
+            .borrow()
+            .iter()
+            .skip(skip as usize)
+            .take(take as usize)
+            .collect()
+    })
+}
+
+pub fn restore(event_id: u64, info: ImageInfo) {
+    IMAGES.with(|images| images.borrow_mut().insert(event_id, info));
+}
+
+pub fn chunks_len() -> u64 {
+    IMAGE_CHUNKS.with(|images| images.borrow().len())
+}
+
+// The `index`-th chunk of the committed images, ordered by (event_id, chunk_index)
+pub fn nth_chunk(index: u64) -> Option<(u64, u32, Vec<u8>)> {
+    IMAGE_CHUNKS.with(|images| {
+        images
+            .borrow()
+            .iter()
+            .nth(index as usize)
+            .map(|((event_id, chunk_index), chunk)| (event_id, chunk_index, chunk.0))
+    })
+}
+
+pub fn restore_chunk(event_id: u64, chunk_index: u32, bytes: Vec<u8>) {
+    IMAGE_CHUNKS.with(|images| images.borrow_mut().insert((event_id, chunk_index), Chunk(bytes)));
+}
+
+// Upload a chunk of the banner image of an event. Uploading chunk 0 starts a new upload,
+// discarding the chunks of an upload that was not committed.
+#[ic_cdk::update]
+fn upload_event_image_chunk(event_id: u64, chunk_index: u32, bytes: Vec<u8>) -> Result<(), EventError> {
+    require_event_host(event_id)?;
+    if chunk_index >= MAX_CHUNKS || bytes.is_empty() || bytes.len() > CHUNK_SIZE {
+        return Err(EventError::InvalidImage);
+    }
+    UPLOADS.with(|uploads| {
+        let mut uploads = uploads.borrow_mut();
+        if chunk_index == 0 {
+            clear(&mut uploads, event_id);
+        }
+        uploads.insert((event_id, chunk_index), Chunk(bytes));
+    });
+    Ok(())
+}
+
+// Replace the image of an event with the uploaded chunks, once their sha256 matches
+#[ic_cdk::update]
+fn commit_event_image(event_id: u64, content_type: String, sha256: Vec<u8>) -> Result<(), EventError> {
+    require_event_host(event_id)?;
+    if !CONTENT_TYPES.contains(&content_type.as_str()) {
+        return Err(EventError::InvalidImage);
+    }
+
+    let chunks = UPLOADS.with(|uploads| chunks_of(&uploads.borrow(), event_id));
+    // Chunks must be numbered from 0 without gaps
+    if chunks.is_empty() || chunks.iter().enumerate().any(|(i, (index, _))| i as u32 != *index) {
+        return Err(EventError::InvalidImage);
+    }
+    let mut hasher = Sha256::new();
+    let mut size = 0;
+    for (_, chunk) in &chunks {
+        hasher.update(&chunk.0);
+        size += chunk.0.len() as u64;
+    }
+    if hasher.finalize().as_slice() != sha256.as_slice() {
+        return Err(EventError::ImageHashMismatch);
+    }
+
+    IMAGE_CHUNKS.with(|images| {
+        let mut images = images.borrow_mut();
+        clear(&mut images, event_id);
+        for (index, chunk) in &chunks {
+            images.insert((event_id, *index), chunk.clone());
+        }
+    });
+    UPLOADS.with(|uploads| clear(&mut uploads.borrow_mut(), event_id));
+    let chunk_count = chunks.len() as u32;
+    IMAGES.with(|images| {
+        images
+            .borrow_mut()
+            .insert(event_id, ImageInfo { content_type: content_type.clone(), sha256, size, chunk_count })
+    });
+    audit::record_update(event_id, format!("image: {}, {} bytes", content_type, size));
+    Ok(())
+}
+
+#[ic_cdk::query]
+fn get_event_image_info(event_id: u64) -> Option<ImageInfo> {
+    IMAGES.with(|images| images.borrow().get(&event_id))
+}
+
+// The committed image of an event with its content type
+pub fn get(event_id: u64) -> Option<(ImageInfo, Vec<u8>)> {
+    let info = IMAGES.with(|images| images.borrow().get(&event_id))?;
+    let bytes = IMAGE_CHUNKS.with(|images| {
+        chunks_of(&images.borrow(), event_id)
+            .into_iter()
+            .flat_map(|(_, chunk)| chunk.0)
+            .collect()
+    });
+    Some((info, bytes))
+}
@@ -0,0 +1,161 @@
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+use crate::types::{Event, EventError};
+use crate::{events_by_ids, require_host, Memory, EVENTS_MAP, MEMORY_MANAGER};
+
+// Invites are stored twice: keyed by (event_id, principal) to check an invite, and
+// by (principal, event_id) to list the invites of a principal
+#[derive(CandidType, Deserialize, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct InviteKey {
+    event_id: u64,
+    principal: String,
+}
+
+#[derive(CandidType, Deserialize, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct InviteeKey {
+    principal: String,
+    event_id: u64,
+}
+
+impl Storable for InviteKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for InviteKey {
+    const MAX_SIZE: u32 = 120;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+impl Storable for InviteeKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for InviteeKey {
+    const MAX_SIZE: u32 = 120;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+thread_local! {
+    static INVITES: RefCell<StableBTreeMap<InviteKey, (), Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(19))),
+        )
+    );
+
+    static INVITES_BY_PRINCIPAL: RefCell<StableBTreeMap<InviteeKey, (), Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(20))),
+        )
+    );
+}
+
+pub fn is_invited(event_id: u64, principal: &str) -> bool {
+    INVITES.with(|invites| {
+        invites
+            .borrow()
+            .contains_key(&InviteKey { event_id, principal: principal.to_string() })
+    })
+}
+
+pub fn len() -> u64 {
+    INVITES.with(|invites| invites.borrow().len())
+}
+
+// Invites of all events ordered by (event_id, principal), from the `skip`-th one
+pub fn all(skip: u64, take: u64) -> Vec<(u64, String)> {
+    INVITES.with(|invites| {
+        invites
+            .borrow()
+            .iter()
+            .skip(skip as usize)
+            .take(take as usize)
+            .map(|(key, _)| (key.event_id, key.principal))
+            .collect()
+    })
+}
+
+pub fn restore(event_id: u64, principal: String) {
+    INVITES.with(|invites| {
+        invites
+            .borrow_mut()
+            .insert(InviteKey { event_id, principal: principal.clone() }, ())
+    });
+    INVITES_BY_PRINCIPAL.with(|invites| {
+        invites.borrow_mut().insert(InviteeKey { principal, event_id }, ())
+    });
+}
+
+// Check that the caller may manage the invites of an event
+fn require_event_host(event_id: u64) -> Result<Event, EventError> {
+    let event = EVENTS_MAP
+        .with(|events_map| events_map.borrow().get(&event_id))
+        .ok_or(EventError::NoSuchEvent)?;
+    require_host(&event)?;
+    Ok(event)
+}
+
+// Allow a principal to join a private event
+#[ic_cdk::update]
+fn invite(event_id: u64, principal: Principal) -> Result<(), EventError> {
+    require_event_host(event_id)?;
+    let principal = principal.to_text();
+    INVITES.with(|invites| {
+        invites
+            .borrow_mut()
+            .insert(InviteKey { event_id, principal: principal.clone() }, ())
+    });
+    INVITES_BY_PRINCIPAL.with(|invites| {
+        invites.borrow_mut().insert(InviteeKey { principal, event_id }, ())
+    });
+    Ok(())
+}
+
+// Withdraw an invite; a participant who already joined stays registered
+#[ic_cdk::update]
+fn revoke_invite(event_id: u64, principal: Principal) -> Result<(), EventError> {
+    require_event_host(event_id)?;
+    let principal = principal.to_text();
+    let removed = INVITES.with(|invites| {
+        invites
+            .borrow_mut()
+            .remove(&InviteKey { event_id, principal: principal.clone() })
+            .is_some()
+    });
+    if !removed {
+        return Err(EventError::NotInvited);
+    }
+    INVITES_BY_PRINCIPAL.with(|invites| {
+        invites.borrow_mut().remove(&InviteeKey { principal, event_id })
+    });
+    Ok(())
+}
+
+// Query the events the caller is invited to
+#[ic_cdk::query]
+fn get_my_invites() -> Vec<(u64, Event)> {
+    let principal = ic_cdk::caller().to_text();
+    let event_ids = INVITES_BY_PRINCIPAL.with(|invites| {
+        invites
+            .borrow()
+            .range(InviteeKey { principal: principal.clone(), event_id: 0 }..)
+            .take_while(|(key, _)| key.principal == principal)
+            .map(|(key, _)| key.event_id)
+            .collect()
+    });
+    events_by_ids(event_ids)
+}
@@ -1,150 +1,996 @@
-use ic_cdk::api::management_canister::http_request::{
-    http_request, CanisterHttpRequestArgument, HttpMethod,
-};
+mod admin;
+mod api;
+mod archive;
+mod attendance;
+mod audit;
+mod backup;
+mod certification;
+mod checkin;
+mod comments;
+mod dates;
+mod export;
+mod external_ids;
+mod geo;
+mod http;
+mod idempotency;
+mod images;
+mod invites;
+mod listings;
+mod natural_keys;
+mod notifications;
+mod recurrence;
+mod participants;
+mod payments;
+mod profiles;
+mod promotions;
+mod ratelimit;
+mod refunds;
+mod reminders;
+mod search;
+mod sessions;
+mod stats;
+mod sync;
+mod tags;
+mod tickets;
+mod timezones;
+mod types;
+mod waitlist;
+mod webhooks;
 
-use candid::{CandidType, Decode, Deserialize, Encode};
+use candid::Principal;
 use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
-use ic_stable_structures::{BoundedStorable, DefaultMemoryImpl, StableBTreeMap, Storable};
-use std::{borrow::Cow, cell::RefCell}; 
+use ic_stable_structures::{DefaultMemoryImpl, StableBTreeMap, StableCell};
+use std::cell::RefCell;
+use std::collections::BTreeSet;
 
-#[derive(CandidType, Deserialize, Clone)]
-struct Participant {
-    address: String,
+use notifications::Notification;
+use profiles::ParticipantProfile;
+use recurrence::RecurrenceRule;
+use types::*;
+
+pub(crate) type Memory = VirtualMemory<DefaultMemoryImpl>;
+// Number of entries returned per page by paginated queries
+const PAGE_SIZE: u64 = 20;
+// Number of events create_events_batch takes per call
+const MAX_BATCH_SIZE: usize = 100;
+
+// Initialize the events map with a new MemoryId
+thread_local! {
+    pub(crate) static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> =
+    RefCell::new(MemoryManager::init(DefaultMemoryImpl::default()));
+
+    pub(crate) static EVENTS_MAP: RefCell<StableBTreeMap<u64, Event, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(9))),
+        )
+    );
+
+    // Events stored by earlier versions, moved to EVENTS_MAP in post_upgrade
+    static LEGACY_EVENTS_MAP: RefCell<StableBTreeMap<u64, LegacyEvent, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(1))),
+        )
+    );
+
+    // The id of the next event. Ids are never handed out twice, so it stays above the
+    // ids of archived, removed and imported events.
+    static NEXT_EVENT_ID: RefCell<StableCell<u64, Memory>> = RefCell::new(
+        StableCell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(52))),
+            0,
+        ).expect("failed to initialize the event id cell")
+    );
 }
 
-// Define the Event structure
-#[derive(CandidType, Deserialize, Clone)]
-struct Event {
-    name: String,
-    date: String,
-    #[serde(default)] // This will default to an empty Vec if `participants` is not present
-    participants: Vec<Participant>,
+// Store an event and certify its new value
+pub(crate) fn store_event(events_map: &mut StableBTreeMap<u64, Event, Memory>, event_id: u64, event: Event) {
+    let previous = events_map.get(&event_id);
+    certification::certify(event_id, &event);
+    stats::on_event_stored(event_id, previous.as_ref(), &event);
+    audit::on_event_stored(event_id, previous.as_ref(), &event);
+    listings::on_event_stored(event_id, previous.as_ref(), &event);
+    natural_keys::on_event_stored(event_id, previous.as_ref(), &event);
+    external_ids::on_event_stored(event_id, previous.as_ref(), &event);
+    sync::on_event_stored(event_id, &event);
+    events_map.insert(event_id, event);
+}
+
+// Put an imported event back with its indexes, statistics and hash. Importing is not a
+// new write, so unlike store_event it appends no audit entry and does not mark the event
+// for sync; both are imported as they were. Reminders and the certified data are left to
+// the caller.
+pub(crate) fn restore_event(events_map: &mut StableBTreeMap<u64, Event, Memory>, event_id: u64, event: Event) {
+    reserve_event_id(event_id);
+    index_fields(event_id, &event);
+    certification::restore(event_id, &event);
+    stats::on_event_stored(event_id, None, &event);
+    listings::on_event_stored(event_id, None, &event);
+    natural_keys::on_event_stored(event_id, None, &event);
+    external_ids::on_event_stored(event_id, None, &event);
+    events_map.insert(event_id, event);
+}
+
+// Take an event out of EVENTS_MAP and the secondary indexes, the counterpart of
+// index_event and store_event
+pub(crate) fn remove_event(events_map: &mut StableBTreeMap<u64, Event, Memory>, event_id: u64) -> Option<Event> {
+    let event = events_map.remove(&event_id)?;
+    tags::unindex_event(event_id, &event.tags, &event.category);
+    dates::unindex_event(event_id, event.start_time);
+    search::unindex_event(event_id, &event.name);
+    if let Some(location) = &event.location {
+        geo::unindex_event(event_id, location);
+    }
+    certification::uncertify(event_id);
+    stats::on_event_removed(event_id, &event);
+    listings::on_event_removed(event_id, &event);
+    natural_keys::on_event_removed(event_id, &event);
+    external_ids::on_event_removed(event_id, &event);
+    sync::on_event_removed(event_id);
+    Some(event)
 }
 
-#[derive(CandidType, Deserialize)]
-enum EventError {
-    NoSuchEvent,
-    JoinError,
-    CancelJoinError,
-    GetEventsError,
-    AlreadyJoined,
-    AlreadyExists
+pub(crate) fn next_event_id() -> u64 {
+    NEXT_EVENT_ID.with(|next_id| *next_id.borrow().get())
 }
 
-// Implement Storable for Event
-impl Storable for Event {
-    fn to_bytes(&self) -> Cow<[u8]> {
-        Cow::Owned(Encode!(self).unwrap())
+// Hand out the next event id
+pub(crate) fn take_event_id() -> u64 {
+    let event_id = next_event_id();
+    reserve_event_id(event_id);
+    event_id
+}
+
+// Make sure an id taken outside take_event_id, e.g. by an import, is not handed out
+pub(crate) fn reserve_event_id(event_id: u64) {
+    if event_id >= next_event_id() {
+        NEXT_EVENT_ID.with(|next_id| next_id.borrow_mut().set(event_id + 1))
+            .expect("failed to save the next event id");
     }
+}
 
-    fn from_bytes(bytes: Cow<[u8]>) -> Self {
-        Decode!(bytes.as_ref(), Self).unwrap()
+// Canisters from before the counter existed handed out ids in order, so the counter
+// starts above the highest id stored in either map
+fn backfill_next_event_id() {
+    let highest = EVENTS_MAP.with(|events_map| events_map.borrow().last_key_value().map(|(event_id, _)| event_id));
+    for event_id in highest.into_iter().chain(archive::highest_id()) {
+        reserve_event_id(event_id);
     }
 }
 
-type Memory = VirtualMemory<DefaultMemoryImpl>;
-const MAX_VALUE_SIZE: u32 = 100;
+// Move events of the legacy map into EVENTS_MAP, keeping their ids, and their
+// participants into the participants map
+fn migrate_legacy_events() {
+    let legacy_events: Vec<(u64, LegacyEvent)> =
+        LEGACY_EVENTS_MAP.with(|legacy| legacy.borrow().iter().collect());
 
-// Implement BoundedStorable for Event
-impl BoundedStorable for Event {
-    const MAX_SIZE: u32 = MAX_VALUE_SIZE; // Adjust the size as needed
-    const IS_FIXED_SIZE: bool = false;
+    for (event_id, legacy_event) in legacy_events {
+        let (event, participants) = legacy_event.migrate(ic_cdk::id());
+        for participant in participants {
+            participants::insert(event_id, participant);
+        }
+        tags::index_event(event_id, &event.tags, &event.category);
+        dates::index_event(event_id, event.start_time);
+        search::index_event(event_id, &event.name);
+        EVENTS_MAP.with(|events_map| store_event(&mut events_map.borrow_mut(), event_id, event));
+        LEGACY_EVENTS_MAP.with(|legacy| legacy.borrow_mut().remove(&event_id));
+    }
 }
 
-// Initialize the events map with a new MemoryId
-thread_local! {
-    static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> =
-    RefCell::new(MemoryManager::init(DefaultMemoryImpl::default()));
+// Only the organizer, the co-hosts and the admin may manage an event
+fn require_host(event: &Event) -> Result<(), EventError> {
+    if is_host_or_admin(event, &ic_cdk::caller()) {
+        Ok(())
+    } else {
+        Err(EventError::Unauthorized)
+    }
+}
 
-    static EVENTS_MAP: RefCell<StableBTreeMap<u64, Event, Memory>> = RefCell::new(
-        StableBTreeMap::init(
-            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(1))), // Use a different MemoryId if needed
-        )
-    );
+fn is_host_or_admin(event: &Event, principal: &Principal) -> bool {
+    event.is_host(principal) || admin::get() == Some(*principal)
+}
+
+// Everyone may see who takes part in public events, only the hosts in private ones
+fn can_see_participants(event: &Event) -> bool {
+    can_see_participants_as(event, &ic_cdk::caller())
+}
+
+fn can_see_participants_as(event: &Event, principal: &Principal) -> bool {
+    event.is_public() || is_host_or_admin(event, principal)
+}
+
+// Private events are only shown to their hosts and the principals invited to them
+fn can_see_event_as(event_id: u64, event: &Event, principal: &Principal) -> bool {
+    can_see_participants_as(event, principal) || invites::is_invited(event_id, &principal.to_text())
+}
+
+// Normalized tags and category of an event, or InvalidTags
+fn normalize_labels(tags: &[String], category: &Option<String>) -> Result<(Vec<String>, Option<String>), EventError> {
+    let tags = tags::normalize_all(tags).ok_or(EventError::InvalidTags)?;
+    let category = match category {
+        Some(category) => Some(tags::normalize(category).ok_or(EventError::InvalidTags)?),
+        None => None,
+    };
+    Ok((tags, category))
+}
+
+// New events must start in the future and end after they start
+fn validate_times(start_time: u64, end_time: u64) -> Result<(), EventError> {
+    if start_time <= ic_cdk::api::time() || end_time <= start_time {
+        return Err(EventError::InvalidTime);
+    }
+    Ok(())
+}
+
+fn validate_location(location: &Option<Location>, venue: &Option<String>) -> Result<(), EventError> {
+    if location.is_some_and(|location| !location.is_valid())
+        || venue.as_ref().is_some_and(|venue| venue.is_empty() || venue.len() > MAX_VENUE_LEN)
+    {
+        return Err(EventError::InvalidLocation);
+    }
+    Ok(())
+}
+
+// Store a new Event after checking that no event with the same name and start time exists
+pub(crate) fn insert_event(
+    events_map: &mut StableBTreeMap<u64, Event, Memory>,
+    payload: CreateEventPayload,
+    series_id: Option<u64>,
+    parent_id: Option<u64>,
+) -> Result<u64, EventError> {
+    let new_event = new_event(events_map, payload, series_id, parent_id)?;
+    Ok(store_new_event(events_map, new_event))
+}
+
+// Validate a payload into the Event it creates, without storing it
+fn new_event(
+    events_map: &StableBTreeMap<u64, Event, Memory>,
+    payload: CreateEventPayload,
+    series_id: Option<u64>,
+    parent_id: Option<u64>,
+) -> Result<Event, EventError> {
+    validate_times(payload.start_time, payload.end_time)?;
+    if payload.name.is_empty() || payload.name.len() > MAX_NAME_LEN {
+        return Err(EventError::InvalidName);
+    }
+
+    // Check if an event with the same name and start time already exists
+    if natural_keys::find(events_map, &payload.name, payload.start_time).is_some() {
+        return Err(EventError::AlreadyExists);
+    }
+
+    let (tags, category) = normalize_labels(&payload.tags, &payload.category)?;
+    if payload.ticket_price.as_ref().is_some_and(|price| !price.is_valid()) {
+        return Err(EventError::InvalidPrice);
+    }
+    if payload.timezone.as_deref().is_some_and(|timezone| !timezones::is_supported(timezone)) {
+        return Err(EventError::InvalidTimezone);
+    }
+    validate_location(&payload.location, &payload.venue)?;
+
+    // If no existing event is found, create a new one
+    Ok(Event {
+        name: payload.name,
+        start_time: payload.start_time,
+        end_time: payload.end_time,
+        participant_count: 0,
+        maybe_count: 0,
+        declined_count: 0,
+        max_participants: payload.max_participants,
+        status: EventStatus::Draft,
+        series_id,
+        parent_id,
+        tags,
+        category,
+        external_id: None,
+        organizer: ic_cdk::caller(),
+        co_hosts: Vec::new(),
+        ticket_price: payload.ticket_price,
+        visibility: payload.visibility,
+        timezone: payload.timezone,
+        location: payload.location,
+        venue: payload.venue,
+    })
+}
+
+fn store_new_event(events_map: &mut StableBTreeMap<u64, Event, Memory>, new_event: Event) -> u64 {
+    let new_event_id = take_event_id();
+    index_event(new_event_id, &new_event);
+    store_event(events_map, new_event_id, new_event);
+    new_event_id
+}
+
+// Add a new event to the secondary indexes and schedule its reminders
+pub(crate) fn index_event(event_id: u64, event: &Event) {
+    index_fields(event_id, event);
+    reminders::schedule(event_id, event.start_time);
+}
+
+fn index_fields(event_id: u64, event: &Event) {
+    tags::index_event(event_id, &event.tags, &event.category);
+    dates::index_event(event_id, event.start_time);
+    search::index_event(event_id, &event.name);
+    if let Some(location) = &event.location {
+        geo::index_event(event_id, location);
+    }
+    if let Some(parent_id) = event.parent_id {
+        sessions::index_event(event_id, parent_id);
+    }
+}
+
+#[ic_cdk::init]
+fn init() {
+    admin::init_if_unset(ic_cdk::caller());
+    api::init_default_source();
+    certification::certify_all();
+    archive::start();
+}
+
+// Timers do not survive upgrades, so reminders of upcoming events are registered again
+#[ic_cdk::post_upgrade]
+fn post_upgrade() {
+    admin::init_if_unset(ic_cdk::caller());
+    // Events created before statistics, the reverse index of participants, the listing
+    // indexes, the natural key and external id indexes and sync existed are counted and
+    // indexed once, before the migration below does so as it moves them
+    if stats::is_empty() {
+        stats::rebuild();
+    }
+    participants::backfill_joined_index();
+    listings::backfill();
+    natural_keys::backfill();
+    external_ids::backfill();
+    archive::backfill_keys();
+    sync::backfill();
+    migrate_legacy_events();
+    refunds::migrate_legacy_queue();
+    backfill_next_event_id();
+    // The certified tree lives on the heap, so it is rebuilt from the stored events
+    certification::certify_all();
+
+    // Events created before the search index existed are indexed once
+    if search::is_empty() {
+        EVENTS_MAP.with(|events_map| {
+            for (event_id, event) in events_map.borrow().iter() {
+                search::index_event(event_id, &event.name);
+            }
+        });
+    }
+
+    let now = ic_cdk::api::time();
+    for (event_id, event) in events_by_ids(dates::events_starting_between(now + 1, u64::MAX)) {
+        reminders::schedule(event_id, event.start_time);
+    }
+    promotions::reschedule_all();
+    refunds::reschedule();
+    webhooks::reschedule();
+    attendance::reschedule();
+    archive::start();
 }
 
 // create and store a new Event
 #[ic_cdk::update]
-fn create_event(name: String, date: String) -> Result<(), EventError> {
+fn create_event(
+    name: String,
+    start_time: u64,
+    end_time: u64,
+    max_participants: Option<u32>,
+    idempotency_key: Option<String>,
+) -> Result<u64, EventError> {
+    // A retry with the key of an earlier call returns the event that call created
+    if let Some(key) = &idempotency_key {
+        if key.is_empty() || key.len() > idempotency::MAX_KEY_LEN {
+            return Err(EventError::InvalidIdempotencyKey);
+        }
+        if let Some(event_id) = idempotency::lookup(key) {
+            return Ok(event_id);
+        }
+    }
+    ratelimit::check(ratelimit::Action::CreateEvent)?;
+    let event_id = EVENTS_MAP.with(|events_map_ref| {
+        let payload = CreateEventPayload {
+            name,
+            start_time,
+            end_time,
+            max_participants,
+            tags: Vec::new(),
+            category: None,
+            ticket_price: None,
+            visibility: Visibility::Public,
+            timezone: None,
+            location: None,
+            venue: None,
+        };
+        insert_event(&mut events_map_ref.borrow_mut(), payload, None, None)
+    })?;
+    if let Some(key) = &idempotency_key {
+        idempotency::record(key, event_id);
+    }
+    Ok(event_id)
+}
+
+// Create several events at once, e.g. to import a season schedule. Each entry is validated
+// and deduplicated like in create_event, and gets its own result, so one invalid entry does
+// not fail the batch. A batch of more than MAX_BATCH_SIZE entries is rejected as a whole
+// with BatchTooLarge. Every entry is validated before any is stored, and the valid ones are
+// charged to the rate limit at once like as many create_event calls.
+#[ic_cdk::update]
+fn create_events_batch(payloads: Vec<CreateEventPayload>) -> Result<Vec<Result<u64, EventError>>, EventError> {
+    if payloads.len() > MAX_BATCH_SIZE {
+        return Err(EventError::BatchTooLarge);
+    }
     EVENTS_MAP.with(|events_map_ref| {
         let mut events_map = events_map_ref.borrow_mut();
+        let mut batch_keys = BTreeSet::new();
+        let events: Vec<Result<Event, EventError>> = payloads
+            .into_iter()
+            .map(|payload| {
+                let event = new_event(&events_map, payload, None, None)?;
+                // Entries are not stored yet, so duplicates within the batch are found here
+                if !batch_keys.insert((event.name.clone(), event.start_time)) {
+                    return Err(EventError::AlreadyExists);
+                }
+                Ok(event)
+            })
+            .collect();
+
+        let valid = events.iter().filter(|event| event.is_ok()).count();
+        ratelimit::check_many(ratelimit::Action::CreateEvent, valid as u32)?;
+        Ok(events
+            .into_iter()
+            .map(|event| event.map(|event| store_new_event(&mut events_map, event)))
+            .collect())
+    })
+}
+
+// Create every occurrence of a recurring event, linked by a series id.
+// The series id is the id of the first occurrence.
+#[ic_cdk::update]
+fn create_recurring_event(payload: CreateEventPayload, rule: RecurrenceRule) -> Result<u64, EventError> {
+    let offsets = rule
+        .occurrence_offsets()
+        .ok_or(EventError::InvalidRecurrence)?;
+    validate_times(payload.start_time, payload.end_time)?;
+    normalize_labels(&payload.tags, &payload.category)?;
+    // Occurrences of events planned in a time zone keep their local time across DST changes
+    let occurrence_start = |offset: u64| match &payload.timezone {
+        Some(timezone) => timezones::shift_local(timezone, payload.start_time, offset)
+            .ok_or(EventError::InvalidTimezone),
+        None => Ok(payload.start_time + offset),
+    };
+    let start_times = offsets
+        .iter()
+        .map(|offset| occurrence_start(*offset))
+        .collect::<Result<Vec<u64>, EventError>>()?;
+    if start_times.iter().collect::<BTreeSet<_>>().len() < start_times.len() {
+        return Err(EventError::AlreadyExists);
+    }
+
+    EVENTS_MAP.with(|events_map_ref| {
+        let mut events_map = events_map_ref.borrow_mut();
+
+        // The series is named after its first occurrence. Every occurrence is validated
+        // before any is stored, so the series is either created fully or not at all.
+        let series_id = next_event_id();
+        let occurrences = start_times
+            .into_iter()
+            .map(|start_time| {
+                let occurrence = CreateEventPayload {
+                    start_time,
+                    end_time: start_time + (payload.end_time - payload.start_time),
+                    ..payload.clone()
+                };
+                new_event(&events_map, occurrence, Some(series_id), None)
+            })
+            .collect::<Result<Vec<Event>, EventError>>()?;
+
+        // Every occurrence counts against the rate limit like a create_event call
+        ratelimit::check_many(ratelimit::Action::CreateEvent, occurrences.len() as u32)?;
+        for occurrence in occurrences {
+            store_new_event(&mut events_map, occurrence);
+        }
+        Ok(series_id)
+    })
+}
+
+// Join an event. Paid events charge the caller, who must be the participant, with
+// `icrc2_transfer_from` first; they need to approve the ticket price for this canister.
+// A profile for the organizer can only be given by participants joining themselves.
+#[ic_cdk::update]
+pub(crate) async fn join_event(
+    event_id: u64,
+    participant_address: String,
+    profile: Option<ParticipantProfile>,
+) -> Result<JoinResult, EventError> {
+    ratelimit::check(ratelimit::Action::Join)?;
+    let event = EVENTS_MAP
+        .with(|events_map| events_map.borrow().get(&event_id))
+        .ok_or(EventError::NoSuchEvent)?;
 
-        // Check if an event with the same name and date already exists
-        for (_, event) in events_map.iter() {
-            if event.name == name && event.date == date {
-                return Err(EventError::AlreadyExists);
+    if !may_join_as(&event, &ic_cdk::caller(), &participant_address) {
+        return Err(EventError::Unauthorized);
+    }
+    if let Some(profile) = &profile {
+        if participant_address != ic_cdk::caller().to_text() {
+            return Err(EventError::Unauthorized);
+        }
+        if !profile.is_valid() {
+            return Err(EventError::InvalidProfile);
+        }
+    }
+
+    let mut charged = false;
+    if let Some(price) = &event.ticket_price {
+        let payer = ic_cdk::caller();
+        if participant_address != payer.to_text() {
+            return Err(EventError::Unauthorized);
+        }
+        if payments::get(event_id, &participant_address).is_none() {
+            // Fail before paying if the caller could not join anyway
+            if !event.status.is_open() {
+                return Err(EventError::EventNotOpen);
             }
+            if event.is_full() {
+                return Err(EventError::EventFull);
+            }
+            payments::charge(event_id, price, payer).await?;
+            charged = true;
         }
+    }
 
-        // If no existing event is found, create a new one
-        let new_event = Event {
-            name,
-            date,
-            participants: Vec::new(),
-        };
+    let result = EVENTS_MAP.with(|events_map_ref| {
+        add_participant(&mut events_map_ref.borrow_mut(), event_id, participant_address.clone())
+    });
+    // The event may have filled up or closed while the payment was made
+    if result.is_err() && charged {
+        if let Some(payment) = payments::take(event_id, &participant_address) {
+            refunds::refund(event_id, participant_address.clone(), payment, 0);
+        }
+    }
+    result?;
+    if let Some(profile) = profile {
+        profiles::set(event_id, ic_cdk::caller(), profile);
+    }
+    Ok(join_result(event_id, &participant_address))
+}
+
+// Invites are personal, so invited principals join private events themselves
+fn may_join_as(event: &Event, caller: &Principal, participant_address: &str) -> bool {
+    event.is_public() || caller.to_text() == participant_address
+}
+
+// Whether a participant who just joined got a spot, and what is left of the event
+fn join_result(event_id: u64, participant_address: &str) -> JoinResult {
+    let remaining_capacity = EVENTS_MAP
+        .with(|events_map| events_map.borrow().get(&event_id))
+        .and_then(|event| event.max_participants.map(|max| max.saturating_sub(event.participant_count)));
+    match waitlist::position(event_id, participant_address) {
+        Some(position) => JoinResult { status: JoinStatus::Waitlisted, position: Some(position), remaining_capacity },
+        None => JoinResult { status: JoinStatus::Registered, position: None, remaining_capacity },
+    }
+}
+
+// Join every occurrence of a series that is still open, skipping the ones already joined.
+// Every occurrence is checked before any is joined, so the series is joined fully or not at all.
+#[ic_cdk::update]
+fn join_series(series_id: u64, participant_address: String) -> Result<(), EventError> {
+    ratelimit::check(ratelimit::Action::Join)?;
+    EVENTS_MAP.with(|events_map_ref| {
+        join_series_as(&mut events_map_ref.borrow_mut(), series_id, &ic_cdk::caller(), participant_address)
+    })
+}
+
+fn join_series_as(
+    events_map: &mut StableBTreeMap<u64, Event, Memory>,
+    series_id: u64,
+    caller: &Principal,
+    participant_address: String,
+) -> Result<(), EventError> {
+    let occurrences: Vec<(u64, Event)> = events_map
+        .iter()
+        .filter(|(_, event)| event.series_id == Some(series_id) && event.status.is_open())
+        .collect();
+
+    if occurrences.is_empty() {
+        return Err(EventError::NoSuchEvent);
+    }
+
+    for (event_id, event) in &occurrences {
+        if !may_join_as(event, caller, &participant_address) {
+            return Err(EventError::Unauthorized);
+        }
+        check_rsvp(*event_id, event, &participant_address, Some(RsvpStatus::Going))?;
+    }
+    for (event_id, _) in occurrences {
+        match add_participant(events_map, event_id, participant_address.clone()) {
+            Ok(()) | Err(EventError::AlreadyJoined) => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(())
+}
+
+// Join an event as Going, or its waitlist if it is full
+pub(crate) fn add_participant(
+    events_map: &mut StableBTreeMap<u64, Event, Memory>,
+    event_id: u64,
+    participant_address: String,
+) -> Result<(), EventError> {
+    let current = participants::get(event_id, &participant_address).map(|p| p.rsvp);
+    if current == Some(RsvpStatus::Going) {
+        return Err(EventError::AlreadyJoined);
+    }
+    apply_rsvp(events_map, event_id, participant_address, Some(RsvpStatus::Going))
+}
+
+// Move a participant to a new RSVP status, or remove them with `None`, keeping the
+// counts of the event, its capacity and its waitlist in sync
+pub(crate) fn apply_rsvp(
+    events_map: &mut StableBTreeMap<u64, Event, Memory>,
+    event_id: u64,
+    participant_address: String,
+    next: Option<RsvpStatus>,
+) -> Result<(), EventError> {
+    // Retrieve the event, clone it, and then modify it
+    let mut event = events_map.get(&event_id).ok_or(EventError::NoSuchEvent)?;
+    check_rsvp(event_id, &event, &participant_address, next)?;
+
+    let previous = participants::get(event_id, &participant_address);
+    let current = previous.as_ref().map(|p| p.rsvp);
+    if current == next {
+        return Ok(());
+    }
+
+    // A full event puts new Going participants on the waitlist instead
+    if next == Some(RsvpStatus::Going) && event.is_full() {
+        if !waitlist::push(event_id, participant_address) {
+            return Err(EventError::AlreadyJoined);
+        }
+        return Ok(());
+    }
+    if next != Some(RsvpStatus::Going) {
+        waitlist::remove(event_id, &participant_address);
+        promotions::clear(event_id, &participant_address);
+    }
+
+    if let Some(current) = current {
+        *event.rsvp_count_mut(current) -= 1;
+    }
+    match next {
+        Some(rsvp) => {
+            *event.rsvp_count_mut(rsvp) += 1;
+            // A participant keeps their ticket when changing their answer
+            let ticket_id = previous.and_then(|p| p.ticket_id);
+            if rsvp == RsvpStatus::Going && ticket_id.is_none() {
+                tickets::issue(event_id, participant_address.clone());
+            }
+            participants::insert(event_id, Participant { address: participant_address, rsvp, ticket_id });
+        }
+        None => {
+            participants::remove(event_id, &participant_address);
+        }
+    }
+
+    // The freed spot goes to the next participant on the waitlist
+    if current == Some(RsvpStatus::Going) && !event.is_full() {
+        if let Some(address) = waitlist::pop_front(event_id) {
+            let previous = participants::get(event_id, &address);
+            if let Some(previous) = &previous {
+                *event.rsvp_count_mut(previous.rsvp) -= 1;
+            }
+            let ticket_id = previous.and_then(|p| p.ticket_id);
+            if ticket_id.is_none() {
+                tickets::issue(event_id, address.clone());
+            }
+            participants::insert(event_id, Participant { address: address.clone(), rsvp: RsvpStatus::Going, ticket_id });
+            event.participant_count += 1;
+            promotions::offer(event_id, &event, address);
+        }
+    }
+
+    event.refresh_capacity_status();
+    // Insert the modified event back into the map
+    store_event(events_map, event_id, event);
+    Ok(())
+}
+
+// Whether apply_rsvp may move a participant to a new RSVP status, without changing anything
+fn check_rsvp(
+    event_id: u64,
+    event: &Event,
+    participant_address: &str,
+    next: Option<RsvpStatus>,
+) -> Result<(), EventError> {
+    if !event.status.is_open() {
+        return Err(EventError::EventNotOpen);
+    }
+    if participant_address.is_empty() || participant_address.len() > MAX_ADDRESS_LEN {
+        return Err(EventError::InvalidAddress);
+    }
+    let current = participants::get(event_id, participant_address).map(|p| p.rsvp);
+    if next != Some(RsvpStatus::Going) || current == next {
+        return Ok(());
+    }
 
-        let new_event_id = events_map.len();
-        events_map.insert(new_event_id, new_event);
+    // Private events only take invited principals and their hosts
+    if !event.is_public() && !invites::is_invited(event_id, participant_address) {
+        let is_host = Principal::from_text(participant_address).is_ok_and(|principal| event.is_host(&principal));
+        if !is_host {
+            return Err(EventError::NotInvited);
+        }
+    }
+
+    // Paid events only take participants who paid, and have no waitlist
+    if event.ticket_price.is_some() {
+        if payments::get(event_id, participant_address).is_none() {
+            return Err(EventError::PaymentRequired);
+        }
+        if event.is_full() {
+            return Err(EventError::EventFull);
+        }
+    }
+    Ok(())
+}
+
+// Set the caller's RSVP to an event. Going takes a spot, or a place on the waitlist
+// if the event is full; Maybe and Declined free the caller's spot.
+#[ic_cdk::update]
+fn set_rsvp(event_id: u64, status: RsvpStatus) -> Result<(), EventError> {
+    ratelimit::check(ratelimit::Action::Join)?;
+    let caller = ic_cdk::caller().to_text();
+    EVENTS_MAP.with(|events_map_ref| {
+        apply_rsvp(&mut events_map_ref.borrow_mut(), event_id, caller, Some(status))
+    })
+}
+
+// Participants may cancel for themselves, the hosts and the admin for anyone
+fn may_cancel_for(event: &Event, caller: &Principal, participant_address: &str) -> bool {
+    caller.to_text() == participant_address || is_host_or_admin(event, caller)
+}
+
+// Function for a user to cancel joining an event.
+// Paid spots cancelled before the event starts are queued for a refund minus the refund fee.
+#[ic_cdk::update]
+fn cancel_join_event(event_id: u64, participant_address: String) -> Result<(), EventError> {
+    let event = EVENTS_MAP.with(|events_map_ref| {
+        let mut events_map = events_map_ref.borrow_mut();
+        let event = events_map.get(&event_id).ok_or(EventError::NoSuchEvent)?;
+        if !may_cancel_for(&event, &ic_cdk::caller(), &participant_address) {
+            return Err(EventError::Unauthorized);
+        }
+        if !event.status.is_open() {
+            return Err(EventError::EventNotOpen);
+        }
+        if participants::get(event_id, &participant_address).is_some() {
+            apply_rsvp(&mut events_map, event_id, participant_address.clone(), None)?;
+        } else if waitlist::remove(event_id, &participant_address) {
+            profiles::remove(event_id, &participant_address);
+            return Ok(None);
+        } else if payments::get(event_id, &participant_address).is_none() {
+            return Err(EventError::CancelJoinError);
+        }
+        profiles::remove(event_id, &participant_address);
+        Ok(Some(event))
+    })?;
 
+    let Some(event) = event else {
+        return Ok(());
+    };
+    let Some(payment) = payments::take(event_id, &participant_address) else {
+        return Ok(());
+    };
+    if ic_cdk::api::time() >= event.start_time {
+        return Ok(());
+    }
+    let fee = event.ticket_price.map(|price| price.refund_fee).unwrap_or_default();
+    refunds::refund(event_id, participant_address, payment, fee);
+    Ok(())
+}
+
+// Set or clear the price of a draft event
+#[ic_cdk::update]
+fn set_ticket_price(event_id: u64, ticket_price: Option<TicketPrice>) -> Result<(), EventError> {
+    if ticket_price.as_ref().is_some_and(|price| !price.is_valid()) {
+        return Err(EventError::InvalidPrice);
+    }
+    EVENTS_MAP.with(|events_map_ref| {
+        let mut events_map = events_map_ref.borrow_mut();
+        let mut event = events_map.get(&event_id).ok_or(EventError::NoSuchEvent)?;
+        require_host(&event)?;
+        // Participants of a published event joined at the previous price
+        if event.status != EventStatus::Draft {
+            return Err(EventError::InvalidStatusTransition);
+        }
+        event.ticket_price = ticket_price;
+        store_event(&mut events_map, event_id, event);
         Ok(())
     })
 }
 
+// Replace the tags and category of an event
+#[ic_cdk::update]
+fn update_event_tags(event_id: u64, tags: Vec<String>, category: Option<String>) -> Result<(), EventError> {
+    let (tags, category) = normalize_labels(&tags, &category)?;
 
-// Get participants of an event
+    EVENTS_MAP.with(|events_map_ref| {
+        let mut events_map = events_map_ref.borrow_mut();
+        let mut event = events_map.get(&event_id).ok_or(EventError::NoSuchEvent)?;
+        require_host(&event)?;
+
+        tags::unindex_event(event_id, &event.tags, &event.category);
+        tags::index_event(event_id, &tags, &category);
+        event.tags = tags;
+        event.category = category;
+        store_event(&mut events_map, event_id, event);
+        Ok(())
+    })
+}
+
+// Set or clear where an event takes place
 #[ic_cdk::update]
-fn join_event(event_id: u64, participant_address: String) -> Result<(), EventError> {
+fn set_event_location(event_id: u64, location: Option<Location>, venue: Option<String>) -> Result<(), EventError> {
+    validate_location(&location, &venue)?;
+
     EVENTS_MAP.with(|events_map_ref| {
         let mut events_map = events_map_ref.borrow_mut();
-        // Retrieve the event, clone it, and then modify it
-        if let Some(mut event) = events_map.get(&event_id) {
-            if event.participants.iter().any(|p| p.address == participant_address) {
-                return Err(EventError::AlreadyJoined);
-            }
+        let mut event = events_map.get(&event_id).ok_or(EventError::NoSuchEvent)?;
+        require_host(&event)?;
 
-            let new_participant = Participant {address: participant_address};
-            event.participants.push(new_participant);
-            // Insert the modified event back into the map
-            events_map.insert(event_id, event);
-            Ok(())
-        } else {
-            Err(EventError::NoSuchEvent)
+        if let Some(previous) = &event.location {
+            geo::unindex_event(event_id, previous);
+        }
+        if let Some(location) = &location {
+            geo::index_event(event_id, location);
         }
+        event.location = location;
+        event.venue = venue;
+        store_event(&mut events_map, event_id, event);
+        Ok(())
     })
 }
 
-// Function for a user to cancel joining an event
+// Move an event to a new status, enforcing the lifecycle rules
+fn set_event_status(event_id: u64, next: EventStatus) -> Result<(), EventError> {
+    EVENTS_MAP.with(|events_map_ref| {
+        let mut events_map = events_map_ref.borrow_mut();
+        let mut event = events_map.get(&event_id).ok_or(EventError::NoSuchEvent)?;
+        require_host(&event)?;
+        event.transition_to(next)?;
+        store_event(&mut events_map, event_id, event);
+        Ok(())
+    })
+}
+
+// Open a draft event for joining
 #[ic_cdk::update]
-fn cancel_join_event(event_id: u64, participant_address: String) -> Result<(), EventError> {
+fn publish_event(event_id: u64) -> Result<(), EventError> {
+    set_event_status(event_id, EventStatus::Published)?;
+    // A draft created with its capacity already reached is full once published
     EVENTS_MAP.with(|events_map_ref| {
         let mut events_map = events_map_ref.borrow_mut();
-        // Retrieve the event, clone it, and then modify it
         if let Some(mut event) = events_map.get(&event_id) {
-            if let Some(index) = event
-                .participants
-                .iter()
-                .position(|p| p.address == participant_address)
-            {
-                event.participants.remove(index);
-                // Insert the modified event back into the map
-                events_map.insert(event_id, event);
-                Ok(())
-            } else {
-                Err(EventError::CancelJoinError)
-            }
+            event.refresh_capacity_status();
+            store_event(&mut events_map, event_id, event);
+        }
+    });
+    Ok(())
+}
+
+// Publish every draft occurrence of a recurring event
+#[ic_cdk::update]
+fn publish_series(series_id: u64) -> Result<(), EventError> {
+    let drafts: Vec<u64> = EVENTS_MAP.with(|events_map| {
+        events_map
+            .borrow()
+            .iter()
+            .filter(|(_, event)| event.series_id == Some(series_id) && event.status == EventStatus::Draft)
+            .map(|(event_id, _)| event_id)
+            .collect()
+    });
+
+    if drafts.is_empty() {
+        return Err(EventError::NoSuchEvent);
+    }
+    for event_id in drafts {
+        publish_event(event_id)?;
+    }
+    Ok(())
+}
+
+// Cancel an event, notifying its participants and waitlist. Payments of a paid
+// event are refunded in full in the background.
+#[ic_cdk::update]
+fn cancel_event(event_id: u64) -> Result<(), EventError> {
+    set_event_status(event_id, EventStatus::Cancelled)?;
+    let Some(event) = EVENTS_MAP.with(|events_map| events_map.borrow().get(&event_id)) else {
+        return Ok(());
+    };
+
+    webhooks::notify(event_id, webhooks::WebhookEventKind::EventCancelled, None);
+    let refunded = refunds::refund_all(event_id);
+    let message = format!("\"{}\" was cancelled", event.name);
+    let recipients = participants::of_event(event_id)
+        .into_iter()
+        .map(|participant| participant.address)
+        .chain(waitlist::addresses(event_id));
+    for address in recipients {
+        let message = if refunded.contains(&address) {
+            format!("{}, your payment will be refunded", message)
         } else {
-            Err(EventError::NoSuchEvent)
+            message.clone()
+        };
+        notifications::push(&address, event_id, message);
+    }
+    Ok(())
+}
+
+#[ic_cdk::update]
+fn complete_event(event_id: u64) -> Result<(), EventError> {
+    set_event_status(event_id, EventStatus::Completed)
+}
+
+// Let another principal manage an event; only the organizer may add co-hosts
+#[ic_cdk::update]
+fn add_co_host(event_id: u64, co_host: Principal) -> Result<(), EventError> {
+    EVENTS_MAP.with(|events_map_ref| {
+        let mut events_map = events_map_ref.borrow_mut();
+        let mut event = events_map.get(&event_id).ok_or(EventError::NoSuchEvent)?;
+        if event.organizer != ic_cdk::caller() {
+            return Err(EventError::Unauthorized);
+        }
+        if event.is_host(&co_host) {
+            return Err(EventError::AlreadyCoHost);
+        }
+        if event.co_hosts.len() >= MAX_CO_HOSTS {
+            return Err(EventError::TooManyCoHosts);
+        }
+        event.co_hosts.push(co_host);
+        store_event(&mut events_map, event_id, event);
+        Ok(())
+    })
+}
+
+// Remove a co-host; the organizer may remove anyone, a co-host only themselves
+#[ic_cdk::update]
+fn remove_co_host(event_id: u64, co_host: Principal) -> Result<(), EventError> {
+    EVENTS_MAP.with(|events_map_ref| {
+        let mut events_map = events_map_ref.borrow_mut();
+        let mut event = events_map.get(&event_id).ok_or(EventError::NoSuchEvent)?;
+        let caller = ic_cdk::caller();
+        if event.organizer != caller && co_host != caller {
+            return Err(EventError::Unauthorized);
         }
+        let index = event
+            .co_hosts
+            .iter()
+            .position(|p| *p == co_host)
+            .ok_or(EventError::NoSuchCoHost)?;
+        event.co_hosts.remove(index);
+        store_event(&mut events_map, event_id, event);
+        Ok(())
     })
 }
 
-// Query events in state
+// Make an event public or private
+#[ic_cdk::update]
+fn set_visibility(event_id: u64, visibility: Visibility) -> Result<(), EventError> {
+    EVENTS_MAP.with(|events_map_ref| {
+        let mut events_map = events_map_ref.borrow_mut();
+        let mut event = events_map.get(&event_id).ok_or(EventError::NoSuchEvent)?;
+        require_host(&event)?;
+        event.visibility = visibility;
+        store_event(&mut events_map, event_id, event);
+        Ok(())
+    })
+}
+
+// Query the public events in state
 #[ic_cdk::query]
 fn get_stored_events() -> Vec<Event> {
     EVENTS_MAP.with(|events_map| {
         events_map
             .borrow()
             .iter()
+            .filter(|(_, event)| event.is_public())
             .map(|(_, event)| event.clone())
             .collect()
     })
@@ -152,74 +998,229 @@ fn get_stored_events() -> Vec<Event> {
 
 #[ic_cdk::query]
 fn get_event_by_id(event_id: u64) -> Option<Event> {
+    let caller = ic_cdk::caller();
+    EVENTS_MAP.with(|events_map| {
+        let events = events_map.borrow();
+        events.get(&event_id).filter(|event| can_see_event_as(event_id, event, &caller))
+    })
+}
+
+// Query all public occurrences of a recurring event, ordered by id
+#[ic_cdk::query]
+fn get_series_occurrences(series_id: u64) -> Vec<Event> {
+    EVENTS_MAP.with(|events_map| {
+        events_map
+            .borrow()
+            .iter()
+            .filter(|(_, event)| event.series_id == Some(series_id) && event.is_public())
+            .map(|(_, event)| event)
+            .collect()
+    })
+}
+
+// Query a page of the events with the given tag
+#[ic_cdk::query]
+fn get_events_by_tag(tag: String, page: u64) -> Vec<(u64, Event)> {
+    let Some(tag) = tags::normalize(&tag) else {
+        return Vec::new();
+    };
+
+    public_events_by_ids(tags::events_with_tag(&tag, page, PAGE_SIZE))
+}
+
+// Query a page of the events whose name contains every word of the query,
+// case-insensitively and also inside words, for type-ahead search
+#[ic_cdk::query]
+fn search_events(query: String, page: u64) -> Vec<(u64, Event)> {
+    let event_ids = search::search(&query, page, PAGE_SIZE, |event_id| {
+        EVENTS_MAP.with(|events_map| {
+            events_map
+                .borrow()
+                .get(&event_id)
+                .filter(|event| event.is_public())
+                .map(|event| event.name)
+        })
+    });
+    events_by_ids(event_ids)
+}
+
+// Query all categories in use, in alphabetical order
+#[ic_cdk::query]
+fn list_categories() -> Vec<String> {
+    tags::categories()
+}
+
+// Look up events by id, keeping the order of the ids
+fn events_by_ids(event_ids: Vec<u64>) -> Vec<(u64, Event)> {
     EVENTS_MAP.with(|events_map| {
         let events = events_map.borrow();
-        events.get(&event_id)
+        event_ids
+            .into_iter()
+            .filter_map(|event_id| events.get(&event_id).map(|event| (event_id, event)))
+            .collect()
     })
 }
 
-// Query participants of given event
+// Like events_by_ids, leaving out private events
+fn public_events_by_ids(event_ids: Vec<u64>) -> Vec<(u64, Event)> {
+    events_by_ids(event_ids)
+        .into_iter()
+        .filter(|(_, event)| event.is_public())
+        .collect()
+}
+
+// Query public events that have not started yet, soonest first
+#[ic_cdk::query]
+fn get_upcoming_events() -> Vec<(u64, Event)> {
+    let now = ic_cdk::api::time();
+    public_events_by_ids(dates::events_starting_between(now + 1, u64::MAX))
+}
+
+// Query public events that have already ended, oldest first
+#[ic_cdk::query]
+fn get_past_events() -> Vec<(u64, Event)> {
+    let now = ic_cdk::api::time();
+    public_events_by_ids(dates::events_starting_between(0, now))
+        .into_iter()
+        .filter(|(_, event)| event.end_time < now)
+        .collect()
+}
+
+// Query public events starting between `from` and `to` (inclusive, ns since the epoch)
+#[ic_cdk::query]
+fn get_events_between(from: u64, to: u64) -> Vec<(u64, Event)> {
+    public_events_by_ids(dates::events_starting_between(from, to))
+}
+
+// Query the events an address is going to, leaving out private events
+#[ic_cdk::query]
+fn get_events_joined_by(principal: Principal) -> Vec<(u64, Event)> {
+    public_events_by_ids(participants::events_of(&principal.to_text()))
+}
+
+// Query the events the caller is going to
+#[ic_cdk::query]
+fn get_my_events() -> Vec<(u64, Event)> {
+    events_by_ids(participants::events_of(&ic_cdk::caller().to_text()))
+}
+
+// Query the Going participants of given event, None for private events of other hosts
 #[ic_cdk::query]
 fn get_participants_of_event(event_id: u64) -> Option<Vec<String>> {
     EVENTS_MAP.with(|events_map| {
         let events = events_map.borrow();
-        events.get(&event_id).map(|event| {
-            event.participants.iter().map(|participant| participant.address.clone()).collect()
+        events.get(&event_id).filter(can_see_participants).map(|_| {
+            participants::of_event(event_id)
+                .into_iter()
+                .filter(|participant| participant.rsvp == RsvpStatus::Going)
+                .map(|participant| participant.address)
+                .collect()
         })
     })
 }
 
+// Query the waitlist of given event, in order, None for private events of other hosts
+#[ic_cdk::query]
+fn get_waitlist(event_id: u64) -> Option<Vec<String>> {
+    EVENTS_MAP.with(|events_map| {
+        let events = events_map.borrow();
+        events
+            .get(&event_id)
+            .filter(can_see_participants)
+            .map(|_| waitlist::addresses(event_id))
+    })
+}
+
+// Query the caller's 1-based position on the waitlist of given event
+#[ic_cdk::query]
+fn get_my_position(event_id: u64) -> Option<u64> {
+    let caller = ic_cdk::caller().to_text();
+    waitlist::position(event_id, &caller)
+}
 
-// Update method to make an HTTPS outcall and fetch events
-#[ic_cdk::update]
-async fn get_events_from_api() -> String {
-    // Setup the URL for the HTTP GET request
-    let url = "https://654c93da77200d6ba8590738.mockapi.io/events".to_string();
+// Query the caller's notification queue, oldest first
+#[ic_cdk::query]
+fn get_my_notifications() -> Vec<Notification> {
+    notifications::queue_of(&ic_cdk::caller().to_text())
+}
 
-    // Prepare headers for the system http_request call
-    let request_headers = vec![];
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    // Setup the HTTP request arguments
-    let request = CanisterHttpRequestArgument {
-        url,
-        method: HttpMethod::GET,
-        body: None,
-        max_response_bytes: None,
-        transform: None,
-        headers: request_headers,
-    };
+    #[test]
+    fn event_ids_are_never_handed_out_twice() {
+        assert_eq!(take_event_id(), 0);
+        assert_eq!(take_event_id(), 1);
 
-    // Make the HTTPS request and wait for the response
-    match http_request(request).await {
-        Ok((response,)) => {
-            if response.status == 200 {
-                // Parse the JSON response into a Vec<Event>
-                let events: Vec<Event> =
-                    serde_json::from_slice(&response.body).expect("Failed to parse JSON response.");
-
-                EVENTS_MAP.with(|events_map_ref| {
-                    let mut events_map = events_map_ref.borrow_mut();
-                    // Create a new map and fill it with the new events
-                    let mut new_map = StableBTreeMap::init(
-                        MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(1))),
-                    );
-                    for (i, event) in events.into_iter().enumerate() {
-                        new_map.insert(i as u64, event);
-                    }
-                    // Replace the old map with the new one
-                    *events_map = new_map;
-                });
-                // Return a success message
-                "Events fetched and stored successfully.".to_string()
-            } else {
-                format!("HTTP request failed with status code: {}", response.status)
-            }
-        }
-        Err((code, message)) => {
-            format!(
-                "The http_request resulted in an error. Code: {:?}, Message: {}",
-                code, message
-            )
-        }
+        // An import of event 10 moves the counter above it, an older id leaves it
+        reserve_event_id(10);
+        assert_eq!(take_event_id(), 11);
+        reserve_event_id(3);
+        assert_eq!(next_event_id(), 12);
+    }
+
+    #[test]
+    fn only_the_participant_or_a_host_may_cancel_a_spot() {
+        let participant = Principal::from_slice(&[1]);
+        let organizer = Principal::from_slice(&[2]);
+        let stranger = Principal::from_slice(&[3]);
+        let mut event = Event::for_test("Meetup", 1);
+        event.organizer = organizer;
+
+        let address = participant.to_text();
+        assert!(may_cancel_for(&event, &participant, &address));
+        assert!(may_cancel_for(&event, &organizer, &address));
+        assert!(!may_cancel_for(&event, &stranger, &address));
+    }
+
+    #[test]
+    fn private_events_are_only_shown_to_hosts_and_invitees() {
+        let organizer = Principal::from_slice(&[1]);
+        let co_host = Principal::from_slice(&[2]);
+        let invitee = Principal::from_slice(&[3]);
+        let stranger = Principal::from_slice(&[4]);
+        let mut event = Event::for_test("Meetup", 1);
+        event.organizer = organizer;
+        event.co_hosts.push(co_host);
+        event.visibility = Visibility::Private;
+        invites::restore(7, invitee.to_text());
+
+        assert!(can_see_event_as(7, &event, &organizer));
+        assert!(can_see_event_as(7, &event, &co_host));
+        assert!(can_see_event_as(7, &event, &invitee));
+        assert!(!can_see_event_as(7, &event, &stranger));
+        assert!(!can_see_event_as(8, &event, &invitee));
+
+        event.visibility = Visibility::Public;
+        assert!(can_see_event_as(8, &event, &stranger));
+    }
+
+    #[test]
+    fn a_series_is_not_joined_unless_every_occurrence_can_be() {
+        let participant = Principal::from_slice(&[1]);
+        let stranger = Principal::from_slice(&[2]);
+        let mut open = Event::for_test("Meetup", 1);
+        open.series_id = Some(0);
+        let mut private = Event::for_test("Meetup", 2);
+        private.series_id = Some(0);
+        private.visibility = Visibility::Private;
+        EVENTS_MAP.with(|events_map| {
+            let mut events_map = events_map.borrow_mut();
+            events_map.insert(0, open);
+            events_map.insert(1, private);
+
+            let address = participant.to_text();
+            assert!(matches!(
+                join_series_as(&mut events_map, 0, &participant, address.clone()),
+                Err(EventError::NotInvited)
+            ));
+            assert!(participants::get(0, &address).is_none());
+            assert_eq!(events_map.get(&0).unwrap().participant_count, 0);
+
+            // Invitees join private occurrences themselves
+            invites::restore(1, address.clone());
+            assert!(matches!(join_series_as(&mut events_map, 0, &stranger, address), Err(EventError::Unauthorized)));
+        });
     }
 }
@@ -0,0 +1,284 @@
+use candid::{CandidType, Decode, Deserialize, Encode};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::ops::Bound;
+use std::{borrow::Cow, cell::RefCell};
+
+use crate::types::Event;
+use crate::{dates, stats, Memory, EVENTS_MAP, MEMORY_MANAGER, PAGE_SIZE};
+
+// Names are sorted on a lowercase prefix of this many bytes, then by id
+const NAME_KEY_LEN: usize = 64;
+// Index entries read per page at most, so runs of private events do not make a page
+// read the whole index; a page may come back short with a cursor to go on from
+const MAX_SCANNED: usize = 10 * PAGE_SIZE as usize;
+
+#[derive(CandidType, Deserialize, Clone, Copy, Default)]
+pub enum SortBy {
+    #[default]
+    DateAscending,
+    DateDescending,
+    Name,
+    // Most Going participants first
+    ParticipantCount,
+    RecentlyCreated,
+}
+
+// Where a listing goes on from: the index key of the last event read, in the index of
+// its order
+#[derive(CandidType, Deserialize, Clone, PartialEq, Debug)]
+pub enum ListCursor {
+    DateAscending((u64, u64)),
+    DateDescending((u64, u64)),
+    Name(NameKey),
+    ParticipantCount((u32, u64)),
+    RecentlyCreated(u64),
+}
+
+#[derive(CandidType)]
+pub struct EventsPage {
+    pub events: Vec<(u64, Event)>,
+    // None once the listing is over
+    pub next: Option<ListCursor>,
+}
+
+#[derive(CandidType, Deserialize, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub struct NameKey {
+    name: String,
+    event_id: u64,
+}
+
+impl Storable for NameKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for NameKey {
+    const MAX_SIZE: u32 = 100;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// Orders the existing indexes do not provide. Stable maps only iterate forward, so the
+// date index is kept a second time keyed by (u64::MAX - start_time, event_id), and the
+// ids in creation order keyed by u64::MAX - event_id.
+thread_local! {
+    static BY_DATE_DESCENDING: RefCell<StableBTreeMap<(u64, u64), (), Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(36))),
+        )
+    );
+
+    static BY_NAME: RefCell<StableBTreeMap<NameKey, (), Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(37))),
+        )
+    );
+
+    static BY_CREATED_DESCENDING: RefCell<StableBTreeMap<u64, (), Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(56))),
+        )
+    );
+}
+
+fn name_key(event_id: u64, name: &str) -> NameKey {
+    let mut name = name.to_lowercase();
+    if name.len() > NAME_KEY_LEN {
+        let end = (0..=NAME_KEY_LEN).rev().find(|i| name.is_char_boundary(*i)).unwrap_or(0);
+        name.truncate(end);
+    }
+    NameKey { name, event_id }
+}
+
+// Called with the previous and new value whenever an event is stored
+pub fn on_event_stored(event_id: u64, previous: Option<&Event>, event: &Event) {
+    if previous.is_none() {
+        BY_CREATED_DESCENDING.with(|index| index.borrow_mut().insert(u64::MAX - event_id, ()));
+    }
+    if previous.map(|previous| previous.start_time) != Some(event.start_time) {
+        BY_DATE_DESCENDING.with(|index| {
+            let mut index = index.borrow_mut();
+            if let Some(previous) = previous {
+                index.remove(&(u64::MAX - previous.start_time, event_id));
+            }
+            index.insert((u64::MAX - event.start_time, event_id), ());
+        });
+    }
+    if previous.map(|previous| previous.name.as_str()) != Some(event.name.as_str()) {
+        BY_NAME.with(|index| {
+            let mut index = index.borrow_mut();
+            if let Some(previous) = previous {
+                index.remove(&name_key(event_id, &previous.name));
+            }
+            index.insert(name_key(event_id, &event.name), ());
+        });
+    }
+}
+
+// Called when an event is moved out of the events map
+pub fn on_event_removed(event_id: u64, event: &Event) {
+    BY_DATE_DESCENDING.with(|index| index.borrow_mut().remove(&(u64::MAX - event.start_time, event_id)));
+    BY_NAME.with(|index| index.borrow_mut().remove(&name_key(event_id, &event.name)));
+    BY_CREATED_DESCENDING.with(|index| index.borrow_mut().remove(&(u64::MAX - event_id)));
+}
+
+// Index the events stored before these indexes existed, once
+pub fn backfill() {
+    if BY_NAME.with(|index| index.borrow().is_empty()) {
+        EVENTS_MAP.with(|events_map| {
+            for (event_id, event) in events_map.borrow().iter() {
+                on_event_stored(event_id, None, &event);
+            }
+        });
+    } else if BY_CREATED_DESCENDING.with(|index| index.borrow().is_empty()) {
+        EVENTS_MAP.with(|events_map| {
+            BY_CREATED_DESCENDING.with(|index| {
+                let mut index = index.borrow_mut();
+                for (event_id, _) in events_map.borrow().iter() {
+                    index.insert(u64::MAX - event_id, ());
+                }
+            })
+        });
+    }
+}
+
+// A page of the public events of an index, from the key after `after`, with the key of
+// the last entry read to go on from. Only the entries of the page are read, so a page
+// costs the same however deep into the listing it is.
+fn page_after<K>(
+    index: &StableBTreeMap<K, (), Memory>,
+    after: Option<K>,
+    event_id_of: impl Fn(&K) -> u64,
+) -> (Vec<(u64, Event)>, Option<K>)
+where
+    K: Storable + BoundedStorable + Ord + Clone,
+{
+    let start = after.map_or(Bound::Unbounded, Bound::Excluded);
+    let mut keys = index.range((start, Bound::Unbounded));
+    EVENTS_MAP.with(|events_map| {
+        let events = events_map.borrow();
+        let mut page = Vec::new();
+        let mut last = None;
+        for _ in 0..MAX_SCANNED {
+            if page.len() == PAGE_SIZE as usize {
+                break;
+            }
+            let Some((key, _)) = keys.next() else {
+                return (page, None);
+            };
+            let event_id = event_id_of(&key);
+            if let Some(event) = events.get(&event_id).filter(|event| event.is_public()) {
+                page.push((event_id, event));
+            }
+            last = Some(key);
+        }
+        (page, last)
+    })
+}
+
+// Query a page of the public events in the given order, after the cursor of the previous
+// page. Every order is read from an index, from the cursor on. A cursor of another order
+// gives an empty page.
+#[ic_cdk::query]
+fn list_events(sort_by: SortBy, after: Option<ListCursor>) -> EventsPage {
+    list(sort_by, after)
+}
+
+pub fn list(sort_by: SortBy, after: Option<ListCursor>) -> EventsPage {
+    let (events, next) = match (sort_by, after) {
+        (SortBy::DateAscending, None) => dates::with_start_time_index(|index| page_of(index, None, ListCursor::DateAscending)),
+        (SortBy::DateAscending, Some(ListCursor::DateAscending(key))) => {
+            dates::with_start_time_index(|index| page_of(index, Some(key), ListCursor::DateAscending))
+        }
+        (SortBy::DateDescending, None) => BY_DATE_DESCENDING.with(|index| page_of(&index.borrow(), None, ListCursor::DateDescending)),
+        (SortBy::DateDescending, Some(ListCursor::DateDescending(key))) => {
+            BY_DATE_DESCENDING.with(|index| page_of(&index.borrow(), Some(key), ListCursor::DateDescending))
+        }
+        (SortBy::Name, None) => BY_NAME.with(|index| name_page(&index.borrow(), None)),
+        (SortBy::Name, Some(ListCursor::Name(key))) => BY_NAME.with(|index| name_page(&index.borrow(), Some(key))),
+        (SortBy::ParticipantCount, None) => {
+            stats::with_participants_index(|index| page_of(index, None, ListCursor::ParticipantCount))
+        }
+        (SortBy::ParticipantCount, Some(ListCursor::ParticipantCount(key))) => {
+            stats::with_participants_index(|index| page_of(index, Some(key), ListCursor::ParticipantCount))
+        }
+        (SortBy::RecentlyCreated, None) => BY_CREATED_DESCENDING.with(|index| created_page(&index.borrow(), None)),
+        (SortBy::RecentlyCreated, Some(ListCursor::RecentlyCreated(key))) => {
+            BY_CREATED_DESCENDING.with(|index| created_page(&index.borrow(), Some(key)))
+        }
+        _ => (Vec::new(), None),
+    };
+    EventsPage { events, next }
+}
+
+// A page of an index keyed by (order, event_id)
+fn page_of<T: Storable + BoundedStorable + Ord + Clone + Copy>(
+    index: &StableBTreeMap<(T, u64), (), Memory>,
+    after: Option<(T, u64)>,
+    cursor: fn((T, u64)) -> ListCursor,
+) -> (Vec<(u64, Event)>, Option<ListCursor>)
+where
+    (T, u64): Storable + BoundedStorable,
+{
+    let (events, last) = page_after(index, after, |(_, event_id)| *event_id);
+    (events, last.map(cursor))
+}
+
+fn name_page(index: &StableBTreeMap<NameKey, (), Memory>, after: Option<NameKey>) -> (Vec<(u64, Event)>, Option<ListCursor>) {
+    let (events, last) = page_after(index, after, |key| key.event_id);
+    (events, last.map(ListCursor::Name))
+}
+
+fn created_page(index: &StableBTreeMap<u64, (), Memory>, after: Option<u64>) -> (Vec<(u64, Event)>, Option<ListCursor>) {
+    let (events, last) = page_after(index, after, |key| u64::MAX - key);
+    (events, last.map(ListCursor::RecentlyCreated))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store(event_id: u64, event: Event) {
+        on_event_stored(event_id, None, &event);
+        EVENTS_MAP.with(|events_map| events_map.borrow_mut().insert(event_id, event));
+    }
+
+    fn ids(page: &EventsPage) -> Vec<u64> {
+        page.events.iter().map(|(event_id, _)| *event_id).collect()
+    }
+
+    #[test]
+    fn recently_created_follows_the_ids_that_exist() {
+        for event_id in [0, 1, 5] {
+            store(event_id, Event::for_test("Meetup", event_id));
+        }
+        let removed = EVENTS_MAP.with(|events_map| events_map.borrow_mut().remove(&1)).unwrap();
+        on_event_removed(1, &removed);
+
+        let page = list(SortBy::RecentlyCreated, None);
+        assert_eq!(ids(&page), vec![5, 0]);
+        assert!(page.next.is_none());
+    }
+
+    #[test]
+    fn pages_go_on_from_the_cursor() {
+        let count = PAGE_SIZE + 5;
+        for event_id in 0..count {
+            store(event_id, Event::for_test(&format!("Meetup {:02}", event_id), event_id));
+        }
+
+        let first = list(SortBy::Name, None);
+        assert_eq!(ids(&first), (0..PAGE_SIZE).collect::<Vec<_>>());
+        let second = list(SortBy::Name, first.next.clone());
+        assert_eq!(ids(&second), (PAGE_SIZE..count).collect::<Vec<_>>());
+        assert!(second.next.is_none());
+
+        // A cursor of another order gives nothing
+        assert!(list(SortBy::RecentlyCreated, first.next).events.is_empty());
+    }
+}
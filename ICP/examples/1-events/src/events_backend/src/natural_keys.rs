@@ -0,0 +1,84 @@
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::StableBTreeMap;
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+
+use crate::types::Event;
+use crate::{Memory, EVENTS_MAP, MEMORY_MANAGER};
+
+// Events keyed by (hash of name and start time, event_id). Hashes may collide, so the
+// events found under a hash are compared with the key before they count as a match.
+thread_local! {
+    static NATURAL_KEYS: RefCell<StableBTreeMap<(u64, u64), (), Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(42))),
+        )
+    );
+}
+
+pub fn hash(name: &str, start_time: u64) -> u64 {
+    let digest = Sha256::new()
+        .chain_update(start_time.to_be_bytes())
+        .chain_update(name.as_bytes())
+        .finalize();
+    u64::from_be_bytes(digest[..8].try_into().unwrap())
+}
+
+// Called with the previous and new value whenever an event is stored
+pub fn on_event_stored(event_id: u64, previous: Option<&Event>, event: &Event) {
+    let key = hash(&event.name, event.start_time);
+    let previous_key = previous.map(|previous| hash(&previous.name, previous.start_time));
+    if previous_key == Some(key) {
+        return;
+    }
+    NATURAL_KEYS.with(|index| {
+        let mut index = index.borrow_mut();
+        if let Some(previous_key) = previous_key {
+            index.remove(&(previous_key, event_id));
+        }
+        index.insert((key, event_id), ());
+    });
+}
+
+// Called when an event is moved out of the events map
+pub fn on_event_removed(event_id: u64, event: &Event) {
+    NATURAL_KEYS.with(|index| index.borrow_mut().remove(&(hash(&event.name, event.start_time), event_id)));
+}
+
+// Index the events stored before the index existed, once
+pub fn backfill() {
+    if NATURAL_KEYS.with(|index| !index.borrow().is_empty()) {
+        return;
+    }
+    EVENTS_MAP.with(|events_map| {
+        for (event_id, event) in events_map.borrow().iter() {
+            on_event_stored(event_id, None, &event);
+        }
+    });
+}
+
+// Id of the event with this name and start time, if one is stored
+pub fn find(events_map: &StableBTreeMap<u64, Event, Memory>, name: &str, start_time: u64) -> Option<u64> {
+    let key = hash(name, start_time);
+    NATURAL_KEYS.with(|index| {
+        index
+            .borrow()
+            .range((key, 0)..=(key, u64::MAX))
+            .map(|((_, event_id), _)| event_id)
+            .find(|event_id| {
+                events_map
+                    .get(event_id)
+                    .is_some_and(|event| event.name == name && event.start_time == start_time)
+            })
+    })
+}
+
+// Query the event with this name and start time
+#[ic_cdk::query]
+fn get_event_by_natural_key(name: String, start_time: u64) -> Option<(u64, Event)> {
+    EVENTS_MAP.with(|events_map| {
+        let events_map = events_map.borrow();
+        let event_id = find(&events_map, &name, start_time)?;
+        events_map.get(&event_id).map(|event| (event_id, event))
+    })
+}
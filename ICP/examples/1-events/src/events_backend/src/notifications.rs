@@ -0,0 +1,119 @@
+use candid::{CandidType, Decode, Deserialize, Encode};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+use crate::{Memory, MEMORY_MANAGER};
+
+// A message waiting in a participant's notification queue
+#[derive(CandidType, Deserialize, Clone)]
+pub struct Notification {
+    pub event_id: u64,
+    pub message: String,
+    pub created_at: u64,
+}
+
+impl Storable for Notification {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+const MAX_MESSAGE_LEN: usize = 250;
+
+impl BoundedStorable for Notification {
+    const MAX_SIZE: u32 = 300;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// Queue entries are keyed by (recipient, seq) so a recipient's queue is a contiguous range
+#[derive(CandidType, Deserialize, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct NotificationKey {
+    recipient: String,
+    seq: u64,
+}
+
+impl Storable for NotificationKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for NotificationKey {
+    const MAX_SIZE: u32 = 150;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+thread_local! {
+    static NOTIFICATIONS_MAP: RefCell<StableBTreeMap<NotificationKey, Notification, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(6))),
+        )
+    );
+}
+
+fn queue_range(recipient: &str) -> std::ops::RangeInclusive<NotificationKey> {
+    NotificationKey { recipient: recipient.to_string(), seq: 0 }
+        ..=NotificationKey { recipient: recipient.to_string(), seq: u64::MAX }
+}
+
+// Append a notification to the recipient's queue. Messages are cut to MAX_MESSAGE_LEN
+// bytes so they fit the bound of a Notification.
+pub fn push(recipient: &str, event_id: u64, mut message: String) {
+    if message.len() > MAX_MESSAGE_LEN {
+        let end = (0..=MAX_MESSAGE_LEN).rev().find(|i| message.is_char_boundary(*i)).unwrap_or(0);
+        message.truncate(end);
+    }
+    NOTIFICATIONS_MAP.with(|map| {
+        let mut map = map.borrow_mut();
+        let seq = map
+            .range(queue_range(recipient))
+            .last()
+            .map(|(key, _)| key.seq + 1)
+            .unwrap_or(0);
+        let notification = Notification {
+            event_id,
+            message,
+            created_at: ic_cdk::api::time(),
+        };
+        map.insert(NotificationKey { recipient: recipient.to_string(), seq }, notification);
+    });
+}
+
+// The recipient's notifications, oldest first
+pub fn queue_of(recipient: &str) -> Vec<Notification> {
+    NOTIFICATIONS_MAP.with(|map| {
+        map.borrow()
+            .range(queue_range(recipient))
+            .map(|(_, notification)| notification)
+            .collect()
+    })
+}
+
+pub fn len() -> u64 {
+    NOTIFICATIONS_MAP.with(|map| map.borrow().len())
+}
+
+// Notifications of all recipients ordered by (recipient, seq), from the `skip`-th one
+pub fn all(skip: u64, take: u64) -> Vec<(String, u64, Notification)> {
+    NOTIFICATIONS_MAP.with(|map| {
+        map.borrow()
+            .iter()
+            .skip(skip as usize)
+            .take(take as usize)
+            .map(|(key, notification)| (key.recipient, key.seq, notification))
+            .collect()
+    })
+}
+
+pub fn restore(recipient: String, seq: u64, notification: Notification) {
+    NOTIFICATIONS_MAP.with(|map| map.borrow_mut().insert(NotificationKey { recipient, seq }, notification));
+}
@@ -0,0 +1,181 @@
+use candid::{CandidType, Decode, Deserialize, Encode};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+use crate::types::{Participant, RsvpStatus};
+use crate::{audit, stats, webhooks, Memory, MEMORY_MANAGER};
+
+// Participants are keyed by (event_id, address), so the participants of an event are a contiguous range
+#[derive(CandidType, Deserialize, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct ParticipantKey {
+    event_id: u64,
+    address: String,
+}
+
+impl Storable for ParticipantKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for ParticipantKey {
+    const MAX_SIZE: u32 = 160;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// The reverse index: events keyed by (address, event_id), so the events of an address
+// are a contiguous range
+#[derive(CandidType, Deserialize, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct JoinedKey {
+    address: String,
+    event_id: u64,
+}
+
+impl Storable for JoinedKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for JoinedKey {
+    const MAX_SIZE: u32 = 160;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+thread_local! {
+    static PARTICIPANTS_MAP: RefCell<StableBTreeMap<ParticipantKey, Participant, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(10))),
+        )
+    );
+
+    // Events each address is going to
+    static JOINED_INDEX: RefCell<StableBTreeMap<JoinedKey, (), Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(35))),
+        )
+    );
+
+    // When each participant first answered, in nanoseconds since the epoch. Kept apart
+    // from the participants, so their bound does not change.
+    static JOINED_AT: RefCell<StableBTreeMap<ParticipantKey, u64, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(51))),
+        )
+    );
+}
+
+fn update_joined(event_id: u64, address: &str, is_going: bool) {
+    let key = JoinedKey { address: address.to_string(), event_id };
+    JOINED_INDEX.with(|index| {
+        if is_going {
+            index.borrow_mut().insert(key, ());
+        } else {
+            index.borrow_mut().remove(&key);
+        }
+    });
+}
+
+fn key(event_id: u64, address: &str) -> ParticipantKey {
+    ParticipantKey { event_id, address: address.to_string() }
+}
+
+pub fn get(event_id: u64, address: &str) -> Option<Participant> {
+    PARTICIPANTS_MAP.with(|map| map.borrow().get(&key(event_id, address)))
+}
+
+pub fn insert(event_id: u64, participant: Participant) {
+    let previous = PARTICIPANTS_MAP.with(|map| {
+        map.borrow_mut().insert(key(event_id, &participant.address), participant.clone())
+    });
+    stats::on_rsvp_changed(&participant.address, previous.as_ref().map(|p| p.rsvp), Some(participant.rsvp));
+    update_joined(event_id, &participant.address, participant.rsvp == RsvpStatus::Going);
+    if previous.is_none() {
+        JOINED_AT.with(|joined_at| {
+            joined_at.borrow_mut().insert(key(event_id, &participant.address), ic_cdk::api::time())
+        });
+    }
+    audit::on_participant_stored(event_id, previous.as_ref(), &participant);
+    webhooks::on_rsvp_changed(event_id, &participant.address, previous.map(|p| p.rsvp), Some(participant.rsvp));
+}
+
+// Remove a participant, returns false if they had not answered
+pub fn remove(event_id: u64, address: &str) -> bool {
+    let previous = PARTICIPANTS_MAP.with(|map| map.borrow_mut().remove(&key(event_id, address)));
+    let removed = previous.is_some();
+    let previous_rsvp = previous.map(|p| p.rsvp);
+    stats::on_rsvp_changed(address, previous_rsvp, None);
+    webhooks::on_rsvp_changed(event_id, address, previous_rsvp, None);
+    if removed {
+        update_joined(event_id, address, false);
+        JOINED_AT.with(|joined_at| joined_at.borrow_mut().remove(&key(event_id, address)));
+        audit::on_participant_removed(event_id, address);
+    }
+    removed
+}
+
+// Participants of an event with any RSVP status, ordered by address
+pub fn of_event(event_id: u64) -> Vec<Participant> {
+    PARTICIPANTS_MAP.with(|map| {
+        map.borrow()
+            .range(key(event_id, "")..key(event_id + 1, ""))
+            .map(|(_, participant)| participant)
+            .collect()
+    })
+}
+
+// When a participant first answered; unknown for those who answered before this was kept
+pub fn joined_at(event_id: u64, address: &str) -> Option<u64> {
+    JOINED_AT.with(|joined_at| joined_at.borrow().get(&key(event_id, address)))
+}
+
+pub fn len() -> u64 {
+    PARTICIPANTS_MAP.with(|map| map.borrow().len())
+}
+
+// Participants of all events ordered by (event_id, address), from the `skip`-th one
+pub fn all(skip: u64, take: u64) -> Vec<(u64, Participant)> {
+    PARTICIPANTS_MAP.with(|map| {
+        map.borrow()
+            .iter()
+            .skip(skip as usize)
+            .take(take as usize)
+            .map(|(key, participant)| (key.event_id, participant))
+            .collect()
+    })
+}
+
+// Ids of the events an address is going to, read from the reverse index
+pub fn events_of(address: &str) -> Vec<u64> {
+    JOINED_INDEX.with(|index| {
+        index
+            .borrow()
+            .range(JoinedKey { address: address.to_string(), event_id: 0 }..)
+            .take_while(|(key, _)| key.address == address)
+            .map(|(key, _)| key.event_id)
+            .collect()
+    })
+}
+
+// Index the participants stored before the reverse index existed, once
+pub fn backfill_joined_index() {
+    if JOINED_INDEX.with(|index| !index.borrow().is_empty()) {
+        return;
+    }
+    PARTICIPANTS_MAP.with(|map| {
+        for (key, participant) in map.borrow().iter() {
+            if participant.rsvp == RsvpStatus::Going {
+                update_joined(key.event_id, &key.address, true);
+            }
+        }
+    });
+}
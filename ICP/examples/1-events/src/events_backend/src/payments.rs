@@ -0,0 +1,256 @@
+use candid::{CandidType, Decode, Deserialize, Encode, Nat, Principal};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell, collections::BTreeSet};
+
+use crate::types::{EventError, TicketPrice};
+use crate::{Memory, MEMORY_MANAGER};
+
+// A ticket payment received from a participant
+#[derive(CandidType, Deserialize, Clone)]
+pub struct Payment {
+    pub amount: u64,
+    pub ledger: Principal,
+    pub block_index: Nat,
+}
+
+impl Storable for Payment {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for Payment {
+    const MAX_SIZE: u32 = 100;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// Payments are keyed by (event_id, address), like participants
+#[derive(CandidType, Deserialize, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct PaymentKey {
+    event_id: u64,
+    address: String,
+}
+
+impl Storable for PaymentKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for PaymentKey {
+    const MAX_SIZE: u32 = 160;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+thread_local! {
+    static PAYMENTS: RefCell<StableBTreeMap<PaymentKey, Payment, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(15))),
+        )
+    );
+
+    // Payers whose payment for an event is in flight, keyed like the payments. Upgrades
+    // only happen once no call is in flight, so it is not kept across them.
+    static PENDING: RefCell<BTreeSet<PaymentKey>> = const { RefCell::new(BTreeSet::new()) };
+}
+
+// The subset of the ICRC-1 and ICRC-2 ledger interfaces used for tickets
+
+#[derive(CandidType, Deserialize, Clone)]
+struct Account {
+    owner: Principal,
+    subaccount: Option<Vec<u8>>,
+}
+
+impl Account {
+    fn of(owner: Principal) -> Self {
+        Account { owner, subaccount: None }
+    }
+}
+
+#[derive(CandidType)]
+struct TransferFromArgs {
+    spender_subaccount: Option<Vec<u8>>,
+    from: Account,
+    to: Account,
+    amount: Nat,
+    fee: Option<Nat>,
+    memo: Option<Vec<u8>>,
+    created_at_time: Option<u64>,
+}
+
+#[derive(CandidType)]
+struct TransferArgs {
+    from_subaccount: Option<Vec<u8>>,
+    to: Account,
+    amount: Nat,
+    fee: Option<Nat>,
+    memo: Option<Vec<u8>>,
+    created_at_time: Option<u64>,
+}
+
+// Variants of both the ICRC-1 TransferError and the ICRC-2 TransferFromError
+#[derive(CandidType, Deserialize)]
+enum LedgerError {
+    BadFee { expected_fee: Nat },
+    BadBurn { min_burn_amount: Nat },
+    InsufficientFunds { balance: Nat },
+    InsufficientAllowance { allowance: Nat },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: Nat },
+    TemporarilyUnavailable,
+    GenericError { error_code: Nat, message: String },
+}
+
+fn key(event_id: u64, address: &str) -> PaymentKey {
+    PaymentKey { event_id, address: address.to_string() }
+}
+
+// Ledger transfers of an event carry its id as memo
+fn memo(event_id: u64) -> Option<Vec<u8>> {
+    Some(event_id.to_be_bytes().to_vec())
+}
+
+// Refunds also carry the block of the payment they send back, so the memo of every
+// attempt to refund a payment is the same
+fn refund_memo(event_id: u64, payment: &Payment) -> Option<Vec<u8>> {
+    let block_index = u64::try_from(&payment.block_index.0).unwrap_or(u64::MAX);
+    Some([event_id.to_be_bytes(), block_index.to_be_bytes()].concat())
+}
+
+pub fn get(event_id: u64, address: &str) -> Option<Payment> {
+    PAYMENTS.with(|payments| payments.borrow().get(&key(event_id, address)))
+}
+
+pub fn take(event_id: u64, address: &str) -> Option<Payment> {
+    PAYMENTS.with(|payments| payments.borrow_mut().remove(&key(event_id, address)))
+}
+
+// Payments of an event with the address they were made for
+pub fn of_event(event_id: u64) -> Vec<(String, Payment)> {
+    PAYMENTS.with(|payments| {
+        payments
+            .borrow()
+            .range(key(event_id, "")..key(event_id + 1, ""))
+            .map(|(key, payment)| (key.address, payment))
+            .collect()
+    })
+}
+
+pub fn len() -> u64 {
+    PAYMENTS.with(|payments| payments.borrow().len())
+}
+
+// Payments of all events ordered by (event_id, address), from the `skip`-th one
+pub fn all(skip: u64, take: u64) -> Vec<(u64, String, Payment)> {
+    PAYMENTS.with(|payments| {
+        payments
+            .borrow()
+            .iter()
+            .skip(skip as usize)
+            .take(take as usize)
+            .map(|(key, payment)| (key.event_id, key.address, payment))
+            .collect()
+    })
+}
+
+pub fn restore(event_id: u64, address: &str, payment: Payment) {
+    PAYMENTS.with(|payments| payments.borrow_mut().insert(key(event_id, address), payment));
+}
+
+// Collect the ticket price from the payer with `icrc2_transfer_from`, using the
+// allowance they approved for this canister, and record the payment. A second charge
+// of the same payer for the event fails with PaymentInProgress while the first is in
+// flight, so a payer is never charged twice.
+pub async fn charge(event_id: u64, price: &TicketPrice, payer: Principal) -> Result<(), EventError> {
+    let pending = key(event_id, &payer.to_text());
+    if !PENDING.with(|pending_ref| pending_ref.borrow_mut().insert(pending.clone())) {
+        return Err(EventError::PaymentInProgress);
+    }
+    let result = transfer_from(event_id, price, payer).await;
+    PENDING.with(|pending_ref| pending_ref.borrow_mut().remove(&pending));
+    let block_index = result?;
+
+    restore(
+        event_id,
+        &payer.to_text(),
+        Payment { amount: price.amount, ledger: price.ledger, block_index },
+    );
+    Ok(())
+}
+
+async fn transfer_from(event_id: u64, price: &TicketPrice, payer: Principal) -> Result<Nat, EventError> {
+    let args = TransferFromArgs {
+        spender_subaccount: None,
+        from: Account::of(payer),
+        to: Account::of(ic_cdk::id()),
+        amount: Nat::from(price.amount),
+        fee: None,
+        memo: memo(event_id),
+        created_at_time: None,
+    };
+    let (result,): (Result<Nat, LedgerError>,) = ic_cdk::call(price.ledger, "icrc2_transfer_from", (args,))
+        .await
+        .map_err(|_| EventError::LedgerUnavailable)?;
+    result.map_err(|_| EventError::PaymentFailed)
+}
+
+async fn ledger_fee(ledger: Principal) -> Result<u64, EventError> {
+    let (fee,): (Nat,) = ic_cdk::call(ledger, "icrc1_fee", ())
+        .await
+        .map_err(|_| EventError::LedgerUnavailable)?;
+    u64::try_from(&fee.0).map_err(|_| EventError::RefundFailed)
+}
+
+// Why a refund was not confirmed by the ledger
+pub enum RefundError {
+    // The ledger made no transfer
+    NotSent,
+    // The ledger call got no reply, so the transfer may have been made
+    Unknown,
+    // The amount does not cover the ledger fee, so there is nothing to send
+    BelowFee { fee: u64 },
+}
+
+// Send `amount` back to a participant with `icrc1_transfer`, returns the amount sent. The
+// ledger fee of the transfer comes out of the amount, as the canister only holds the
+// payments it received. Retries of a refund pass the `created_at_time` of the first
+// attempt, so the ledger rejects a retry of a refund that went through as a duplicate
+// instead of paying twice.
+pub async fn refund(
+    event_id: u64,
+    payment: &Payment,
+    amount: u64,
+    to: Principal,
+    created_at_time: u64,
+) -> Result<u64, RefundError> {
+    let fee = ledger_fee(payment.ledger).await.map_err(|_| RefundError::NotSent)?;
+    let Some(amount) = amount.checked_sub(fee).filter(|amount| *amount > 0) else {
+        return Err(RefundError::BelowFee { fee });
+    };
+    let args = TransferArgs {
+        from_subaccount: None,
+        to: Account::of(to),
+        amount: Nat::from(amount),
+        fee: Some(Nat::from(fee)),
+        memo: refund_memo(event_id, payment),
+        created_at_time: Some(created_at_time),
+    };
+    let (result,): (Result<Nat, LedgerError>,) = ic_cdk::call(payment.ledger, "icrc1_transfer", (args,))
+        .await
+        .map_err(|_| RefundError::Unknown)?;
+    match result {
+        Ok(_) | Err(LedgerError::Duplicate { .. }) => Ok(amount),
+        Err(_) => Err(RefundError::NotSent),
+    }
+}
@@ -0,0 +1,147 @@
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+use crate::types::EventError;
+use crate::{participants, waitlist, Memory, EVENTS_MAP, MEMORY_MANAGER};
+
+const MAX_DISPLAY_NAME_LEN: usize = 64;
+// Long enough for a hex sha256
+const MAX_CONTACT_HASH_LEN: usize = 64;
+const MAX_DIETARY_NOTES_LEN: usize = 200;
+
+// What a participant tells the organizer when registering. Contacts are only kept as a
+// hash the organizer can compare against, never in clear text.
+#[derive(CandidType, Deserialize, Clone, Default)]
+pub struct ParticipantProfile {
+    pub display_name: Option<String>,
+    pub contact_hash: Option<String>,
+    pub dietary_notes: Option<String>,
+}
+
+impl ParticipantProfile {
+    pub fn is_valid(&self) -> bool {
+        let within = |field: &Option<String>, max_len: usize| {
+            field.as_ref().is_none_or(|value| !value.is_empty() && value.len() <= max_len)
+        };
+        within(&self.display_name, MAX_DISPLAY_NAME_LEN)
+            && within(&self.contact_hash, MAX_CONTACT_HASH_LEN)
+            && within(&self.dietary_notes, MAX_DIETARY_NOTES_LEN)
+    }
+}
+
+impl Storable for ParticipantProfile {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for ParticipantProfile {
+    const MAX_SIZE: u32 = 450;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+#[derive(CandidType, Deserialize, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct ProfileKey {
+    event_id: u64,
+    principal: Principal,
+}
+
+impl Storable for ProfileKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for ProfileKey {
+    const MAX_SIZE: u32 = 80;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// Kept apart from the participants, so only the organizer reads them
+thread_local! {
+    static PROFILES: RefCell<StableBTreeMap<ProfileKey, ParticipantProfile, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(46))),
+        )
+    );
+}
+
+fn key(event_id: u64, principal: Principal) -> ProfileKey {
+    ProfileKey { event_id, principal }
+}
+
+pub fn set(event_id: u64, principal: Principal, profile: ParticipantProfile) {
+    PROFILES.with(|profiles| profiles.borrow_mut().insert(key(event_id, principal), profile));
+}
+
+pub fn remove(event_id: u64, address: &str) {
+    if let Ok(principal) = Principal::from_text(address) {
+        PROFILES.with(|profiles| profiles.borrow_mut().remove(&key(event_id, principal)));
+    }
+}
+
+pub fn len() -> u64 {
+    PROFILES.with(|profiles| profiles.borrow().len())
+}
+
+// Profiles of all events ordered by (event_id, principal), from the `skip`-th one
+pub fn all(skip: u64, take: u64) -> Vec<(u64, Principal, ParticipantProfile)> {
+    PROFILES.with(|profiles| {
+        profiles
+            .borrow()
+            .iter()
+            .skip(skip as usize)
+            .take(take as usize)
+            .map(|(key, profile)| (key.event_id, key.principal, profile))
+            .collect()
+    })
+}
+
+// Replace the caller's profile of an event they registered for
+#[ic_cdk::update]
+fn set_my_profile(event_id: u64, profile: ParticipantProfile) -> Result<(), EventError> {
+    if !profile.is_valid() {
+        return Err(EventError::InvalidProfile);
+    }
+    let caller = ic_cdk::caller();
+    let address = caller.to_text();
+    if participants::get(event_id, &address).is_none() && !waitlist::addresses(event_id).contains(&address) {
+        return Err(EventError::NotRegistered);
+    }
+    set(event_id, caller, profile);
+    Ok(())
+}
+
+#[ic_cdk::query]
+fn get_my_profile(event_id: u64) -> Option<ParticipantProfile> {
+    PROFILES.with(|profiles| profiles.borrow().get(&key(event_id, ic_cdk::caller())))
+}
+
+// Query the profiles of the participants of an event; only its organizer may
+#[ic_cdk::query]
+fn get_participant_profiles(event_id: u64) -> Result<Vec<(Principal, ParticipantProfile)>, EventError> {
+    let event = EVENTS_MAP
+        .with(|events_map| events_map.borrow().get(&event_id))
+        .ok_or(EventError::NoSuchEvent)?;
+    if event.organizer != ic_cdk::caller() {
+        return Err(EventError::Unauthorized);
+    }
+    Ok(PROFILES.with(|profiles| {
+        profiles
+            .borrow()
+            .range(key(event_id, Principal::management_canister())..)
+            .take_while(|(key, _)| key.event_id == event_id)
+            .map(|(key, profile)| (key.principal, profile))
+            .collect()
+    }))
+}
@@ -0,0 +1,149 @@
+use candid::{CandidType, Decode, Deserialize, Encode};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::time::Duration;
+use std::{borrow::Cow, cell::RefCell};
+
+use crate::dates::NANOS_PER_SECOND;
+use crate::types::{Event, EventError};
+use crate::{apply_rsvp, notifications, require_host, Memory, EVENTS_MAP, MEMORY_MANAGER};
+
+// How long a participant promoted from the waitlist has to confirm their spot
+const CONFIRMATION_HOURS: u64 = 12;
+
+#[derive(CandidType, Deserialize, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct PromotionKey {
+    event_id: u64,
+    address: String,
+}
+
+impl Storable for PromotionKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for PromotionKey {
+    const MAX_SIZE: u32 = 120;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// Deadline of each promotion that was not confirmed yet
+thread_local! {
+    static PROMOTIONS: RefCell<StableBTreeMap<PromotionKey, u64, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(33))),
+        )
+    );
+}
+
+fn key(event_id: u64, address: &str) -> PromotionKey {
+    PromotionKey { event_id, address: address.to_string() }
+}
+
+fn schedule(event_id: u64, address: String, deadline: u64) {
+    let delay = deadline.saturating_sub(ic_cdk::api::time());
+    ic_cdk_timers::set_timer(Duration::from_nanos(delay), move || expire(event_id, address, deadline));
+}
+
+// Called when a participant got a spot from the waitlist. They must confirm it before
+// the deadline, or at the latest when the event starts, or it goes to the next in line.
+pub fn offer(event_id: u64, event: &Event, address: String) {
+    let now = ic_cdk::api::time();
+    let deadline = (now + CONFIRMATION_HOURS * 60 * 60 * NANOS_PER_SECOND).min(event.start_time);
+    if deadline <= now {
+        notifications::push(&address, event_id, format!("You got a spot for \"{}\"", event.name));
+        return;
+    }
+    notifications::push(
+        &address,
+        event_id,
+        format!("You got a spot for \"{}\", please confirm it", event.name),
+    );
+    PROMOTIONS.with(|promotions| promotions.borrow_mut().insert(key(event_id, &address), deadline));
+    schedule(event_id, address, deadline);
+}
+
+// Forget the promotion of a participant who is no longer going
+pub fn clear(event_id: u64, address: &str) {
+    PROMOTIONS.with(|promotions| promotions.borrow_mut().remove(&key(event_id, address)));
+}
+
+pub fn len() -> u64 {
+    PROMOTIONS.with(|promotions| promotions.borrow().len())
+}
+
+// Promotions ordered by (event_id, address) with their deadline, from the `skip`-th one
+pub fn all(skip: u64, take: u64) -> Vec<(u64, String, u64)> {
+    PROMOTIONS.with(|promotions| {
+        promotions
+            .borrow()
+            .iter()
+            .skip(skip as usize)
+            .take(take as usize)
+            .map(|(key, deadline)| (key.event_id, key.address, deadline))
+            .collect()
+    })
+}
+
+// Keep a promotion without scheduling its deadline, which reschedule_all does
+pub fn restore(event_id: u64, address: String, deadline: u64) {
+    PROMOTIONS.with(|promotions| promotions.borrow_mut().insert(PromotionKey { event_id, address }, deadline));
+}
+
+// Timers do not survive upgrades, so the deadlines are registered again
+pub fn reschedule_all() {
+    let pending: Vec<(PromotionKey, u64)> = PROMOTIONS.with(|promotions| promotions.borrow().iter().collect());
+    for (key, deadline) in pending {
+        schedule(key.event_id, key.address, deadline);
+    }
+}
+
+// Give an unconfirmed spot to the next participant on the waitlist
+fn expire(event_id: u64, address: String, deadline: u64) {
+    let pending = PROMOTIONS.with(|promotions| promotions.borrow().get(&key(event_id, &address)));
+    if pending != Some(deadline) {
+        return;
+    }
+    clear(event_id, &address);
+    EVENTS_MAP.with(|events_map_ref| {
+        let mut events_map = events_map_ref.borrow_mut();
+        let Some(event) = events_map.get(&event_id) else {
+            return;
+        };
+        // Spots of events that were closed in the meantime are kept
+        if apply_rsvp(&mut events_map, event_id, address.clone(), None).is_ok() {
+            notifications::push(
+                &address,
+                event_id,
+                format!("Your spot for \"{}\" expired", event.name),
+            );
+        }
+    });
+}
+
+// Confirm a spot received from the waitlist. Only the promoted participant or a host
+// may confirm it.
+#[ic_cdk::update]
+fn confirm_spot(event_id: u64, participant_address: String) -> Result<(), EventError> {
+    let event = EVENTS_MAP
+        .with(|events_map| events_map.borrow().get(&event_id))
+        .ok_or(EventError::NoSuchEvent)?;
+    if participant_address != ic_cdk::caller().to_text() {
+        require_host(&event)?;
+    }
+    PROMOTIONS
+        .with(|promotions| promotions.borrow_mut().remove(&key(event_id, &participant_address)))
+        .map(|_| ())
+        .ok_or(EventError::NoPendingPromotion)
+}
+
+// Query until when a participant promoted from the waitlist can confirm their spot
+#[ic_cdk::query]
+fn get_confirmation_deadline(event_id: u64, participant_address: String) -> Option<u64> {
+    PROMOTIONS.with(|promotions| promotions.borrow().get(&key(event_id, &participant_address)))
+}
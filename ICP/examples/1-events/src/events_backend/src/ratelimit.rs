@@ -0,0 +1,226 @@
+use candid::{CandidType, Decode, Deserialize, Encode};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, StableCell, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+use crate::dates::NANOS_PER_SECOND;
+use crate::types::EventError;
+use crate::{admin, Memory, MEMORY_MANAGER};
+
+// At most `max` calls per principal in any window of `window_secs`; 0 disables the limit
+#[derive(CandidType, Deserialize, Clone, Copy)]
+pub struct RateLimit {
+    pub max: u32,
+    pub window_secs: u64,
+}
+
+#[derive(CandidType, Deserialize, Clone, Copy)]
+pub struct RateLimits {
+    pub create_event: RateLimit,
+    pub join: RateLimit,
+}
+
+impl Default for RateLimits {
+    fn default() -> Self {
+        RateLimits {
+            create_event: RateLimit { max: 10, window_secs: 60 * 60 },
+            join: RateLimit { max: 30, window_secs: 60 },
+        }
+    }
+}
+
+impl Storable for RateLimits {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+// The rate limited operations
+#[derive(CandidType, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Action {
+    CreateEvent,
+    Join,
+}
+
+#[derive(CandidType, Deserialize, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct CounterKey {
+    principal: String,
+    action: Action,
+}
+
+impl Storable for CounterKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for CounterKey {
+    const MAX_SIZE: u32 = 120;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// A sliding window counter: the calls of the current fixed window, plus those of the
+// previous one weighted by how much of it still overlaps the sliding window
+#[derive(CandidType, Deserialize, Clone, Default)]
+struct Counter {
+    window_start: u64,
+    count: u32,
+    previous_count: u32,
+}
+
+impl Storable for Counter {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for Counter {
+    const MAX_SIZE: u32 = 60;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+impl Counter {
+    // Move the counter to the fixed window containing `now`
+    fn advance(&mut self, now: u64, window: u64) {
+        let window_start = now - now % window;
+        if window_start == self.window_start {
+            return;
+        }
+        // The previous window only counts if it is the one right before
+        let follows = window_start.checked_sub(self.window_start) == Some(window);
+        self.previous_count = if follows { self.count } else { 0 };
+        self.count = 0;
+        self.window_start = window_start;
+    }
+
+    // Estimated number of calls in the sliding window ending at `now`
+    fn estimate(&self, now: u64, window: u64) -> u64 {
+        let elapsed = now - self.window_start;
+        let previous_weight = window - elapsed;
+        // In u128, as windows of up to u64::MAX nanoseconds would overflow the product
+        (self.previous_count as u128 * previous_weight as u128 / window as u128) as u64 + self.count as u64
+    }
+}
+
+thread_local! {
+    static RATE_LIMITS: RefCell<StableCell<RateLimits, Memory>> = RefCell::new(
+        StableCell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(17))),
+            RateLimits::default(),
+        ).expect("failed to initialize the rate limits cell")
+    );
+
+    static COUNTERS: RefCell<StableBTreeMap<CounterKey, Counter, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(18))),
+        )
+    );
+}
+
+fn limit_of(action: Action) -> RateLimit {
+    let limits = RATE_LIMITS.with(|limits| *limits.borrow().get());
+    match action {
+        Action::CreateEvent => limits.create_event,
+        Action::Join => limits.join,
+    }
+}
+
+// Count a call of the caller, or fail with RateLimited if they reached the limit.
+// The admin is not limited.
+pub fn check(action: Action) -> Result<(), EventError> {
+    check_many(action, 1)
+}
+
+// Count `calls` calls of the caller at once, e.g. one per event of a recurring series.
+// Either all of them fit in the limit and are counted, or none is.
+pub fn check_many(action: Action, calls: u32) -> Result<(), EventError> {
+    let limit = limit_of(action);
+    let caller = ic_cdk::caller();
+    if limit.max == 0 || limit.window_secs == 0 || admin::get() == Some(caller) {
+        return Ok(());
+    }
+
+    let now = ic_cdk::api::time();
+    let window = limit.window_secs.saturating_mul(NANOS_PER_SECOND);
+    let key = CounterKey { principal: caller.to_text(), action };
+    COUNTERS.with(|counters| {
+        let mut counters = counters.borrow_mut();
+        let mut counter = counters.get(&key).unwrap_or_default();
+        counter.advance(now, window);
+        if counter.estimate(now, window) + calls as u64 > limit.max as u64 {
+            return Err(EventError::RateLimited);
+        }
+        counter.count += calls;
+        counters.insert(key, counter);
+        Ok(())
+    })
+}
+
+#[ic_cdk::update]
+fn set_rate_limits(limits: RateLimits) -> Result<(), EventError> {
+    admin::require_admin()?;
+    RATE_LIMITS.with(|cell| {
+        cell.borrow_mut()
+            .set(limits)
+            .expect("failed to store the rate limits")
+    });
+    Ok(())
+}
+
+#[ic_cdk::query]
+fn get_rate_limits() -> RateLimits {
+    RATE_LIMITS.with(|limits| *limits.borrow().get())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WINDOW: u64 = 100;
+
+    #[test]
+    fn calls_of_the_previous_window_count_by_their_overlap() {
+        let mut counter = Counter::default();
+        counter.advance(1_000, WINDOW);
+        counter.count = 10;
+
+        // A quarter into the next window, three quarters of the previous one still count
+        counter.advance(1_125, WINDOW);
+        assert_eq!((counter.previous_count, counter.count), (10, 0));
+        assert_eq!(counter.estimate(1_125, WINDOW), 7);
+        counter.count = 2;
+        assert_eq!(counter.estimate(1_175, WINDOW), 4);
+    }
+
+    #[test]
+    fn windows_long_past_are_forgotten() {
+        let mut counter = Counter::default();
+        counter.advance(1_000, WINDOW);
+        counter.count = 10;
+        counter.advance(1_250, WINDOW);
+        assert_eq!((counter.previous_count, counter.count), (0, 0));
+        assert_eq!(counter.estimate(1_250, WINDOW), 0);
+    }
+
+    #[test]
+    fn windows_of_any_length_are_counted_without_overflow() {
+        let window = u64::MAX.saturating_mul(NANOS_PER_SECOND);
+        let mut counter = Counter::default();
+        counter.advance(1_000, window);
+        counter.previous_count = u32::MAX;
+        counter.count = 1;
+        assert_eq!(counter.estimate(1_000, window), u32::MAX as u64);
+    }
+}
@@ -0,0 +1,62 @@
+use candid::{CandidType, Deserialize};
+
+use crate::dates::NANOS_PER_DAY;
+
+// Upper bound on how many occurrences a single rule may materialize
+pub const MAX_OCCURRENCES: u32 = 52;
+
+#[derive(CandidType, Deserialize, Clone, Copy)]
+pub enum Frequency {
+    Daily,
+    Weekly,
+}
+
+// Describes how often a recurring event repeats and how many occurrences it has
+#[derive(CandidType, Deserialize, Clone)]
+pub struct RecurrenceRule {
+    pub frequency: Frequency,
+    pub interval: u32, // e.g. 2 with Weekly means every other week
+    pub count: u32,    // total number of occurrences, including the first one
+}
+
+impl RecurrenceRule {
+    fn step(&self) -> u64 {
+        let days = match self.frequency {
+            Frequency::Daily => 1,
+            Frequency::Weekly => 7,
+        };
+        days * self.interval as u64 * NANOS_PER_DAY
+    }
+
+    // Offsets (in ns) of all occurrences from the first one, starting with 0
+    pub fn occurrence_offsets(&self) -> Option<Vec<u64>> {
+        if self.interval == 0 || self.count == 0 || self.count > MAX_OCCURRENCES {
+            return None;
+        }
+        Some((0..self.count as u64).map(|i| i * self.step()).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(frequency: Frequency, interval: u32, count: u32) -> RecurrenceRule {
+        RecurrenceRule { frequency, interval, count }
+    }
+
+    #[test]
+    fn occurrences_are_spaced_by_the_interval() {
+        let offsets = rule(Frequency::Weekly, 2, 3).occurrence_offsets().unwrap();
+        assert_eq!(offsets, vec![0, 14 * NANOS_PER_DAY, 28 * NANOS_PER_DAY]);
+        assert_eq!(rule(Frequency::Daily, 1, 1).occurrence_offsets(), Some(vec![0]));
+    }
+
+    #[test]
+    fn empty_or_unbounded_rules_are_rejected() {
+        assert_eq!(rule(Frequency::Daily, 0, 3).occurrence_offsets(), None);
+        assert_eq!(rule(Frequency::Daily, 1, 0).occurrence_offsets(), None);
+        assert_eq!(rule(Frequency::Daily, 1, MAX_OCCURRENCES + 1).occurrence_offsets(), None);
+        assert!(rule(Frequency::Daily, 1, MAX_OCCURRENCES).occurrence_offsets().is_some());
+    }
+}
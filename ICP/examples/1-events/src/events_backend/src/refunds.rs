@@ -0,0 +1,457 @@
+use candid::{CandidType, Decode, Deserialize, Encode, Nat, Principal};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::time::Duration;
+use std::{borrow::Cow, cell::Cell, cell::RefCell};
+
+use crate::dates::NANOS_PER_SECOND;
+use crate::payments::{self, Payment, RefundError};
+use crate::types::EventError;
+use crate::{admin, notifications, require_host, Memory, EVENTS_MAP, MEMORY_MANAGER};
+
+// A refund is given up after this many failed ledger calls, until the admin retries it
+const MAX_REFUND_ATTEMPTS: u32 = 8;
+const BASE_RETRY_DELAY_SECS: u64 = 60;
+const MAX_RETRY_DELAY_SECS: u64 = 6 * 60 * 60;
+// Ledgers deduplicate transfers created within the last day, and reject older ones
+const LEDGER_DEDUP_WINDOW_SECS: u64 = 24 * 60 * 60;
+
+// Refunds are keyed by the payment they send back, so every payment of a payer is
+// refunded on its own
+#[derive(CandidType, Deserialize, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct RefundKey {
+    event_id: u64,
+    address: String,
+    block_index: Nat,
+}
+
+impl RefundKey {
+    fn of(event_id: u64, address: String, refund: &Refund) -> Self {
+        RefundKey { event_id, address, block_index: refund.payment.block_index.clone() }
+    }
+
+    // The first key of an event
+    fn start(event_id: u64) -> Self {
+        RefundKey { event_id, address: String::new(), block_index: Nat::from(0u64) }
+    }
+}
+
+impl Storable for RefundKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for RefundKey {
+    const MAX_SIZE: u32 = 180;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// Refunds were keyed by (event_id, address) before, which kept one per payer
+#[derive(CandidType, Deserialize, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct LegacyRefundKey {
+    event_id: u64,
+    address: String,
+}
+
+impl Storable for LegacyRefundKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for LegacyRefundKey {
+    const MAX_SIZE: u32 = 160;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// A payment to send back
+#[derive(CandidType, Deserialize, Clone)]
+pub struct Refund {
+    pub payment: Payment,
+    // What is sent back when less than the payment, after a refund fee. None refunds
+    // the payment in full.
+    pub amount: Option<u64>,
+    pub attempts: u32,
+    // None once the refund was given up
+    pub next_attempt: Option<u64>,
+    // The created_at_time of every ledger call for the refund, so the ledger deduplicates
+    // them. Set on the first attempt for refunds queued before it was kept.
+    pub created_at: Option<u64>,
+    // Some(true) once a ledger call for the refund got no reply, so the refund may have
+    // been sent. It is only sent again with a new created_at once the admin checked it was not.
+    pub unconfirmed: Option<bool>,
+}
+
+impl Refund {
+    fn amount(&self) -> u64 {
+        self.amount.unwrap_or(self.payment.amount)
+    }
+}
+
+impl Storable for Refund {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for Refund {
+    const MAX_SIZE: u32 = 150;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct PendingRefund {
+    pub address: String,
+    pub amount: u64,
+    pub attempts: u32,
+    pub next_attempt: Option<u64>,
+    pub block_index: Nat,
+    pub unconfirmed: bool,
+}
+
+thread_local! {
+    static LEGACY_REFUND_QUEUE: RefCell<StableBTreeMap<LegacyRefundKey, Refund, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(34))),
+        )
+    );
+
+    static REFUND_QUEUE: RefCell<StableBTreeMap<RefundKey, Refund, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(55))),
+        )
+    );
+
+    // When the queue is processed next, if a timer is set
+    static NEXT_RUN: Cell<Option<u64>> = const { Cell::new(None) };
+}
+
+fn retry_delay(attempts: u32) -> u64 {
+    let seconds = BASE_RETRY_DELAY_SECS
+        .checked_shl(attempts)
+        .unwrap_or(MAX_RETRY_DELAY_SECS)
+        .min(MAX_RETRY_DELAY_SECS);
+    seconds * NANOS_PER_SECOND
+}
+
+// Make sure the queue is processed at `at` at the latest
+fn schedule(at: u64) {
+    if NEXT_RUN.with(|next| next.get()).is_some_and(|next| next <= at) {
+        return;
+    }
+    NEXT_RUN.with(|next| next.set(Some(at)));
+    let delay = at.saturating_sub(ic_cdk::api::time());
+    ic_cdk_timers::set_timer(Duration::from_nanos(delay), || {
+        NEXT_RUN.with(|next| next.set(None));
+        ic_cdk::spawn(process());
+    });
+}
+
+fn schedule_next() {
+    let next = REFUND_QUEUE.with(|queue| {
+        queue.borrow().iter().filter_map(|(_, refund)| refund.next_attempt).min()
+    });
+    if let Some(next) = next {
+        schedule(next);
+    }
+}
+
+// Send the refunds that are due, one ledger call at a time
+async fn process() {
+    let now = ic_cdk::api::time();
+    let due: Vec<(RefundKey, Refund)> = REFUND_QUEUE.with(|queue| {
+        queue
+            .borrow()
+            .iter()
+            .filter(|(_, refund)| refund.next_attempt.is_some_and(|at| at <= now))
+            .collect()
+    });
+
+    for (key, mut refund) in due {
+        // Another run leaves the refund alone while its ledger call is in flight. Until the
+        // call replies the refund may have been sent, which stays recorded if this traps.
+        refund.next_attempt = Some(ic_cdk::api::time() + retry_delay(refund.attempts));
+        let created_at = *refund.created_at.get_or_insert(now);
+        let unconfirmed = refund.unconfirmed;
+        refund.unconfirmed = Some(true);
+        REFUND_QUEUE.with(|queue| queue.borrow_mut().insert(key.clone(), refund.clone()));
+
+        let amount = refund.amount();
+        let result = match Principal::from_text(&key.address) {
+            Ok(to) => payments::refund(key.event_id, &refund.payment, amount, to, created_at).await,
+            Err(_) => Err(RefundError::NotSent),
+        };
+        match result {
+            Ok(sent) => {
+                // Only this payment's refund is removed, refunds of other payments of the
+                // payer queued meanwhile are kept
+                REFUND_QUEUE.with(|queue| queue.borrow_mut().remove(&key));
+                notifications::push(
+                    &key.address,
+                    key.event_id,
+                    format!("{} of your payment of {} was refunded after the ledger fee", sent, refund.payment.amount),
+                );
+            }
+            // Nothing can be sent, so the refund stays queued without further attempts, for
+            // the admin to see and drop with resolve_refund
+            Err(RefundError::BelowFee { fee }) => {
+                refund.unconfirmed = unconfirmed;
+                refund.next_attempt = None;
+                REFUND_QUEUE.with(|queue| queue.borrow_mut().insert(key.clone(), refund));
+                notifications::push(
+                    &key.address,
+                    key.event_id,
+                    format!("Your refund of {} was not sent as it does not cover the ledger fee of {}", amount, fee),
+                );
+            }
+            Err(error) => {
+                if let RefundError::NotSent = error {
+                    refund.unconfirmed = unconfirmed;
+                }
+                refund.attempts += 1;
+                refund.next_attempt = (refund.attempts < MAX_REFUND_ATTEMPTS)
+                    .then(|| ic_cdk::api::time() + retry_delay(refund.attempts));
+                REFUND_QUEUE.with(|queue| queue.borrow_mut().insert(key, refund));
+            }
+        }
+    }
+    schedule_next();
+}
+
+fn enqueue(event_id: u64, address: String, payment: Payment, amount: Option<u64>, now: u64) {
+    let refund = Refund {
+        payment,
+        amount,
+        attempts: 0,
+        next_attempt: Some(now),
+        created_at: Some(now),
+        unconfirmed: None,
+    };
+    REFUND_QUEUE.with(|queue| queue.borrow_mut().insert(RefundKey::of(event_id, address, &refund), refund));
+}
+
+// Queue a refund of one payment minus `fee`. Every attempt to send it carries the same
+// created_at_time, so the ledger never sends it twice.
+pub fn refund(event_id: u64, address: String, payment: Payment, fee: u64) {
+    let amount = payment.amount.saturating_sub(fee);
+    if amount == 0 {
+        return;
+    }
+    let now = ic_cdk::api::time();
+    enqueue(event_id, address, payment, Some(amount), now);
+    schedule(now);
+}
+
+// Queue a full refund of every payment of an event, returns the addresses refunded
+pub fn refund_all(event_id: u64) -> Vec<String> {
+    let now = ic_cdk::api::time();
+    let addresses: Vec<String> = payments::of_event(event_id)
+        .into_iter()
+        .map(|(address, _)| address)
+        .collect();
+    for address in &addresses {
+        let Some(payment) = payments::take(event_id, address) else {
+            continue;
+        };
+        enqueue(event_id, address.clone(), payment, None, now);
+    }
+    if !addresses.is_empty() {
+        schedule(now);
+    }
+    addresses
+}
+
+// Timers do not survive upgrades, so processing is scheduled again
+pub fn reschedule() {
+    schedule_next();
+}
+
+pub fn len() -> u64 {
+    REFUND_QUEUE.with(|queue| queue.borrow().len())
+}
+
+// Queued refunds ordered by (event_id, address, payment block), from the `skip`-th one
+pub fn all(skip: u64, take: u64) -> Vec<(u64, String, Refund)> {
+    REFUND_QUEUE.with(|queue| {
+        queue
+            .borrow()
+            .iter()
+            .skip(skip as usize)
+            .take(take as usize)
+            .map(|(key, refund)| (key.event_id, key.address, refund))
+            .collect()
+    })
+}
+
+// Queue a refund without scheduling it, which reschedule does
+pub fn restore(event_id: u64, address: String, refund: Refund) {
+    REFUND_QUEUE.with(|queue| queue.borrow_mut().insert(RefundKey::of(event_id, address, &refund), refund));
+}
+
+// Move the refunds queued by (event_id, address) into the queue keyed by payment
+pub fn migrate_legacy_queue() {
+    let legacy: Vec<(LegacyRefundKey, Refund)> =
+        LEGACY_REFUND_QUEUE.with(|legacy| legacy.borrow().iter().collect());
+    for (key, refund) in legacy {
+        restore(key.event_id, key.address.clone(), refund);
+        LEGACY_REFUND_QUEUE.with(|legacy| legacy.borrow_mut().remove(&key));
+    }
+}
+
+// Schedule a given up refund again, returns false if it may have been sent already and
+// would go out as a new transfer
+fn retry(refund: &mut Refund, now: u64) -> bool {
+    let expired = |created_at: u64| now.saturating_sub(created_at) >= LEDGER_DEDUP_WINDOW_SECS * NANOS_PER_SECOND;
+    if refund.created_at.is_none_or(expired) {
+        if refund.unconfirmed == Some(true) {
+            return false;
+        }
+        refund.created_at = Some(now);
+    }
+    refund.attempts = 0;
+    refund.next_attempt = Some(now);
+    true
+}
+
+// Retry the refunds that were given up. Refunds first sent more than a day ago are
+// past the ledger's deduplication window, so they are sent as new transfers, unless
+// they may have been sent already; those are left for the admin to check on the ledger.
+#[ic_cdk::update]
+fn retry_refunds() -> Result<(), EventError> {
+    admin::require_admin()?;
+    let now = ic_cdk::api::time();
+    REFUND_QUEUE.with(|queue| {
+        let mut queue = queue.borrow_mut();
+        let given_up: Vec<(RefundKey, Refund)> =
+            queue.iter().filter(|(_, refund)| refund.next_attempt.is_none()).collect();
+        for (key, mut refund) in given_up {
+            if retry(&mut refund, now) {
+                queue.insert(key, refund);
+            }
+        }
+    });
+    schedule_next();
+    Ok(())
+}
+
+// Settle a refund that may have been sent, once the admin checked the ledger. A refund
+// that was sent is dropped, one that was not is sent again by the next retry_refunds.
+#[ic_cdk::update]
+fn resolve_refund(event_id: u64, address: String, block_index: Nat, sent: bool) -> Result<(), EventError> {
+    admin::require_admin()?;
+    let key = RefundKey { event_id, address, block_index };
+    REFUND_QUEUE.with(|queue| {
+        let mut queue = queue.borrow_mut();
+        let mut refund = queue.get(&key).ok_or(EventError::NoSuchRefund)?;
+        if sent {
+            queue.remove(&key);
+        } else {
+            refund.unconfirmed = None;
+            queue.insert(key, refund);
+        }
+        Ok(())
+    })
+}
+
+// Query the refunds of an event that were not sent yet
+#[ic_cdk::query]
+fn get_pending_refunds(event_id: u64) -> Result<Vec<PendingRefund>, EventError> {
+    let event = EVENTS_MAP
+        .with(|events_map| events_map.borrow().get(&event_id))
+        .ok_or(EventError::NoSuchEvent)?;
+    require_host(&event)?;
+    Ok(REFUND_QUEUE.with(|queue| {
+        queue
+            .borrow()
+            .range(RefundKey::start(event_id)..RefundKey::start(event_id + 1))
+            .map(|(key, refund)| PendingRefund {
+                address: key.address,
+                amount: refund.amount(),
+                attempts: refund.attempts,
+                next_attempt: refund.next_attempt,
+                block_index: key.block_index,
+                unconfirmed: refund.unconfirmed == Some(true),
+            })
+            .collect()
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retries_back_off_up_to_the_maximum_delay() {
+        assert_eq!(retry_delay(0), BASE_RETRY_DELAY_SECS * NANOS_PER_SECOND);
+        assert_eq!(retry_delay(3), 8 * BASE_RETRY_DELAY_SECS * NANOS_PER_SECOND);
+        assert_eq!(retry_delay(20), MAX_RETRY_DELAY_SECS * NANOS_PER_SECOND);
+        assert_eq!(retry_delay(u32::MAX), MAX_RETRY_DELAY_SECS * NANOS_PER_SECOND);
+    }
+
+    fn payment(block_index: u64) -> Payment {
+        Payment { amount: 100, ledger: Principal::anonymous(), block_index: Nat::from(block_index) }
+    }
+
+    #[test]
+    fn every_payment_of_a_payer_is_refunded() {
+        enqueue(1, "aaaaa-aa".to_string(), payment(7), None, 5);
+        enqueue(1, "aaaaa-aa".to_string(), payment(9), Some(90), 6);
+        let queued = all(0, 10);
+        assert_eq!(queued.len(), 2);
+        assert_eq!(queued[0].2.payment.block_index, Nat::from(7u64));
+        assert_eq!(queued[1].2.amount(), 90);
+    }
+
+    #[test]
+    fn legacy_refunds_are_keyed_by_their_payment() {
+        let refund = Refund {
+            payment: payment(7),
+            amount: None,
+            attempts: 1,
+            next_attempt: None,
+            created_at: Some(5),
+            unconfirmed: None,
+        };
+        LEGACY_REFUND_QUEUE.with(|legacy| {
+            legacy.borrow_mut().insert(LegacyRefundKey { event_id: 1, address: "aaaaa-aa".to_string() }, refund)
+        });
+        enqueue(1, "aaaaa-aa".to_string(), payment(9), None, 6);
+        migrate_legacy_queue();
+        assert_eq!(len(), 2);
+        assert!(LEGACY_REFUND_QUEUE.with(|legacy| legacy.borrow().is_empty()));
+    }
+
+    #[test]
+    fn unconfirmed_refunds_are_not_sent_again_past_the_dedup_window() {
+        let window = LEDGER_DEDUP_WINDOW_SECS * NANOS_PER_SECOND;
+        let mut refund = Refund {
+            payment: payment(7),
+            amount: None,
+            attempts: MAX_REFUND_ATTEMPTS,
+            next_attempt: None,
+            created_at: Some(5),
+            unconfirmed: Some(true),
+        };
+        assert!(retry(&mut refund.clone(), 5 + window - 1));
+        assert!(!retry(&mut refund, 5 + window));
+        assert_eq!(refund.created_at, Some(5));
+
+        refund.unconfirmed = None;
+        assert!(retry(&mut refund, 5 + window));
+        assert_eq!(refund.created_at, Some(5 + window));
+        assert_eq!(refund.next_attempt, Some(5 + window));
+    }
+}
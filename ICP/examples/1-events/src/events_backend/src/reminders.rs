@@ -0,0 +1,42 @@
+use std::time::Duration;
+
+use crate::dates::NANOS_PER_SECOND;
+use crate::types::RsvpStatus;
+use crate::{notifications, participants, EVENTS_MAP};
+
+// How long before the start of an event each reminder is sent, with its label
+const REMINDERS: [(u64, &str); 2] = [(24 * 60 * 60, "24 hours"), (60 * 60, "1 hour")];
+
+// Schedule the reminders of an event that are still in the future.
+// Timers live on the heap, so this is also called for every upcoming event after an upgrade.
+pub fn schedule(event_id: u64, start_time: u64) {
+    let now = ic_cdk::api::time();
+    for (seconds_before, label) in REMINDERS {
+        let fire_at = start_time.saturating_sub(seconds_before * NANOS_PER_SECOND);
+        if fire_at <= now {
+            continue;
+        }
+        ic_cdk_timers::set_timer(Duration::from_nanos(fire_at - now), move || {
+            send_reminder(event_id, start_time, label)
+        });
+    }
+}
+
+// Push a reminder to every participant, unless the event is no longer open or was rescheduled
+fn send_reminder(event_id: u64, start_time: u64, label: &str) {
+    let Some(event) = EVENTS_MAP.with(|events_map| events_map.borrow().get(&event_id)) else {
+        return;
+    };
+    if !event.status.is_open() || event.start_time != start_time {
+        return;
+    }
+
+    let message = format!("\"{}\" starts in {}", event.name, label);
+    // Participants who declined are not reminded
+    for participant in participants::of_event(event_id) {
+        if participant.rsvp == RsvpStatus::Declined {
+            continue;
+        }
+        notifications::push(&participant.address, event_id, message.clone());
+    }
+}
@@ -0,0 +1,131 @@
+use candid::{CandidType, Decode, Deserialize, Encode};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+use crate::{Memory, MEMORY_MANAGER};
+
+// Longer terms are indexed by their first characters only
+const MAX_TERM_LEN: usize = 32;
+
+// Index entry linking a suffix of a word of an event name to the event. A word
+// containing a query is a word with a suffix starting with it, so substring and
+// prefix matches are both a range over the suffixes starting with the query.
+#[derive(CandidType, Deserialize, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct SearchKey {
+    term: String,
+    event_id: u64,
+}
+
+impl Storable for SearchKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for SearchKey {
+    const MAX_SIZE: u32 = 160;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+thread_local! {
+    static SEARCH_INDEX: RefCell<StableBTreeMap<SearchKey, (), Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(11))),
+        )
+    );
+}
+
+fn truncate(term: &str) -> String {
+    term.chars().take(MAX_TERM_LEN).collect()
+}
+
+// Lowercase words of a name or query
+fn words(text: &str) -> Vec<String> {
+    text.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+// Every suffix of every word of a name, without duplicates
+fn terms(name: &str) -> Vec<String> {
+    let mut terms: Vec<String> = Vec::new();
+    for word in words(name) {
+        for (index, _) in word.char_indices() {
+            let term = truncate(&word[index..]);
+            if !terms.contains(&term) {
+                terms.push(term);
+            }
+        }
+    }
+    terms
+}
+
+pub fn index_event(event_id: u64, name: &str) {
+    SEARCH_INDEX.with(|index| {
+        let mut index = index.borrow_mut();
+        for term in terms(name) {
+            index.insert(SearchKey { term, event_id }, ());
+        }
+    });
+}
+
+pub fn unindex_event(event_id: u64, name: &str) {
+    SEARCH_INDEX.with(|index| {
+        let mut index = index.borrow_mut();
+        for term in terms(name) {
+            index.remove(&SearchKey { term, event_id });
+        }
+    });
+}
+
+pub fn is_empty() -> bool {
+    SEARCH_INDEX.with(|index| index.borrow().is_empty())
+}
+
+// Ids of the events with a word containing the given term, in ascending order
+fn events_containing(term: &str) -> Vec<u64> {
+    let term = truncate(term);
+    let mut event_ids: Vec<u64> = SEARCH_INDEX.with(|index| {
+        index
+            .borrow()
+            .range(SearchKey { term: term.clone(), event_id: 0 }..)
+            .take_while(|(key, _)| key.term.starts_with(&term))
+            .map(|(key, _)| key.event_id)
+            .collect()
+    });
+    event_ids.sort_unstable();
+    event_ids.dedup();
+    event_ids
+}
+
+// Ids of the events whose name contains every word of the query, one page at a time.
+// `name_of` is used to check the full words, since the index only holds truncated terms.
+pub fn search(query: &str, page: u64, page_size: u64, name_of: impl Fn(u64) -> Option<String>) -> Vec<u64> {
+    let query_words = words(query);
+    // The longest word is the most selective one to read from the index
+    let Some(longest) = query_words.iter().max_by_key(|word| word.len()) else {
+        return Vec::new();
+    };
+
+    events_containing(longest)
+        .into_iter()
+        .filter(|event_id| {
+            let Some(name) = name_of(*event_id) else {
+                return false;
+            };
+            let name_words = words(&name);
+            query_words
+                .iter()
+                .all(|query_word| name_words.iter().any(|name_word| name_word.contains(query_word.as_str())))
+        })
+        .skip((page * page_size) as usize)
+        .take(page_size as usize)
+        .collect()
+}
@@ -0,0 +1,163 @@
+use candid::{CandidType, Deserialize};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+
+use crate::types::{CreateEventPayload, Event, EventError};
+use crate::{
+    add_participant, insert_event, join_event, ratelimit, require_host, Memory, EVENTS_MAP, MEMORY_MANAGER,
+};
+
+// Sessions can be nested this many levels below the root event of a series
+const MAX_DEPTH: usize = 3;
+const MAX_SESSIONS: usize = 50;
+
+// An event with its sessions, recursively
+#[derive(CandidType, Deserialize)]
+pub struct SeriesNode {
+    pub event_id: u64,
+    pub event: Event,
+    pub sessions: Vec<SeriesNode>,
+}
+
+// Sessions keyed by (parent_id, event_id)
+thread_local! {
+    static SESSIONS: RefCell<StableBTreeMap<(u64, u64), (), Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(38))),
+        )
+    );
+}
+
+pub fn index_event(event_id: u64, parent_id: u64) {
+    SESSIONS.with(|sessions| sessions.borrow_mut().insert((parent_id, event_id), ()));
+}
+
+pub fn len() -> u64 {
+    SESSIONS.with(|sessions| sessions.borrow().len())
+}
+
+// (parent_id, event_id) of every session, from the `skip`-th one
+pub fn all(skip: u64, take: u64) -> Vec<(u64, u64)> {
+    SESSIONS.with(|sessions| {
+        sessions
+            .borrow()
+            .iter()
+            .skip(skip as usize)
+            .take(take as usize)
+            .map(|(key, _)| key)
+            .collect()
+    })
+}
+
+// Ids of the direct sessions of an event, in the order they were created
+fn sessions_of(parent_id: u64) -> Vec<u64> {
+    SESSIONS.with(|sessions| {
+        sessions
+            .borrow()
+            .range((parent_id, 0)..=(parent_id, u64::MAX))
+            .map(|((_, event_id), _)| event_id)
+            .collect()
+    })
+}
+
+// Ids of every session below an event, parents before their sessions
+fn descendants_of(event_id: u64) -> Vec<u64> {
+    let mut descendants = Vec::new();
+    let mut pending = vec![event_id];
+    while let Some(parent_id) = pending.pop() {
+        for session_id in sessions_of(parent_id) {
+            descendants.push(session_id);
+            pending.push(session_id);
+        }
+    }
+    descendants
+}
+
+// Create a session of an event, e.g. a talk of a conference. Only the hosts of the
+// parent event can add sessions, and a session must take place within its parent.
+#[ic_cdk::update]
+fn create_session(parent_id: u64, payload: CreateEventPayload) -> Result<u64, EventError> {
+    ratelimit::check(ratelimit::Action::CreateEvent)?;
+    EVENTS_MAP.with(|events_map_ref| {
+        let mut events_map = events_map_ref.borrow_mut();
+        let parent = events_map.get(&parent_id).ok_or(EventError::NoSuchEvent)?;
+        require_host(&parent)?;
+        if payload.start_time < parent.start_time || payload.end_time > parent.end_time {
+            return Err(EventError::InvalidTime);
+        }
+
+        let mut depth = 1;
+        let mut ancestor = parent.parent_id;
+        while let Some(ancestor_id) = ancestor {
+            depth += 1;
+            ancestor = events_map.get(&ancestor_id).and_then(|event| event.parent_id);
+        }
+        if depth > MAX_DEPTH {
+            return Err(EventError::InvalidParent);
+        }
+        if sessions_of(parent_id).len() >= MAX_SESSIONS {
+            return Err(EventError::TooManySessions);
+        }
+
+        insert_event(&mut events_map, payload, None, Some(parent_id))
+    })
+}
+
+fn build_tree(event_id: u64, event: Event) -> SeriesNode {
+    let sessions = sessions_of(event_id)
+        .into_iter()
+        .filter_map(|session_id| {
+            let session = EVENTS_MAP.with(|events_map| events_map.borrow().get(&session_id))?;
+            session.is_public().then(|| build_tree(session_id, session))
+        })
+        .collect();
+    SeriesNode { event_id, event, sessions }
+}
+
+// Query the whole series an event belongs to, from its root event down. Private sessions
+// are left out.
+#[ic_cdk::query]
+fn get_series(event_id: u64) -> Result<SeriesNode, EventError> {
+    let mut root_id = event_id;
+    let mut root = EVENTS_MAP
+        .with(|events_map| events_map.borrow().get(&event_id))
+        .ok_or(EventError::NoSuchEvent)?;
+    while let Some(parent_id) = root.parent_id {
+        let Some(parent) = EVENTS_MAP.with(|events_map| events_map.borrow().get(&parent_id)) else {
+            break;
+        };
+        root_id = parent_id;
+        root = parent;
+    }
+    Ok(build_tree(root_id, root))
+}
+
+// Join an event and every session below it that is open. Sessions with a ticket price
+// are skipped, as each needs its own payment, and so are private sessions the participant
+// joins for someone else. Returns the ids of the sessions joined.
+#[ic_cdk::update]
+async fn join_with_sessions(event_id: u64, participant_address: String) -> Result<Vec<u64>, EventError> {
+    match join_event(event_id, participant_address.clone(), None).await {
+        Ok(_) | Err(EventError::AlreadyJoined) => {}
+        Err(err) => return Err(err),
+    }
+
+    let is_caller = participant_address == ic_cdk::caller().to_text();
+    EVENTS_MAP.with(|events_map_ref| {
+        let mut events_map = events_map_ref.borrow_mut();
+        let mut joined = Vec::new();
+        for session_id in descendants_of(event_id) {
+            let Some(session) = events_map.get(&session_id) else {
+                continue;
+            };
+            if !session.status.is_open() || session.ticket_price.is_some() || (!session.is_public() && !is_caller) {
+                continue;
+            }
+            if add_participant(&mut events_map, session_id, participant_address.clone()).is_ok() {
+                joined.push(session_id);
+            }
+        }
+        Ok(joined)
+    })
+}
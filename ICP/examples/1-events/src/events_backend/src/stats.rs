@@ -0,0 +1,195 @@
+use candid::{CandidType, Decode, Deserialize, Encode};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, StableCell, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+use crate::types::{Event, RsvpStatus};
+use crate::{dates, participants, Memory, EVENTS_MAP, MEMORY_MANAGER};
+
+const TOP_EVENTS: usize = 10;
+
+// Totals updated on every write, so statistics never need a scan of the events map
+#[derive(CandidType, Deserialize, Clone, Copy, Default)]
+struct Totals {
+    events: u64,
+    // Sum of the Going participants of all events
+    joins: u64,
+}
+
+impl Storable for Totals {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+#[derive(CandidType, Deserialize, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct AddressKey {
+    address: String,
+}
+
+impl Storable for AddressKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for AddressKey {
+    const MAX_SIZE: u32 = 100;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct EventStats {
+    pub total_events: u64,
+    // Events that have not started yet, and those that have
+    pub upcoming_events: u64,
+    pub past_events: u64,
+    // Addresses going to at least one event
+    pub unique_participants: u64,
+    pub average_participants: f64,
+    // The public events with the most Going participants, most joined first
+    pub top_events: Vec<(u64, Event)>,
+}
+
+thread_local! {
+    static TOTALS: RefCell<StableCell<Totals, Memory>> = RefCell::new(
+        StableCell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(21))),
+            Totals::default(),
+        ).expect("failed to initialize the totals cell")
+    );
+
+    // Number of events each address is going to
+    static EVENTS_PER_ADDRESS: RefCell<StableBTreeMap<AddressKey, u32, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(22))),
+        )
+    );
+
+    // Events keyed by (u32::MAX - participant_count, event_id), so iterating it
+    // yields the most joined events first
+    static BY_PARTICIPANTS: RefCell<StableBTreeMap<(u32, u64), (), Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(23))),
+        )
+    );
+}
+
+fn update_totals(update: impl FnOnce(&mut Totals)) {
+    TOTALS.with(|totals| {
+        let mut totals = totals.borrow_mut();
+        let mut value = *totals.get();
+        update(&mut value);
+        totals.set(value).expect("failed to store the totals");
+    });
+}
+
+// Called with the previous and new value whenever an event is stored
+pub fn on_event_stored(event_id: u64, previous: Option<&Event>, event: &Event) {
+    let previous_count = previous.map(|previous| previous.participant_count).unwrap_or_default();
+    update_totals(|totals| {
+        if previous.is_none() {
+            totals.events += 1;
+        }
+        totals.joins = totals.joins - previous_count as u64 + event.participant_count as u64;
+    });
+    BY_PARTICIPANTS.with(|index| {
+        let mut index = index.borrow_mut();
+        if previous.is_some() {
+            index.remove(&(u32::MAX - previous_count, event_id));
+        }
+        index.insert((u32::MAX - event.participant_count, event_id), ());
+    });
+}
+
+// Called when an event is moved out of the events map
+pub fn on_event_removed(event_id: u64, event: &Event) {
+    update_totals(|totals| {
+        totals.events -= 1;
+        totals.joins -= event.participant_count as u64;
+    });
+    BY_PARTICIPANTS.with(|index| index.borrow_mut().remove(&(u32::MAX - event.participant_count, event_id)));
+}
+
+// Called with the previous and new RSVP whenever a participant is stored or removed
+pub fn on_rsvp_changed(address: &str, previous: Option<RsvpStatus>, next: Option<RsvpStatus>) {
+    let was_going = previous == Some(RsvpStatus::Going);
+    let is_going = next == Some(RsvpStatus::Going);
+    if was_going == is_going {
+        return;
+    }
+    let key = AddressKey { address: address.to_string() };
+    EVENTS_PER_ADDRESS.with(|counts| {
+        let mut counts = counts.borrow_mut();
+        let count = counts.get(&key).unwrap_or_default();
+        if is_going {
+            counts.insert(key, count + 1);
+        } else if count <= 1 {
+            counts.remove(&key);
+        } else {
+            counts.insert(key, count - 1);
+        }
+    });
+}
+
+// Run `f` over the index of all events, most Going participants first
+pub fn with_participants_index<R>(f: impl FnOnce(&StableBTreeMap<(u32, u64), (), Memory>) -> R) -> R {
+    BY_PARTICIPANTS.with(|index| f(&index.borrow()))
+}
+
+pub fn is_empty() -> bool {
+    TOTALS.with(|totals| totals.borrow().get().events == 0)
+}
+
+// Compute the statistics of events stored before they were tracked, in a single pass
+pub fn rebuild() {
+    EVENTS_MAP.with(|events_map| {
+        for (event_id, event) in events_map.borrow().iter() {
+            on_event_stored(event_id, None, &event);
+            for participant in participants::of_event(event_id) {
+                on_rsvp_changed(&participant.address, None, Some(participant.rsvp));
+            }
+        }
+    });
+}
+
+#[ic_cdk::query]
+fn get_event_stats() -> EventStats {
+    let totals = TOTALS.with(|totals| *totals.borrow().get());
+    let now = ic_cdk::api::time();
+    let upcoming_events = dates::count_starting_between(now + 1, u64::MAX);
+
+    let top_events = BY_PARTICIPANTS.with(|index| {
+        EVENTS_MAP.with(|events_map| {
+            let events = events_map.borrow();
+            index
+                .borrow()
+                .iter()
+                .filter_map(|((_, event_id), _)| events.get(&event_id).map(|event| (event_id, event)))
+                .filter(|(_, event)| event.is_public())
+                .take(TOP_EVENTS)
+                .collect()
+        })
+    });
+
+    EventStats {
+        total_events: totals.events,
+        upcoming_events,
+        past_events: totals.events.saturating_sub(upcoming_events),
+        unique_participants: EVENTS_PER_ADDRESS.with(|counts| counts.borrow().len()),
+        average_participants: if totals.events == 0 {
+            0.0
+        } else {
+            totals.joins as f64 / totals.events as f64
+        },
+        top_events,
+    }
+}
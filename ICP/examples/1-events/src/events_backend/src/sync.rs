@@ -0,0 +1,295 @@
+use ic_cdk::api::management_canister::http_request::{
+    http_request, CanisterHttpRequestArgument, HttpHeader, HttpMethod, TransformContext,
+};
+
+use candid::{CandidType, Decode, Deserialize, Encode};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, StableCell, Storable};
+use serde::Serialize;
+use std::{borrow::Cow, cell::RefCell};
+
+use crate::types::{Event, EventError};
+use crate::webhooks::transform_outcall_response;
+use crate::{admin, Memory, EVENTS_MAP, MEMORY_MANAGER};
+
+const MAX_URL_LEN: usize = 400;
+// Events pushed per call to push_events_to_api; the rest stay dirty for the next call
+const MAX_PUSHES_PER_CALL: usize = 20;
+const MAX_RESPONSE_BYTES: u64 = 4096;
+const MAX_ERROR_LEN: usize = 200;
+
+// The endpoint locally created events are POSTed to
+#[derive(CandidType, Deserialize, Clone, Default)]
+struct PushConfig {
+    url: Option<String>,
+}
+
+impl Storable for PushConfig {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+// Dirty events changed since their last successful push. An event changed while it is
+// being pushed is Dirty again once the push ends. Only Synced events are left out of the
+// next push, so a push cut short by an upgrade is sent again; the id of the payload
+// keeps that idempotent.
+#[derive(CandidType, Deserialize, Clone, Copy, PartialEq)]
+pub enum SyncState {
+    Dirty,
+    Pushing,
+    Synced,
+    // The last push failed; the event is pushed again by the next call
+    Failed,
+}
+
+#[derive(CandidType, Deserialize, Clone)]
+pub struct SyncStatus {
+    pub state: SyncState,
+    // Number of times the event was stored, and the number it had when it was last pushed
+    pub version: u64,
+    pub pushed_version: Option<u64>,
+    pub failed_attempts: u32,
+    pub last_error: Option<String>,
+    pub last_pushed_at: Option<u64>,
+}
+
+impl Storable for SyncStatus {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for SyncStatus {
+    const MAX_SIZE: u32 = 350;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+#[derive(CandidType, Deserialize, Default)]
+pub struct PushReport {
+    pub pushed: u32,
+    pub failed: u32,
+    // Dirty events left for the next call
+    pub remaining: u64,
+}
+
+// The JSON body of a push. `id` stays the same across pushes of an event, so the
+// endpoint can upsert on it.
+#[derive(Serialize)]
+struct PushedEvent<'a> {
+    id: String,
+    #[serde(flatten)]
+    event: &'a Event,
+}
+
+thread_local! {
+    static PUSH_CONFIG: RefCell<StableCell<PushConfig, Memory>> = RefCell::new(
+        StableCell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(43))),
+            PushConfig::default(),
+        ).expect("failed to initialize the push config cell")
+    );
+
+    static SYNC_STATUS: RefCell<StableBTreeMap<u64, SyncStatus, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(44))),
+        )
+    );
+
+    // Ids of the events that are not Synced, so a push does not scan the synced ones
+    static DIRTY: RefCell<StableBTreeMap<u64, (), Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(45))),
+        )
+    );
+}
+
+fn store(event_id: u64, status: SyncStatus) {
+    DIRTY.with(|dirty| {
+        let mut dirty = dirty.borrow_mut();
+        if status.state != SyncState::Synced {
+            dirty.insert(event_id, ());
+        } else {
+            dirty.remove(&event_id);
+        }
+    });
+    SYNC_STATUS.with(|statuses| statuses.borrow_mut().insert(event_id, status));
+}
+
+// Called whenever an event is stored. Only events created here are pushed; imported
+// ones belong to their source.
+pub fn on_event_stored(event_id: u64, event: &Event) {
+    if event.external_id.is_some() {
+        return;
+    }
+    let status = match SYNC_STATUS.with(|statuses| statuses.borrow().get(&event_id)) {
+        Some(status) => SyncStatus {
+            state: SyncState::Dirty,
+            version: status.version + 1,
+            ..status
+        },
+        None => SyncStatus {
+            state: SyncState::Dirty,
+            version: 1,
+            pushed_version: None,
+            failed_attempts: 0,
+            last_error: None,
+            last_pushed_at: None,
+        },
+    };
+    store(event_id, status);
+}
+
+// Called when an event is moved out of the events map. Its status is kept, and the event
+// is pushed again once it is stored again.
+pub fn on_event_removed(event_id: u64) {
+    DIRTY.with(|dirty| dirty.borrow_mut().remove(&event_id));
+}
+
+// Mark the events created before sync existed as dirty, once
+pub fn backfill() {
+    if SYNC_STATUS.with(|statuses| !statuses.borrow().is_empty()) {
+        return;
+    }
+    EVENTS_MAP.with(|events_map| {
+        for (event_id, event) in events_map.borrow().iter() {
+            on_event_stored(event_id, &event);
+        }
+    });
+}
+
+// POST an event, returns the error of a failed push
+async fn push(url: &str, event_id: u64, event: &Event) -> Result<(), String> {
+    let body = serde_json::to_vec(&PushedEvent {
+        id: format!("{}:{}", ic_cdk::id(), event_id),
+        event,
+    })
+    .unwrap();
+    let request = CanisterHttpRequestArgument {
+        url: url.to_string(),
+        method: HttpMethod::POST,
+        headers: vec![
+            HttpHeader { name: "Content-Type".to_string(), value: "application/json".to_string() },
+            HttpHeader { name: "Idempotency-Key".to_string(), value: format!("{}:{}", ic_cdk::id(), event_id) },
+        ],
+        body: Some(body),
+        max_response_bytes: Some(MAX_RESPONSE_BYTES),
+        transform: Some(TransformContext::new(transform_outcall_response, Vec::new())),
+    };
+    match http_request(request).await {
+        Ok((response,)) if response.status >= 200u32 && response.status < 300u32 => Ok(()),
+        Ok((response,)) => Err(format!("HTTP request failed with status code: {}", response.status)),
+        Err((code, message)) => Err(format!("Code: {:?}, Message: {}", code, message)),
+    }
+}
+
+// Set or clear the endpoint events are pushed to
+#[ic_cdk::update]
+fn set_push_endpoint(url: Option<String>) -> Result<(), EventError> {
+    admin::require_admin()?;
+    if url.as_ref().is_some_and(|url| !url.starts_with("https://") || url.len() > MAX_URL_LEN) {
+        return Err(EventError::InvalidUrl);
+    }
+    PUSH_CONFIG.with(|config| {
+        config
+            .borrow_mut()
+            .set(PushConfig { url })
+            .expect("failed to store the push config")
+    });
+    Ok(())
+}
+
+#[ic_cdk::query]
+pub(crate) fn get_push_endpoint() -> Option<String> {
+    PUSH_CONFIG.with(|config| config.borrow().get().url.clone())
+}
+
+pub fn restore_push_endpoint(url: String) {
+    PUSH_CONFIG.with(|config| {
+        config
+            .borrow_mut()
+            .set(PushConfig { url: Some(url) })
+            .expect("failed to store the push config")
+    });
+}
+
+pub fn statuses_len() -> u64 {
+    SYNC_STATUS.with(|statuses| statuses.borrow().len())
+}
+
+// Sync statuses ordered by event id, from the `skip`-th one
+pub fn all_statuses(skip: u64, take: u64) -> Vec<(u64, SyncStatus)> {
+    SYNC_STATUS.with(|statuses| statuses.borrow().iter().skip(skip as usize).take(take as usize).collect())
+}
+
+// Replaces the status an imported event got when it was stored
+pub fn restore_status(event_id: u64, status: SyncStatus) {
+    store(event_id, status);
+}
+
+// Push the events created or changed here since their last push to the push endpoint,
+// the counterpart of get_events_from_api
+#[ic_cdk::update]
+async fn push_events_to_api() -> Result<PushReport, EventError> {
+    admin::require_admin()?;
+    let url = PUSH_CONFIG
+        .with(|config| config.borrow().get().url.clone())
+        .ok_or(EventError::InvalidUrl)?;
+
+    let event_ids: Vec<u64> = DIRTY.with(|dirty| {
+        dirty.borrow().iter().take(MAX_PUSHES_PER_CALL).map(|(event_id, _)| event_id).collect()
+    });
+    let mut report = PushReport::default();
+    for event_id in event_ids {
+        let Some(status) = SYNC_STATUS.with(|statuses| statuses.borrow().get(&event_id)) else {
+            continue;
+        };
+        let Some(event) = EVENTS_MAP.with(|events_map| events_map.borrow().get(&event_id)) else {
+            continue;
+        };
+        let version = status.version;
+        store(event_id, SyncStatus { state: SyncState::Pushing, ..status });
+
+        let result = push(&url, event_id, &event).await;
+        let Some(mut status) = SYNC_STATUS.with(|statuses| statuses.borrow().get(&event_id)) else {
+            continue;
+        };
+        match result {
+            Ok(()) => {
+                report.pushed += 1;
+                status.pushed_version = Some(version);
+                status.failed_attempts = 0;
+                status.last_error = None;
+                status.last_pushed_at = Some(ic_cdk::api::time());
+                status.state = if status.version == version { SyncState::Synced } else { SyncState::Dirty };
+            }
+            Err(mut error) => {
+                report.failed += 1;
+                if error.len() > MAX_ERROR_LEN {
+                    let end = (0..=MAX_ERROR_LEN).rev().find(|i| error.is_char_boundary(*i)).unwrap_or(0);
+                    error.truncate(end);
+                }
+                status.failed_attempts += 1;
+                status.last_error = Some(error);
+                status.state = SyncState::Failed;
+            }
+        }
+        store(event_id, status);
+    }
+    report.remaining = DIRTY.with(|dirty| dirty.borrow().len());
+    Ok(report)
+}
+
+// Query whether an event was pushed since its last change
+#[ic_cdk::query]
+fn get_sync_status(event_id: u64) -> Option<SyncStatus> {
+    SYNC_STATUS.with(|statuses| statuses.borrow().get(&event_id))
+}
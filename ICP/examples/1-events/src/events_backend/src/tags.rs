@@ -0,0 +1,137 @@
+use candid::{CandidType, Decode, Deserialize, Encode};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+use crate::{Memory, MEMORY_MANAGER};
+
+pub const MAX_TAGS: usize = 10;
+pub const MAX_TAG_LEN: usize = 32;
+
+// Index entry linking a tag (or category) to an event. Ordering by (tag, event_id)
+// keeps all events of a tag next to each other, so they can be read with a range.
+#[derive(CandidType, Deserialize, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct TagKey {
+    tag: String,
+    event_id: u64,
+}
+
+impl Storable for TagKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for TagKey {
+    const MAX_SIZE: u32 = 100;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+impl TagKey {
+    fn first(tag: &str) -> Self {
+        TagKey { tag: tag.to_string(), event_id: 0 }
+    }
+
+    fn last(tag: &str) -> Self {
+        TagKey { tag: tag.to_string(), event_id: u64::MAX }
+    }
+}
+
+thread_local! {
+    static TAG_INDEX: RefCell<StableBTreeMap<TagKey, (), Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(3))),
+        )
+    );
+
+    static CATEGORY_INDEX: RefCell<StableBTreeMap<TagKey, (), Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(4))),
+        )
+    );
+}
+
+// Lowercase and trim a tag, rejecting empty or overly long ones
+pub fn normalize(tag: &str) -> Option<String> {
+    let tag = tag.trim().to_lowercase();
+    if tag.is_empty() || tag.len() > MAX_TAG_LEN {
+        None
+    } else {
+        Some(tag)
+    }
+}
+
+// Normalize a list of tags, dropping duplicates
+pub fn normalize_all(tags: &[String]) -> Option<Vec<String>> {
+    if tags.len() > MAX_TAGS {
+        return None;
+    }
+    let mut normalized: Vec<String> = Vec::new();
+    for tag in tags {
+        let tag = normalize(tag)?;
+        if !normalized.contains(&tag) {
+            normalized.push(tag);
+        }
+    }
+    Some(normalized)
+}
+
+// Add an event to the index of each of its tags and of its category
+pub fn index_event(event_id: u64, tags: &[String], category: &Option<String>) {
+    TAG_INDEX.with(|index| {
+        let mut index = index.borrow_mut();
+        for tag in tags {
+            index.insert(TagKey { tag: tag.clone(), event_id }, ());
+        }
+    });
+    if let Some(category) = category {
+        CATEGORY_INDEX.with(|index| {
+            index.borrow_mut().insert(TagKey { tag: category.clone(), event_id }, ())
+        });
+    }
+}
+
+// Remove an event from the index of each of its tags and of its category
+pub fn unindex_event(event_id: u64, tags: &[String], category: &Option<String>) {
+    TAG_INDEX.with(|index| {
+        let mut index = index.borrow_mut();
+        for tag in tags {
+            index.remove(&TagKey { tag: tag.clone(), event_id });
+        }
+    });
+    if let Some(category) = category {
+        CATEGORY_INDEX.with(|index| {
+            index.borrow_mut().remove(&TagKey { tag: category.clone(), event_id })
+        });
+    }
+}
+
+// Ids of the events with the given tag, one page at a time
+pub fn events_with_tag(tag: &str, page: u64, page_size: u64) -> Vec<u64> {
+    TAG_INDEX.with(|index| {
+        index
+            .borrow()
+            .range(TagKey::first(tag)..=TagKey::last(tag))
+            .skip((page * page_size) as usize)
+            .take(page_size as usize)
+            .map(|(key, _)| key.event_id)
+            .collect()
+    })
+}
+
+// All categories that are used by at least one event, in alphabetical order
+pub fn categories() -> Vec<String> {
+    CATEGORY_INDEX.with(|index| {
+        let mut categories: Vec<String> = Vec::new();
+        for (key, _) in index.borrow().iter() {
+            if categories.last() != Some(&key.tag) {
+                categories.push(key.tag);
+            }
+        }
+        categories
+    })
+}
@@ -0,0 +1,230 @@
+use candid::{CandidType, Decode, Deserialize, Encode, Nat, Principal};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{StableCell, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+use crate::types::{Event, EventError, Participant, RsvpStatus};
+use crate::{admin, participants, Memory, EVENTS_MAP, MEMORY_MANAGER};
+
+// The DIP721 canister minting tickets; the events canister must be one of its custodians
+#[derive(CandidType, Deserialize, Clone, Default)]
+struct TicketConfig {
+    canister: Option<Principal>,
+}
+
+impl Storable for TicketConfig {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+thread_local! {
+    static TICKET_CONFIG: RefCell<StableCell<TicketConfig, Memory>> = RefCell::new(
+        StableCell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(14))),
+            TicketConfig::default(),
+        ).expect("failed to initialize the ticket config cell")
+    );
+}
+
+// The subset of the nft-container's DIP721 interface used for tickets
+
+#[derive(CandidType, Deserialize)]
+enum MetadataPurpose {
+    Preview,
+    Rendered,
+}
+
+#[allow(clippy::enum_variant_names)]
+#[derive(CandidType, Deserialize)]
+enum MetadataVal {
+    TextContent(String),
+    BlobContent(Vec<u8>),
+    NatContent(Nat),
+    Nat8Content(u8),
+    Nat16Content(u16),
+    Nat32Content(u32),
+    Nat64Content(u64),
+}
+
+#[derive(CandidType, Deserialize)]
+struct MetadataPart {
+    purpose: MetadataPurpose,
+    key_val_data: Vec<(String, MetadataVal)>,
+    data: Vec<u8>,
+}
+
+#[derive(CandidType, Deserialize)]
+struct MintResult {
+    token_id: u64,
+    id: Nat,
+}
+
+#[derive(CandidType, Deserialize)]
+enum MintError {
+    Unauthorized,
+}
+
+#[derive(CandidType, Deserialize)]
+enum ApiError {
+    Unauthorized,
+    InvalidTokenId,
+    ZeroAddress,
+    Other,
+}
+
+pub fn canister() -> Option<Principal> {
+    TICKET_CONFIG.with(|config| config.borrow().get().canister)
+}
+
+pub fn restore_canister(canister: Principal) {
+    TICKET_CONFIG.with(|config| config.borrow_mut().set(TicketConfig { canister: Some(canister) }))
+        .expect("failed to save the ticket config");
+}
+
+fn metadata(event_id: u64, event: &Event) -> Vec<MetadataPart> {
+    vec![MetadataPart {
+        purpose: MetadataPurpose::Rendered,
+        key_val_data: vec![
+            ("event_id".to_string(), MetadataVal::Nat64Content(event_id)),
+            ("event_name".to_string(), MetadataVal::TextContent(event.name.clone())),
+            ("start_time".to_string(), MetadataVal::Nat64Content(event.start_time)),
+        ],
+        data: Vec::new(),
+    }]
+}
+
+async fn mint_dip721(ticket_canister: Principal, owner: Principal, metadata: Vec<MetadataPart>) -> Result<u64, EventError> {
+    let (receipt,): (Result<MintResult, MintError>,) =
+        ic_cdk::call(ticket_canister, "mintDip721", (owner, metadata, Vec::<u8>::new()))
+            .await
+            .map_err(|_| EventError::TicketCanisterUnavailable)?;
+    Ok(receipt.map_err(|_| EventError::TicketMintFailed)?.token_id)
+}
+
+// Mint a proof of attendance to a participant who checked in. The nft-container refuses
+// to transfer tokens marked as not transferable, so the proof stays with its owner.
+pub async fn mint_attendance_proof(event_id: u64, event: &Event, owner: Principal) -> Result<u64, EventError> {
+    let ticket_canister = canister().ok_or(EventError::NoTicketCanister)?;
+    let mut metadata = metadata(event_id, event);
+    metadata[0].key_val_data.extend([
+        ("kind".to_string(), MetadataVal::TextContent("attendance".to_string())),
+        ("transferable".to_string(), MetadataVal::Nat8Content(0)),
+    ]);
+    mint_dip721(ticket_canister, owner, metadata).await
+}
+
+// Mint a ticket for a Going participant and store its token id on the participant
+async fn mint(event_id: u64, address: String) -> Result<u64, EventError> {
+    let ticket_canister = canister().ok_or(EventError::NoTicketCanister)?;
+    // Tickets can only be minted to participants identified by a principal
+    let owner = Principal::from_text(&address).map_err(|_| EventError::InvalidAddress)?;
+    let event = EVENTS_MAP
+        .with(|events_map| events_map.borrow().get(&event_id))
+        .ok_or(EventError::NoSuchEvent)?;
+
+    let token_id = mint_dip721(ticket_canister, owner, metadata(event_id, &event)).await?;
+
+    // The participant may have cancelled while the ticket was minted; the ticket then stays unused
+    if let Some(participant) = participants::get(event_id, &address) {
+        participants::insert(event_id, Participant { ticket_id: Some(token_id), ..participant });
+    }
+    Ok(token_id)
+}
+
+// Mint a ticket in the background for a participant who just got a spot
+pub fn issue(event_id: u64, address: String) {
+    if canister().is_none() || Principal::from_text(&address).is_err() {
+        return;
+    }
+    ic_cdk::spawn(async move {
+        // A failed mint leaves the participant without a ticket, they can retry with `claim_ticket`
+        let _ = mint(event_id, address).await;
+    });
+}
+
+// Whether the caller owns the ticket of a participant; participants without a ticket pass
+pub async fn verify_owner(participant: &Participant, caller: Principal) -> Result<(), EventError> {
+    let (Some(token_id), Some(ticket_canister)) = (participant.ticket_id, canister()) else {
+        return Ok(());
+    };
+    let (owner,): (Result<Principal, ApiError>,) = ic_cdk::call(ticket_canister, "ownerOfDip721", (token_id,))
+        .await
+        .map_err(|_| EventError::TicketCanisterUnavailable)?;
+    match owner {
+        Ok(owner) if owner == caller => Ok(()),
+        _ => Err(EventError::TicketNotOwned),
+    }
+}
+
+// An event with the caller's ticket for it
+#[derive(CandidType)]
+struct EventWithTicket {
+    event: Event,
+    // Only set while the caller owns the ticket
+    ticket_id: Option<u64>,
+    ticket_metadata: Option<Vec<MetadataPart>>,
+}
+
+// Query an event together with the caller's ticket and its metadata, read from the
+// nft-container within the same call. Composite queries can only call canisters on the
+// same subnet.
+#[ic_cdk::query(composite = true)]
+async fn get_event_with_my_ticket(event_id: u64) -> Result<EventWithTicket, EventError> {
+    let event = EVENTS_MAP
+        .with(|events_map| events_map.borrow().get(&event_id))
+        .ok_or(EventError::NoSuchEvent)?;
+    let caller = ic_cdk::caller();
+    let ticket_id = participants::get(event_id, &caller.to_text()).and_then(|participant| participant.ticket_id);
+    let (Some(token_id), Some(ticket_canister)) = (ticket_id, canister()) else {
+        return Ok(EventWithTicket { event, ticket_id: None, ticket_metadata: None });
+    };
+
+    let (owner,): (Result<Principal, ApiError>,) = ic_cdk::call(ticket_canister, "ownerOfDip721", (token_id,))
+        .await
+        .map_err(|_| EventError::TicketCanisterUnavailable)?;
+    if owner.ok() != Some(caller) {
+        return Ok(EventWithTicket { event, ticket_id: None, ticket_metadata: None });
+    }
+    let (metadata,): (Result<Vec<MetadataPart>, ApiError>,) =
+        ic_cdk::call(ticket_canister, "getMetadataDip721", (token_id,))
+            .await
+            .map_err(|_| EventError::TicketCanisterUnavailable)?;
+    Ok(EventWithTicket { event, ticket_id: Some(token_id), ticket_metadata: metadata.ok() })
+}
+
+// Set or clear the nft-container canister used to mint tickets
+#[ic_cdk::update]
+fn set_ticket_canister(canister: Option<Principal>) -> Result<(), EventError> {
+    admin::require_admin()?;
+    TICKET_CONFIG.with(|config| {
+        config
+            .borrow_mut()
+            .set(TicketConfig { canister })
+            .expect("failed to store the ticket config")
+    });
+    Ok(())
+}
+
+#[ic_cdk::query]
+fn get_ticket_canister() -> Option<Principal> {
+    canister()
+}
+
+// Mint the caller's ticket for an event they are going to, if they do not have one yet
+#[ic_cdk::update]
+async fn claim_ticket(event_id: u64) -> Result<u64, EventError> {
+    let caller = ic_cdk::caller().to_text();
+    let participant = participants::get(event_id, &caller).ok_or(EventError::NotRegistered)?;
+    if participant.rsvp != RsvpStatus::Going {
+        return Err(EventError::NotRegistered);
+    }
+    if let Some(token_id) = participant.ticket_id {
+        return Ok(token_id);
+    }
+    mint(event_id, caller).await
+}
@@ -0,0 +1,217 @@
+use candid::{CandidType, Deserialize};
+
+use crate::dates::{civil_from_days, days_from_civil, NANOS_PER_SECOND};
+use crate::types::{Event, EventError};
+use crate::EVENTS_MAP;
+
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+const HOUR: i64 = 60 * 60;
+
+// The daylight saving time rule a zone follows. There is no tz database on chain, so only
+// the rules of the zones listed below are implemented.
+#[derive(Clone, Copy)]
+enum DstRule {
+    None,
+    // Last Sunday of March to last Sunday of October, at 01:00 UTC
+    EuropeanUnion,
+    // Second Sunday of March to first Sunday of November, at 02:00 local time
+    NorthAmerica,
+    // First Sunday of October to first Sunday of April, at 02:00 standard time
+    SouthEastAustralia,
+}
+
+// IANA identifier, standard UTC offset in minutes and DST rule of the supported zones
+const ZONES: [(&str, i64, DstRule); 40] = [
+    ("UTC", 0, DstRule::None),
+    ("Europe/London", 0, DstRule::EuropeanUnion),
+    ("Europe/Dublin", 0, DstRule::EuropeanUnion),
+    ("Europe/Lisbon", 0, DstRule::EuropeanUnion),
+    ("Europe/Amsterdam", 60, DstRule::EuropeanUnion),
+    ("Europe/Berlin", 60, DstRule::EuropeanUnion),
+    ("Europe/Brussels", 60, DstRule::EuropeanUnion),
+    ("Europe/Madrid", 60, DstRule::EuropeanUnion),
+    ("Europe/Paris", 60, DstRule::EuropeanUnion),
+    ("Europe/Prague", 60, DstRule::EuropeanUnion),
+    ("Europe/Rome", 60, DstRule::EuropeanUnion),
+    ("Europe/Stockholm", 60, DstRule::EuropeanUnion),
+    ("Europe/Vienna", 60, DstRule::EuropeanUnion),
+    ("Europe/Warsaw", 60, DstRule::EuropeanUnion),
+    ("Europe/Zurich", 60, DstRule::EuropeanUnion),
+    ("Europe/Athens", 120, DstRule::EuropeanUnion),
+    ("Europe/Bucharest", 120, DstRule::EuropeanUnion),
+    ("Europe/Helsinki", 120, DstRule::EuropeanUnion),
+    ("Europe/Kyiv", 120, DstRule::EuropeanUnion),
+    ("Europe/Istanbul", 180, DstRule::None),
+    ("Europe/Moscow", 180, DstRule::None),
+    ("Africa/Lagos", 60, DstRule::None),
+    ("Africa/Johannesburg", 120, DstRule::None),
+    ("Africa/Nairobi", 180, DstRule::None),
+    ("Asia/Dubai", 240, DstRule::None),
+    ("Asia/Kolkata", 330, DstRule::None),
+    ("Asia/Singapore", 480, DstRule::None),
+    ("Asia/Shanghai", 480, DstRule::None),
+    ("Asia/Hong_Kong", 480, DstRule::None),
+    ("Asia/Seoul", 540, DstRule::None),
+    ("Asia/Tokyo", 540, DstRule::None),
+    ("Australia/Brisbane", 600, DstRule::None),
+    ("Australia/Sydney", 600, DstRule::SouthEastAustralia),
+    ("Australia/Melbourne", 600, DstRule::SouthEastAustralia),
+    ("America/Sao_Paulo", -180, DstRule::None),
+    ("America/New_York", -300, DstRule::NorthAmerica),
+    ("America/Toronto", -300, DstRule::NorthAmerica),
+    ("America/Chicago", -360, DstRule::NorthAmerica),
+    ("America/Denver", -420, DstRule::NorthAmerica),
+    ("America/Los_Angeles", -480, DstRule::NorthAmerica),
+];
+
+fn zone(timezone: &str) -> Option<(i64, DstRule)> {
+    ZONES
+        .iter()
+        .find(|(name, _, _)| *name == timezone)
+        .map(|(_, offset, rule)| (offset * 60, *rule))
+}
+
+pub fn is_supported(timezone: &str) -> bool {
+    zone(timezone).is_some()
+}
+
+// Days since the epoch of the n-th (from 1) Sunday of a month, or the last one with n = 0
+fn sunday_of(year: i64, month: u32, n: i64) -> i64 {
+    // 1970-01-01 was a Thursday
+    let weekday = |days: i64| (days + 4).rem_euclid(7);
+    if n == 0 {
+        let (next_year, next_month) = if month == 12 { (year + 1, 1) } else { (year, month + 1) };
+        let last = days_from_civil(next_year, next_month, 1) - 1;
+        last - weekday(last)
+    } else {
+        let first = days_from_civil(year, month, 1);
+        first + (7 - weekday(first)) % 7 + 7 * (n - 1)
+    }
+}
+
+// Whether DST is in effect at `utc` seconds since the epoch
+fn is_dst(rule: DstRule, standard: i64, utc: i64) -> bool {
+    let (year, _, _) = civil_from_days((utc + standard).div_euclid(SECONDS_PER_DAY));
+    let at = |days: i64, seconds: i64| days * SECONDS_PER_DAY + seconds;
+    match rule {
+        DstRule::None => false,
+        DstRule::EuropeanUnion => {
+            let start = at(sunday_of(year, 3, 0), HOUR);
+            let end = at(sunday_of(year, 10, 0), HOUR);
+            (start..end).contains(&utc)
+        }
+        DstRule::NorthAmerica => {
+            let start = at(sunday_of(year, 3, 2), 2 * HOUR - standard);
+            let end = at(sunday_of(year, 11, 1), 2 * HOUR - standard - HOUR);
+            (start..end).contains(&utc)
+        }
+        DstRule::SouthEastAustralia => {
+            let end = at(sunday_of(year, 4, 1), 3 * HOUR - standard - HOUR);
+            let start = at(sunday_of(year, 10, 1), 2 * HOUR - standard);
+            utc < end || utc >= start
+        }
+    }
+}
+
+// UTC offset in seconds of a zone at a time in nanoseconds since the epoch
+pub fn offset_at(timezone: &str, time: u64) -> Option<i64> {
+    let (standard, rule) = zone(timezone)?;
+    let utc = (time / NANOS_PER_SECOND) as i64;
+    Some(standard + if is_dst(rule, standard, utc) { HOUR } else { 0 })
+}
+
+// The UTC time of a local wall clock time, both in nanoseconds. Local times skipped by a
+// DST change resolve to an hour later, repeated ones to the first of the two.
+fn to_utc(timezone: &str, local: u64) -> Option<u64> {
+    let (standard, _) = zone(timezone)?;
+    let shift = |time: u64, offset: i64| (time as i64 - offset * NANOS_PER_SECOND as i64).max(0) as u64;
+    let daylight = shift(local, standard + HOUR);
+    if offset_at(timezone, daylight)? == standard + HOUR {
+        return Some(daylight);
+    }
+    Some(shift(local, standard))
+}
+
+// Move a time by a duration of local time, keeping the wall clock time across DST changes
+pub fn shift_local(timezone: &str, time: u64, duration: u64) -> Option<u64> {
+    let offset = offset_at(timezone, time)?;
+    let local = (time as i64 + offset * NANOS_PER_SECOND as i64).max(0) as u64;
+    to_utc(timezone, local + duration)
+}
+
+// Format a time in nanoseconds as an ISO 8601 local date-time with its UTC offset,
+// e.g. `2024-05-01T19:00:00+03:00`
+pub fn format_local(time: u64, offset: i64) -> String {
+    let local = (time / NANOS_PER_SECOND) as i64 + offset;
+    let (year, month, day) = civil_from_days(local.div_euclid(SECONDS_PER_DAY));
+    let seconds = local.rem_euclid(SECONDS_PER_DAY);
+    let sign = if offset < 0 { '-' } else { '+' };
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}{}{:02}:{:02}",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60,
+        sign,
+        offset.abs() / 3600,
+        offset.abs() / 60 % 60
+    )
+}
+
+// An event with its start and end as local date-times of a time zone
+#[derive(CandidType, Deserialize)]
+pub struct EventInTimezone {
+    pub event: Event,
+    pub timezone: String,
+    pub start_local: String,
+    pub end_local: String,
+}
+
+// Query an event with its times in `timezone`, by default the zone of the event or UTC
+#[ic_cdk::query]
+fn get_event_in_tz(event_id: u64, timezone: Option<String>) -> Result<EventInTimezone, EventError> {
+    let event = EVENTS_MAP
+        .with(|events_map| events_map.borrow().get(&event_id))
+        .ok_or(EventError::NoSuchEvent)?;
+    let timezone = timezone
+        .or_else(|| event.timezone.clone())
+        .unwrap_or_else(|| "UTC".to_string());
+    let start_offset = offset_at(&timezone, event.start_time).ok_or(EventError::InvalidTimezone)?;
+    let end_offset = offset_at(&timezone, event.end_time).ok_or(EventError::InvalidTimezone)?;
+    Ok(EventInTimezone {
+        start_local: format_local(event.start_time, start_offset),
+        end_local: format_local(event.end_time, end_offset),
+        event,
+        timezone,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dates::{parse_iso_date, NANOS_PER_DAY};
+
+    fn utc(date: &str) -> u64 {
+        parse_iso_date(date).unwrap()
+    }
+
+    #[test]
+    fn offsets_follow_daylight_saving_time() {
+        assert_eq!(offset_at("Europe/Berlin", utc("2024-01-15T12:00")), Some(HOUR));
+        assert_eq!(offset_at("Europe/Berlin", utc("2024-07-15T12:00")), Some(2 * HOUR));
+        assert_eq!(offset_at("America/New_York", utc("2024-07-15T12:00")), Some(-4 * HOUR));
+        assert_eq!(offset_at("Australia/Sydney", utc("2024-01-15T12:00")), Some(11 * HOUR));
+        assert_eq!(offset_at("Asia/Tokyo", utc("2024-07-15T12:00")), Some(9 * HOUR));
+        assert_eq!(offset_at("Mars/Olympus_Mons", 0), None);
+    }
+
+    #[test]
+    fn local_shifts_keep_the_wall_clock_across_dst_changes() {
+        // 19:00 in Berlin the week before and the week after summer time starts
+        let before = utc("2024-03-25T18:00");
+        assert_eq!(shift_local("Europe/Berlin", before, 7 * NANOS_PER_DAY), Some(utc("2024-04-01T17:00")));
+        assert_eq!(shift_local("UTC", before, 7 * NANOS_PER_DAY), Some(before + 7 * NANOS_PER_DAY));
+    }
+}
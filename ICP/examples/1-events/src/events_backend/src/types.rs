@@ -0,0 +1,461 @@
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use serde::Serialize;
+use ic_stable_structures::{BoundedStorable, Storable};
+use std::borrow::Cow;
+
+use crate::dates;
+
+pub const MAX_NAME_LEN: usize = 200;
+// Long enough for the text form of any principal
+pub const MAX_ADDRESS_LEN: usize = 64;
+pub const MAX_CO_HOSTS: usize = 10;
+pub const MAX_VENUE_LEN: usize = 200;
+
+#[derive(CandidType, Deserialize, Clone, Serialize)]
+pub struct Participant {
+    pub address: String,
+    #[serde(default)] // Participants stored before RSVPs existed had joined
+    pub rsvp: RsvpStatus,
+    // Token id of the NFT ticket minted for the participant
+    #[serde(default)]
+    pub ticket_id: Option<u64>,
+}
+
+// How a participant answered an event's invitation; only Going takes a spot
+#[derive(CandidType, Deserialize, Clone, Copy, PartialEq, Default, Serialize)]
+pub enum RsvpStatus {
+    #[default]
+    Going,
+    Maybe,
+    Declined,
+}
+
+// Implement Storable for Participant
+impl Storable for Participant {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for Participant {
+    const MAX_SIZE: u32 = 150;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// Define the Event structure.
+// Participants are stored in their own map, so the size of an Event does not grow with them.
+#[derive(CandidType, Deserialize, Clone, Serialize)]
+pub struct Event {
+    pub name: String,
+    pub start_time: u64, // nanoseconds since the epoch
+    pub end_time: u64,
+    // Number of participants per RSVP status; only `participant_count` (Going) counts towards capacity
+    pub participant_count: u32,
+    #[serde(default)]
+    pub maybe_count: u32,
+    #[serde(default)]
+    pub declined_count: u32,
+    // Once this many participants joined, new joins go to the waitlist
+    pub max_participants: Option<u32>,
+    #[serde(default)] // Events imported from the API are published right away
+    pub status: EventStatus,
+    // Id of the first occurrence, shared by all occurrences of a recurring event
+    pub series_id: Option<u64>,
+    // Event this one is a session of, e.g. the conference of a talk
+    pub parent_id: Option<u64>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    pub category: Option<String>,
+    // `<source id>:<id>` of events imported from an external API
+    pub external_id: Option<String>,
+    // Creator of the event; imported and migrated events belong to the canister itself
+    pub organizer: Principal,
+    // Principals that may manage the event alongside the organizer
+    #[serde(default)]
+    pub co_hosts: Vec<Principal>,
+    // Price of a spot, paid on join; free events have none
+    pub ticket_price: Option<TicketPrice>,
+    #[serde(default)]
+    pub visibility: Visibility,
+    // IANA identifier of the zone the event is planned in; times stay in UTC
+    pub timezone: Option<String>,
+    pub location: Option<Location>,
+    // Name or address of the place
+    pub venue: Option<String>,
+}
+
+// A point in decimal degrees
+#[derive(CandidType, Deserialize, Serialize, Clone, Copy)]
+pub struct Location {
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+impl Location {
+    pub fn is_valid(&self) -> bool {
+        (-90.0..=90.0).contains(&self.latitude) && (-180.0..=180.0).contains(&self.longitude)
+    }
+}
+
+// Private events are left out of listings and can only be joined by invited principals
+#[derive(CandidType, Deserialize, Serialize, Clone, Copy, PartialEq, Default)]
+pub enum Visibility {
+    #[default]
+    Public,
+    Private,
+}
+
+// Price of a paid event, in the smallest unit of an ICRC-1 ledger
+#[derive(CandidType, Deserialize, Clone, Serialize)]
+pub struct TicketPrice {
+    pub amount: u64,
+    pub ledger: Principal,
+    // Kept from the refund when a participant cancels before the event starts
+    pub refund_fee: u64,
+}
+
+impl TicketPrice {
+    pub fn is_valid(&self) -> bool {
+        self.amount > 0 && self.refund_fee <= self.amount
+    }
+}
+
+// Whether a join took a spot or a place on the waitlist
+#[derive(CandidType, Deserialize, Clone, Copy, PartialEq)]
+pub enum JoinStatus {
+    Registered,
+    Waitlisted,
+}
+
+// What a join did, so frontends can show it without a second query
+#[derive(CandidType, Deserialize, Clone)]
+pub struct JoinResult {
+    pub status: JoinStatus,
+    // 1-based position on the waitlist of waitlisted participants
+    pub position: Option<u64>,
+    // Spots left after the join; None for events without a limit
+    pub remaining_capacity: Option<u32>,
+}
+
+// The lifecycle of an Event
+#[derive(CandidType, Deserialize, Clone, Copy, PartialEq, Default, Serialize)]
+pub enum EventStatus {
+    Draft, // Created but not yet open for joining
+    #[default]
+    Published, // Open for joining
+    Full, // Capacity reached, new participants go to the waitlist
+    Cancelled,
+    Completed,
+}
+
+impl EventStatus {
+    // Whether an event may move from this status to `next`
+    pub fn can_transition_to(self, next: EventStatus) -> bool {
+        use EventStatus::*;
+        matches!(
+            (self, next),
+            (Draft, Published)
+                | (Published, Full)
+                | (Full, Published)
+                | (Draft | Published | Full, Cancelled)
+                | (Published | Full, Completed)
+        )
+    }
+
+    pub fn is_open(self) -> bool {
+        self == EventStatus::Published || self == EventStatus::Full
+    }
+}
+
+impl Event {
+    // A published public event without participants, for tests
+    #[cfg(test)]
+    pub fn for_test(name: &str, start_time: u64) -> Event {
+        Event {
+            name: name.to_string(),
+            start_time,
+            end_time: start_time,
+            participant_count: 0,
+            maybe_count: 0,
+            declined_count: 0,
+            max_participants: None,
+            status: EventStatus::Published,
+            series_id: None,
+            parent_id: None,
+            tags: Vec::new(),
+            category: None,
+            external_id: None,
+            organizer: Principal::anonymous(),
+            co_hosts: Vec::new(),
+            ticket_price: None,
+            visibility: Visibility::Public,
+            timezone: None,
+            location: None,
+            venue: None,
+        }
+    }
+
+    // Whether the principal is the organizer or one of the co-hosts
+    pub fn is_host(&self, principal: &Principal) -> bool {
+        self.organizer == *principal || self.co_hosts.contains(principal)
+    }
+
+    pub fn is_public(&self) -> bool {
+        self.visibility == Visibility::Public
+    }
+
+    pub fn is_full(&self) -> bool {
+        match self.max_participants {
+            Some(max) => self.participant_count >= max,
+            None => false,
+        }
+    }
+
+    // The counter of participants with the given RSVP status
+    pub fn rsvp_count_mut(&mut self, rsvp: RsvpStatus) -> &mut u32 {
+        match rsvp {
+            RsvpStatus::Going => &mut self.participant_count,
+            RsvpStatus::Maybe => &mut self.maybe_count,
+            RsvpStatus::Declined => &mut self.declined_count,
+        }
+    }
+
+    pub fn transition_to(&mut self, next: EventStatus) -> Result<(), EventError> {
+        if !self.status.can_transition_to(next) {
+            return Err(EventError::InvalidStatusTransition);
+        }
+        self.status = next;
+        Ok(())
+    }
+
+    // Keep Published/Full in sync with the participant count
+    pub fn refresh_capacity_status(&mut self) {
+        if self.status == EventStatus::Published && self.is_full() {
+            self.status = EventStatus::Full;
+        } else if self.status == EventStatus::Full && !self.is_full() {
+            self.status = EventStatus::Published;
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize, Clone)]
+pub enum EventError {
+    NoSuchEvent,
+    JoinError,
+    CancelJoinError,
+    GetEventsError,
+    AlreadyJoined,
+    AlreadyExists,
+    EventNotOpen,
+    InvalidStatusTransition,
+    InvalidRecurrence,
+    InvalidTags,
+    InvalidTime,
+    Unauthorized,
+    InvalidUrl,
+    NoSuchSource,
+    InvalidName,
+    InvalidAddress,
+    AlreadyCoHost,
+    NoSuchCoHost,
+    TooManyCoHosts,
+    RandomnessUnavailable,
+    InvalidCheckinCode,
+    NotRegistered,
+    AlreadyCheckedIn,
+    NoTicketCanister,
+    TicketCanisterUnavailable,
+    TicketMintFailed,
+    TicketNotOwned,
+    InvalidPrice,
+    PaymentRequired,
+    PaymentFailed,
+    RefundFailed,
+    LedgerUnavailable,
+    EventFull,
+    InvalidComment,
+    NoSuchComment,
+    RateLimited,
+    NotInvited,
+    BatchTooLarge,
+    InvalidIdempotencyKey,
+    InvalidImage,
+    ImageHashMismatch,
+    InvalidTimezone,
+    InvalidLocation,
+    NoPendingPromotion,
+    InvalidParent,
+    TooManySessions,
+    InvalidProfile,
+    EventNotCompleted,
+    PaymentInProgress,
+    NoSuchRefund,
+}
+
+// Implement Storable for Event
+impl Storable for Event {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+// Large enough for the longest name, all tags, the category, all co-hosts and the price
+const MAX_VALUE_SIZE: u32 = 2048;
+
+// Implement BoundedStorable for Event
+impl BoundedStorable for Event {
+    const MAX_SIZE: u32 = MAX_VALUE_SIZE;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// Input for creating a single event
+#[derive(CandidType, Deserialize, Clone)]
+pub struct CreateEventPayload {
+    pub name: String,
+    pub start_time: u64,
+    pub end_time: u64,
+    pub max_participants: Option<u32>,
+    pub tags: Vec<String>,
+    pub category: Option<String>,
+    pub ticket_price: Option<TicketPrice>,
+    #[serde(default)]
+    pub visibility: Visibility,
+    pub timezone: Option<String>,
+    pub location: Option<Location>,
+    pub venue: Option<String>,
+}
+
+// An event as stored by earlier versions, which kept participants inside the event
+// and used a 100 byte bound. Every field that did not exist from the start is
+// optional, so this decodes any earlier layout.
+#[derive(CandidType, Deserialize)]
+pub struct LegacyEvent {
+    pub name: String,
+    pub date: Option<String>,
+    pub start_time: Option<u64>,
+    pub end_time: Option<u64>,
+    #[serde(default)]
+    pub participants: Vec<Participant>,
+    pub max_participants: Option<u32>,
+    pub status: Option<EventStatus>,
+    pub series_id: Option<u64>,
+    pub tags: Option<Vec<String>>,
+    pub category: Option<String>,
+    pub external_id: Option<String>,
+}
+
+impl Storable for LegacyEvent {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for LegacyEvent {
+    const MAX_SIZE: u32 = 100;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+impl LegacyEvent {
+    // Split a legacy event into the current Event and its participants
+    pub fn migrate(self, organizer: Principal) -> (Event, Vec<Participant>) {
+        let start_time = self
+            .start_time
+            .or_else(|| self.date.as_deref().and_then(dates::parse_iso_date))
+            .unwrap_or_default();
+        let event = Event {
+            name: self.name,
+            start_time,
+            end_time: self.end_time.unwrap_or(start_time),
+            participant_count: self.participants.len() as u32,
+            maybe_count: 0,
+            declined_count: 0,
+            max_participants: self.max_participants,
+            status: self.status.unwrap_or_default(),
+            series_id: self.series_id,
+            parent_id: None,
+            tags: self.tags.unwrap_or_default(),
+            category: self.category,
+            external_id: self.external_id,
+            organizer,
+            co_hosts: Vec::new(),
+            ticket_price: None,
+            visibility: Visibility::Public,
+            timezone: None,
+            location: None,
+            venue: None,
+        };
+        (event, self.participants)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn events_only_move_along_allowed_transitions() {
+        let mut event = Event::for_test("Meetup", 1);
+        event.status = EventStatus::Draft;
+        assert!(matches!(event.transition_to(EventStatus::Completed), Err(EventError::InvalidStatusTransition)));
+        assert!(event.transition_to(EventStatus::Published).is_ok());
+        assert!(event.transition_to(EventStatus::Completed).is_ok());
+        assert!(event.transition_to(EventStatus::Published).is_err());
+        assert!(event.transition_to(EventStatus::Cancelled).is_err());
+        assert!(event.status == EventStatus::Completed);
+    }
+
+    #[test]
+    fn capacity_status_follows_the_participant_count() {
+        let mut event = Event::for_test("Meetup", 1);
+        event.max_participants = Some(2);
+        event.participant_count = 2;
+        event.refresh_capacity_status();
+        assert!(event.status == EventStatus::Full);
+
+        event.participant_count = 1;
+        event.refresh_capacity_status();
+        assert!(event.status == EventStatus::Published);
+
+        // Cancelled events stay cancelled
+        event.status = EventStatus::Cancelled;
+        event.participant_count = 2;
+        event.refresh_capacity_status();
+        assert!(event.status == EventStatus::Cancelled);
+    }
+
+    #[test]
+    fn legacy_events_are_migrated_with_their_participants() {
+        let legacy = LegacyEvent {
+            name: "Meetup".to_string(),
+            date: Some("2024-05-01".to_string()),
+            start_time: None,
+            end_time: None,
+            participants: vec![Participant { address: "alice".to_string(), rsvp: RsvpStatus::Going, ticket_id: None }],
+            max_participants: Some(10),
+            status: None,
+            series_id: None,
+            tags: None,
+            category: None,
+            external_id: Some("0:1".to_string()),
+        };
+        let (event, participants) = legacy.migrate(Principal::anonymous());
+        assert_eq!(event.start_time, dates::parse_iso_date("2024-05-01").unwrap());
+        assert_eq!(event.end_time, event.start_time);
+        assert_eq!(event.participant_count, 1);
+        assert!(event.status == EventStatus::default());
+        assert_eq!(event.external_id.as_deref(), Some("0:1"));
+        assert_eq!(participants.len(), 1);
+    }
+}
@@ -0,0 +1,151 @@
+use candid::{CandidType, Decode, Deserialize, Encode};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+use crate::{Memory, MEMORY_MANAGER};
+
+// A single entry on an event's waitlist
+#[derive(CandidType, Deserialize, Clone)]
+pub struct WaitlistEntry {
+    pub address: String,
+}
+
+impl Storable for WaitlistEntry {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+const MAX_ENTRY_SIZE: u32 = 100;
+
+impl BoundedStorable for WaitlistEntry {
+    const MAX_SIZE: u32 = MAX_ENTRY_SIZE;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// Waitlist entries are keyed by (event_id, ticket), so iterating the range of
+// an event yields its entries in the order they were added (FIFO).
+thread_local! {
+    static WAITLIST_MAP: RefCell<StableBTreeMap<(u64, u64), WaitlistEntry, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(2))),
+        )
+    );
+}
+
+fn entries_of(event_id: u64) -> Vec<((u64, u64), WaitlistEntry)> {
+    WAITLIST_MAP.with(|waitlist| {
+        waitlist
+            .borrow()
+            .range((event_id, 0)..(event_id + 1, 0))
+            .collect()
+    })
+}
+
+// Add an address at the end of the event's waitlist, returns false if it is already waitlisted
+pub fn push(event_id: u64, address: String) -> bool {
+    let entries = entries_of(event_id);
+    if entries.iter().any(|(_, entry)| entry.address == address) {
+        return false;
+    }
+
+    let ticket = entries.last().map(|((_, ticket), _)| ticket + 1).unwrap_or(0);
+    WAITLIST_MAP.with(|waitlist| {
+        waitlist
+            .borrow_mut()
+            .insert((event_id, ticket), WaitlistEntry { address })
+    });
+    true
+}
+
+// Remove and return the first address on the event's waitlist
+pub fn pop_front(event_id: u64) -> Option<String> {
+    let (key, entry) = entries_of(event_id).into_iter().next()?;
+    WAITLIST_MAP.with(|waitlist| waitlist.borrow_mut().remove(&key));
+    Some(entry.address)
+}
+
+// Remove an address from the event's waitlist, returns false if it was not waitlisted
+pub fn remove(event_id: u64, address: &str) -> bool {
+    let key = entries_of(event_id)
+        .into_iter()
+        .find(|(_, entry)| entry.address == address)
+        .map(|(key, _)| key);
+
+    match key {
+        Some(key) => {
+            WAITLIST_MAP.with(|waitlist| waitlist.borrow_mut().remove(&key));
+            true
+        }
+        None => false,
+    }
+}
+
+// Addresses on the event's waitlist, in order
+pub fn addresses(event_id: u64) -> Vec<String> {
+    entries_of(event_id)
+        .into_iter()
+        .map(|(_, entry)| entry.address)
+        .collect()
+}
+
+// 1-based position of an address on the event's waitlist
+pub fn position(event_id: u64, address: &str) -> Option<u64> {
+    addresses(event_id)
+        .iter()
+        .position(|a| a == address)
+        .map(|index| index as u64 + 1)
+}
+
+pub fn len() -> u64 {
+    WAITLIST_MAP.with(|waitlist| waitlist.borrow().len())
+}
+
+// Entries of all waitlists in order, from the `skip`-th one
+pub fn all(skip: u64, take: u64) -> Vec<(u64, String)> {
+    WAITLIST_MAP.with(|waitlist| {
+        waitlist
+            .borrow()
+            .iter()
+            .skip(skip as usize)
+            .take(take as usize)
+            .map(|((event_id, _), entry)| (event_id, entry.address))
+            .collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn waitlists_are_first_in_first_out_per_event() {
+        assert!(push(1, "alice".to_string()));
+        assert!(push(1, "bob".to_string()));
+        assert!(push(1, "carol".to_string()));
+        assert!(!push(1, "bob".to_string()));
+        assert!(push(2, "alice".to_string()));
+        assert_eq!(position(1, "carol"), Some(3));
+
+        assert!(remove(1, "bob"));
+        assert!(!remove(1, "bob"));
+        assert_eq!(position(1, "carol"), Some(2));
+        assert_eq!(pop_front(1).as_deref(), Some("alice"));
+        assert_eq!(addresses(1), vec!["carol".to_string()]);
+        assert_eq!(addresses(2), vec!["alice".to_string()]);
+    }
+
+    #[test]
+    fn addresses_pushed_after_a_pop_go_last() {
+        push(3, "alice".to_string());
+        push(3, "bob".to_string());
+        pop_front(3);
+        push(3, "alice".to_string());
+        assert_eq!(addresses(3), vec!["bob".to_string(), "alice".to_string()]);
+    }
+}
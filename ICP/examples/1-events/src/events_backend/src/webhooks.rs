@@ -0,0 +1,439 @@
+use ic_cdk::api::management_canister::http_request::{
+    http_request, CanisterHttpRequestArgument, HttpHeader, HttpMethod, HttpResponse, TransformArgs, TransformContext,
+};
+use ic_cdk::api::management_canister::main::raw_rand;
+
+use candid::{CandidType, Decode, Deserialize, Encode};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::time::Duration;
+use std::{borrow::Cow, cell::Cell, cell::RefCell};
+
+use crate::dates::NANOS_PER_SECOND;
+use crate::types::{EventError, RsvpStatus};
+use crate::{require_host, Memory, EVENTS_MAP, MEMORY_MANAGER};
+
+const MAX_URL_LEN: usize = 400;
+const SECRET_BYTES: usize = 32;
+// Deliveries kept per event for list_webhook_deliveries, the oldest are dropped first
+const MAX_DELIVERIES: usize = 50;
+const MAX_DELIVERY_ATTEMPTS: u32 = 5;
+const BASE_RETRY_DELAY_SECS: u64 = 30;
+const MAX_RETRY_DELAY_SECS: u64 = 60 * 60;
+// Deliveries sent per timer run; the rest are sent by the next run
+const MAX_DELIVERIES_PER_RUN: usize = 20;
+// Only the status code is read, so replies are kept short
+const MAX_RESPONSE_BYTES: u64 = 4096;
+
+#[derive(CandidType, Deserialize, Clone)]
+pub struct Webhook {
+    pub url: String,
+    // Key of the HMAC-SHA256 signature of every payload
+    pub secret: Vec<u8>,
+}
+
+impl Storable for Webhook {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for Webhook {
+    const MAX_SIZE: u32 = 500;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+#[derive(CandidType, Deserialize, Clone, Copy, PartialEq)]
+pub enum WebhookEventKind {
+    ParticipantJoined,
+    ParticipantLeft,
+    EventCancelled,
+}
+
+impl WebhookEventKind {
+    fn name(self) -> &'static str {
+        match self {
+            WebhookEventKind::ParticipantJoined => "participant_joined",
+            WebhookEventKind::ParticipantLeft => "participant_left",
+            WebhookEventKind::EventCancelled => "event_cancelled",
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize, Clone, Copy, PartialEq)]
+pub enum DeliveryStatus {
+    Pending,
+    Delivered,
+    // Given up after MAX_DELIVERY_ATTEMPTS, or the webhook was removed
+    Failed,
+}
+
+#[derive(CandidType, Deserialize, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct DeliveryKey {
+    event_id: u64,
+    delivery_id: u64,
+}
+
+impl Storable for DeliveryKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for DeliveryKey {
+    const MAX_SIZE: u32 = 60;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+#[derive(CandidType, Deserialize, Clone)]
+pub struct WebhookDelivery {
+    pub delivery_id: u64,
+    pub kind: WebhookEventKind,
+    // The participant who joined or left
+    pub address: Option<String>,
+    pub created_at: u64,
+    pub status: DeliveryStatus,
+    pub attempts: u32,
+    pub next_attempt: Option<u64>,
+    // Status code of the last response, if the endpoint was reached
+    pub last_status_code: Option<u16>,
+}
+
+impl Storable for WebhookDelivery {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for WebhookDelivery {
+    const MAX_SIZE: u32 = 300;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// (next_attempt, (event_id, delivery_id)) of a pending delivery
+type DueKey = (u64, (u64, u64));
+
+// The JSON body POSTed to the webhook URL
+#[derive(Serialize)]
+struct Payload<'a> {
+    delivery_id: u64,
+    event_id: u64,
+    kind: &'static str,
+    address: Option<&'a str>,
+    timestamp: u64,
+}
+
+thread_local! {
+    static WEBHOOKS: RefCell<StableBTreeMap<u64, Webhook, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(39))),
+        )
+    );
+
+    static DELIVERIES: RefCell<StableBTreeMap<DeliveryKey, WebhookDelivery, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(40))),
+        )
+    );
+
+    // Pending deliveries by time of their next attempt, so the due ones are read without
+    // scanning the delivered ones
+    static DUE: RefCell<StableBTreeMap<DueKey, (), Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(41))),
+        )
+    );
+
+    // When the deliveries are processed next, if a timer is set
+    static NEXT_RUN: Cell<Option<u64>> = const { Cell::new(None) };
+}
+
+fn deliveries_range(event_id: u64) -> std::ops::Range<DeliveryKey> {
+    DeliveryKey { event_id, delivery_id: 0 }..DeliveryKey { event_id: event_id + 1, delivery_id: 0 }
+}
+
+fn retry_delay(attempts: u32) -> u64 {
+    let seconds = BASE_RETRY_DELAY_SECS
+        .checked_shl(attempts)
+        .unwrap_or(MAX_RETRY_DELAY_SECS)
+        .min(MAX_RETRY_DELAY_SECS);
+    seconds * NANOS_PER_SECOND
+}
+
+fn hmac_sha256(key: &[u8], message: &[u8]) -> [u8; 32] {
+    let mut block = [0u8; 64];
+    if key.len() > block.len() {
+        block[..32].copy_from_slice(&Sha256::digest(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+    let pad = |byte: u8| block.iter().map(|b| b ^ byte).collect::<Vec<u8>>();
+    let inner = Sha256::new().chain_update(pad(0x36)).chain_update(message).finalize();
+    Sha256::new().chain_update(pad(0x5c)).chain_update(inner).finalize().into()
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// Make sure the deliveries are processed at `at` at the latest
+fn schedule(at: u64) {
+    if NEXT_RUN.with(|next| next.get()).is_some_and(|next| next <= at) {
+        return;
+    }
+    NEXT_RUN.with(|next| next.set(Some(at)));
+    let delay = at.saturating_sub(ic_cdk::api::time());
+    ic_cdk_timers::set_timer(Duration::from_nanos(delay), || {
+        NEXT_RUN.with(|next| next.set(None));
+        ic_cdk::spawn(process());
+    });
+}
+
+fn schedule_next() {
+    if let Some(((at, _), _)) = DUE.with(|due| due.borrow().iter().next()) {
+        schedule(at);
+    }
+}
+
+// Timers do not survive upgrades, so processing is scheduled again
+pub fn reschedule() {
+    schedule_next();
+}
+
+// Store a delivery, keeping its entry in the due index in sync
+fn store(event_id: u64, delivery: WebhookDelivery) {
+    let key = DeliveryKey { event_id, delivery_id: delivery.delivery_id };
+    let previous = DELIVERIES.with(|deliveries| deliveries.borrow_mut().insert(key, delivery.clone()));
+    DUE.with(|due| {
+        let mut due = due.borrow_mut();
+        if let Some(at) = previous.and_then(|previous| previous.next_attempt) {
+            due.remove(&(at, (event_id, delivery.delivery_id)));
+        }
+        if let Some(at) = delivery.next_attempt {
+            due.insert((at, (event_id, delivery.delivery_id)), ());
+        }
+    });
+}
+
+pub fn len() -> u64 {
+    WEBHOOKS.with(|webhooks| webhooks.borrow().len())
+}
+
+// Webhooks ordered by event id, from the `skip`-th one
+pub fn all(skip: u64, take: u64) -> Vec<(u64, Webhook)> {
+    WEBHOOKS.with(|webhooks| webhooks.borrow().iter().skip(skip as usize).take(take as usize).collect())
+}
+
+pub fn restore(event_id: u64, webhook: Webhook) {
+    WEBHOOKS.with(|webhooks| webhooks.borrow_mut().insert(event_id, webhook));
+}
+
+pub fn deliveries_len() -> u64 {
+    DELIVERIES.with(|deliveries| deliveries.borrow().len())
+}
+
+// Deliveries ordered by (event_id, delivery_id), from the `skip`-th one
+pub fn all_deliveries(skip: u64, take: u64) -> Vec<(u64, WebhookDelivery)> {
+    DELIVERIES.with(|deliveries| {
+        deliveries
+            .borrow()
+            .iter()
+            .skip(skip as usize)
+            .take(take as usize)
+            .map(|(key, delivery)| (key.event_id, delivery))
+            .collect()
+    })
+}
+
+// Keep a delivery without scheduling it, which reschedule does
+pub fn restore_delivery(event_id: u64, delivery: WebhookDelivery) {
+    store(event_id, delivery);
+}
+
+// Queue a delivery to the webhook of an event, if it has one
+pub fn notify(event_id: u64, kind: WebhookEventKind, address: Option<&str>) {
+    if !WEBHOOKS.with(|webhooks| webhooks.borrow().contains_key(&event_id)) {
+        return;
+    }
+    let existing: Vec<WebhookDelivery> = DELIVERIES.with(|deliveries| {
+        deliveries.borrow().range(deliveries_range(event_id)).map(|(_, delivery)| delivery).collect()
+    });
+    let delivery_id = existing.last().map(|last| last.delivery_id + 1).unwrap_or(0);
+    for oldest in existing.iter().take((existing.len() + 1).saturating_sub(MAX_DELIVERIES)) {
+        DELIVERIES.with(|deliveries| {
+            deliveries.borrow_mut().remove(&DeliveryKey { event_id, delivery_id: oldest.delivery_id })
+        });
+        if let Some(at) = oldest.next_attempt {
+            DUE.with(|due| due.borrow_mut().remove(&(at, (event_id, oldest.delivery_id))));
+        }
+    }
+
+    let now = ic_cdk::api::time();
+    store(
+        event_id,
+        WebhookDelivery {
+            delivery_id,
+            kind,
+            address: address.map(str::to_string),
+            created_at: now,
+            status: DeliveryStatus::Pending,
+            attempts: 0,
+            next_attempt: Some(now),
+            last_status_code: None,
+        },
+    );
+    schedule(now);
+}
+
+// Called with the previous and new RSVP whenever a participant is stored or removed
+pub fn on_rsvp_changed(event_id: u64, address: &str, previous: Option<RsvpStatus>, next: Option<RsvpStatus>) {
+    let was_going = previous == Some(RsvpStatus::Going);
+    let is_going = next == Some(RsvpStatus::Going);
+    if is_going && !was_going {
+        notify(event_id, WebhookEventKind::ParticipantJoined, Some(address));
+    } else if was_going && !is_going {
+        notify(event_id, WebhookEventKind::ParticipantLeft, Some(address));
+    }
+}
+
+// Replicas must agree on the response of an outcall, so only its status is kept. Also
+// used for the pushes of sync.
+#[ic_cdk::query]
+pub(crate) fn transform_outcall_response(args: TransformArgs) -> HttpResponse {
+    HttpResponse { status: args.response.status, headers: Vec::new(), body: Vec::new() }
+}
+
+// POST a delivery to the webhook, returns the status code of the response
+async fn send(event_id: u64, webhook: &Webhook, delivery: &WebhookDelivery) -> Option<u16> {
+    let body = serde_json::to_vec(&Payload {
+        delivery_id: delivery.delivery_id,
+        event_id,
+        kind: delivery.kind.name(),
+        address: delivery.address.as_deref(),
+        timestamp: delivery.created_at,
+    })
+    .unwrap();
+    let signature = hmac_sha256(&webhook.secret, &body);
+    let header = |name: &str, value: String| HttpHeader { name: name.to_string(), value };
+    let request = CanisterHttpRequestArgument {
+        url: webhook.url.clone(),
+        method: HttpMethod::POST,
+        headers: vec![
+            header("Content-Type", "application/json".to_string()),
+            header("X-Webhook-Signature", format!("sha256={}", to_hex(&signature))),
+            header("X-Webhook-Delivery", format!("{}-{}", event_id, delivery.delivery_id)),
+        ],
+        body: Some(body),
+        max_response_bytes: Some(MAX_RESPONSE_BYTES),
+        transform: Some(TransformContext::new(transform_outcall_response, Vec::new())),
+    };
+    match http_request(request).await {
+        Ok((response,)) => u16::try_from(response.status.0).ok(),
+        Err(_) => None,
+    }
+}
+
+// Send the deliveries that are due, one request at a time
+async fn process() {
+    let now = ic_cdk::api::time();
+    let due: Vec<(u64, u64)> = DUE.with(|due| {
+        due.borrow()
+            .range(..=(now, (u64::MAX, u64::MAX)))
+            .take(MAX_DELIVERIES_PER_RUN)
+            .map(|((_, key), _)| key)
+            .collect()
+    });
+
+    for (event_id, delivery_id) in due {
+        let key = DeliveryKey { event_id, delivery_id };
+        let Some(mut delivery) = DELIVERIES.with(|deliveries| deliveries.borrow().get(&key)) else {
+            continue;
+        };
+        let Some(webhook) = WEBHOOKS.with(|webhooks| webhooks.borrow().get(&event_id)) else {
+            delivery.status = DeliveryStatus::Failed;
+            delivery.next_attempt = None;
+            store(event_id, delivery);
+            continue;
+        };
+        // Another run leaves the delivery alone while its request is in flight
+        delivery.next_attempt = Some(ic_cdk::api::time() + retry_delay(delivery.attempts));
+        store(event_id, delivery.clone());
+
+        let status_code = send(event_id, &webhook, &delivery).await;
+        delivery.attempts += 1;
+        delivery.last_status_code = status_code;
+        if status_code.is_some_and(|code| (200..300).contains(&code)) {
+            delivery.status = DeliveryStatus::Delivered;
+            delivery.next_attempt = None;
+        } else if delivery.attempts >= MAX_DELIVERY_ATTEMPTS {
+            delivery.status = DeliveryStatus::Failed;
+            delivery.next_attempt = None;
+        } else {
+            delivery.next_attempt = Some(ic_cdk::api::time() + retry_delay(delivery.attempts));
+        }
+        // The delivery may have been dropped for a newer one in the meantime
+        if DELIVERIES.with(|deliveries| deliveries.borrow().contains_key(&key)) {
+            store(event_id, delivery);
+        }
+    }
+    schedule_next();
+}
+
+// Register the webhook of an event, replacing the previous one. Returns the hex secret
+// the payloads are signed with: the X-Webhook-Signature header holds `sha256=` and the
+// HMAC-SHA256 of the body.
+#[ic_cdk::update]
+async fn set_webhook(event_id: u64, url: String) -> Result<String, EventError> {
+    let event = EVENTS_MAP
+        .with(|events_map| events_map.borrow().get(&event_id))
+        .ok_or(EventError::NoSuchEvent)?;
+    require_host(&event)?;
+    if !url.starts_with("https://") || url.len() > MAX_URL_LEN {
+        return Err(EventError::InvalidUrl);
+    }
+
+    let (bytes,) = raw_rand().await.map_err(|_| EventError::RandomnessUnavailable)?;
+    let secret: Vec<u8> = bytes.into_iter().take(SECRET_BYTES).collect();
+    let hex = to_hex(&secret);
+    WEBHOOKS.with(|webhooks| webhooks.borrow_mut().insert(event_id, Webhook { url, secret }));
+    Ok(hex)
+}
+
+// Remove the webhook of an event; deliveries that were not sent yet fail
+#[ic_cdk::update]
+fn remove_webhook(event_id: u64) -> Result<(), EventError> {
+    let event = EVENTS_MAP
+        .with(|events_map| events_map.borrow().get(&event_id))
+        .ok_or(EventError::NoSuchEvent)?;
+    require_host(&event)?;
+    WEBHOOKS.with(|webhooks| webhooks.borrow_mut().remove(&event_id));
+    Ok(())
+}
+
+// Query the latest deliveries to the webhook of an event, newest first
+#[ic_cdk::query]
+fn list_webhook_deliveries(event_id: u64) -> Result<Vec<WebhookDelivery>, EventError> {
+    let event = EVENTS_MAP
+        .with(|events_map| events_map.borrow().get(&event_id))
+        .ok_or(EventError::NoSuchEvent)?;
+    require_host(&event)?;
+    let mut deliveries: Vec<WebhookDelivery> = DELIVERIES.with(|deliveries| {
+        deliveries.borrow().range(deliveries_range(event_id)).map(|(_, delivery)| delivery).collect()
+    });
+    deliveries.reverse();
+    Ok(deliveries)
+}
@@ -0,0 +1,183 @@
+use ic_cdk::export::Principal;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::ops::Bound;
+use crate::types::*;
+use crate::{Memory, MEMORY_MANAGER};
+
+/// Pages of list_accounts_page hold at most this many accounts
+const MAX_ACCOUNTS_PAGE_SIZE: u64 = 1_000;
+
+/// The owner of an account. Keys are ordered by the principal's bytes, like principals.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct AccountKey(Principal);
+
+impl Storable for AccountKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(self.0.as_slice().to_vec())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        AccountKey(Principal::from_slice(&bytes))
+    }
+}
+
+impl BoundedStorable for AccountKey {
+    const MAX_SIZE: u32 = 29;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+impl Storable for Tokens {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(self.amount_e8s.to_le_bytes().to_vec())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Tokens { amount_e8s: u64::from_le_bytes(bytes.as_ref().try_into().unwrap()) }
+    }
+}
+
+impl BoundedStorable for Tokens {
+    const MAX_SIZE: u32 = 8;
+    const IS_FIXED_SIZE: bool = true;
+}
+
+type AccountsMap = RefCell<StableBTreeMap<AccountKey, Tokens, Memory>>;
+
+thread_local! {
+    // The balances live in stable memory, so they survive upgrades without being copied
+    // to and from the heap
+    static ACCOUNTS: AccountsMap = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(0))),
+        )
+    );
+
+    static UTILITY_ACCOUNTS: AccountsMap = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(2))),
+        )
+    );
+}
+
+/// The accounts of a class of internal tokens, by owner, of the governance tokens by
+/// default. A handle on the class's stable map, so the service reads and writes
+/// balances as if it held them.
+#[derive(Default)]
+pub struct Accounts {
+    class: TokenClass,
+}
+
+impl Accounts {
+    pub fn of(class: TokenClass) -> Accounts {
+        Accounts { class }
+    }
+
+    fn with<R>(&self, f: impl FnOnce(&AccountsMap) -> R) -> R {
+        match self.class {
+            TokenClass::Governance => ACCOUNTS.with(f),
+            TokenClass::Utility => UTILITY_ACCOUNTS.with(f),
+        }
+    }
+
+    pub fn get(&self, owner: &Principal) -> Option<Tokens> {
+        self.with(|accounts| accounts.borrow().get(&AccountKey(*owner)))
+    }
+
+    /// The balance of an owner, zero without an account
+    pub fn balance(&self, owner: &Principal) -> Tokens {
+        self.get(owner).unwrap_or_default()
+    }
+
+    pub fn insert(&mut self, owner: Principal, tokens: Tokens) {
+        self.with(|accounts| accounts.borrow_mut().insert(AccountKey(owner), tokens));
+    }
+
+    /// Add tokens to an account, opening it if needed. Traps if the balance would overflow,
+    /// callers that can fail check it with can_credit first.
+    pub fn credit(&mut self, owner: Principal, amount: Tokens) {
+        let balance = self.balance(&owner);
+        self.insert(owner, balance + amount);
+    }
+
+    /// Whether an account can take `amount` more tokens without its balance overflowing
+    pub fn can_credit(&self, owner: &Principal, amount: Tokens) -> bool {
+        self.balance(owner).checked_add(amount).is_some()
+    }
+
+    /// Take tokens out of an account. Returns false, taking nothing, when the account holds
+    /// fewer.
+    pub fn debit(&mut self, owner: Principal, amount: Tokens) -> bool {
+        match self.get(&owner) {
+            Some(balance) if balance >= amount => {
+                self.insert(owner, Tokens { amount_e8s: balance.amount_e8s - amount.amount_e8s });
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn remove(&mut self, owner: &Principal) {
+        self.with(|accounts| accounts.borrow_mut().remove(&AccountKey(*owner)));
+    }
+
+    pub fn len(&self) -> u64 {
+        self.with(|accounts| accounts.borrow().len())
+    }
+
+    /// Every account. Reads the whole map, so only for the rare scans of all holders.
+    pub fn entries(&self) -> Vec<(Principal, Tokens)> {
+        self.with(|accounts| accounts.borrow().iter().map(|(owner, tokens)| (owner.0, tokens)).collect())
+    }
+
+    /// Run `f` over the accounts after `cursor`, in the order of their owners. They are
+    /// read from stable memory as `f` goes, so it only pays for the accounts it reads.
+    pub fn scan_after<R>(
+        &self,
+        cursor: Option<Principal>,
+        f: impl FnOnce(&mut dyn Iterator<Item = (Principal, Tokens)>) -> R,
+    ) -> R {
+        let start = match cursor {
+            Some(cursor) => Bound::Excluded(AccountKey(cursor)),
+            None => Bound::Unbounded,
+        };
+        self.with(|accounts| {
+            f(&mut accounts.borrow().range((start, Bound::Unbounded)).map(|(owner, tokens)| (owner.0, tokens)))
+        })
+    }
+
+    /// The accounts after `cursor`, in the order of their owners
+    pub fn page(&self, cursor: Option<Principal>, limit: u64) -> Vec<Account> {
+        let start = match cursor {
+            Some(cursor) => Bound::Excluded(AccountKey(cursor)),
+            None => Bound::Unbounded,
+        };
+        self.with(|accounts| {
+            accounts.borrow()
+                .range((start, Bound::Unbounded))
+                .take(limit as usize)
+                .map(|(owner, tokens)| Account { owner: owner.0, tokens })
+                .collect()
+        })
+    }
+}
+
+/// Returns up to `limit` accounts, at most 1000, whose owners come after `cursor`. The
+/// owner of the last account is the cursor of the next page; the first page has none.
+#[ic_cdk::query]
+fn list_accounts_page(cursor: Option<Principal>, limit: u64) -> Vec<Account> {
+    Accounts::default().page(cursor, limit.min(MAX_ACCOUNTS_PAGE_SIZE))
+}
+
+#[ic_cdk::query]
+fn get_account_count() -> u64 {
+    Accounts::default().len()
+}
+
+/// Returns the caller's balance of a class of tokens
+#[ic_cdk::query]
+fn account_balance_of(class: TokenClass) -> Tokens {
+    Accounts::of(class).balance(&ic_cdk::api::caller())
+}
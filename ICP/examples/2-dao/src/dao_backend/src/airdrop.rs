@@ -0,0 +1,98 @@
+use std::time::Duration;
+use crate::types::*;
+use crate::{ledger, pause, SERVICE};
+
+/// Recipients credited per timer tick, so a tick stays within the instruction limit
+const RECIPIENTS_PER_BATCH: usize = 500;
+/// Recipients of an airdrop, so its list stays within the message size limit
+pub const MAX_RECIPIENTS: usize = 50_000;
+/// How long a paused DAO waits before looking at the next batch again
+const PAUSED_RETRY_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Execute an accepted Airdrop proposal. The tokens of every recipient are taken from
+/// the DAO's own account at once, then credited in batches by timers.
+pub fn start(proposal_id: u64, amount: Tokens, recipients: AirdropRecipients) -> Result<(), String> {
+    ledger::require_internal_accounts()?;
+
+    SERVICE.with(|service| {
+        let mut service = service.borrow_mut();
+        // With a threshold, the recipients are the accounts above it when the proposal executes
+        let recipients = match recipients {
+            AirdropRecipients::List(recipients) => recipients,
+            AirdropRecipients::HoldersAbove(threshold) => service.accounts.entries().into_iter()
+                .filter(|(_, balance)| *balance > threshold)
+                .map(|(owner, _)| owner)
+                .collect(),
+        };
+        if recipients.len() > MAX_RECIPIENTS {
+            return Err(format!("An airdrop has at most {} recipients", MAX_RECIPIENTS));
+        }
+
+        let total = amount.amount_e8s.checked_mul(recipients.len() as u64)
+            .map(|amount_e8s| Tokens { amount_e8s })
+            .ok_or_else(|| "The airdrop total overflows".to_string())?;
+        if !service.accounts.debit(ic_cdk::api::id(), total) {
+            return Err(format!("The DAO's account has insufficient funds to airdrop {:?}", total));
+        }
+        service.airdrops.push(Airdrop { proposal_id, amount, recipients, distributed: 0 });
+        Ok(())
+    })?;
+
+    schedule(proposal_id);
+    Ok(())
+}
+
+fn schedule(proposal_id: u64) {
+    ic_cdk_timers::set_timer(Duration::ZERO, move || distribute_batch(proposal_id));
+}
+
+/// Credit the next batch of recipients, and schedule the one after it
+fn distribute_batch(proposal_id: u64) {
+    crate::metrics::count_timer_run();
+    if pause::is_paused() {
+        ic_cdk_timers::set_timer(PAUSED_RETRY_INTERVAL, move || distribute_batch(proposal_id));
+        return;
+    }
+    let done = SERVICE.with(|service| {
+        let mut service = service.borrow_mut();
+        let Some(airdrop) = service.airdrops.iter_mut().find(|airdrop| airdrop.proposal_id == proposal_id) else {
+            return true;
+        };
+        let start = airdrop.distributed as usize;
+        let batch: Vec<_> = airdrop.recipients.iter().skip(start).take(RECIPIENTS_PER_BATCH).copied().collect();
+        let amount = airdrop.amount;
+        airdrop.distributed += batch.len() as u64;
+        let done = airdrop.distributed as usize >= airdrop.recipients.len();
+
+        for recipient in batch {
+            service.accounts.credit(recipient, amount);
+        }
+        done
+    });
+    if !done {
+        schedule(proposal_id);
+    }
+}
+
+/// Timers do not survive upgrades, so post_upgrade resumes the unfinished airdrops
+pub fn reschedule() {
+    let unfinished: Vec<u64> = SERVICE.with(|service| {
+        service.borrow().airdrops.iter()
+            .filter(|airdrop| (airdrop.distributed as usize) < airdrop.recipients.len())
+            .map(|airdrop| airdrop.proposal_id)
+            .collect()
+    });
+    for proposal_id in unfinished {
+        schedule(proposal_id);
+    }
+}
+
+/// Returns the progress of the airdrop of a proposal: recipients credited, and in all
+#[ic_cdk::query]
+fn get_airdrop_progress(proposal_id: u64) -> Option<(u64, u64)> {
+    SERVICE.with(|service| {
+        service.borrow().airdrops.iter()
+            .find(|airdrop| airdrop.proposal_id == proposal_id)
+            .map(|airdrop| (airdrop.distributed, airdrop.recipients.len() as u64))
+    })
+}
@@ -0,0 +1,57 @@
+use ic_cdk::export::Principal;
+use crate::types::*;
+use crate::{ledger, pause, SERVICE};
+
+/// Let `spender` transfer up to `amount` tokens from the caller's account, fees
+/// included, replacing any previous allowance. An allowance of zero revokes it.
+#[ic_cdk::update]
+fn approve(spender: Principal, amount: Tokens) -> Result<(), DaoError> {
+    pause::require_not_paused()?;
+    ledger::require_internal_accounts()?;
+
+    SERVICE.with(|service| {
+        let mut service = service.borrow_mut();
+        let owner = ic_cdk::api::caller();
+        if amount.amount_e8s == 0 {
+            service.allowances.remove(&(owner, spender));
+        } else {
+            service.allowances.insert((owner, spender), amount);
+        }
+        Ok(())
+    })
+}
+
+/// Transfer tokens from an account that approved the caller, e.g. a canister charging
+/// fees in DAO tokens. The amount and the transfer fee are taken from the allowance.
+#[ic_cdk::update]
+fn transfer_from(args: TransferFromArgs) -> Result<(), DaoError> {
+    pause::require_not_paused()?;
+    ledger::require_internal_accounts()?;
+
+    SERVICE.with(|service| {
+        let mut service = service.borrow_mut();
+        let spender = ic_cdk::api::caller();
+        let charged = args.amount + service.system_params.transfer_fee;
+
+        let allowance = service.allowances.get(&(args.from, spender)).copied().unwrap_or_default();
+        if allowance < charged {
+            return Err(format!("The allowance is insufficient to transfer {:?}", args.amount));
+        }
+        service.transfer(TokenClass::Governance, args.from, args.to, args.amount)?;
+
+        let mut remaining = allowance;
+        remaining -= charged;
+        if remaining.amount_e8s == 0 {
+            service.allowances.remove(&(args.from, spender));
+        } else {
+            service.allowances.insert((args.from, spender), remaining);
+        }
+        Ok(())
+    }).map_err(DaoError::from)
+}
+
+/// Returns the tokens `spender` may still transfer from `owner`'s account
+#[ic_cdk::query]
+fn allowance(owner: Principal, spender: Principal) -> Tokens {
+    SERVICE.with(|service| service.borrow().allowances.get(&(owner, spender)).copied().unwrap_or_default())
+}
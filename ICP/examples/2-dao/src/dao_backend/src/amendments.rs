@@ -0,0 +1,70 @@
+use crate::types::*;
+use crate::{pause, BasicDaoService, SERVICE};
+
+pub fn review_window_nanos(params: &SystemParams) -> u64 {
+    params.review_window_seconds.unwrap_or_default() * 1_000_000_000
+}
+
+impl BasicDaoService {
+    /// Keep the current version of a proposal among its revisions
+    pub fn record_revision(&mut self, proposal: &Proposal) {
+        self.revisions.entry(proposal.id).or_default().push(ProposalRevision {
+            version: proposal.version.unwrap_or(1),
+            text: proposal.text.clone(),
+            action: proposal.action.clone(),
+            timestamp: ic_cdk::api::time(),
+        });
+    }
+}
+
+/// Amend the text or action of the caller's proposal while it is in review, before voting
+/// opens. Returns the new version of the proposal.
+#[ic_cdk::update]
+fn amend_proposal(proposal_id: u64, amendment: ProposalAmendment) -> Result<u32, DaoError> {
+    pause::require_not_paused()?;
+
+    // Validation may read the service, so it comes first
+    if let Some(text) = &amendment.text {
+        text.validate()?;
+    }
+    if let Some(action) = &amendment.action {
+        action.validate()?;
+    }
+
+    SERVICE.with(|service| {
+        let mut service = service.borrow_mut();
+        let proposal = service.proposals.get_mut(&proposal_id)
+            .ok_or_else(|| DaoError::from("Proposal not found".to_string()))?;
+
+        if proposal.proposer != ic_cdk::api::caller() {
+            return Err(DaoError::Unauthorized);
+        }
+        let in_review = proposal.voting_opens_at.is_some_and(|opens_at| ic_cdk::api::time() < opens_at);
+        if proposal.state != ProposalState::Open || !in_review {
+            return Err(DaoError::from("Only a proposal in review can be amended".to_string()));
+        }
+
+        if let Some(action) = &amendment.action {
+            // The amended action keeps the proposal's topic
+            action.resolve_topic(Some(proposal.topic()))?;
+        }
+
+        if let Some(text) = amendment.text {
+            proposal.text = Some(text);
+        }
+        if let Some(action) = amendment.action {
+            proposal.action = action;
+        }
+        let version = proposal.version.unwrap_or(1) + 1;
+        proposal.version = Some(version);
+        let proposal = proposal.clone();
+        service.record_revision(&proposal);
+        Ok(version)
+    })
+}
+
+/// Returns every version of a proposal, oldest first
+#[ic_cdk::query]
+fn get_proposal_revisions(proposal_id: u64) -> Vec<ProposalRevision> {
+    SERVICE.with(|service| service.borrow().revisions.get(&proposal_id).cloned().unwrap_or_default())
+}
@@ -0,0 +1,20 @@
+use crate::types::*;
+use crate::{BasicDaoService, SERVICE};
+
+/// Oldest audit events are dropped beyond this many
+const MAX_AUDIT_EVENTS: usize = 10_000;
+
+impl BasicDaoService {
+    pub fn record(&mut self, kind: AuditEventKind) {
+        self.audit_log.push(AuditEvent { timestamp: ic_cdk::api::time(), kind });
+        if self.audit_log.len() > MAX_AUDIT_EVENTS {
+            self.audit_log.remove(0);
+        }
+    }
+}
+
+/// Returns the recorded changes of the DAO's state, newest first
+#[ic_cdk::query]
+fn get_audit_log() -> Vec<AuditEvent> {
+    SERVICE.with(|service| service.borrow().audit_log.iter().rev().cloned().collect())
+}
@@ -0,0 +1,136 @@
+use ic_cdk::api::management_canister::main::{
+    canister_status, start_canister, stop_canister, update_settings, CanisterIdRecord, CanisterSettings,
+    UpdateSettingsArgument,
+};
+use ic_cdk::export::Principal;
+use crate::types::*;
+use crate::SERVICE;
+
+const MAX_NAME_LENGTH: usize = 100;
+
+pub fn is_governed(canister_id: &Principal) -> bool {
+    SERVICE.with(|service| service.borrow().governed_canisters.contains_key(canister_id))
+}
+
+/// Checked on submission of RegisterCanister, UnregisterCanister and ManageCanister proposals
+pub fn validate(action: &ProposalAction) -> Result<(), String> {
+    match action {
+        ProposalAction::RegisterCanister { canister_id, name } => {
+            if name.len() > MAX_NAME_LENGTH {
+                return Err(format!("A canister name has at most {} characters", MAX_NAME_LENGTH));
+            }
+            if is_governed(canister_id) {
+                return Err(format!("{} is already registered", canister_id));
+            }
+            Ok(())
+        }
+        ProposalAction::UnregisterCanister { canister_id } => require_governed(canister_id),
+        ProposalAction::ManageCanister { canister_id, operation } => {
+            require_governed(canister_id)?;
+            let dao = ic_cdk::api::id();
+            match operation {
+                CanisterOperation::RemoveController(controller) if *controller == dao => {
+                    Err("The DAO cannot remove itself as a controller".to_string())
+                }
+                CanisterOperation::UpdateSettings(CanisterSettings { controllers: Some(controllers), .. })
+                    if !controllers.contains(&dao) => {
+                    Err("The controllers must include the DAO".to_string())
+                }
+                _ => Ok(()),
+            }
+        }
+        _ => Ok(()),
+    }
+}
+
+fn require_governed(canister_id: &Principal) -> Result<(), String> {
+    match is_governed(canister_id) {
+        true => Ok(()),
+        false => Err(format!("{} is not a registered canister", canister_id)),
+    }
+}
+
+/// Execute an accepted RegisterCanister proposal. The DAO must already be a controller of
+/// the canister for its ManageCanister proposals to succeed.
+pub fn register(proposal_id: u64, canister_id: Principal, name: String) -> Result<(), String> {
+    SERVICE.with(|service| {
+        let mut service = service.borrow_mut();
+        if service.governed_canisters.contains_key(&canister_id) {
+            return Err(format!("{} is already registered", canister_id));
+        }
+        service.governed_canisters.insert(canister_id, GovernedCanister {
+            canister_id,
+            name,
+            registered_at: ic_cdk::api::time(),
+        });
+        service.record(AuditEventKind::CanisterRegistered { proposal_id, canister_id });
+        Ok(())
+    })
+}
+
+/// Execute an accepted UnregisterCanister proposal. The DAO stays a controller of the
+/// canister, which only no longer takes ManageCanister proposals.
+pub fn unregister(proposal_id: u64, canister_id: Principal) -> Result<(), String> {
+    SERVICE.with(|service| {
+        let mut service = service.borrow_mut();
+        service.governed_canisters.remove(&canister_id)
+            .ok_or_else(|| format!("{} is not a registered canister", canister_id))?;
+        service.record(AuditEventKind::CanisterUnregistered { proposal_id, canister_id });
+        Ok(())
+    })
+}
+
+/// Execute an accepted ManageCanister proposal with the management canister
+pub async fn manage(proposal_id: u64, canister_id: Principal, operation: CanisterOperation) -> Result<(), String> {
+    require_governed(&canister_id)?;
+    let record = CanisterIdRecord { canister_id };
+    let result = match &operation {
+        CanisterOperation::AddController(controller) => {
+            update_controllers(canister_id, |controllers| {
+                if !controllers.contains(controller) {
+                    controllers.push(*controller);
+                }
+            }).await
+        }
+        CanisterOperation::RemoveController(controller) => {
+            update_controllers(canister_id, |controllers| controllers.retain(|c| c != controller)).await
+        }
+        CanisterOperation::Start => start_canister(record).await,
+        CanisterOperation::Stop => stop_canister(record).await,
+        CanisterOperation::UpdateSettings(settings) => {
+            update_settings(UpdateSettingsArgument { canister_id, settings: settings.clone() }).await
+        }
+    };
+    result.map_err(|(code, msg)| {
+        format!(
+            "Proposal execution failed: \
+            {:?} of canister: {}, rejection code: {:?}, message: {}",
+            operation, canister_id, code, msg
+        )
+    })?;
+
+    SERVICE.with(|service| {
+        service.borrow_mut().record(AuditEventKind::CanisterManaged { proposal_id, canister_id, operation });
+    });
+    Ok(())
+}
+
+/// Read the controllers of a canister and set them to the changed list
+async fn update_controllers(
+    canister_id: Principal,
+    change: impl FnOnce(&mut Vec<Principal>),
+) -> ic_cdk::api::call::CallResult<()> {
+    let (status,) = canister_status(CanisterIdRecord { canister_id }).await?;
+    let mut controllers = status.settings.controllers;
+    change(&mut controllers);
+    update_settings(UpdateSettingsArgument {
+        canister_id,
+        settings: CanisterSettings { controllers: Some(controllers), ..Default::default() },
+    }).await
+}
+
+/// Lists the canisters the DAO governs
+#[ic_cdk::query]
+fn list_governed_canisters() -> Vec<GovernedCanister> {
+    SERVICE.with(|service| service.borrow().governed_canisters.values().cloned().collect())
+}
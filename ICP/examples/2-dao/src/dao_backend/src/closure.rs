@@ -0,0 +1,170 @@
+use ic_cdk::export::Principal;
+use crate::accounts::Accounts;
+use crate::types::*;
+use crate::{ledger, members, pause, BasicDaoService, SERVICE};
+
+/// A sweep goes through at most this many dust accounts, to stay within the instruction
+/// limit; further sweeps take the rest
+const MAX_SWEPT_ACCOUNTS: usize = 1_000;
+/// A sweep reads at most this many accounts, so sweeps of mostly non-dust accounts stay
+/// within the instruction limit too
+const MAX_SCANNED_ACCOUNTS: usize = 10_000;
+
+impl BasicDaoService {
+    /// Close `owner`'s account of a class of tokens, sending its balance minus the transfer
+    /// fee to `to`. Governance accounts give up the allowances they granted, and cannot be
+    /// closed while their owner has voting rewards to claim, which would open it again.
+    fn close_account_of(&mut self, class: TokenClass, owner: Principal, to: Principal) -> Result<Tokens, String> {
+        let mut accounts = Accounts::of(class);
+        let Some(balance) = accounts.get(&owner) else {
+            return Err("Caller does not have an account".to_string());
+        };
+        if class == TokenClass::Governance && self.pending_rewards.contains_key(&owner) {
+            return Err("Claim the pending voting rewards before closing the account".to_string());
+        }
+        let fee = self.system_params.transfer_fee_of(class);
+
+        let amount = if balance > fee {
+            let amount = Tokens { amount_e8s: balance.amount_e8s - fee.amount_e8s };
+            self.transfer(class, owner, to, amount)?;
+            amount
+        } else {
+            self.collect_fee(class, balance);
+            Tokens::default()
+        };
+        accounts.remove(&owner);
+        if class == TokenClass::Governance {
+            self.allowances.retain(|(granted_by, _), _| *granted_by != owner);
+        }
+        Ok(amount)
+    }
+
+    /// Move the accounts of a class below the policy's threshold into its treasury, from
+    /// where the previous sweep of the class stopped. The treasury and `kept` are never
+    /// swept. Once a sweep reaches the last account, the next one starts over. Returns how
+    /// many accounts were swept and the tokens moved.
+    fn sweep_dust_of(&mut self, class: TokenClass, policy: &DustPolicy, kept: Principal) -> (u64, Tokens) {
+        let mut accounts = Accounts::of(class);
+        let cursor = self.dust_sweep_cursors.get(&class).copied();
+        let (dust, next_cursor) = accounts.scan_after(cursor, |entries| {
+            let mut dust = Vec::new();
+            let mut last = None;
+            for (owner, balance) in (&mut *entries).take(MAX_SCANNED_ACCOUNTS) {
+                last = Some(owner);
+                if balance < policy.threshold && owner != policy.treasury && owner != kept {
+                    dust.push((owner, balance));
+                    if dust.len() == MAX_SWEPT_ACCOUNTS {
+                        break;
+                    }
+                }
+            }
+            let next_cursor = if entries.next().is_some() { last } else { None };
+            (dust, next_cursor)
+        });
+
+        let mut amount = Tokens::default();
+        for (owner, balance) in &dust {
+            accounts.remove(owner);
+            amount += *balance;
+        }
+        accounts.credit(policy.treasury, amount);
+        match next_cursor {
+            Some(cursor) => self.dust_sweep_cursors.insert(class, cursor),
+            None => self.dust_sweep_cursors.remove(&class),
+        };
+        (dust.len() as u64, amount)
+    }
+}
+
+/// Close the caller's account of a class of tokens, of the governance tokens by default.
+/// The balance, minus the transfer fee, goes to `to`, and the account is removed. A
+/// balance that does not cover the fee is taken as the fee. Returns the amount sent to `to`.
+#[ic_cdk::update]
+fn close_account(to: Principal, class: Option<TokenClass>) -> Result<Tokens, DaoError> {
+    pause::require_not_paused()?;
+    let class = class.unwrap_or_default();
+    ledger::require_internal_accounts_of(class)?;
+    let caller = ic_cdk::api::caller();
+    if to == caller {
+        return Err(DaoError::from("An account cannot be closed into itself".to_string()));
+    }
+
+    SERVICE.with(|service| {
+        let mut service = service.borrow_mut();
+        let amount = service.close_account_of(class, caller, to).map_err(DaoError::from)?;
+        service.record(AuditEventKind::AccountClosed { owner: caller, to, amount, class });
+        Ok(amount)
+    })
+}
+
+/// Move the balances of the accounts of a class below the dust policy's threshold to its
+/// treasury account, and remove those accounts. The DAO's own account and the treasury
+/// are kept. Each sweep goes on from where the previous one stopped. Only callable by
+/// Admins. Returns how many accounts were swept.
+#[ic_cdk::update]
+fn sweep_dust(class: Option<TokenClass>) -> Result<u64, DaoError> {
+    pause::require_not_paused()?;
+    members::require_role(&ic_cdk::api::caller(), MemberRole::Admin)?;
+    let class = class.unwrap_or_default();
+    ledger::require_internal_accounts_of(class)?;
+
+    SERVICE.with(|service| {
+        let mut service = service.borrow_mut();
+        let Some(policy) = service.system_params.dust_policy.clone() else {
+            return Err("No dust policy is set".to_string());
+        };
+        let (swept, amount) = service.sweep_dust_of(class, &policy, ic_cdk::api::id());
+        service.record(AuditEventKind::DustSwept { treasury: policy.treasury, accounts: swept, amount, class });
+        Ok(swept)
+    }).map_err(DaoError::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn principal(byte: u8) -> Principal {
+        Principal::from_slice(&[byte; 29])
+    }
+
+    #[test]
+    fn closing_an_account_drops_its_allowances_unless_rewards_are_pending() {
+        let (owner, to, spender) = (principal(1), principal(2), principal(3));
+        let mut service = BasicDaoService::default();
+        Accounts::of(TokenClass::Governance).insert(owner, Tokens { amount_e8s: 100 });
+        service.allowances.insert((owner, spender), Tokens { amount_e8s: 50 });
+        service.allowances.insert((spender, owner), Tokens { amount_e8s: 50 });
+
+        service.pending_rewards.insert(owner, Tokens { amount_e8s: 1 });
+        assert!(service.close_account_of(TokenClass::Governance, owner, to).is_err());
+        assert_eq!(Accounts::default().balance(&owner).amount_e8s, 100);
+
+        service.pending_rewards.clear();
+        assert_eq!(service.close_account_of(TokenClass::Governance, owner, to), Ok(Tokens { amount_e8s: 100 }));
+        assert!(Accounts::default().get(&owner).is_none());
+        assert!(!service.allowances.contains_key(&(owner, spender)));
+        // Allowances granted to the owner are not theirs to drop
+        assert!(service.allowances.contains_key(&(spender, owner)));
+    }
+
+    #[test]
+    fn dust_sweeps_go_on_from_where_the_last_one_stopped() {
+        let treasury = principal(0);
+        let policy = DustPolicy { threshold: Tokens { amount_e8s: 10 }, treasury };
+        let mut service = BasicDaoService::default();
+        let mut accounts = Accounts::default();
+        let count = MAX_SWEPT_ACCOUNTS as u64 + 5;
+        for index in 0..count {
+            let owner = Principal::from_slice(&(index + 1).to_be_bytes());
+            accounts.insert(owner, Tokens { amount_e8s: 1 });
+        }
+
+        let swept = MAX_SWEPT_ACCOUNTS as u64;
+        assert_eq!(service.sweep_dust_of(TokenClass::Governance, &policy, treasury), (swept, Tokens { amount_e8s: swept }));
+        assert!(service.dust_sweep_cursors.contains_key(&TokenClass::Governance));
+        assert_eq!(service.sweep_dust_of(TokenClass::Governance, &policy, treasury), (5, Tokens { amount_e8s: 5 }));
+        assert!(service.dust_sweep_cursors.is_empty());
+        assert_eq!(accounts.len(), 1);
+        assert_eq!(accounts.balance(&treasury).amount_e8s, count);
+    }
+}
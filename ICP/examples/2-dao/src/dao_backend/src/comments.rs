@@ -0,0 +1,79 @@
+use crate::types::*;
+use crate::{pause, SERVICE};
+
+const MAX_COMMENT_LENGTH: usize = 2_000;
+const MAX_COMMENTS_PER_PROPOSAL: usize = 1_000;
+const COMMENTS_PAGE_SIZE: usize = 20;
+
+/// Comment on a proposal. Returns the id of the comment within the proposal's thread.
+#[ic_cdk::update]
+fn comment_on_proposal(proposal_id: u64, text: String) -> Result<u64, DaoError> {
+    pause::require_not_paused()?;
+    if text.trim().is_empty() || text.chars().count() > MAX_COMMENT_LENGTH {
+        return Err(DaoError::from(format!("A comment must have 1 to {} characters", MAX_COMMENT_LENGTH)));
+    }
+
+    SERVICE.with(|service| {
+        let mut service = service.borrow_mut();
+        if !service.proposals.contains_key(&proposal_id) {
+            return Err("Proposal not found".to_string());
+        }
+
+        let thread = service.comments.entry(proposal_id).or_default();
+        if thread.comments.len() >= MAX_COMMENTS_PER_PROPOSAL {
+            return Err("The proposal has too many comments".to_string());
+        }
+        let id = thread.comments.len() as u64;
+        thread.comments.push(ProposalComment {
+            id,
+            author: ic_cdk::api::caller(),
+            text,
+            timestamp: ic_cdk::api::time(),
+        });
+        Ok(id)
+    }).map_err(DaoError::from)
+}
+
+/// Pin a comment at the top of the thread. Only callable by the proposer, e.g. to
+/// clarify the proposal; pinning another comment unpins the previous one.
+#[ic_cdk::update]
+fn pin_comment(proposal_id: u64, comment_id: u64) -> Result<(), DaoError> {
+    pause::require_not_paused()?;
+
+    SERVICE.with(|service| {
+        let mut service = service.borrow_mut();
+        let proposer = service.proposals.get(&proposal_id)
+            .ok_or_else(|| DaoError::from("Proposal not found".to_string()))?
+            .proposer;
+        if proposer != ic_cdk::api::caller() {
+            return Err(DaoError::Unauthorized);
+        }
+
+        let thread = service.comments.get_mut(&proposal_id)
+            .filter(|thread| comment_id < thread.comments.len() as u64)
+            .ok_or_else(|| DaoError::from("Comment not found".to_string()))?;
+        thread.pinned = Some(comment_id);
+        Ok(())
+    })
+}
+
+/// Returns a page of the comments on a proposal, oldest first, and the pinned comment
+#[ic_cdk::query]
+fn get_proposal_comments(proposal_id: u64, page: u64) -> CommentsPage {
+    SERVICE.with(|service| {
+        let service = service.borrow();
+        let Some(thread) = service.comments.get(&proposal_id) else {
+            return CommentsPage::default();
+        };
+
+        CommentsPage {
+            pinned: thread.pinned.and_then(|id| thread.comments.get(id as usize)).cloned(),
+            comments: thread.comments.iter()
+                .skip(page as usize * COMMENTS_PAGE_SIZE)
+                .take(COMMENTS_PAGE_SIZE)
+                .cloned()
+                .collect(),
+            total: thread.comments.len() as u64,
+        }
+    })
+}
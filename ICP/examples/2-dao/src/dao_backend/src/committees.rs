@@ -0,0 +1,229 @@
+use ic_cdk::export::Principal;
+use crate::types::*;
+use crate::{ledger, pause, BasicDaoService, SERVICE};
+
+/// A committee has at most this many members
+pub const MAX_COMMITTEE_MEMBERS: usize = 50;
+const MAX_NAME_LENGTH: usize = 100;
+const MAX_SUMMARY_LENGTH: usize = 1_000;
+
+impl Committee {
+    fn is_member(&self, principal: &Principal) -> bool {
+        self.members.contains(principal)
+    }
+}
+
+/// Checked on submission of CreateCommittee proposals
+pub fn validate_committee(name: &str, members: &[Principal], vote_threshold: u32) -> Result<(), String> {
+    ledger::require_internal_accounts()?;
+    if name.len() > MAX_NAME_LENGTH {
+        return Err(format!("A committee name has at most {} characters", MAX_NAME_LENGTH));
+    }
+    if members.is_empty() || members.len() > MAX_COMMITTEE_MEMBERS {
+        return Err(format!("A committee has between 1 and {} members", MAX_COMMITTEE_MEMBERS));
+    }
+    if vote_threshold == 0 || vote_threshold as usize > members.len() {
+        return Err("The vote threshold must be between 1 and the number of members".to_string());
+    }
+    Ok(())
+}
+
+/// Take tokens from the DAO's account into a committee's budget
+fn take_from_dao(service: &mut BasicDaoService, amount: Tokens) -> Result<(), String> {
+    if !service.accounts.debit(ic_cdk::api::id(), amount) {
+        return Err(format!("The DAO's account has insufficient funds to allocate {:?}", amount));
+    }
+    Ok(())
+}
+
+/// Execute an accepted CreateCommittee proposal, allocating its budget from the DAO's account
+pub fn create(proposal_id: u64, payload: CreateCommitteePayload) -> Result<(), String> {
+    validate_committee(&payload.name, &payload.members, payload.vote_threshold)?;
+
+    SERVICE.with(|service| {
+        let mut service = service.borrow_mut();
+        take_from_dao(&mut service, payload.budget)?;
+
+        let id = service.next_committee_id;
+        service.next_committee_id += 1;
+        service.committees.insert(id, Committee {
+            id,
+            name: payload.name,
+            members: payload.members,
+            vote_threshold: payload.vote_threshold,
+            budget: payload.budget,
+            spent: Tokens::default(),
+            recipients: payload.recipients,
+            created_by_proposal: proposal_id,
+        });
+        Ok(())
+    })
+}
+
+/// Execute an accepted FundCommittee proposal
+pub fn fund(committee_id: u64, amount: Tokens) -> Result<(), String> {
+    ledger::require_internal_accounts()?;
+
+    SERVICE.with(|service| {
+        let mut service = service.borrow_mut();
+        if !service.committees.contains_key(&committee_id) {
+            return Err("Committee not found".to_string());
+        }
+        take_from_dao(&mut service, amount)?;
+        service.committees.get_mut(&committee_id).unwrap().budget += amount;
+        Ok(())
+    })
+}
+
+/// Execute an accepted DissolveCommittee proposal. The rest of its budget goes back to the
+/// DAO's account, and its open proposals are rejected.
+pub fn dissolve(committee_id: u64) -> Result<(), String> {
+    SERVICE.with(|service| {
+        let mut service = service.borrow_mut();
+        let committee = service.committees.remove(&committee_id)
+            .ok_or_else(|| "Committee not found".to_string())?;
+        service.accounts.credit(ic_cdk::api::id(), committee.budget);
+        for proposal in service.committee_proposals.values_mut() {
+            if proposal.committee_id == committee_id && proposal.state == ProposalState::Open {
+                proposal.state = ProposalState::Rejected;
+            }
+        }
+        Ok(())
+    })
+}
+
+/// Submit a transfer from the budget of a committee the caller is a member of, to be
+/// approved by the committee's vote threshold of members
+#[ic_cdk::update]
+fn submit_committee_proposal(committee_id: u64, to: Principal, amount: Tokens, summary: String) -> Result<u64, DaoError> {
+    pause::require_not_paused()?;
+    ledger::require_internal_accounts()?;
+    if summary.len() > MAX_SUMMARY_LENGTH {
+        return Err(DaoError::from(format!("A summary has at most {} characters", MAX_SUMMARY_LENGTH)));
+    }
+
+    SERVICE.with(|service| {
+        let mut service = service.borrow_mut();
+        let caller = ic_cdk::api::caller();
+        let committee = service.committees.get(&committee_id)
+            .ok_or_else(|| DaoError::from("Committee not found".to_string()))?;
+
+        if !committee.is_member(&caller) {
+            return Err(DaoError::Unauthorized);
+        }
+        if committee.recipients.as_ref().is_some_and(|recipients| !recipients.contains(&to)) {
+            return Err(DaoError::from(format!("{} is outside the committee's scope", to)));
+        }
+        if committee.budget < amount {
+            return Err(DaoError::from(format!("The committee's budget is short of {:?}", amount)));
+        }
+
+        let id = service.committee_proposals.keys().max().map_or(1, |id| id + 1);
+        service.committee_proposals.insert(id, CommitteeProposal {
+            id,
+            committee_id,
+            proposer: caller,
+            to,
+            amount,
+            summary,
+            state: ProposalState::Open,
+            votes_yes: vec![],
+            votes_no: vec![],
+            timestamp: ic_cdk::api::time(),
+        });
+        Ok(id)
+    })
+}
+
+/// Vote on a proposal of a committee the caller is a member of. Members have one vote
+/// each. The transfer is made as soon as the committee's vote threshold votes Yes, if the
+/// budget still covers it.
+#[ic_cdk::update]
+fn vote_on_committee_proposal(proposal_id: u64, vote: Vote) -> Result<ProposalState, DaoError> {
+    pause::require_not_paused()?;
+
+    SERVICE.with(|service| {
+        let mut service = service.borrow_mut();
+        let caller = ic_cdk::api::caller();
+        let proposal = service.committee_proposals.get(&proposal_id)
+            .ok_or_else(|| DaoError::from("Committee proposal not found".to_string()))?;
+        let committee = service.committees.get(&proposal.committee_id)
+            .ok_or_else(|| DaoError::from("Committee not found".to_string()))?;
+
+        if !committee.is_member(&caller) {
+            return Err(DaoError::Unauthorized);
+        }
+        if proposal.state != ProposalState::Open {
+            return Err(DaoError::from(format!("Committee proposal {} is not open for voting", proposal_id)));
+        }
+        if proposal.votes_yes.contains(&caller) || proposal.votes_no.contains(&caller) {
+            return Err(DaoError::from("Already voted".to_string()));
+        }
+
+        let threshold = committee.vote_threshold as usize;
+        let members = committee.members.len();
+        let proposal = service.committee_proposals.get_mut(&proposal_id).unwrap();
+        match vote {
+            Vote::Yes => proposal.votes_yes.push(caller),
+            Vote::No => proposal.votes_no.push(caller),
+        }
+
+        if proposal.votes_yes.len() >= threshold {
+            let (committee_id, to, amount) = (proposal.committee_id, proposal.to, proposal.amount);
+            let committee = service.committees.get_mut(&committee_id).unwrap();
+            let state = if committee.budget >= amount {
+                committee.budget -= amount;
+                committee.spent += amount;
+                service.accounts.credit(to, amount);
+                service.record(AuditEventKind::CommitteeTransfer { committee_id, proposal_id, to, amount });
+                ProposalState::Succeeded
+            } else {
+                ProposalState::Failed("The committee's budget no longer covers the transfer".to_string())
+            };
+            service.committee_proposals.get_mut(&proposal_id).unwrap().state = state;
+        } else if members - proposal.votes_no.len() < threshold {
+            proposal.state = ProposalState::Rejected;
+        }
+        Ok(service.committee_proposals[&proposal_id].state.clone())
+    })
+}
+
+#[ic_cdk::query]
+fn list_committees() -> Vec<Committee> {
+    SERVICE.with(|service| service.borrow().committees.values().cloned().collect())
+}
+
+/// Returns a page of 20 proposals of a committee, newest first
+#[ic_cdk::query]
+fn list_committee_proposals(committee_id: u64, page: u64) -> Vec<CommitteeProposal> {
+    SERVICE.with(|service| {
+        let service = service.borrow();
+        let mut proposals: Vec<CommitteeProposal> = service.committee_proposals.values()
+            .filter(|proposal| proposal.committee_id == committee_id)
+            .cloned()
+            .collect();
+        proposals.sort_by_key(|proposal| std::cmp::Reverse(proposal.id));
+        proposals.into_iter()
+            .skip(page as usize * crate::PROPOSALS_PAGE_SIZE)
+            .take(crate::PROPOSALS_PAGE_SIZE)
+            .collect()
+    })
+}
+
+/// Reports to the parent DAO how a committee used its budget
+#[ic_cdk::query]
+fn get_committee_report(committee_id: u64) -> Option<CommitteeReport> {
+    SERVICE.with(|service| {
+        let service = service.borrow();
+        let committee = service.committees.get(&committee_id)?;
+        let proposals = || service.committee_proposals.values().filter(|proposal| proposal.committee_id == committee_id);
+        Some(CommitteeReport {
+            committee_id,
+            budget: committee.budget,
+            spent: committee.spent,
+            open_proposals: proposals().filter(|proposal| proposal.state == ProposalState::Open).count() as u64,
+            executed_proposals: proposals().filter(|proposal| proposal.state == ProposalState::Succeeded).count() as u64,
+            rejected_proposals: proposals().filter(|proposal| proposal.state == ProposalState::Rejected).count() as u64,
+        })
+    })
+}
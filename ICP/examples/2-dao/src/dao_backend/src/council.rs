@@ -0,0 +1,42 @@
+use crate::types::*;
+use crate::{members, pause, BasicDaoService, SERVICE};
+
+const DEFAULT_VETO_WINDOW_SECONDS: u64 = 24 * 60 * 60;
+const MAX_JUSTIFICATION_LENGTH: usize = 2_000;
+
+/// How long accepted proposals wait before execution, so the council can veto them.
+/// Without Council members, they are executed right away.
+pub fn veto_window_nanos(service: &BasicDaoService) -> u64 {
+    if !service.members.values().any(|roles| roles.contains(&MemberRole::Council)) {
+        return 0;
+    }
+    service.system_params.veto_window_seconds.unwrap_or(DEFAULT_VETO_WINDOW_SECONDS) * 1_000_000_000
+}
+
+/// Veto an accepted proposal within its veto window. Only callable by a Council member,
+/// who must justify the veto.
+#[ic_cdk::update]
+fn veto_proposal(proposal_id: u64, justification: String) -> Result<(), DaoError> {
+    pause::require_not_paused()?;
+    let caller = ic_cdk::api::caller();
+    members::require_role(&caller, MemberRole::Council)?;
+    if justification.trim().is_empty() || justification.chars().count() > MAX_JUSTIFICATION_LENGTH {
+        return Err(DaoError::from(format!("A justification must have 1 to {} characters", MAX_JUSTIFICATION_LENGTH)));
+    }
+
+    SERVICE.with(|service| {
+        let mut service = service.borrow_mut();
+        let veto_window_nanos = veto_window_nanos(&service);
+        let now = ic_cdk::api::time();
+        let proposal = service.proposals.get_mut(&proposal_id)
+            .ok_or_else(|| DaoError::from("Proposal not found".to_string()))?;
+        let in_window = proposal.accepted_at.is_some_and(|accepted_at| now < accepted_at.saturating_add(veto_window_nanos));
+        if proposal.state != ProposalState::Accepted || !in_window {
+            return Err(DaoError::from("Only an accepted proposal within its veto window can be vetoed".to_string()));
+        }
+
+        proposal.state = ProposalState::Vetoed;
+        proposal.veto = Some(Veto { vetoed_by: caller, justification, timestamp: now });
+        Ok(())
+    })
+}
@@ -0,0 +1,67 @@
+#[cfg(not(test))]
+use std::time::Duration;
+
+/// The system API the executor uses. Tests replace it with a mock environment, in which
+/// time only moves when told to and timers run when the test runs them.
+#[cfg(not(test))]
+pub fn time() -> u64 {
+    ic_cdk::api::time()
+}
+
+#[cfg(not(test))]
+pub fn canister_balance() -> u128 {
+    ic_cdk::api::canister_balance128()
+}
+
+#[cfg(not(test))]
+pub fn set_timer(delay: Duration, func: impl FnOnce() + 'static) {
+    ic_cdk_timers::set_timer(delay, func);
+}
+
+#[cfg(test)]
+pub use mock::*;
+
+#[cfg(test)]
+pub mod mock {
+    use std::cell::{Cell, RefCell};
+    use std::time::Duration;
+
+    type Timer = (u64, Box<dyn FnOnce()>);
+
+    thread_local! {
+        static NOW: Cell<u64> = const { Cell::new(1) };
+        static TIMERS: RefCell<Vec<Timer>> = const { RefCell::new(Vec::new()) };
+    }
+
+    pub fn time() -> u64 {
+        NOW.with(Cell::get)
+    }
+
+    pub fn canister_balance() -> u128 {
+        0
+    }
+
+    pub fn set_timer(delay: Duration, func: impl FnOnce() + 'static) {
+        let at = time() + delay.as_nanos() as u64;
+        TIMERS.with(|timers| timers.borrow_mut().push((at, Box::new(func))));
+    }
+
+    pub fn advance(delay: Duration) {
+        NOW.with(|now| now.set(now.get() + delay.as_nanos() as u64));
+    }
+
+    /// Run the timers that are due, returns how many ran
+    pub fn run_timers() -> usize {
+        let now = time();
+        let due: Vec<Timer> = TIMERS.with(|timers| {
+            let (due, pending) = timers.borrow_mut().drain(..).partition(|(at, _)| *at <= now);
+            *timers.borrow_mut() = pending;
+            due
+        });
+        let count = due.len();
+        for (_, func) in due {
+            func();
+        }
+        count
+    }
+}
@@ -0,0 +1,130 @@
+use ic_cdk::export::candid::{CandidType, Deserialize, Encode};
+use ic_cdk::export::Principal;
+use crate::types::*;
+use crate::SERVICE;
+
+const MAX_EVENT_NAME_LENGTH: usize = 200;
+
+/// The errors of the events canister, as its Candid interface has them
+#[derive(CandidType, Deserialize, Debug)]
+enum EventError {
+    NoSuchEvent,
+    JoinError,
+    CancelJoinError,
+    GetEventsError,
+    AlreadyJoined,
+    AlreadyExists,
+    EventNotOpen,
+    InvalidStatusTransition,
+    InvalidRecurrence,
+    InvalidTags,
+    InvalidTime,
+    Unauthorized,
+    InvalidUrl,
+    NoSuchSource,
+    InvalidName,
+    InvalidAddress,
+    AlreadyCoHost,
+    NoSuchCoHost,
+    TooManyCoHosts,
+    RandomnessUnavailable,
+    InvalidCheckinCode,
+    NotRegistered,
+    AlreadyCheckedIn,
+    NoTicketCanister,
+    TicketCanisterUnavailable,
+    TicketMintFailed,
+    TicketNotOwned,
+    InvalidPrice,
+    PaymentRequired,
+    PaymentFailed,
+    RefundFailed,
+    LedgerUnavailable,
+    EventFull,
+    InvalidComment,
+    NoSuchComment,
+    RateLimited,
+    NotInvited,
+    BatchTooLarge,
+    InvalidIdempotencyKey,
+    InvalidImage,
+    ImageHashMismatch,
+    InvalidTimezone,
+    InvalidLocation,
+    NoPendingPromotion,
+    InvalidParent,
+    TooManySessions,
+    InvalidProfile,
+    EventNotCompleted,
+}
+
+fn events_canister() -> Result<Principal, String> {
+    SERVICE.with(|service| service.borrow().system_params.events_canister)
+        .ok_or_else(|| "No events canister is set".to_string())
+}
+
+fn call_error(method: &str) -> impl FnOnce((ic_cdk::api::call::RejectionCode, String)) -> String + '_ {
+    move |(code, msg)| {
+        format!("Could not call {} of the events canister: rejection code: {:?}, message: {}", method, code, msg)
+    }
+}
+
+/// The result of a call, kept with the proposal for members to decode with didc
+fn encode_reply<T: CandidType>(reply: &T) -> Result<Option<Vec<u8>>, String> {
+    Encode!(reply).map(Some).map_err(|e| format!("Could not encode the reply: {}", e))
+}
+
+/// Checked on submission of the proposals calling the events canister
+pub fn validate(action: &ProposalAction) -> Result<(), String> {
+    events_canister()?;
+    if let ProposalAction::CreateEvent { name, start_time, end_time, .. } = action {
+        if name.trim().is_empty() || name.chars().count() > MAX_EVENT_NAME_LENGTH {
+            return Err(format!("An event's name must have 1 to {} characters", MAX_EVENT_NAME_LENGTH));
+        }
+        if end_time <= start_time {
+            return Err("An event must end after it starts".to_string());
+        }
+    }
+    Ok(())
+}
+
+/// Execute an accepted CreateEvent proposal: create the event, organized by the DAO, and
+/// publish it. The proposal id is the idempotency key of the creation, so a retried
+/// execution publishes the event created by an earlier attempt instead of another one.
+pub async fn create_event(
+    proposal_id: u64,
+    name: String,
+    start_time: u64,
+    end_time: u64,
+    max_participants: Option<u32>,
+) -> Result<Option<Vec<u8>>, String> {
+    let canister_id = events_canister()?;
+    let idempotency_key = Some(format!("dao-proposal-{}", proposal_id));
+    let (created,): (Result<u64, EventError>,) =
+        ic_cdk::call(canister_id, "create_event", (name, start_time, end_time, max_participants, idempotency_key)).await
+            .map_err(call_error("create_event"))?;
+    let event_id = created.map_err(|e| format!("The events canister did not create the event: {:?}", e))?;
+
+    let (published,): (Result<(), EventError>,) = ic_cdk::call(canister_id, "publish_event", (event_id,)).await
+        .map_err(call_error("publish_event"))?;
+    published.map_err(|e| format!("The events canister did not publish event {}: {:?}", event_id, e))?;
+    encode_reply(&event_id)
+}
+
+/// Execute an accepted CancelEvent proposal. The events canister refunds and notifies the
+/// participants.
+pub async fn cancel_event(event_id: u64) -> Result<Option<Vec<u8>>, String> {
+    let (cancelled,): (Result<(), EventError>,) = ic_cdk::call(events_canister()?, "cancel_event", (event_id,)).await
+        .map_err(call_error("cancel_event"))?;
+    cancelled.map_err(|e| format!("The events canister did not cancel event {}: {:?}", event_id, e))?;
+    encode_reply(&event_id)
+}
+
+/// Execute an accepted SetEventsAdmin proposal. The DAO must be the events canister's
+/// admin for the call to succeed, and is no longer once it hands the role over.
+pub async fn set_admin(admin: Principal) -> Result<Option<Vec<u8>>, String> {
+    let (set,): (Result<(), EventError>,) = ic_cdk::call(events_canister()?, "set_admin", (admin,)).await
+        .map_err(call_error("set_admin"))?;
+    set.map_err(|e| format!("The events canister did not change its admin: {:?}", e))?;
+    encode_reply(&admin)
+}
@@ -0,0 +1,450 @@
+use std::future::Future;
+use std::time::Duration;
+use crate::types::*;
+use crate::{council, env, set_proposal_state, SERVICE};
+
+const DEFAULT_MAX_EXECUTION_ATTEMPTS: u32 = 3;
+const DEFAULT_EXECUTION_RETRY_DELAY_SECONDS: u64 = 60;
+const NANOS_PER_SECOND: u64 = 1_000_000_000;
+/// Replies of CallExternal proposals are kept up to this size
+const MAX_REPLY_BYTES: usize = 4 * 1024;
+/// How long an execution may hold a proposal, after which the proposal is executed again
+const EXECUTION_LEASE_SECONDS: u64 = 60 * 60;
+const INTERRUPTED_ERROR: &str = "The execution was interrupted by an upgrade; its action may have taken effect";
+const EXPIRED_ERROR: &str = "The execution did not end before its lease expired";
+
+/// Mark a proposal Executing under a new lease. Heartbeats interleave with the awaits of
+/// executions, so only the lease holder may change the proposal's state afterwards.
+pub fn acquire_lease(proposal: &mut Proposal) -> ExecutionLease {
+    let now = env::time();
+    let lease = ExecutionLease {
+        acquired_at: now,
+        expires_at: now.saturating_add(EXECUTION_LEASE_SECONDS * NANOS_PER_SECOND),
+    };
+    proposal.state = ProposalState::Executing;
+    proposal.execution_lease = Some(lease);
+    lease
+}
+
+/// Take the lease of a proposal right before executing it, so waiting behind other
+/// executions does not count towards it. None when the proposal is no longer due, e.g.
+/// because another heartbeat took it meanwhile.
+fn take_lease(proposal_id: u64) -> Option<ExecutionLease> {
+    SERVICE.with(|service| {
+        let mut service = service.borrow_mut();
+        let veto_window_nanos = council::veto_window_nanos(&service);
+        let proposal = service.proposals.get_mut(&proposal_id)?;
+        is_due(proposal, env::time(), veto_window_nanos).then(|| acquire_lease(proposal))
+    })
+}
+
+/// Accepted proposals are executed once their veto window is over
+fn is_due(proposal: &Proposal, now: u64, veto_window_nanos: u64) -> bool {
+    proposal.state == ProposalState::Accepted
+        && proposal.accepted_at.is_none_or(|accepted_at| now >= accepted_at.saturating_add(veto_window_nanos))
+}
+
+/// Put the proposals whose execution outlived its lease back among the Accepted proposals,
+/// counting the execution as a failed attempt. The proposal Fails once it has used up its
+/// attempts. The late outcome of the lost execution is ignored.
+fn expire_leases() {
+    let now = env::time();
+    SERVICE.with(|service| {
+        let mut service = service.borrow_mut();
+        let max_attempts = service.system_params.max_execution_attempts.unwrap_or(DEFAULT_MAX_EXECUTION_ATTEMPTS);
+        let expired: Vec<u64> = service.proposals.values()
+            .filter(|proposal| proposal.state == ProposalState::Executing)
+            .filter(|proposal| proposal.execution_lease.is_some_and(|lease| lease.expires_at <= now))
+            .map(|proposal| proposal.id)
+            .collect();
+        for proposal_id in expired {
+            let Some(proposal) = service.proposals.get_mut(&proposal_id) else {
+                continue;
+            };
+            let attempt = proposal.execution_attempts.unwrap_or_default() + 1;
+            proposal.execution_attempts = Some(attempt);
+            proposal.execution_failures.get_or_insert_with(Vec::new).push(ExecutionFailure {
+                attempt,
+                timestamp: now,
+                error: EXPIRED_ERROR.to_string(),
+            });
+            proposal.execution_lease = None;
+            if attempt >= max_attempts {
+                proposal.state = ProposalState::Failed(EXPIRED_ERROR.to_string());
+                service.publish(proposal_id);
+            } else {
+                proposal.state = ProposalState::Accepted;
+            }
+        }
+    })
+}
+
+/// Give up the lease of an execution that ended. Returns false when the execution no
+/// longer held it, so its outcome must not be applied.
+fn release_lease(proposal_id: u64, lease: ExecutionLease) -> bool {
+    SERVICE.with(|service| {
+        let mut service = service.borrow_mut();
+        match service.proposals.get_mut(&proposal_id) {
+            Some(proposal) if holds_lease(proposal, lease) => {
+                proposal.execution_lease = None;
+                true
+            }
+            _ => false,
+        }
+    })
+}
+
+fn holds_lease(proposal: &Proposal, lease: ExecutionLease) -> bool {
+    proposal.state == ProposalState::Executing && proposal.execution_lease == Some(lease)
+}
+
+/// Execute all accepted proposals whose veto window is over, one at a time, with
+/// `execute`. Heartbeats interleave with its awaits, so each proposal is held under a
+/// lease, taken right before it executes, until its execution returns. Proposals whose
+/// lease expired are first put back among the Accepted proposals.
+pub async fn execute_accepted<F, Fut>(execute: F)
+where
+    F: Fn(u64) -> Fut,
+    Fut: Future<Output = Result<Option<Vec<u8>>, String>>,
+{
+    expire_leases();
+    let accepted_proposals: Vec<u64> = SERVICE.with(|service| {
+        let service = service.borrow();
+        let veto_window_nanos = council::veto_window_nanos(&service);
+        let now = env::time();
+        service.proposals.values()
+            .filter(|proposal| is_due(proposal, now, veto_window_nanos))
+            .map(|proposal| proposal.id)
+            .collect()
+    });
+
+    for proposal_id in accepted_proposals {
+        let Some(lease) = take_lease(proposal_id) else {
+            continue;
+        };
+        let cycles_before = env::canister_balance();
+        let result = execute(proposal_id).await;
+        if !release_lease(proposal_id, lease) {
+            continue;
+        }
+        record(proposal_id, cycles_before, &result);
+        let result = result.map(|_| ());
+        SERVICE.with(|service| {
+            let stats = &mut service.borrow_mut().stats;
+            match result {
+                Ok(()) => stats.executions_succeeded += 1,
+                Err(_) => stats.executions_failed += 1,
+            }
+        });
+        match result {
+            Ok(()) => set_proposal_state(proposal_id, ProposalState::Succeeded),
+            Err(msg) => on_failure(proposal_id, msg),
+        }
+    }
+}
+
+/// Fail the proposals left Executing by an upgrade. The upgrade waits for the calls in
+/// flight, so their executions will never end, e.g. because a callback trapped. Their
+/// action may have taken effect, so they are not retried: a RetryExecution proposal
+/// executes them again.
+pub fn fail_interrupted_executions() {
+    let interrupted: Vec<u64> = SERVICE.with(|service| {
+        service.borrow().proposals.values()
+            .filter(|proposal| proposal.state == ProposalState::Executing)
+            .map(|proposal| proposal.id)
+            .collect()
+    });
+    for proposal_id in interrupted {
+        SERVICE.with(|service| {
+            if let Some(proposal) = service.borrow_mut().proposals.get_mut(&proposal_id) {
+                let attempt = proposal.execution_attempts.unwrap_or_default() + 1;
+                proposal.execution_attempts = Some(attempt);
+                proposal.execution_failures.get_or_insert_with(Vec::new).push(ExecutionFailure {
+                    attempt,
+                    timestamp: env::time(),
+                    error: INTERRUPTED_ERROR.to_string(),
+                });
+                proposal.execution_lease = None;
+            }
+        });
+        set_proposal_state(proposal_id, ProposalState::Failed(INTERRUPTED_ERROR.to_string()));
+    }
+}
+
+/// Keep the outcome of an execution attempt on the proposal, so members can check what it
+/// returned
+pub fn record(proposal_id: u64, cycles_before: u128, result: &Result<Option<Vec<u8>>, String>) {
+    let cycles_used = cycles_before.saturating_sub(env::canister_balance());
+    SERVICE.with(|service| {
+        if let Some(proposal) = service.borrow_mut().proposals.get_mut(&proposal_id) {
+            let reply = result.as_ref().ok().cloned().flatten();
+            let reply_truncated = reply.as_ref().is_some_and(|reply| reply.len() > MAX_REPLY_BYTES);
+            proposal.execution = Some(ProposalExecutionRecord {
+                executed_at: env::time(),
+                reply: reply.map(|mut reply| {
+                    reply.truncate(MAX_REPLY_BYTES);
+                    reply
+                }),
+                reply_truncated,
+                cycles_used,
+                error: result.as_ref().err().cloned(),
+            });
+        }
+    })
+}
+
+/// Record a failed execution of a proposal. The proposal Fails once it has used up its
+/// attempts, otherwise a timer puts it back among the Accepted proposals, which the
+/// heartbeat executes, after an exponential backoff.
+pub fn on_failure(proposal_id: u64, error: String) {
+    let retry_delay = SERVICE.with(|service| {
+        let mut service = service.borrow_mut();
+        let max_attempts = service.system_params.max_execution_attempts.unwrap_or(DEFAULT_MAX_EXECUTION_ATTEMPTS);
+        let base_delay = service.system_params.execution_retry_delay_seconds
+            .unwrap_or(DEFAULT_EXECUTION_RETRY_DELAY_SECONDS);
+        let proposal = service.proposals.get_mut(&proposal_id)?;
+
+        let attempt = proposal.execution_attempts.unwrap_or_default() + 1;
+        proposal.execution_attempts = Some(attempt);
+        proposal.execution_failures.get_or_insert_with(Vec::new).push(ExecutionFailure {
+            attempt,
+            timestamp: env::time(),
+            error: error.clone(),
+        });
+
+        if attempt >= max_attempts {
+            proposal.state = ProposalState::Failed(error);
+            service.publish(proposal_id);
+            return None;
+        }
+        let delay_seconds = base_delay.saturating_mul(1 << (attempt - 1).min(32));
+        proposal.state = ProposalState::RetryScheduled {
+            retry_at: env::time().saturating_add(delay_seconds.saturating_mul(NANOS_PER_SECOND)),
+        };
+        Some(Duration::from_secs(delay_seconds))
+    });
+
+    if let Some(delay) = retry_delay {
+        env::set_timer(delay, move || requeue(proposal_id));
+    }
+}
+
+/// Put a proposal whose retry is due back among the Accepted proposals
+fn requeue(proposal_id: u64) {
+    crate::metrics::count_timer_run();
+    SERVICE.with(|service| {
+        if let Some(proposal) = service.borrow_mut().proposals.get_mut(&proposal_id) {
+            if matches!(proposal.state, ProposalState::RetryScheduled { .. }) {
+                proposal.state = ProposalState::Accepted;
+            }
+        }
+    })
+}
+
+/// Timers do not survive upgrades, so post_upgrade sets those of the scheduled retries
+/// again
+pub fn reschedule() {
+    let now = env::time();
+    let scheduled: Vec<(u64, u64)> = SERVICE.with(|service| {
+        service.borrow().proposals.values()
+            .filter_map(|proposal| match proposal.state {
+                ProposalState::RetryScheduled { retry_at } => Some((proposal.id, retry_at)),
+                _ => None,
+            })
+            .collect()
+    });
+    for (proposal_id, retry_at) in scheduled {
+        let delay = Duration::from_nanos(retry_at.saturating_sub(now));
+        env::set_timer(delay, move || requeue(proposal_id));
+    }
+}
+
+/// Execute an accepted RetryExecution proposal: the Failed proposal is executed again
+/// by the next heartbeat, with a new round of attempts. Its failures are kept.
+pub fn retry_execution(proposal_id: u64) -> Result<(), String> {
+    SERVICE.with(|service| {
+        let mut service = service.borrow_mut();
+        let proposal = service.proposals.get_mut(&proposal_id)
+            .ok_or_else(|| format!("Proposal {} not found", proposal_id))?;
+
+        if !matches!(proposal.state, ProposalState::Failed(_)) {
+            return Err(format!("Proposal {} has not failed", proposal_id));
+        }
+        proposal.execution_attempts = Some(0);
+        proposal.state = ProposalState::Accepted;
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ic_cdk::export::Principal;
+    use std::cell::RefCell;
+    use std::pin::Pin;
+    use std::rc::Rc;
+    use std::task::{Context, Poll, Waker};
+
+    type Reply = Result<Option<Vec<u8>>, String>;
+    type ReplySlot = Rc<RefCell<Option<Reply>>>;
+
+    /// A call of an execution, which returns once the test replies to it
+    struct PendingCall(ReplySlot);
+
+    impl Future for PendingCall {
+        type Output = Reply;
+
+        fn poll(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Reply> {
+            match self.0.borrow_mut().take() {
+                Some(reply) => Poll::Ready(reply),
+                None => Poll::Pending,
+            }
+        }
+    }
+
+    /// The canisters the executions call, as a list of the calls made so far
+    #[derive(Default)]
+    struct MockCalls(RefCell<Vec<(u64, ReplySlot)>>);
+
+    impl MockCalls {
+        fn heartbeat(&self) -> Pin<Box<dyn Future<Output = ()> + '_>> {
+            Box::pin(execute_accepted(move |proposal_id| {
+                let reply = Rc::new(RefCell::new(None));
+                self.0.borrow_mut().push((proposal_id, reply.clone()));
+                PendingCall(reply)
+            }))
+        }
+
+        fn count(&self) -> usize {
+            self.0.borrow().len()
+        }
+
+        fn reply(&self, call: usize, reply: Reply) {
+            *self.0.borrow()[call].1.borrow_mut() = Some(reply);
+        }
+    }
+
+    /// Run a heartbeat until its next await, returns whether it ended
+    fn poll(heartbeat: &mut Pin<Box<dyn Future<Output = ()> + '_>>) -> bool {
+        heartbeat.as_mut().poll(&mut Context::from_waker(Waker::noop())).is_ready()
+    }
+
+    fn accept_proposal(id: u64) {
+        let mut proposal = Proposal::for_test(id, Principal::anonymous());
+        proposal.state = ProposalState::Accepted;
+        SERVICE.with(|service| service.borrow_mut().proposals.insert(id, proposal));
+    }
+
+    fn state_of(id: u64) -> ProposalState {
+        SERVICE.with(|service| service.borrow().proposals[&id].state.clone())
+    }
+
+    #[test]
+    fn an_executing_proposal_is_not_picked_up_again() {
+        accept_proposal(1);
+        let calls = MockCalls::default();
+
+        let mut first = calls.heartbeat();
+        assert!(!poll(&mut first));
+        assert_eq!(state_of(1), ProposalState::Executing);
+        assert!(poll(&mut calls.heartbeat()));
+        assert_eq!(calls.count(), 1);
+
+        calls.reply(0, Ok(None));
+        assert!(poll(&mut first));
+        assert_eq!(state_of(1), ProposalState::Succeeded);
+        assert!(poll(&mut calls.heartbeat()));
+        assert_eq!(calls.count(), 1);
+    }
+
+    #[test]
+    fn a_proposal_whose_lease_expired_is_executed_again() {
+        accept_proposal(1);
+        let calls = MockCalls::default();
+
+        let mut first = calls.heartbeat();
+        assert!(!poll(&mut first));
+        env::advance(Duration::from_secs(2 * EXECUTION_LEASE_SECONDS));
+        let mut second = calls.heartbeat();
+        assert!(!poll(&mut second));
+        assert_eq!(calls.count(), 2);
+        assert_eq!(state_of(1), ProposalState::Executing);
+        let failures = SERVICE.with(|service| service.borrow().proposals[&1].execution_failures.clone().unwrap());
+        assert_eq!(failures[0].error, EXPIRED_ERROR);
+
+        // The late outcome of the first execution is ignored
+        calls.reply(0, Err("rejected".to_string()));
+        assert!(poll(&mut first));
+        assert_eq!(state_of(1), ProposalState::Executing);
+        calls.reply(1, Ok(None));
+        assert!(poll(&mut second));
+        assert_eq!(state_of(1), ProposalState::Succeeded);
+    }
+
+    #[test]
+    fn leases_are_taken_as_each_proposal_executes() {
+        accept_proposal(1);
+        accept_proposal(2);
+        let calls = MockCalls::default();
+
+        let mut heartbeat = calls.heartbeat();
+        assert!(!poll(&mut heartbeat));
+        // Proposals are executed in no particular order
+        let first = calls.0.borrow()[0].0;
+        let second = 3 - first;
+        assert_eq!(state_of(first), ProposalState::Executing);
+        assert_eq!(state_of(second), ProposalState::Accepted);
+
+        // The second proposal's lease starts when it executes, not when the heartbeat began
+        env::advance(Duration::from_secs(EXECUTION_LEASE_SECONDS / 2));
+        calls.reply(0, Ok(None));
+        assert!(!poll(&mut heartbeat));
+        let lease = SERVICE.with(|service| service.borrow().proposals[&second].execution_lease.unwrap());
+        assert_eq!(lease.acquired_at, env::time());
+        calls.reply(1, Ok(None));
+        assert!(poll(&mut heartbeat));
+        assert_eq!(state_of(second), ProposalState::Succeeded);
+    }
+
+    #[test]
+    fn a_reported_failure_is_retried_until_the_attempts_run_out() {
+        accept_proposal(1);
+        SERVICE.with(|service| service.borrow_mut().system_params.max_execution_attempts = Some(2));
+        let calls = MockCalls::default();
+
+        let mut first = calls.heartbeat();
+        assert!(!poll(&mut first));
+        calls.reply(0, Err("rejected".to_string()));
+        assert!(poll(&mut first));
+        assert!(matches!(state_of(1), ProposalState::RetryScheduled { .. }));
+
+        // Not before the backoff is over
+        assert!(poll(&mut calls.heartbeat()));
+        assert_eq!(calls.count(), 1);
+        env::advance(Duration::from_secs(DEFAULT_EXECUTION_RETRY_DELAY_SECONDS));
+        assert_eq!(env::run_timers(), 1);
+        assert_eq!(state_of(1), ProposalState::Accepted);
+
+        let mut second = calls.heartbeat();
+        assert!(!poll(&mut second));
+        calls.reply(1, Err("rejected again".to_string()));
+        assert!(poll(&mut second));
+        assert_eq!(state_of(1), ProposalState::Failed("rejected again".to_string()));
+        let attempts = SERVICE.with(|service| service.borrow().proposals[&1].execution_failures.clone().unwrap().len());
+        assert_eq!(attempts, 2);
+    }
+
+    #[test]
+    fn an_execution_interrupted_by_an_upgrade_fails_without_a_retry() {
+        accept_proposal(1);
+        let calls = MockCalls::default();
+        assert!(!poll(&mut calls.heartbeat()));
+
+        // The heartbeat is dropped with its call, as by an upgrade
+        fail_interrupted_executions();
+        assert_eq!(state_of(1), ProposalState::Failed(INTERRUPTED_ERROR.to_string()));
+        assert_eq!(env::run_timers(), 0);
+        assert!(poll(&mut calls.heartbeat()));
+        assert_eq!(calls.count(), 1);
+    }
+}
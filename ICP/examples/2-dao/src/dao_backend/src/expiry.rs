@@ -0,0 +1,97 @@
+use std::time::Duration;
+use crate::types::*;
+use crate::{pause, settle_deposit, BasicDaoService, PROPOSALS_PAGE_SIZE, SERVICE};
+
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// Proposals expired per sweep, so a sweep never runs out of instructions
+const MAX_EXPIRED_PER_SWEEP: usize = 100;
+
+/// Timers do not survive upgrades, so this is called from init and post_upgrade
+pub fn start() {
+    ic_cdk_timers::set_timer_interval(SWEEP_INTERVAL, sweep);
+}
+
+/// When an Open proposal expires: the voting period of its topic after voting opened,
+/// never when the topic has no voting period
+pub fn expires_at(params: &SystemParams, proposal: &Proposal) -> Option<u64> {
+    let voting_period = params.effective_params(proposal.topic()).voting_period_seconds?;
+    let opens_at = proposal.voting_opens_at.unwrap_or(proposal.timestamp);
+    Some(opens_at + voting_period * 1_000_000_000)
+}
+
+/// Whether voting on an Open proposal has ended at `now`
+pub fn has_expired(params: &SystemParams, proposal: &Proposal, now: u64) -> bool {
+    expires_at(params, proposal).is_some_and(|expires_at| now >= expires_at)
+}
+
+/// Mark an Open proposal as Expired and settle its deposit by the expired deposit policy
+pub fn expire(service: &mut BasicDaoService, proposal_id: u64) {
+    service.proposals.get_mut(&proposal_id).unwrap().state = ProposalState::Expired;
+    settle_deposit(service, proposal_id);
+    let deposit = service.proposals[&proposal_id].deposit.as_ref().map(|deposit| deposit.state.clone());
+    service.record(AuditEventKind::ProposalExpired { proposal_id, deposit });
+}
+
+/// Mark the Open proposals whose voting opened longer than the voting period of their topic
+/// ago as Expired, settling their deposits by the expired deposit policy
+fn sweep() {
+    crate::metrics::count_timer_run();
+    if pause::is_paused() {
+        return;
+    }
+
+    SERVICE.with(|service| {
+        let mut service = service.borrow_mut();
+        let now = ic_cdk::api::time();
+        let expired: Vec<u64> = service.proposals.values()
+            .filter(|proposal| proposal.state == ProposalState::Open)
+            .filter(|proposal| has_expired(&service.system_params, proposal, now))
+            .map(|proposal| proposal.id)
+            .take(MAX_EXPIRED_PER_SWEEP)
+            .collect();
+
+        for proposal_id in expired {
+            expire(&mut service, proposal_id);
+        }
+    });
+}
+
+/// Returns a page of 20 expired proposals, newest first
+#[ic_cdk::query]
+fn get_expired_proposals(page: u64) -> Vec<Proposal> {
+    SERVICE.with(|service| {
+        let service = service.borrow();
+        let mut proposals: Vec<&Proposal> = service.proposals.values()
+            .filter(|proposal| proposal.state == ProposalState::Expired)
+            .collect();
+        proposals.sort_by_key(|proposal| std::cmp::Reverse(proposal.id));
+        proposals.into_iter()
+            .skip(page as usize * PROPOSALS_PAGE_SIZE)
+            .take(PROPOSALS_PAGE_SIZE)
+            .cloned()
+            .collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ic_cdk::export::Principal;
+
+    const SECOND: u64 = 1_000_000_000;
+
+    #[test]
+    fn voting_ends_a_voting_period_after_it_opens() {
+        let params = SystemParams { proposal_expiry_seconds: Some(60), ..Default::default() };
+        let mut proposal = Proposal::for_test(1, Principal::anonymous());
+        assert!(!has_expired(&params, &proposal, 59 * SECOND));
+        assert!(has_expired(&params, &proposal, 60 * SECOND));
+
+        // Proposals in review expire a voting period after voting opens
+        proposal.voting_opens_at = Some(30 * SECOND);
+        assert!(!has_expired(&params, &proposal, 89 * SECOND));
+        assert!(has_expired(&params, &proposal, 90 * SECOND));
+
+        assert!(!has_expired(&SystemParams::default(), &proposal, u64::MAX));
+    }
+}
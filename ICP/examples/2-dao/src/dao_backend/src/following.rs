@@ -0,0 +1,168 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use ic_cdk::export::Principal;
+use crate::types::*;
+use crate::{pause, BasicDaoService, SERVICE};
+
+impl BasicDaoService {
+    /// Whom a follower follows on a topic
+    fn followee_of(&self, follower: Principal, topic: Topic) -> Option<Principal> {
+        self.followees.get(&(follower, Some(topic)))
+            .or_else(|| self.followees.get(&(follower, None)))
+            .copied()
+    }
+
+    /// The followers of each followee on a topic
+    fn followers_by_followee(&self, topic: Topic) -> HashMap<Principal, Vec<Principal>> {
+        let followers: HashSet<Principal> = self.followees.keys().map(|(follower, _)| *follower).collect();
+        let mut by_followee: HashMap<Principal, Vec<Principal>> = HashMap::new();
+        for follower in followers {
+            if let Some(followee) = self.followee_of(follower, topic) {
+                by_followee.entry(followee).or_default().push(follower);
+            }
+        }
+        by_followee
+    }
+
+    /// Cast the vote of a voter for their followers, and for their followers in turn. The
+    /// ballots cast by following are cast again when the vote changes; followers who voted
+    /// themselves keep their vote. Followers without voting power on the proposal are
+    /// skipped.
+    pub fn cascade_to_followers(&mut self, proposal_id: u64, voter: Principal, vote: Vote) {
+        let Some(topic) = self.proposals.get(&proposal_id).map(|proposal| proposal.topic()) else {
+            return;
+        };
+        let followers = self.followers_by_followee(topic);
+
+        let mut reached = HashSet::from([voter]);
+        let mut followees = VecDeque::from([voter]);
+        while let Some(followee) = followees.pop_front() {
+            for follower in followers.get(&followee).into_iter().flatten() {
+                if !reached.insert(*follower) {
+                    continue;
+                }
+                let proposal = &self.proposals[&proposal_id];
+                let followed_ballot = proposal.ballot_of(follower).cloned();
+                let voting_power = match &followed_ballot {
+                    Some(ballot) if !ballot.followed || ballot.vote == vote => continue,
+                    Some(ballot) => ballot.voting_power,
+                    // Voted before ballots were kept
+                    None if proposal.voters.contains(follower) => continue,
+                    None => self.proposal_voting_power(proposal_id, follower),
+                };
+                if voting_power.amount_e8s == 0 {
+                    continue;
+                }
+                let weighted_power = self.weighted_power(proposal_id, voting_power);
+                match &followed_ballot {
+                    Some(ballot) => self.proposals.get_mut(&proposal_id).unwrap().retract(ballot),
+                    None => self.index_vote(*follower, proposal_id),
+                }
+                let ballot = Ballot { voter: *follower, vote, voting_power, followed: true, weighted_power };
+                self.proposals.get_mut(&proposal_id).unwrap().cast(ballot);
+                followees.push_back(*follower);
+            }
+        }
+    }
+}
+
+/// Follow a principal on a topic, or on every topic without one: the caller's votes are
+/// cast like the followee's until the caller votes themself. Following nobody stops it.
+#[ic_cdk::update]
+fn follow(topic: Option<Topic>, followee: Option<Principal>) -> Result<(), DaoError> {
+    pause::require_not_paused()?;
+
+    SERVICE.with(|service| {
+        let mut service = service.borrow_mut();
+        let caller = ic_cdk::api::caller();
+        match followee {
+            Some(followee) if followee == caller => return Err(DaoError::from("Cannot follow oneself".to_string())),
+            Some(followee) => service.followees.insert((caller, topic), followee),
+            None => service.followees.remove(&(caller, topic)),
+        };
+        Ok(())
+    })
+}
+
+/// Returns whom the caller follows, by topic
+#[ic_cdk::query]
+fn get_followees() -> Vec<Follow> {
+    SERVICE.with(|service| {
+        let caller = ic_cdk::api::caller();
+        service.borrow().followees.iter()
+            .filter(|((follower, _), _)| *follower == caller)
+            .map(|((follower, topic), followee)| Follow { follower: *follower, topic: *topic, followee: *followee })
+            .collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn principal(n: u8) -> Principal {
+        Principal::from_slice(&[n; 29])
+    }
+
+    /// A DAO with a proposal voted on by principals 1 to 4, with 1 token each
+    fn service() -> BasicDaoService {
+        let mut proposal = Proposal::for_test(1, principal(0));
+        proposal.voting_power_snapshot = Some((1..=4).map(|n| (principal(n), Tokens { amount_e8s: 1 })).collect());
+        let mut service = BasicDaoService::default();
+        service.proposals.insert(1, proposal);
+        service
+    }
+
+    fn cast_own(service: &mut BasicDaoService, voter: Principal, vote: Vote) {
+        let proposal = service.proposals.get_mut(&1).unwrap();
+        if let Some(ballot) = proposal.ballot_of(&voter).cloned() {
+            proposal.retract(&ballot);
+        }
+        let voting_power = Tokens { amount_e8s: 1 };
+        proposal.cast(Ballot { voter, vote, voting_power, followed: false, weighted_power: None });
+        service.cascade_to_followers(1, voter, vote);
+    }
+
+    fn tally(service: &BasicDaoService) -> (u64, u64) {
+        let proposal = &service.proposals[&1];
+        (proposal.votes_yes.amount_e8s, proposal.votes_no.amount_e8s)
+    }
+
+    #[test]
+    fn followed_votes_change_with_the_followee() {
+        // 3 follows 2, who follows 1
+        let mut service = service();
+        service.followees.insert((principal(2), None), principal(1));
+        service.followees.insert((principal(3), None), principal(2));
+
+        cast_own(&mut service, principal(1), Vote::Yes);
+        assert_eq!(tally(&service), (3, 0));
+
+        // 2 votes themself, 3 still follows them
+        cast_own(&mut service, principal(2), Vote::No);
+        assert_eq!(tally(&service), (1, 2));
+        assert!(service.proposals[&1].ballot_of(&principal(3)).is_some_and(|ballot| ballot.followed && ballot.vote == Vote::No));
+        assert_eq!(service.proposals[&1].voters.len(), 3);
+    }
+
+    #[test]
+    fn own_votes_are_kept() {
+        let mut service = service();
+        service.followees.insert((principal(2), None), principal(1));
+        service.followees.insert((principal(3), Some(Topic::Treasury)), principal(1));
+
+        cast_own(&mut service, principal(2), Vote::No);
+        cast_own(&mut service, principal(1), Vote::Yes);
+        assert_eq!(tally(&service), (2, 1));
+        assert!(!service.proposals[&1].ballot_of(&principal(2)).unwrap().followed);
+    }
+
+    #[test]
+    fn cycles_of_followers_end() {
+        let mut service = service();
+        for n in 1..=4 {
+            service.followees.insert((principal(n), None), principal(n % 4 + 1));
+        }
+        cast_own(&mut service, principal(1), Vote::Yes);
+        assert_eq!(tally(&service), (4, 0));
+    }
+}
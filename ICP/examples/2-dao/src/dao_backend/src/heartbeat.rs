@@ -1,57 +1,151 @@
 use ic_cdk_macros::heartbeat;
-use crate::{SERVICE, update_proposal_state};
-use crate::types::ProposalState;
+use ic_cdk::api::management_canister::main::{install_code, CanisterInstallMode, InstallCodeArgument};
+use ic_cdk::export::Principal;
+use crate::accounts::Accounts;
+use crate::{airdrop, canisters, committees, events_canister, execution, ledger, members, pause, slashing, streams, supply, treasury, wasm, SERVICE};
+use crate::types::{AuditEventKind, ProposalAction, ProposalPayload, TokenClass, Tokens, UpdateSystemParamsPayload};
 
 #[heartbeat]
 async fn heartbeat() {
-    execute_accepted_proposals().await;
-}
-
-/// Execute all accepted proposals
-async fn execute_accepted_proposals() {
-    let accepted_proposals: Vec<u64> = SERVICE.with(|service| {
-        service.borrow_mut()
-            .proposals
-            .iter_mut()
-            .filter(|(_, proposal)| proposal.state == ProposalState::Accepted)
-            .map(|(id, proposal)| { 
-                proposal.state = ProposalState::Executing; 
-                *id 
-            })
-            .collect()
+    SERVICE.with(|service| {
+        let stats = &mut service.borrow_mut().stats;
+        stats.heartbeats += 1;
+        stats.last_heartbeat_at = ic_cdk::api::time();
     });
-
-    for proposal_id in accepted_proposals {
-        let state = match execute_proposal(proposal_id).await {
-            Ok(()) => ProposalState::Succeeded,
-            Err(msg) => ProposalState::Failed(msg)
-        };
-
-        update_proposal_state(proposal_id, state);
+    // Accepted proposals wait for the DAO to be unpaused
+    if !pause::is_paused() {
+        execution::execute_accepted(execute_proposal).await;
     }
+    treasury::refresh_balance().await;
 }
 
-/// Execute the given proposal
-async fn execute_proposal(proposal_id: u64) -> Result<(), String> {
+/// Execute the given proposal. Returns the reply of the call of a CallExternal proposal.
+async fn execute_proposal(proposal_id: u64) -> Result<Option<Vec<u8>>, String> {
     // Retrieve the proposal details from the SERVICE
     let proposal = SERVICE.with(|service| {
         service.borrow().proposals.get(&proposal_id).cloned()
     }).ok_or_else(|| "Proposal not found".to_string())?;
 
+    let result = match proposal.action {
+        ProposalAction::TransferTokens { to, amount, class } => transfer_tokens(to, amount, class.unwrap_or_default()),
+        ProposalAction::UpdateSystemParams(payload) => update_system_params(Some(proposal_id), *payload),
+        ProposalAction::UpgradeCanister { canister_id, wasm_module, arg } => {
+            upgrade_canister(canister_id, wasm_module, arg).await
+        }
+        ProposalAction::UpgradeCanisterToStagedWasm { canister_id, wasm_hash, arg } => {
+            wasm::upgrade_to_staged_wasm(proposal_id, canister_id, wasm_hash, arg).await
+        }
+        ProposalAction::RegisterCanister { canister_id, name } => canisters::register(proposal_id, canister_id, name),
+        ProposalAction::UnregisterCanister { canister_id } => canisters::unregister(proposal_id, canister_id),
+        ProposalAction::ManageCanister { canister_id, operation } => {
+            canisters::manage(proposal_id, canister_id, operation).await
+        }
+        ProposalAction::CreateCommittee(payload) => committees::create(proposal_id, payload),
+        ProposalAction::FundCommittee { committee_id, amount } => committees::fund(committee_id, amount),
+        ProposalAction::DissolveCommittee { committee_id } => committees::dissolve(committee_id),
+        ProposalAction::TransferFromTreasury { to, amount } => {
+            treasury::transfer_from_treasury(proposal_id, to, amount).await
+        }
+        ProposalAction::CallExternal(payload) => return call_external(payload).await.map(Some),
+        ProposalAction::Mint { to, amount, class } => supply::mint(proposal_id, to, amount, class.unwrap_or_default()).await,
+        ProposalAction::Burn { from, amount } => supply::burn(proposal_id, from, amount),
+        ProposalAction::RetryExecution { proposal_id } => execution::retry_execution(proposal_id),
+        ProposalAction::AddMember { principal, role } => members::add_member(proposal_id, principal, role),
+        ProposalAction::RemoveMember { principal, role } => members::remove_member(proposal_id, principal, role),
+        ProposalAction::Airdrop { amount, recipients } => airdrop::start(proposal_id, amount, recipients),
+        ProposalAction::CreateStream { to, rate_per_second, duration_seconds } => {
+            streams::create(proposal_id, to, rate_per_second, duration_seconds)
+        }
+        ProposalAction::CancelStream { stream_id } => streams::cancel(stream_id),
+        ProposalAction::Slash { proposal_id: flagged_proposal_id, percent, policy } => {
+            slashing::slash(proposal_id, flagged_proposal_id, percent, policy)
+        }
+        ProposalAction::CreateEvent { name, start_time, end_time, max_participants } => {
+            return events_canister::create_event(proposal_id, name, start_time, end_time, max_participants).await
+        }
+        ProposalAction::CancelEvent { event_id } => return events_canister::cancel_event(event_id).await,
+        ProposalAction::SetEventsAdmin { admin } => return events_canister::set_admin(admin).await,
+    };
+    result.map(|()| None)
+}
+
+/// Transfer tokens of a class from the DAO's own account. No fee is taken, as the DAO
+/// would pay it to itself.
+fn transfer_tokens(to: Principal, amount: Tokens, class: TokenClass) -> Result<(), String> {
+    ledger::require_internal_accounts_of(class)?;
+
+    let mut accounts = Accounts::of(class);
+    if !accounts.debit(ic_cdk::api::id(), amount) {
+        return Err(format!("The DAO's account has insufficient funds to transfer {:?}", amount));
+    }
+    accounts.credit(to, amount);
+    Ok(())
+}
+
+/// Validate and apply new system params, recording the change in the audit log
+pub fn update_system_params(proposal_id: Option<u64>, payload: UpdateSystemParamsPayload) -> Result<(), String> {
+    payload.validate()?;
+
+    SERVICE.with(|service| {
+        let mut service = service.borrow_mut();
+        let previous = Box::new(service.system_params.clone());
+        service.system_params.apply(payload);
+        let updated = Box::new(service.system_params.clone());
+        service.record(AuditEventKind::SystemParamsUpdated { proposal_id, previous, updated });
+    });
+    Ok(())
+}
+
+/// Upgrade a canister, which must list the DAO among its controllers
+async fn upgrade_canister(canister_id: Principal, wasm_module: Vec<u8>, arg: Vec<u8>) -> Result<(), String> {
+    install_code(InstallCodeArgument {
+        mode: CanisterInstallMode::Upgrade,
+        canister_id,
+        wasm_module,
+        arg,
+    }).await
+        .map_err(|(code, msg)| {
+            format!(
+                "Proposal execution failed: \
+                upgrade of canister: {}, rejection code: {:?}, message: {}",
+                canister_id, code, msg
+            )
+        })
+}
+
+/// Keep CallExternal proposals to the methods of the call allowlist, or away from the
+/// management canister without one. Checked on submission, and again on execution in case
+/// the allowlist changed in between.
+pub fn require_allowed_call(payload: &ProposalPayload) -> Result<(), String> {
+    let allowed = SERVICE.with(|service| match &service.borrow().system_params.call_allowlist {
+        Some(allowlist) => allowlist.iter()
+            .any(|target| target.canister_id == payload.canister_id && target.method == payload.method),
+        None => payload.canister_id != Principal::management_canister(),
+    });
+    match allowed {
+        true => Ok(()),
+        false => Err(format!(
+            "Method {} of canister {} is not on the call allowlist",
+            payload.method, payload.canister_id
+        )),
+    }
+}
+
+async fn call_external(payload: ProposalPayload) -> Result<Vec<u8>, String> {
+    require_allowed_call(&payload)?;
     ic_cdk::api::call::call_raw(
-        proposal.payload.canister_id,
-        &proposal.payload.method,
-        &proposal.payload.message.clone(),
+        payload.canister_id,
+        &payload.method,
+        &payload.message,
         0
     ).await
         .map_err(|(code, msg)| {
             format!(
                 "Proposal execution failed: \
                 canister: {}, method: {}, rejection code: {:?}, message: {}",
-                proposal.payload.canister_id,
-                &proposal.payload.method,
+                payload.canister_id,
+                payload.method,
                 code, msg
             )
         })
-        .map(|_| ())
 }
@@ -0,0 +1,62 @@
+use ic_cdk::export::Principal;
+use crate::types::*;
+use crate::{BasicDaoService, PROPOSALS_PAGE_SIZE, SERVICE};
+
+impl BasicDaoService {
+    /// Index the proposals by proposer and by voter, from the proposals themselves. The
+    /// indexes are not saved across upgrades, but rebuilt.
+    pub fn rebuild_history_indexes(&mut self) {
+        self.proposals_by_proposer.clear();
+        self.votes_by_voter.clear();
+        let mut ids: Vec<u64> = self.proposals.keys().copied().collect();
+        ids.sort();
+        for id in ids {
+            let proposal = &self.proposals[&id];
+            self.proposals_by_proposer.entry(proposal.proposer).or_default().push(id);
+            for voter in &proposal.voters {
+                self.votes_by_voter.entry(*voter).or_default().push(id);
+            }
+        }
+    }
+
+    /// Record the first vote of a voter on a proposal in the voter's history
+    pub fn index_vote(&mut self, voter: Principal, proposal_id: u64) {
+        self.votes_by_voter.entry(voter).or_default().push(proposal_id);
+    }
+}
+
+/// A page of ids from an index in submission order, newest first
+fn page_of(ids: Option<&Vec<u64>>, page: u64) -> Vec<u64> {
+    ids.into_iter().flatten().rev()
+        .skip(page as usize * PROPOSALS_PAGE_SIZE)
+        .take(PROPOSALS_PAGE_SIZE)
+        .copied()
+        .collect()
+}
+
+/// Returns a page of 20 proposals submitted by a principal, newest first
+#[ic_cdk::query]
+fn get_proposals_by_proposer(proposer: Principal, page: u64) -> Vec<Proposal> {
+    SERVICE.with(|service| {
+        let service = service.borrow();
+        page_of(service.proposals_by_proposer.get(&proposer), page).iter()
+            .filter_map(|id| service.proposals.get(id).cloned())
+            .collect()
+    })
+}
+
+/// Returns a page of 20 votes cast by a principal, on the newest proposals first
+#[ic_cdk::query]
+fn get_votes_by_voter(voter: Principal, page: u64) -> Vec<VoteRecord> {
+    SERVICE.with(|service| {
+        let service = service.borrow();
+        page_of(service.votes_by_voter.get(&voter), page).iter()
+            .filter_map(|id| service.proposals.get(id))
+            .map(|proposal| VoteRecord {
+                proposal_id: proposal.id,
+                ballot: proposal.ballot_of(&voter).cloned(),
+                proposal_state: proposal.state.clone(),
+            })
+            .collect()
+    })
+}
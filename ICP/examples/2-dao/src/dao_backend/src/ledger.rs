@@ -0,0 +1,209 @@
+use futures::future::join_all;
+use ic_cdk::export::candid::{CandidType, Deserialize, Nat};
+use ic_cdk::export::Principal;
+use crate::types::*;
+use crate::{pause, SERVICE};
+
+/// A snapshot makes a call per ledger voter, so it is kept to this many voters
+const MAX_LEDGER_VOTERS: usize = 500;
+
+/// The balance calls of a snapshot that are in flight at once
+const SNAPSHOT_BATCH_SIZE: usize = 50;
+
+/// No deposit is taken with a token ledger, so a proposer waits this long between two
+/// submissions unless `min_submission_interval_seconds` says otherwise
+const LEDGER_SUBMISSION_INTERVAL_SECONDS: u64 = 60 * 60;
+
+#[derive(CandidType)]
+struct TransferArg {
+    from_subaccount: Option<Vec<u8>>,
+    to: IcrcAccount,
+    amount: Nat,
+    fee: Option<Nat>,
+    memo: Option<Vec<u8>>,
+    created_at_time: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+enum TransferError {
+    BadFee { expected_fee: Nat },
+    BadBurn { min_burn_amount: Nat },
+    InsufficientFunds { balance: Nat },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    TemporarilyUnavailable,
+    Duplicate { duplicate_of: Nat },
+    GenericError { error_code: Nat, message: String },
+}
+
+/// The ICRC-1 ledger of the governance token, when the DAO does not keep its own accounts.
+/// Balances and transfers then live on the ledger, and the DAO only stores governance
+/// state: proposals, votes and params.
+pub fn token_ledger() -> Option<Principal> {
+    SERVICE.with(|service| service.borrow().system_params.token_ledger)
+}
+
+/// Error for the endpoints that move internal tokens, which the ledger holds instead
+pub fn require_internal_accounts() -> Result<(), String> {
+    match token_ledger() {
+        Some(ledger) => Err(format!("Tokens are held on the ledger {}, use icrc1_transfer there", ledger)),
+        None => Ok(()),
+    }
+}
+
+/// The ledger only holds governance tokens, utility tokens are always internal
+pub fn require_internal_accounts_of(class: TokenClass) -> Result<(), String> {
+    match class {
+        TokenClass::Governance => require_internal_accounts(),
+        TokenClass::Utility => Ok(()),
+    }
+}
+
+/// The balance of a principal on the token ledger
+pub async fn balance_of(ledger: Principal, owner: Principal) -> Result<Tokens, String> {
+    let account = IcrcAccount { owner, subaccount: None };
+    let (balance,): (Nat,) = ic_cdk::call(ledger, "icrc1_balance_of", (account,)).await
+        .map_err(|(code, msg)| {
+            format!("Could not read the balance from the ledger: rejection code: {:?}, message: {}", code, msg)
+        })?;
+    let amount_e8s = u64::try_from(&balance.0).map_err(|_| "Balance does not fit in Tokens".to_string())?;
+    Ok(Tokens { amount_e8s })
+}
+
+/// The time a proposer waits between two submissions, which defaults to an hour with a
+/// token ledger to stand in for the deposit
+pub fn min_submission_interval_seconds(params: &SystemParams) -> Option<u64> {
+    params.min_submission_interval_seconds
+        .or(params.token_ledger.map(|_| LEDGER_SUBMISSION_INTERVAL_SECONDS))
+}
+
+/// Marks the ledger snapshot in progress, so only one is taken at a time
+struct SnapshotGuard;
+
+impl SnapshotGuard {
+    fn acquire() -> Result<SnapshotGuard, String> {
+        SERVICE.with(|service| {
+            let mut service = service.borrow_mut();
+            if service.taking_ledger_snapshot {
+                return Err("Another proposal is being submitted, retry shortly".to_string());
+            }
+            service.taking_ledger_snapshot = true;
+            Ok(SnapshotGuard)
+        })
+    }
+}
+
+impl Drop for SnapshotGuard {
+    fn drop(&mut self) {
+        SERVICE.with(|service| service.borrow_mut().taking_ledger_snapshot = false);
+    }
+}
+
+/// The balances of some voters, read concurrently, SNAPSHOT_BATCH_SIZE at a time
+async fn balances_of(ledger: Principal, voters: &[Principal]) -> Vec<(Principal, Result<Tokens, String>)> {
+    let mut balances = Vec::with_capacity(voters.len());
+    for batch in voters.chunks(SNAPSHOT_BATCH_SIZE) {
+        let results = join_all(batch.iter().map(|voter| balance_of(ledger, *voter))).await;
+        balances.extend(batch.iter().copied().zip(results));
+    }
+    balances
+}
+
+/// The ledger balance of every ledger voter that has some, the voting power snapshot of a
+/// proposal submitted with a token ledger. Ledger holders cannot be listed, so only the
+/// principals registered with register_ledger_voter are read. The balances that could not
+/// be read are read once more, and the voters whose balance still cannot be are left out,
+/// rather than failing the submission.
+pub async fn snapshot(ledger: Principal, voters: Vec<Principal>) -> Result<Vec<(Principal, Tokens)>, String> {
+    let _guard = SnapshotGuard::acquire()?;
+
+    let mut balances = balances_of(ledger, &voters).await;
+    let failed: Vec<Principal> = balances.iter()
+        .filter(|(_, balance)| balance.is_err())
+        .map(|(voter, _)| *voter)
+        .collect();
+    if !failed.is_empty() {
+        balances.extend(balances_of(ledger, &failed).await);
+    }
+
+    Ok(balances.into_iter()
+        .filter_map(|(voter, balance)| balance.ok().map(|balance| (voter, balance)))
+        .filter(|(_, balance)| balance.amount_e8s > 0)
+        .collect())
+}
+
+/// Register the caller to vote with their token ledger balance on the proposals submitted
+/// afterwards, which are weighted by the balances at submission
+#[ic_cdk::update]
+fn register_ledger_voter() -> Result<(), DaoError> {
+    pause::require_not_paused()?;
+    if token_ledger().is_none() {
+        return Err(DaoError::from("The DAO has no token ledger, its stakes vote".to_string()));
+    }
+
+    SERVICE.with(|service| {
+        let mut service = service.borrow_mut();
+        let caller = ic_cdk::api::caller();
+        if !service.ledger_voters.contains(&caller) && service.ledger_voters.len() >= MAX_LEDGER_VOTERS {
+            return Err(DaoError::from(format!("At most {} ledger voters can register", MAX_LEDGER_VOTERS)));
+        }
+        service.ledger_voters.insert(caller);
+        Ok(())
+    })
+}
+
+/// Transfer ledger funds of the DAO canister with `icrc1_transfer`, with a proposal id as
+/// memo. When the DAO canister is the minting account of the ledger, this mints.
+pub async fn icrc1_transfer(ledger: Principal, to: IcrcAccount, amount: Nat, proposal_id: u64) -> Result<Nat, String> {
+    let arg = TransferArg {
+        from_subaccount: None,
+        to,
+        amount,
+        fee: None,
+        memo: Some(proposal_id.to_be_bytes().to_vec()),
+        created_at_time: None,
+    };
+
+    let reply: Result<(Result<Nat, TransferError>,), _> = ic_cdk::call(ledger, "icrc1_transfer", (arg,)).await;
+    match reply {
+        Ok((Ok(block_index),)) => Ok(block_index),
+        Ok((Err(error),)) => Err(format!("rejected by the ledger: {:?}", error)),
+        Err((code, msg)) => Err(format!("rejection code: {:?}, message: {}", code, msg)),
+    }
+}
+
+/// The total supply of the token ledger
+pub async fn total_supply(ledger: Principal) -> Result<Tokens, String> {
+    let (supply,): (Nat,) = ic_cdk::call(ledger, "icrc1_total_supply", ()).await
+        .map_err(|(code, msg)| {
+            format!("Could not read the total supply from the ledger: rejection code: {:?}, message: {}", code, msg)
+        })?;
+    let amount_e8s = u64::try_from(&supply.0).map_err(|_| "Total supply does not fit in Tokens".to_string())?;
+    Ok(Tokens { amount_e8s })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_token_ledger_makes_proposers_wait_between_submissions() {
+        let mut params = SystemParams::default();
+        assert_eq!(min_submission_interval_seconds(&params), None);
+
+        params.token_ledger = Some(Principal::anonymous());
+        assert_eq!(min_submission_interval_seconds(&params), Some(LEDGER_SUBMISSION_INTERVAL_SECONDS));
+
+        params.min_submission_interval_seconds = Some(60);
+        assert_eq!(min_submission_interval_seconds(&params), Some(60));
+    }
+
+    #[test]
+    fn one_ledger_snapshot_is_taken_at_a_time() {
+        let guard = SnapshotGuard::acquire().unwrap();
+        assert!(SnapshotGuard::acquire().is_err());
+
+        drop(guard);
+        assert!(SnapshotGuard::acquire().is_ok());
+    }
+}
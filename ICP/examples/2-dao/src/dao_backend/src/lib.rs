@@ -1,33 +1,276 @@
+mod accounts;
+mod airdrop;
+mod allowances;
+mod amendments;
+mod audit;
+mod canisters;
+mod closure;
+mod comments;
+mod committees;
+mod council;
+mod env;
+mod events_canister;
+mod execution;
+mod expiry;
+mod following;
 mod heartbeat;
+mod history;
+mod ledger;
+mod members;
+mod metrics;
+mod nft;
+mod notifications;
+mod pause;
+mod rewards;
+mod slashing;
+mod snapshots;
+mod sns;
+mod staking;
+mod streams;
+mod supply;
+mod tally;
+mod templates;
+mod treasury;
 mod types;
+mod wasm;
 
+use crate::accounts::Accounts;
 use crate::types::*;
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use ic_cdk::export::candid::{Decode, Encode};
 use ic_cdk::export::Principal;
+use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
+use ic_stable_structures::writer::Writer;
+use ic_stable_structures::{DefaultMemoryImpl, Memory as _};
+
+pub(crate) type Memory = VirtualMemory<DefaultMemoryImpl>;
+
+const PROPOSALS_PAGE_SIZE: usize = 20;
+/// The state saved by pre_upgrade, apart from the accounts in their own stable map
+const UPGRADE_STATE_MEMORY_ID: MemoryId = MemoryId::new(1);
 
 thread_local! {
     static SERVICE: RefCell<BasicDaoService> = RefCell::default();
+
+    pub(crate) static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> =
+        RefCell::new(MemoryManager::init(DefaultMemoryImpl::default()));
 }
 
 #[derive(Default)]
 pub struct BasicDaoService {
-    pub accounts: HashMap<Principal, Tokens>,
+    // In stable memory
+    pub accounts: Accounts,
     pub proposals: HashMap<u64, Proposal>,
     pub next_proposal_id: u64,
     pub system_params: SystemParams,
+    pub stakes: HashMap<u64, Stake>,
+    pub next_stake_id: u64,
+    pub treasury_transactions: Vec<TreasuryTransaction>,
+    // Changed by Mint and Burn proposals, and by burned fees and deposits
+    pub total_supply: Tokens,
+    // The utility tokens, whose accounts are Accounts::of(TokenClass::Utility). Changed by
+    // Mint proposals and burned fees.
+    pub utility_supply: Tokens,
+    // The transfer fees taken so far, burned or paid to the treasury
+    pub fees_collected: Tokens,
+    pub utility_fees_collected: Tokens,
+    pub audit_log: Vec<AuditEvent>,
+    // Set by the guardian to stop token movements and proposal execution
+    pub paused: bool,
+    // The discussion threads, by proposal id
+    pub comments: HashMap<u64, ProposalThread>,
+    // The tokens that spenders may transfer from accounts, by (owner, spender)
+    pub allowances: HashMap<(Principal, Principal), Tokens>,
+    // The roles of the members. While it is empty, anyone with an account submits proposals.
+    pub members: HashMap<Principal, Vec<MemberRole>>,
+    pub airdrops: Vec<Airdrop>,
+    // Voting rewards accrued by voters, until they claim them
+    pub pending_rewards: HashMap<Principal, Tokens>,
+    // The followee of each follower, by (follower, topic)
+    pub followees: HashMap<(Principal, Option<Topic>), Principal>,
+    // Every version of the proposals, by proposal id
+    pub revisions: HashMap<u64, Vec<ProposalRevision>>,
+    // The wasms staged for upgrades, by hash
+    pub staged_wasms: HashMap<Vec<u8>, StagedWasm>,
+    pub canister_upgrades: Vec<CanisterUpgrade>,
+    // The wasms being uploaded, by uploader. Not saved across upgrades.
+    pub wasm_uploads: HashMap<Principal, Vec<u8>>,
+    pub governed_canisters: HashMap<Principal, GovernedCanister>,
+    pub committees: HashMap<u64, Committee>,
+    pub next_committee_id: u64,
+    pub committee_proposals: HashMap<u64, CommitteeProposal>,
+    // The canisters notified of proposal state changes, by canister id
+    pub subscriptions: HashMap<Principal, Subscription>,
+    pub streams: HashMap<u64, Stream>,
+    // The council's flags of malicious proposals, by proposal id
+    pub malicious_flags: HashMap<u64, MaliciousFlag>,
+    pub templates: HashMap<u64, ProposalTemplate>,
+    pub next_template_id: u64,
+    // The owner of the last account each dust sweep read, where the next one goes on
+    pub dust_sweep_cursors: HashMap<TokenClass, Principal>,
+    // Notifications not sent yet
+    pub outbox: Vec<notifications::PendingNotification>,
+    // The ids of the proposals submitted by each proposer and voted on by each voter, in
+    // submission order. Rebuilt from the proposals after upgrades.
+    pub proposals_by_proposer: HashMap<Principal, Vec<u64>>,
+    pub votes_by_voter: HashMap<Principal, Vec<u64>>,
+    // The principals who vote with their token ledger balance, see ledger::snapshot
+    pub ledger_voters: HashSet<Principal>,
+    // Not saved across upgrades, the heartbeat reads it again
+    pub treasury_balance: Option<TreasuryBalance>,
+    pub refreshing_treasury_balance: bool,
+    // Not saved across upgrades, as no snapshot is taken across one
+    pub taking_ledger_snapshot: bool,
+    pub stats: ExecutionStats,
 }
 
 impl From<BasicDaoStableStorage> for BasicDaoService {
     fn from(stable: BasicDaoStableStorage) -> BasicDaoService {
-        let accounts = stable.accounts.clone().into_iter().map(|a| (a.owner, a.tokens)).collect();
-        let proposals = stable.proposals.clone().into_iter().map(|p| (p.id, p)).collect();
+        let mut accounts = Accounts::default();
+        for account in &stable.accounts {
+            accounts.insert(account.owner, account.tokens);
+        }
+        let mut utility_accounts = Accounts::of(TokenClass::Utility);
+        for account in stable.utility_accounts.iter().flatten() {
+            utility_accounts.insert(account.owner, account.tokens);
+        }
+        let proposals: HashMap<u64, Proposal> = stable.proposals.clone().into_iter().map(|p| (p.id, p)).collect();
+        // Ids keep increasing from the saved counters, so the ids of disbursed stakes are not
+        // given again. States saved without counters go on after the restored ids.
+        let next_proposal_id = proposals.keys().max().map_or(1, |id| id + 1).max(stable.next_proposal_id.unwrap_or(1));
+        let stakes: HashMap<u64, Stake> = stable.stakes.unwrap_or_default().into_iter().map(|s| (s.id, s)).collect();
+        let next_stake_id = stakes.keys().max().map_or(1, |id| id + 1).max(stable.next_stake_id.unwrap_or(1));
+        // The proposals of dissolved committees are kept, so their ids are not given again
+        let committees: HashMap<u64, Committee> = stable.committees.unwrap_or_default().into_iter()
+            .map(|committee| (committee.id, committee))
+            .collect();
+        let committee_proposals: HashMap<u64, CommitteeProposal> = stable.committee_proposals.unwrap_or_default().into_iter()
+            .map(|proposal| (proposal.id, proposal))
+            .collect();
+        let next_committee_id = committees.keys()
+            .chain(committee_proposals.values().map(|proposal| &proposal.committee_id))
+            .max()
+            .map_or(1, |id| id + 1)
+            .max(stable.next_committee_id.unwrap_or(1));
+        let templates: HashMap<u64, ProposalTemplate> = stable.templates.unwrap_or_default().into_iter()
+            .map(|template| (template.id, template))
+            .collect();
+        let next_template_id = templates.keys().max().map_or(1, |id| id + 1).max(stable.next_template_id.unwrap_or(1));
+        let members = match stable.members {
+            Some(members) => members.into_iter().map(|member| (member.principal, member.roles)).collect(),
+            None => members::from_params(&stable.system_params),
+        };
 
-        BasicDaoService {
+        let mut service = BasicDaoService {
             accounts,
             proposals,
-            next_proposal_id: 1,
+            next_proposal_id,
             system_params: stable.system_params,
+            stakes,
+            next_stake_id,
+            treasury_transactions: stable.treasury_transactions.unwrap_or_default(),
+            total_supply: Tokens::default(),
+            utility_supply: Tokens::default(),
+            fees_collected: stable.fees_collected.unwrap_or_default(),
+            utility_fees_collected: stable.utility_fees_collected.unwrap_or_default(),
+            audit_log: stable.audit_log.unwrap_or_default(),
+            paused: stable.paused.unwrap_or_default(),
+            comments: stable.comments.unwrap_or_default().into_iter().collect(),
+            allowances: stable.allowances.unwrap_or_default().into_iter()
+                .map(|allowance| ((allowance.owner, allowance.spender), allowance.amount))
+                .collect(),
+            members,
+            airdrops: stable.airdrops.unwrap_or_default(),
+            pending_rewards: stable.pending_rewards.unwrap_or_default().into_iter().collect(),
+            followees: stable.follows.unwrap_or_default().into_iter()
+                .map(|follow| ((follow.follower, follow.topic), follow.followee))
+                .collect(),
+            revisions: stable.revisions.unwrap_or_default().into_iter().collect(),
+            staged_wasms: stable.staged_wasms.unwrap_or_default().into_iter()
+                .map(|wasm| (wasm.hash.clone(), wasm))
+                .collect(),
+            canister_upgrades: stable.canister_upgrades.unwrap_or_default(),
+            wasm_uploads: HashMap::new(),
+            governed_canisters: stable.governed_canisters.unwrap_or_default().into_iter()
+                .map(|canister| (canister.canister_id, canister))
+                .collect(),
+            committees,
+            next_committee_id,
+            committee_proposals,
+            subscriptions: stable.subscriptions.unwrap_or_default().into_iter()
+                .map(|subscription| (subscription.canister_id, subscription))
+                .collect(),
+            streams: stable.streams.unwrap_or_default().into_iter().map(|stream| (stream.id, stream)).collect(),
+            malicious_flags: stable.malicious_flags.unwrap_or_default().into_iter()
+                .map(|flag| (flag.proposal_id, flag))
+                .collect(),
+            templates,
+            next_template_id,
+            dust_sweep_cursors: stable.dust_sweep_cursors.unwrap_or_default().into_iter().collect(),
+            outbox: stable.outbox.unwrap_or_default(),
+            proposals_by_proposer: HashMap::new(),
+            votes_by_voter: HashMap::new(),
+            ledger_voters: stable.ledger_voters.unwrap_or_default().into_iter().collect(),
+            treasury_balance: None,
+            refreshing_treasury_balance: false,
+            taking_ledger_snapshot: false,
+            stats: ExecutionStats::default(),
+        };
+        service.total_supply = stable.total_supply.unwrap_or_else(|| supply::circulating(&service));
+        service.utility_supply = stable.utility_supply.unwrap_or_else(supply::circulating_utility);
+        service.rebuild_history_indexes();
+        service
+    }
+}
+
+impl From<&BasicDaoService> for BasicDaoStableStorage {
+    fn from(service: &BasicDaoService) -> BasicDaoStableStorage {
+        let proposals = service.proposals.values().cloned().collect();
+
+        BasicDaoStableStorage {
+            // The accounts live in stable memory, where they survive upgrades on their own
+            accounts: vec![],
+            proposals,
+            system_params: service.system_params.clone(),
+            stakes: Some(service.stakes.values().cloned().collect()),
+            treasury_transactions: Some(service.treasury_transactions.clone()),
+            total_supply: Some(service.total_supply),
+            audit_log: Some(service.audit_log.clone()),
+            paused: Some(service.paused),
+            comments: Some(service.comments.iter().map(|(id, thread)| (*id, thread.clone())).collect()),
+            allowances: Some(service.allowances.iter()
+                .map(|((owner, spender), amount)| Allowance { owner: *owner, spender: *spender, amount: *amount })
+                .collect()),
+            members: Some(service.members.iter()
+                .map(|(principal, roles)| Member { principal: *principal, roles: roles.clone() })
+                .collect()),
+            airdrops: Some(service.airdrops.clone()),
+            pending_rewards: Some(service.pending_rewards.iter().map(|(voter, rewards)| (*voter, *rewards)).collect()),
+            follows: Some(service.followees.iter()
+                .map(|((follower, topic), followee)| Follow { follower: *follower, topic: *topic, followee: *followee })
+                .collect()),
+            revisions: Some(service.revisions.iter().map(|(id, revisions)| (*id, revisions.clone())).collect()),
+            staged_wasms: Some(service.staged_wasms.values().cloned().collect()),
+            canister_upgrades: Some(service.canister_upgrades.clone()),
+            governed_canisters: Some(service.governed_canisters.values().cloned().collect()),
+            committees: Some(service.committees.values().cloned().collect()),
+            committee_proposals: Some(service.committee_proposals.values().cloned().collect()),
+            subscriptions: Some(service.subscriptions.values().cloned().collect()),
+            streams: Some(service.streams.values().cloned().collect()),
+            malicious_flags: Some(service.malicious_flags.values().cloned().collect()),
+            templates: Some(service.templates.values().cloned().collect()),
+            fees_collected: Some(service.fees_collected),
+            utility_fees_collected: Some(service.utility_fees_collected),
+            next_proposal_id: Some(service.next_proposal_id),
+            next_stake_id: Some(service.next_stake_id),
+            next_committee_id: Some(service.next_committee_id),
+            next_template_id: Some(service.next_template_id),
+            utility_accounts: Some(vec![]),
+            utility_supply: Some(service.utility_supply),
+            ledger_voters: Some(service.ledger_voters.iter().copied().collect()),
+            dust_sweep_cursors: Some(service.dust_sweep_cursors.iter().map(|(class, owner)| (*class, *owner)).collect()),
+            outbox: Some(service.outbox.clone()),
         }
     }
 }
@@ -42,6 +285,63 @@ fn init(init_state: BasicDaoStableStorage) {
 
     // Store the initialized service in the thread-local SERVICE
     SERVICE.with(|service| *service.borrow_mut() = init_service);
+    expiry::start();
+}
+
+//UPGRADE
+#[ic_cdk::pre_upgrade]
+fn pre_upgrade() {
+    let stable = SERVICE.with(|service| BasicDaoStableStorage::from(&*service.borrow()));
+    let bytes = Encode!(&VersionedStableStorage::V2(Box::new(stable))).expect("failed to encode the DAO state");
+
+    let mut memory = MEMORY_MANAGER.with(|m| m.borrow().get(UPGRADE_STATE_MEMORY_ID));
+    let mut writer = Writer::new(&mut memory, 0);
+    writer.write(&(bytes.len() as u64).to_le_bytes())
+        .and_then(|_| writer.write(&bytes))
+        .expect("failed to save the DAO state to stable memory");
+}
+
+fn load_upgrade_state() -> VersionedStableStorage {
+    let memory = MEMORY_MANAGER.with(|m| m.borrow().get(UPGRADE_STATE_MEMORY_ID));
+    let mut len = [0; 8];
+    memory.read(0, &mut len);
+    let mut bytes = vec![0; u64::from_le_bytes(len) as usize];
+    memory.read(8, &mut bytes);
+    Decode!(&bytes, VersionedStableStorage).expect("failed to restore the DAO state from stable memory")
+}
+
+fn restore_upgrade_state() {
+    // Versions before the accounts moved to stable memory saved the whole state with
+    // stable_save, where the memory manager now keeps its header. That state is read
+    // before the memory manager first lays out the stable memory, over it.
+    let mut magic = [0; 3];
+    ic_cdk::api::stable::stable64_read(0, &mut magic);
+    let stable = if &magic == b"MGR" {
+        load_upgrade_state()
+    } else {
+        // Trapping rolls the upgrade back, rather than starting with an empty DAO
+        let (stable,): (VersionedStableStorage,) = ic_cdk::storage::stable_restore()
+            .expect("failed to restore the DAO state from stable memory");
+        stable
+    };
+    let restored_service = BasicDaoService::from(stable.into_latest());
+    SERVICE.with(|service| *service.borrow_mut() = restored_service);
+}
+
+#[ic_cdk::post_upgrade]
+fn post_upgrade() {
+    ic_cdk::setup();
+
+    // Versions before this one kept no state in stable memory, so there is nothing to
+    // restore when upgrading from them, but the timers are set all the same
+    if ic_cdk::api::stable::stable_size() > 0 {
+        restore_upgrade_state();
+    }
+    execution::fail_interrupted_executions();
+    execution::reschedule();
+    airdrop::reschedule();
+    notifications::reschedule();
+    expiry::start();
 }
 
 //QUERIES
@@ -50,19 +350,27 @@ fn get_system_params() -> SystemParams {
     SERVICE.with(|service| service.borrow().system_params.clone())
 }
 
+/// Returns the params the proposals of a topic are held to
+#[ic_cdk::query]
+fn get_effective_params(topic: Topic) -> EffectiveParams {
+    SERVICE.with(|service| service.borrow().system_params.effective_params(topic))
+}
+
 #[ic_cdk::query]
 fn account_balance() -> Tokens {
     SERVICE.with(|service| {
         let service = service.borrow();
         let caller = ic_cdk::api::caller();
-        service.accounts.get(&caller).cloned().unwrap_or_default()
+        service.accounts.balance(&caller)
     })
 }
 
+/// Returns every account in a single response, which does not scale to many holders;
+/// list_accounts_page pages through them
 #[ic_cdk::query]
 fn list_accounts() -> Vec<Account> {
     SERVICE.with(|service| {
-        service.borrow().accounts.iter().map(|(owner, tokens)| Account { owner: *owner, tokens: *tokens }).collect()
+        service.borrow().accounts.entries().into_iter().map(|(owner, tokens)| Account { owner, tokens }).collect()
     })
 }
 
@@ -81,142 +389,631 @@ fn list_proposals() -> Vec<Proposal> {
     })
 }
 
+/// Returns a page of the proposals, by id, of a topic or of all topics without one
+#[ic_cdk::query]
+fn list_proposals_filtered(topic: Option<Topic>, page: u64) -> Vec<Proposal> {
+    SERVICE.with(|service| {
+        let service = service.borrow();
+        let mut proposals: Vec<&Proposal> = service.proposals.values()
+            .filter(|proposal| topic.is_none_or(|topic| proposal.topic() == topic))
+            .collect();
+        proposals.sort_by_key(|proposal| proposal.id);
+        proposals.into_iter()
+            .skip(page as usize * PROPOSALS_PAGE_SIZE)
+            .take(PROPOSALS_PAGE_SIZE)
+            .cloned()
+            .collect()
+    })
+}
+
+/// Transfer governance tokens, or utility tokens, from the caller's account
 #[ic_cdk::update]
-fn transfer(args: TransferArgs) -> Result<(), String> {
+fn transfer(args: TransferArgs) -> Result<(), DaoError> {
+    pause::require_not_paused()?;
+    let class = args.class.unwrap_or_default();
+    ledger::require_internal_accounts_of(class)?;
+
     SERVICE.with(|service| {
-        //due to service being mutable reference
-        //In Rust, you cannot have a mutable borrow (service.borrow_mut()) and then try to access a field of the borrowed value 
-        let transfer_fee = service.borrow().system_params.transfer_fee;
-        let mut service = service.borrow_mut();
         let caller = ic_cdk::api::caller();
+        service.borrow_mut().transfer(class, caller, args.to, args.amount)
+    }).map_err(DaoError::from)
+}
+
+/// Transfers in a batch are limited, so a batch stays within the instruction limit
+const MAX_BATCH_TRANSFERS: usize = 1_000;
 
-        if let Some(account) = service.accounts.get_mut(&caller) {
-            if *account < args.amount {
-                return Err(format!(
-                    "Caller's account has insufficient funds to transfer {:?}",
-                    args.amount
-                ));
-            } else {
-                *account -= args.amount + transfer_fee;
-                let to_account = service.accounts.entry(args.to).or_default();
-                *to_account += args.amount;
+/// The amounts of the transfers of a class in a batch with a fee each, None when the sum
+/// overflows
+fn batch_total(transfers: &[TransferArgs], class: TokenClass, fee: Tokens) -> Option<Tokens> {
+    transfers.iter()
+        .filter(|transfer| transfer.class.unwrap_or_default() == class)
+        .try_fold(Tokens::default(), |total, transfer| total.checked_add(transfer.amount)?.checked_add(fee))
+}
+
+/// Transfer tokens from the caller's account to several accounts, e.g. for payroll. Every
+/// transfer is checked before any is made: the caller's balance must cover every amount
+/// and a fee per transfer, and each recipient must be able to take all it is sent,
+/// otherwise none is made. Returns the result of each transfer, in order.
+#[ic_cdk::update]
+fn transfer_batch(transfers: Vec<TransferArgs>) -> Vec<Result<(), DaoError>> {
+    let fail_all = |error: DaoError| vec![Err(error); transfers.len()];
+    if let Err(error) = pause::require_not_paused() {
+        return fail_all(error);
+    }
+    for transfer in &transfers {
+        if let Err(error) = ledger::require_internal_accounts_of(transfer.class.unwrap_or_default()) {
+            return fail_all(error.into());
+        }
+    }
+    if transfers.len() > MAX_BATCH_TRANSFERS {
+        return fail_all(format!("A batch has at most {} transfers", MAX_BATCH_TRANSFERS).into());
+    }
+
+    SERVICE.with(|service| service.borrow_mut().transfer_batch_from(ic_cdk::api::caller(), &transfers))
+}
+
+impl BasicDaoService {
+    /// Make a batch of transfers from `from`, all of them or none
+    fn transfer_batch_from(&mut self, from: Principal, transfers: &[TransferArgs]) -> Vec<Result<(), DaoError>> {
+        if let Err(error) = self.check_batch(from, transfers) {
+            return vec![Err(DaoError::from(error)); transfers.len()];
+        }
+        transfers.iter()
+            .map(|transfer| {
+                let class = transfer.class.unwrap_or_default();
+                self.transfer(class, from, transfer.to, transfer.amount).map_err(DaoError::from)
+            })
+            .collect()
+    }
+
+    /// Check that every transfer of a batch from `from` would be made, so none fails after
+    /// the ones before it were made
+    fn check_batch(&self, from: Principal, transfers: &[TransferArgs]) -> Result<(), String> {
+        for class in [TokenClass::Governance, TokenClass::Utility] {
+            let fee = self.system_params.transfer_fee_of(class);
+            let Some(total) = batch_total(transfers, class, fee) else {
+                return Err("The amounts of the batch overflow".to_string());
+            };
+
+            let accounts = Accounts::of(class);
+            let sends_class = transfers.iter().any(|transfer| transfer.class.unwrap_or_default() == class);
+            if sends_class && accounts.get(&from).is_none() {
+                return Err("An account is needed to transfer funds".to_string());
+            }
+            if accounts.balance(&from) < total {
+                return Err(format!("Caller's account has insufficient funds to transfer {:?}", total));
+            }
+
+            // A recipient may be sent several transfers, which must fit together
+            let mut credits: HashMap<Principal, Tokens> = HashMap::new();
+            for transfer in transfers.iter().filter(|transfer| transfer.class.unwrap_or_default() == class) {
+                if transfer.to == from {
+                    continue;
+                }
+                let credit = credits.entry(transfer.to).or_default();
+                match credit.checked_add(transfer.amount) {
+                    Some(sum) if accounts.can_credit(&transfer.to, sum) => *credit = sum,
+                    _ => return Err(format!("The account of {} cannot hold the tokens of the batch", transfer.to)),
+                }
             }
-        } else {
-            return Err("Caller needs an account to transfer funds".to_string());
         }
+        Ok(())
+    }
+}
+
+impl BasicDaoService {
+    /// Keep a proposer from submitting more proposals than the system params allow
+    fn check_submission_limits(&self, proposer: &Principal) -> Result<(), DaoError> {
+        let params = &self.system_params;
+        let proposals = || self.proposals.values().filter(|proposal| proposal.proposer == *proposer);
 
+        if let Some(limit) = params.max_open_proposals_per_proposer {
+            if proposals().filter(|proposal| proposal.state == ProposalState::Open).count() >= limit as usize {
+                return Err(DaoError::TooManyOpenProposals { limit });
+            }
+        }
+        if let Some(interval) = ledger::min_submission_interval_seconds(params) {
+            let last_submission = proposals().map(|proposal| proposal.timestamp).max();
+            if let Some(retry_at) = last_submission.map(|submitted_at| submitted_at + interval * 1_000_000_000) {
+                if ic_cdk::api::time() < retry_at {
+                    return Err(DaoError::SubmissionCooldown { retry_at });
+                }
+            }
+        }
         Ok(())
-    })
+    }
+
+    /// Move tokens of a class between accounts, taking the class's transfer fee from the sender
+    pub fn transfer(&mut self, class: TokenClass, from: Principal, to: Principal, amount: Tokens) -> Result<(), String> {
+        let transfer_fee = self.system_params.transfer_fee_of(class);
+        let mut accounts = Accounts::of(class);
+
+        if accounts.get(&from).is_none() {
+            return Err("An account is needed to transfer funds".to_string());
+        }
+        let Some(debit) = amount.checked_add(transfer_fee) else {
+            return Err(format!("Cannot transfer {:?}, the amount and fee overflow", amount));
+        };
+        if from != to && !accounts.can_credit(&to, amount) {
+            return Err(format!("The recipient's account cannot hold {:?} more", amount));
+        }
+        if !accounts.debit(from, debit) {
+            return Err(format!(
+                "Account has insufficient funds to transfer {:?}",
+                amount
+            ));
+        }
+        accounts.credit(to, amount);
+        self.collect_fee(class, transfer_fee);
+
+        Ok(())
+    }
+
+    /// The supply of a class of internal tokens
+    pub fn supply_mut(&mut self, class: TokenClass) -> &mut Tokens {
+        match class {
+            TokenClass::Governance => &mut self.total_supply,
+            TokenClass::Utility => &mut self.utility_supply,
+        }
+    }
+
+    pub fn fees_collected(&self, class: TokenClass) -> Tokens {
+        match class {
+            TokenClass::Governance => self.fees_collected,
+            TokenClass::Utility => self.utility_fees_collected,
+        }
+    }
+
+    /// Burn a fee taken from a payer, or pay it to the treasury, as the fee policy says
+    pub fn collect_fee(&mut self, class: TokenClass, fee: Tokens) {
+        match self.system_params.fee_policy.clone().unwrap_or_default() {
+            DepositPolicy::Burn => *self.supply_mut(class) -= fee,
+            DepositPolicy::ToTreasury(treasury) => Accounts::of(class).credit(treasury, fee),
+        }
+        match class {
+            TokenClass::Governance => self.fees_collected += fee,
+            TokenClass::Utility => self.utility_fees_collected += fee,
+        }
+    }
 }
 
+/// Submit a proposal. Its topic is that of its action; only CallExternal proposals,
+/// whose effect the DAO cannot tell, may be given another one. Voting opens once the
+/// review window is over, until then the proposer may amend the proposal.
 #[ic_cdk::update]
-fn submit_proposal(payload: ProposalPayload) -> Result<u64, String> {
+async fn submit_proposal(action: ProposalAction, topic: Option<Topic>, text: Option<ProposalText>) -> Result<u64, DaoError> {
+    pause::require_not_paused()?;
+    members::require_proposer(&ic_cdk::api::caller())?;
+    SERVICE.with(|service| service.borrow().check_submission_limits(&ic_cdk::api::caller()))?;
+    action.validate()?;
+    let topic = action.resolve_topic(topic)?;
+    if let Some(text) = &text {
+        text.validate()?;
+    }
+
+    // The NFTs of an NFT-gated topic, or the ledger balances with a token ledger, are read
+    // before anything changes, as other messages run while the canister replies
+    let (nft_voting, token_ledger, ledger_voters) = SERVICE.with(|service| {
+        let service = service.borrow();
+        let ledger_voters: Vec<Principal> = service.ledger_voters.iter().copied().collect();
+        (nft::gates(&service.system_params, topic), service.system_params.token_ledger, ledger_voters)
+    });
+    let external_snapshot = match (nft_voting, token_ledger) {
+        (Some(nft_voting), _) => Some(nft::snapshot(&nft_voting).await?),
+        (None, Some(token_ledger)) => Some(ledger::snapshot(token_ledger, ledger_voters).await?),
+        (None, None) => None,
+    };
+    pause::require_not_paused()?;
+    SERVICE.with(|service| service.borrow().check_submission_limits(&ic_cdk::api::caller()))?;
+
     SERVICE.with(|service| {
         //due to service being mutable reference
         //In Rust, you cannot have a mutable borrow (service.borrow_mut()) and then try to access a field of the borrowed value 
-        let proposal_submission_deposit = service.borrow().system_params.proposal_submission_deposit;
+        let proposal_submission_deposit = service.borrow().system_params.effective_params(topic).submission_deposit;
+        let token_ledger = service.borrow().system_params.token_ledger;
         let mut service = service.borrow_mut();
         let caller = ic_cdk::api::caller();
 
-        if let Some(account) = service.accounts.get_mut(&caller) {
-            if *account < proposal_submission_deposit {
+        // The DAO holds no tokens of its members with a token ledger, so no deposit is taken;
+        // check_submission_limits makes the proposer wait between submissions instead
+        if token_ledger.is_none() {
+            if service.accounts.get(&caller).is_none() {
+                return Err("Caller does not have an account".to_string());
+            }
+            if !service.accounts.debit(caller, proposal_submission_deposit) {
                 return Err("Insufficient funds to submit proposal".to_string());
             }
-            *account -= proposal_submission_deposit;
-        } else {
-            return Err("Caller does not have an account".to_string());
         }
 
         let proposal_id = service.next_proposal_id;
         service.next_proposal_id += 1;
+        // Votes are weighted by the voting power at submission, so tokens staked or
+        // moved while the proposal is open change nothing
+        let voting_power_snapshot = Some(external_snapshot.unwrap_or_else(|| service.voting_power_snapshot()));
 
         let new_proposal = Proposal {
             id: proposal_id,
             timestamp: ic_cdk::api::time(),
             proposer: caller,
-            payload,
+            action,
             state: ProposalState::Open,
             votes_yes: Default::default(),
             votes_no: Default::default(),
             voters: Vec::new(),
+            deposit: token_ledger.is_none().then_some(ProposalDeposit {
+                amount: proposal_submission_deposit,
+                state: DepositState::Held,
+            }),
+            voting_power_snapshot,
+            execution_failures: None,
+            execution_attempts: None,
+            accepted_at: None,
+            veto: None,
+            ballots: None,
+            topic: Some(topic),
+            text,
+            version: Some(1),
+            voting_opens_at: Some(ic_cdk::api::time() + amendments::review_window_nanos(&service.system_params)),
+            execution: None,
+            execution_lease: None,
         };
 
+        service.record_revision(&new_proposal);
         service.proposals.insert(proposal_id, new_proposal);
+        service.proposals_by_proposer.entry(caller).or_default().push(proposal_id);
         Ok(proposal_id)
-    })
+    }).map_err(DaoError::from)
 }
 
 #[ic_cdk::update]
-fn vote(args: VoteArgs) -> Result<ProposalState, String> {
+async fn vote(args: VoteArgs) -> Result<ProposalState, DaoError> {
+    pause::require_not_paused()?;
+
     let caller = ic_cdk::api::caller();
 
-    // Get voting_power outside of the mutable borrow block
-    let voting_power = SERVICE.with(|service| {
-        service.borrow().accounts.get(&caller)
-            .cloned()
-            .ok_or_else(|| "Caller does not have an account".to_string())
-    })?;
+    // Proposals submitted with a token ledger before ledger balances were snapshot read
+    // the caller's ledger balance at vote time
+    let has_snapshot = SERVICE.with(|service| {
+        service.borrow().proposals.get(&args.proposal_id).is_some_and(|proposal| proposal.voting_power_snapshot.is_some())
+    });
+    let ledger_balance = match ledger::token_ledger().filter(|_| !has_snapshot) {
+        Some(ledger) => Some(ledger::balance_of(ledger, caller).await?),
+        None => None,
+    };
 
     SERVICE.with(|service| {
-        let proposal_vote_threshold = service.borrow().system_params.proposal_vote_threshold;
         let mut service = service.borrow_mut();
 
-        let proposal = service.proposals.get_mut(&args.proposal_id)
+        let proposal = service.proposals.get(&args.proposal_id)
             .ok_or_else(|| "Proposal not found".to_string())?;
-
-        if proposal.voters.contains(&caller) {
+        if proposal.state != ProposalState::Open {
+            return Err("Proposal is not open for voting".to_string());
+        }
+        let now = ic_cdk::api::time();
+        if proposal.voting_opens_at.is_some_and(|opens_at| now < opens_at) {
+            return Err("Proposal is still in review, voting has not opened yet".to_string());
+        }
+        // The sweep may not have run since the voting period ended
+        if expiry::has_expired(&service.system_params, proposal, now) {
+            expiry::expire(&mut service, args.proposal_id);
+            return Err("Proposal has expired".to_string());
+        }
+        // A vote cast by following is replaced by the voter's own
+        let followed_ballot = proposal.ballot_of(&caller).filter(|ballot| ballot.followed).cloned();
+        if proposal.voters.contains(&caller) && followed_ballot.is_none() {
             return Err("Caller has already voted".to_string());
         }
 
-        match args.vote {
-            Vote::Yes => proposal.votes_yes += voting_power,
-            Vote::No => proposal.votes_no += voting_power,
+        let voting_power = ledger_balance.unwrap_or_else(|| service.proposal_voting_power(args.proposal_id, &caller));
+        if voting_power.amount_e8s == 0 {
+            return Err("Caller has no voting power on this proposal".to_string());
         }
 
-        proposal.voters.push(caller);
+        let weighted_power = service.weighted_power(args.proposal_id, voting_power);
+        if followed_ballot.is_none() {
+            service.index_vote(caller, args.proposal_id);
+        }
+        let proposal = service.proposals.get_mut(&args.proposal_id).unwrap();
+        if let Some(ballot) = followed_ballot {
+            proposal.retract(&ballot);
+        }
+        proposal.cast(Ballot { voter: caller, vote: args.vote, voting_power, followed: false, weighted_power });
+        service.cascade_to_followers(args.proposal_id, caller, args.vote);
 
+        let topic = service.proposals[&args.proposal_id].topic();
+        let proposal_vote_threshold = service.system_params.effective_params(topic).vote_threshold;
+        let proposal = service.proposals.get_mut(&args.proposal_id).unwrap();
         if proposal.votes_yes >= proposal_vote_threshold {
             proposal.state = ProposalState::Accepted;
+            proposal.accepted_at = Some(ic_cdk::api::time());
         } else if proposal.votes_no >= proposal_vote_threshold {
             proposal.state = ProposalState::Rejected;
         }
 
-        Ok(proposal.state.clone())
+        let state = proposal.state.clone();
+        if state != ProposalState::Open {
+            settle_deposit(&mut service, args.proposal_id);
+            service.accrue_voting_rewards(args.proposal_id);
+            service.publish(args.proposal_id);
+        }
+        Ok(state)
+    }).map_err(DaoError::from)
+}
+
+/// Withdraw an Open proposal of the caller that has no votes yet. Its deposit is
+/// refunded.
+#[ic_cdk::update]
+fn cancel_proposal(proposal_id: u64) -> Result<(), DaoError> {
+    pause::require_not_paused()?;
+
+    SERVICE.with(|service| {
+        let mut service = service.borrow_mut();
+        let proposal = service.proposals.get_mut(&proposal_id)
+            .ok_or_else(|| DaoError::from("Proposal not found".to_string()))?;
+
+        if proposal.proposer != ic_cdk::api::caller() {
+            return Err(DaoError::Unauthorized);
+        }
+        if proposal.state != ProposalState::Open || !proposal.voters.is_empty() {
+            return Err(DaoError::from("Only an Open proposal without votes can be cancelled".to_string()));
+        }
+        proposal.state = ProposalState::Cancelled;
+        settle_deposit(&mut service, proposal_id);
+        Ok(())
     })
 }
 
+/// Refund the deposit of an accepted or cancelled proposal to its proposer, or apply
+/// the rejected or expired deposit policy to that of a rejected or expired one
+fn settle_deposit(service: &mut BasicDaoService, proposal_id: u64) {
+    let policy = service.system_params.rejected_deposit_policy.clone().unwrap_or_default();
+    let expired_policy = service.system_params.expired_deposit_policy.clone().unwrap_or_default();
+    let Some(proposal) = service.proposals.get_mut(&proposal_id) else {
+        return;
+    };
+    let Some(deposit) = proposal.deposit.as_mut().filter(|deposit| deposit.state == DepositState::Held) else {
+        return;
+    };
+
+    let recipient = match proposal.state {
+        ProposalState::Accepted | ProposalState::Cancelled => {
+            deposit.state = DepositState::Refunded;
+            Some(proposal.proposer)
+        }
+        ProposalState::Rejected => {
+            deposit.state = DepositState::Forfeited(policy.clone());
+            match policy {
+                DepositPolicy::Burn => None,
+                DepositPolicy::ToTreasury(treasury) => Some(treasury),
+            }
+        }
+        ProposalState::Expired => match expired_policy {
+            ExpiredDepositPolicy::Refund => {
+                deposit.state = DepositState::Refunded;
+                Some(proposal.proposer)
+            }
+            ExpiredDepositPolicy::ToTreasury(treasury) => {
+                deposit.state = DepositState::Forfeited(DepositPolicy::ToTreasury(treasury));
+                Some(treasury)
+            }
+        },
+        _ => return,
+    };
+    let amount = deposit.amount;
+    match recipient {
+        Some(recipient) => service.accounts.credit(recipient, amount),
+        None => service.total_supply -= amount,
+    }
+}
+
+/// Set the state of a proposal by hand. Only callable by an Admin, other callers are
+/// ignored.
 #[ic_cdk::update]
 fn update_proposal_state(proposal_id: u64, new_state: ProposalState) {
+    if members::require_role(&ic_cdk::api::caller(), MemberRole::Admin).is_err() {
+        return;
+    }
+    set_proposal_state(proposal_id, new_state);
+}
+
+pub fn set_proposal_state(proposal_id: u64, new_state: ProposalState) {
     SERVICE.with(|service| {
         let mut service = service.borrow_mut();
 
         if let Some(proposal) = service.proposals.get_mut(&proposal_id) {
             proposal.state = new_state;
+            service.publish(proposal_id);
         }
     })
 }
 
 
+/// Only callable by the DAO canister itself, e.g. from a CallExternal proposal. Other
+/// callers are ignored; invalid params are not applied.
 #[ic_cdk::update]
 fn update_system_params(payload: UpdateSystemParamsPayload) {
-    SERVICE.with(|service| {
-        let mut service = service.borrow_mut();
-        let caller = ic_cdk::api::caller();
+    // Only the canister itself can update system parameters
+    if ic_cdk::api::caller() != ic_cdk::api::id() {
+        return;
+    }
+    let _ = heartbeat::update_system_params(None, payload);
+}
 
-        if caller != ic_cdk::api::id() {
-            // Only the canister itself can update system parameters
-            return;
-        }
 
-        if let Some(transfer_fee) = payload.transfer_fee {
-            service.system_params.transfer_fee = transfer_fee;
-        }
-        if let Some(proposal_vote_threshold) = payload.proposal_vote_threshold {
-            service.system_params.proposal_vote_threshold = proposal_vote_threshold;
-        }
-        if let Some(proposal_submission_deposit) = payload.proposal_submission_deposit {
-            service.system_params.proposal_submission_deposit = proposal_submission_deposit;
-        }
-    })
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The state post_upgrade restores from what pre_upgrade saved
+    fn upgrade() {
+        pre_upgrade();
+        let restored_service = BasicDaoService::from(load_upgrade_state().into_latest());
+        SERVICE.with(|service| *service.borrow_mut() = restored_service);
+    }
+
+    fn next_ids() -> (u64, u64) {
+        SERVICE.with(|service| {
+            let service = service.borrow();
+            (service.next_proposal_id, service.next_stake_id)
+        })
+    }
+
+    #[test]
+    fn ids_continue_across_upgrades() {
+        SERVICE.with(|service| {
+            let mut service = service.borrow_mut();
+            for id in 1..=3 {
+                service.proposals.insert(id, Proposal::for_test(id, Principal::anonymous()));
+            }
+            service.next_proposal_id = 4;
+            service.stakes.insert(1, Stake {
+                id: 1,
+                owner: Principal::anonymous(),
+                amount: Tokens { amount_e8s: 1 },
+                dissolve_delay_seconds: 0,
+                state: StakeState::Locked,
+                created_at: 0,
+            });
+            service.next_stake_id = 2;
+        });
+        upgrade();
+        assert_eq!(next_ids(), (4, 2));
+
+        // The ids of the latest proposal and stake are not given again once they are gone
+        SERVICE.with(|service| {
+            let mut service = service.borrow_mut();
+            service.proposals.remove(&3);
+            service.stakes.remove(&1);
+        });
+        upgrade();
+        assert_eq!(next_ids(), (4, 2));
+        upgrade();
+        assert_eq!(next_ids(), (4, 2));
+    }
+
+    #[test]
+    fn states_saved_without_counters_go_on_after_the_restored_ids() {
+        let mut stable = BasicDaoStableStorage::from(&BasicDaoService::default());
+        stable.proposals = vec![Proposal::for_test(7, Principal::anonymous())];
+        stable.next_proposal_id = None;
+        stable.next_stake_id = None;
+
+        let service = BasicDaoService::from(stable);
+        assert_eq!((service.next_proposal_id, service.next_stake_id), (8, 1));
+    }
+
+    #[test]
+    fn ids_of_dissolved_committees_and_deleted_templates_are_not_given_again() {
+        SERVICE.with(|service| {
+            let mut service = service.borrow_mut();
+            service.next_committee_id = 3;
+            service.next_template_id = 5;
+        });
+        upgrade();
+        let next_ids = SERVICE.with(|service| {
+            let service = service.borrow();
+            (service.next_committee_id, service.next_template_id)
+        });
+        assert_eq!(next_ids, (3, 5));
+
+        // States saved without counters go on after every committee a proposal names
+        let mut stable = BasicDaoStableStorage::from(&BasicDaoService::default());
+        stable.next_committee_id = None;
+        stable.next_template_id = None;
+        stable.committee_proposals = Some(vec![CommitteeProposal {
+            id: 1,
+            committee_id: 2,
+            proposer: Principal::anonymous(),
+            to: Principal::anonymous(),
+            amount: Tokens { amount_e8s: 1 },
+            summary: String::new(),
+            state: ProposalState::Rejected,
+            votes_yes: vec![],
+            votes_no: vec![],
+            timestamp: 0,
+        }]);
+        let service = BasicDaoService::from(stable);
+        assert_eq!((service.next_committee_id, service.next_template_id), (3, 1));
+    }
+
+    #[test]
+    fn notifications_not_sent_yet_are_kept_across_upgrades() {
+        let notification = ProposalNotification {
+            proposal_id: 1,
+            topic: Topic::Governance,
+            state: ProposalState::Accepted,
+            timestamp: 1,
+        };
+        SERVICE.with(|service| {
+            service.borrow_mut().outbox.push(notifications::PendingNotification::for_test(Principal::anonymous(), notification))
+        });
+        upgrade();
+        assert_eq!(SERVICE.with(|service| service.borrow().outbox.len()), 1);
+    }
 
+    #[test]
+    fn ledger_voters_are_kept_across_upgrades() {
+        let voter = Principal::from_slice(&[1; 29]);
+        SERVICE.with(|service| service.borrow_mut().ledger_voters.insert(voter));
+        upgrade();
+        assert!(SERVICE.with(|service| service.borrow().ledger_voters.contains(&voter)));
+    }
+
+    #[test]
+    fn transfers_whose_amounts_overflow_are_rejected() {
+        let from = Principal::from_slice(&[1; 29]);
+        let to = Principal::from_slice(&[2; 29]);
+        let mut service = BasicDaoService::default();
+        service.system_params.transfer_fee = Tokens { amount_e8s: 10 };
+        let fee = service.system_params.transfer_fee_of(TokenClass::Governance);
+        let mut accounts = Accounts::of(TokenClass::Governance);
+        accounts.insert(from, Tokens { amount_e8s: 10 * fee.amount_e8s + 10 });
+
+        // The debit of amount and fee would wrap to zero
+        let amount = Tokens { amount_e8s: u64::MAX - fee.amount_e8s + 1 };
+        assert!(service.transfer(TokenClass::Governance, from, to, amount).is_err());
+        // The recipient's balance would wrap
+        accounts.insert(to, Tokens { amount_e8s: u64::MAX });
+        assert!(service.transfer(TokenClass::Governance, from, to, Tokens { amount_e8s: 1 }).is_err());
+        assert_eq!(accounts.balance(&from).amount_e8s, 10 * fee.amount_e8s + 10);
+        assert_eq!(accounts.balance(&to).amount_e8s, u64::MAX);
+    }
+
+    #[test]
+    fn a_batch_with_a_transfer_that_cannot_be_made_makes_none() {
+        let from = Principal::from_slice(&[1; 29]);
+        let paid = Principal::from_slice(&[2; 29]);
+        let full = Principal::from_slice(&[3; 29]);
+        let mut service = BasicDaoService::default();
+        let mut accounts = Accounts::default();
+        accounts.insert(from, Tokens { amount_e8s: 100 });
+        accounts.insert(full, Tokens { amount_e8s: u64::MAX - 10 });
+        let transfer = |to: Principal, amount_e8s: u64| TransferArgs { to, amount: Tokens { amount_e8s }, class: None };
+
+        // Each transfer to the full account fits on its own, but not both
+        let results = service.transfer_batch_from(from, &[transfer(paid, 10), transfer(full, 6), transfer(full, 6)]);
+        assert!(results.iter().all(|result| result.is_err()));
+        assert_eq!(accounts.balance(&from).amount_e8s, 100);
+        assert!(accounts.get(&paid).is_none());
+
+        let results = service.transfer_batch_from(from, &[transfer(paid, 10), transfer(full, 6)]);
+        assert!(results.iter().all(|result| result.is_ok()));
+        assert_eq!(accounts.balance(&paid).amount_e8s, 10);
+        assert_eq!(accounts.balance(&full).amount_e8s, u64::MAX - 4);
+    }
+
+    #[test]
+    fn batches_whose_total_overflows_are_rejected() {
+        let transfer = |amount_e8s: u64, class: Option<TokenClass>| TransferArgs {
+            to: Principal::anonymous(),
+            amount: Tokens { amount_e8s },
+            class,
+        };
+        let fee = Tokens { amount_e8s: 10 };
+        let transfers = vec![transfer(u64::MAX / 2, None), transfer(5, Some(TokenClass::Utility)), transfer(u64::MAX / 2, None)];
+        assert_eq!(batch_total(&transfers, TokenClass::Utility, fee), Some(Tokens { amount_e8s: 15 }));
+        // Both halves fit, but not with their fees
+        assert_eq!(batch_total(&transfers, TokenClass::Governance, fee), None);
+        assert_eq!(batch_total(&transfers[..1], TokenClass::Governance, fee), Some(Tokens { amount_e8s: u64::MAX / 2 + 10 }));
+    }
+}
@@ -0,0 +1,83 @@
+use std::collections::HashMap;
+use ic_cdk::export::Principal;
+use crate::types::*;
+use crate::{BasicDaoService, SERVICE};
+
+impl BasicDaoService {
+    pub fn has_role(&self, principal: &Principal, role: MemberRole) -> bool {
+        self.members.get(principal).is_some_and(|roles| roles.contains(&role))
+    }
+}
+
+/// The registry of a DAO set up without one: the guardian and the council of the params
+pub fn from_params(params: &SystemParams) -> HashMap<Principal, Vec<MemberRole>> {
+    let mut members: HashMap<Principal, Vec<MemberRole>> = HashMap::new();
+    if let Some(guardian) = params.guardian {
+        members.entry(guardian).or_default().push(MemberRole::Guardian);
+    }
+    for council_member in params.council.iter().flatten() {
+        members.entry(*council_member).or_default().push(MemberRole::Council);
+    }
+    members
+}
+
+pub fn require_role(principal: &Principal, role: MemberRole) -> Result<(), DaoError> {
+    if !SERVICE.with(|service| service.borrow().has_role(principal, role)) {
+        return Err(DaoError::Unauthorized);
+    }
+    Ok(())
+}
+
+/// Members submit proposals. A DAO without members is open to anyone with an account.
+pub fn require_proposer(principal: &Principal) -> Result<(), DaoError> {
+    let is_open = SERVICE.with(|service| !service.borrow().members.values().any(|roles| roles.contains(&MemberRole::Member)));
+    if is_open {
+        return Ok(());
+    }
+    require_role(principal, MemberRole::Member)
+}
+
+/// Execute an accepted AddMember proposal
+pub fn add_member(proposal_id: u64, principal: Principal, role: MemberRole) -> Result<(), String> {
+    SERVICE.with(|service| {
+        let mut service = service.borrow_mut();
+        let roles = service.members.entry(principal).or_default();
+        if roles.contains(&role) {
+            return Err(format!("{} already has the role {:?}", principal, role));
+        }
+        roles.push(role);
+        service.record(AuditEventKind::MemberAdded { proposal_id, principal, role });
+        Ok(())
+    })
+}
+
+/// Execute an accepted RemoveMember proposal
+pub fn remove_member(proposal_id: u64, principal: Principal, role: MemberRole) -> Result<(), String> {
+    SERVICE.with(|service| {
+        let mut service = service.borrow_mut();
+        let roles = service.members.get_mut(&principal)
+            .filter(|roles| roles.contains(&role))
+            .ok_or_else(|| format!("{} does not have the role {:?}", principal, role))?;
+        roles.retain(|member_role| *member_role != role);
+        if roles.is_empty() {
+            service.members.remove(&principal);
+        }
+        service.record(AuditEventKind::MemberRemoved { proposal_id, principal, role });
+        Ok(())
+    })
+}
+
+#[ic_cdk::query]
+fn list_members() -> Vec<Member> {
+    SERVICE.with(|service| {
+        service.borrow().members.iter()
+            .map(|(principal, roles)| Member { principal: *principal, roles: roles.clone() })
+            .collect()
+    })
+}
+
+/// Returns the roles of the caller
+#[ic_cdk::query]
+fn get_my_roles() -> Vec<MemberRole> {
+    SERVICE.with(|service| service.borrow().members.get(&ic_cdk::api::caller()).cloned().unwrap_or_default())
+}
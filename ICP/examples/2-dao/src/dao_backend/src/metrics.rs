@@ -0,0 +1,92 @@
+use std::collections::HashMap;
+use ic_cdk::export::candid::{CandidType, Deserialize};
+use crate::types::*;
+use crate::SERVICE;
+
+const WASM_PAGE_SIZE: u64 = 64 * 1024;
+
+#[derive(CandidType, Deserialize)]
+struct HttpRequest {
+    method: String,
+    url: String,
+    headers: HashMap<String, String>,
+    body: Vec<u8>,
+}
+
+#[derive(CandidType)]
+struct HttpResponse {
+    status_code: u16,
+    headers: HashMap<String, String>,
+    body: Vec<u8>,
+}
+
+pub fn count_timer_run() {
+    SERVICE.with(|service| service.borrow_mut().stats.timer_runs += 1);
+}
+
+fn heap_memory_bytes() -> u64 {
+    #[cfg(target_arch = "wasm32")]
+    return core::arch::wasm32::memory_size(0) as u64 * WASM_PAGE_SIZE;
+    #[cfg(not(target_arch = "wasm32"))]
+    0
+}
+
+/// Returns the resources and activity of the DAO canister, for monitoring
+#[ic_cdk::query]
+fn get_metrics() -> Metrics {
+    SERVICE.with(|service| {
+        let service = service.borrow();
+        let count = |state: fn(&ProposalState) -> bool| {
+            service.proposals.values().filter(|proposal| state(&proposal.state)).count() as u64
+        };
+        Metrics {
+            cycle_balance: ic_cdk::api::canister_balance128(),
+            heap_memory_bytes: heap_memory_bytes(),
+            stable_memory_bytes: ic_cdk::api::stable::stable64_size() * WASM_PAGE_SIZE,
+            accounts: service.accounts.len(),
+            open_proposals: count(|state| *state == ProposalState::Open),
+            accepted_proposals: count(|state| *state == ProposalState::Accepted),
+            failed_proposals: count(|state| matches!(state, ProposalState::Failed(_))),
+            stats: service.stats.clone(),
+        }
+    })
+}
+
+/// The metrics in the Prometheus text format
+fn prometheus(metrics: &Metrics) -> String {
+    let gauges = [
+        ("dao_cycle_balance", "Cycles held by the canister", metrics.cycle_balance),
+        ("dao_heap_memory_bytes", "Heap memory used by the canister", metrics.heap_memory_bytes as u128),
+        ("dao_stable_memory_bytes", "Stable memory used by the canister", metrics.stable_memory_bytes as u128),
+        ("dao_accounts", "Accounts holding tokens", metrics.accounts as u128),
+        ("dao_open_proposals", "Proposals open for voting", metrics.open_proposals as u128),
+        ("dao_accepted_proposals", "Proposals waiting to be executed", metrics.accepted_proposals as u128),
+        ("dao_failed_proposals", "Proposals whose execution failed", metrics.failed_proposals as u128),
+        ("dao_heartbeats", "Heartbeats since the last upgrade", metrics.stats.heartbeats as u128),
+        ("dao_last_heartbeat_timestamp_nanos", "Time of the last heartbeat", metrics.stats.last_heartbeat_at as u128),
+        ("dao_timer_runs", "Timer runs since the last upgrade", metrics.stats.timer_runs as u128),
+        ("dao_executions_succeeded", "Successful proposal executions since the last upgrade", metrics.stats.executions_succeeded as u128),
+        ("dao_executions_failed", "Failed proposal executions since the last upgrade", metrics.stats.executions_failed as u128),
+    ];
+    gauges.iter()
+        .map(|(name, help, value)| format!("# HELP {name} {help}\n# TYPE {name} gauge\n{name} {value}\n"))
+        .collect()
+}
+
+/// Serves the metrics for Prometheus at /metrics
+#[ic_cdk::query]
+fn http_request(request: HttpRequest) -> HttpResponse {
+    let path = request.url.split('?').next().unwrap_or("/");
+    if request.method != "GET" || path != "/metrics" {
+        return HttpResponse {
+            status_code: 404,
+            headers: HashMap::from([("Content-Type".to_string(), "text/plain".to_string())]),
+            body: b"Not found".to_vec(),
+        };
+    }
+    HttpResponse {
+        status_code: 200,
+        headers: HashMap::from([("Content-Type".to_string(), "text/plain; version=0.0.4".to_string())]),
+        body: prometheus(&get_metrics()).into_bytes(),
+    }
+}
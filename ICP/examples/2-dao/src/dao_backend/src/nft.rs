@@ -0,0 +1,106 @@
+use ic_cdk::export::candid::{CandidType, Deserialize, Nat};
+use ic_cdk::export::Principal;
+use std::collections::HashMap;
+use crate::types::*;
+
+/// A snapshot makes two calls per NFT, so it is kept to collections of this size
+const MAX_SNAPSHOT_NFTS: u64 = 500;
+
+#[derive(CandidType, Deserialize, Debug)]
+enum ApiError {
+    Unauthorized,
+    InvalidTokenId,
+    ZeroAddress,
+    Other,
+}
+
+#[derive(CandidType, Deserialize)]
+enum MetadataPurpose {
+    Preview,
+    Rendered,
+}
+
+// The variant names are those of the collection's Candid interface
+#[allow(clippy::enum_variant_names)]
+#[derive(CandidType, Deserialize)]
+enum MetadataVal {
+    TextContent(String),
+    BlobContent(Vec<u8>),
+    NatContent(Nat),
+    Nat8Content(u8),
+    Nat16Content(u16),
+    Nat32Content(u32),
+    Nat64Content(u64),
+}
+
+#[derive(CandidType, Deserialize)]
+struct MetadataPart {
+    purpose: MetadataPurpose,
+    key_val_data: Vec<(String, MetadataVal)>,
+    data: Vec<u8>,
+}
+
+impl MetadataVal {
+    fn as_u64(&self) -> Option<u64> {
+        match self {
+            MetadataVal::NatContent(value) => u64::try_from(&value.0).ok(),
+            MetadataVal::Nat8Content(value) => Some(*value as u64),
+            MetadataVal::Nat16Content(value) => Some(*value as u64),
+            MetadataVal::Nat32Content(value) => Some(*value as u64),
+            MetadataVal::Nat64Content(value) => Some(*value),
+            MetadataVal::TextContent(_) | MetadataVal::BlobContent(_) => None,
+        }
+    }
+}
+
+/// Whether the voting power of the proposals of a topic comes from NFTs
+pub fn gates(params: &SystemParams, topic: Topic) -> Option<NftVoting> {
+    params.nft_voting.clone().filter(|nft| nft.topics.is_empty() || nft.topics.contains(&topic))
+}
+
+fn call_error(method: &str) -> impl FnOnce((ic_cdk::api::call::RejectionCode, String)) -> String + '_ {
+    move |(code, msg)| {
+        format!("Could not call {} of the NFT collection: rejection code: {:?}, message: {}", method, code, msg)
+    }
+}
+
+/// The voting power of every holder of the collection: `votes_per_nft` per NFT, times the
+/// NFT's `weight_key` trait when there is one. Read from the DIP-721 collection, such as
+/// the nft-container example, one NFT at a time. Burned NFTs, held by the management
+/// canister, do not vote.
+pub async fn snapshot(nft: &NftVoting) -> Result<Vec<(Principal, Tokens)>, String> {
+    let (supply,): (u64,) = ic_cdk::call(nft.collection, "totalSupplyDip721", ()).await
+        .map_err(call_error("totalSupplyDip721"))?;
+    if supply > MAX_SNAPSHOT_NFTS {
+        return Err(format!("NFT voting takes collections of at most {} NFTs", MAX_SNAPSHOT_NFTS));
+    }
+
+    let mut powers: HashMap<Principal, Tokens> = HashMap::new();
+    for token_id in 0..supply {
+        let (owner,): (Result<Principal, ApiError>,) = ic_cdk::call(nft.collection, "ownerOfDip721", (token_id,)).await
+            .map_err(call_error("ownerOfDip721"))?;
+        let Ok(owner) = owner else {
+            continue;
+        };
+        if owner == Principal::management_canister() {
+            continue;
+        }
+
+        let weight = match &nft.weight_key {
+            Some(key) => {
+                let (metadata,): (Result<Vec<MetadataPart>, ApiError>,) =
+                    ic_cdk::call(nft.collection, "getMetadataDip721", (token_id,)).await
+                        .map_err(call_error("getMetadataDip721"))?;
+                metadata.ok().iter().flatten()
+                    .flat_map(|part| part.key_val_data.iter())
+                    .find(|(trait_key, _)| trait_key == key)
+                    .and_then(|(_, value)| value.as_u64())
+                    .unwrap_or(1)
+            }
+            None => 1,
+        };
+        let power = Tokens { amount_e8s: nft.votes_per_nft.amount_e8s.saturating_mul(weight) };
+        *powers.entry(owner).or_default() += power;
+    }
+    Ok(powers.into_iter().filter(|(_, power)| power.amount_e8s > 0).collect())
+}
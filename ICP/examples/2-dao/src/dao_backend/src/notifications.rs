@@ -0,0 +1,133 @@
+use std::time::Duration;
+use candid::{CandidType, Deserialize, Encode};
+use ic_cdk::export::Principal;
+use crate::types::*;
+use crate::{members, pause, BasicDaoService, SERVICE};
+
+/// The method of the subscribers that takes a ProposalNotification
+const CALLBACK_METHOD: &str = "on_proposal_notification";
+const MAX_SUBSCRIPTIONS: usize = 100;
+/// A notification that cannot be sent is tried again this many times, further apart
+/// each time
+const MAX_DELIVERY_ATTEMPTS: u32 = 5;
+const RETRY_DELAY: Duration = Duration::from_secs(30);
+
+/// A notification waiting to be sent to a subscriber
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct PendingNotification {
+    subscriber: Principal,
+    notification: ProposalNotification,
+    // The failed attempts to send it
+    attempts: u32,
+}
+
+impl PendingNotification {
+    /// A notification not attempted yet, for tests
+    #[cfg(test)]
+    pub fn for_test(subscriber: Principal, notification: ProposalNotification) -> PendingNotification {
+        PendingNotification { subscriber, notification, attempts: 0 }
+    }
+}
+
+impl BasicDaoService {
+    /// Queue a notification of a proposal's new state for the subscribers to its topic.
+    /// Only Accepted, Succeeded and Failed are published.
+    pub fn publish(&mut self, proposal_id: u64) {
+        let Some(proposal) = self.proposals.get(&proposal_id) else {
+            return;
+        };
+        if !matches!(proposal.state, ProposalState::Accepted | ProposalState::Succeeded | ProposalState::Failed(_)) {
+            return;
+        }
+        let topic = proposal.topic();
+        let subscribers: Vec<Principal> = self.subscriptions.values()
+            .filter(|subscription| subscription.topics.is_empty() || subscription.topics.contains(&topic))
+            .map(|subscription| subscription.canister_id)
+            .collect();
+        if subscribers.is_empty() {
+            return;
+        }
+        let notification = ProposalNotification {
+            proposal_id,
+            topic,
+            state: proposal.state.clone(),
+            timestamp: ic_cdk::api::time(),
+        };
+        for subscriber in subscribers {
+            self.outbox.push(PendingNotification { subscriber, notification: notification.clone(), attempts: 0 });
+        }
+        ic_cdk_timers::set_timer(Duration::ZERO, deliver);
+    }
+}
+
+/// Send the queued notifications, each with its own call. A notification whose call
+/// fails, or is rejected by the subscriber, is kept for a later attempt. The queue is
+/// kept across upgrades.
+fn deliver() {
+    crate::metrics::count_timer_run();
+    let outbox = SERVICE.with(|service| std::mem::take(&mut service.borrow_mut().outbox));
+    for pending in outbox {
+        ic_cdk::spawn(send(pending));
+    }
+}
+
+async fn send(pending: PendingNotification) {
+    let args = Encode!(&pending.notification).expect("failed to encode a notification");
+    // Subscribers may reply anything, so the reply is not decoded
+    if ic_cdk::api::call::call_raw(pending.subscriber, CALLBACK_METHOD, &args, 0).await.is_ok() {
+        return;
+    }
+    let attempts = pending.attempts + 1;
+    if attempts >= MAX_DELIVERY_ATTEMPTS {
+        return;
+    }
+    SERVICE.with(|service| service.borrow_mut().outbox.push(PendingNotification { attempts, ..pending }));
+    ic_cdk_timers::set_timer(RETRY_DELAY * attempts, deliver);
+}
+
+/// Timers do not survive upgrades, so post_upgrade sends the notifications saved in the
+/// queue again
+pub fn reschedule() {
+    if SERVICE.with(|service| !service.borrow().outbox.is_empty()) {
+        ic_cdk_timers::set_timer(Duration::ZERO, deliver);
+    }
+}
+
+/// Subscribe a canister to the state changes of proposals of some topics, or of all
+/// topics when none are given. The DAO calls its `on_proposal_notification` method with
+/// a ProposalNotification when a proposal is Accepted, Succeeded or Failed. Only the
+/// canister itself or an Admin can subscribe it.
+#[ic_cdk::update]
+fn subscribe(callback_canister: Principal, topics: Vec<Topic>) -> Result<(), DaoError> {
+    pause::require_not_paused()?;
+    require_subscriber_or_admin(&callback_canister)?;
+
+    SERVICE.with(|service| {
+        let mut service = service.borrow_mut();
+        if !service.subscriptions.contains_key(&callback_canister) && service.subscriptions.len() >= MAX_SUBSCRIPTIONS {
+            return Err(format!("At most {} canisters can subscribe", MAX_SUBSCRIPTIONS));
+        }
+        service.subscriptions.insert(callback_canister, Subscription { canister_id: callback_canister, topics });
+        Ok(())
+    }).map_err(DaoError::from)
+}
+
+#[ic_cdk::update]
+fn unsubscribe(callback_canister: Principal) -> Result<(), DaoError> {
+    require_subscriber_or_admin(&callback_canister)?;
+    SERVICE.with(|service| service.borrow_mut().subscriptions.remove(&callback_canister));
+    Ok(())
+}
+
+fn require_subscriber_or_admin(callback_canister: &Principal) -> Result<(), DaoError> {
+    let caller = ic_cdk::api::caller();
+    if caller == *callback_canister {
+        return Ok(());
+    }
+    members::require_role(&caller, MemberRole::Admin)
+}
+
+#[ic_cdk::query]
+fn list_subscriptions() -> Vec<Subscription> {
+    SERVICE.with(|service| service.borrow().subscriptions.values().cloned().collect())
+}
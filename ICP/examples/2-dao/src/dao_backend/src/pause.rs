@@ -0,0 +1,33 @@
+use crate::types::*;
+use crate::{members, SERVICE};
+
+pub fn is_paused() -> bool {
+    SERVICE.with(|service| service.borrow().paused)
+}
+
+pub fn require_not_paused() -> Result<(), DaoError> {
+    if is_paused() {
+        return Err(DaoError::Paused);
+    }
+    Ok(())
+}
+
+/// Pause or resume token transfers, staking, proposal submission, voting and proposal
+/// execution. Only callable by a Guardian.
+#[ic_cdk::update]
+fn set_paused(paused: bool) -> Result<(), DaoError> {
+    let caller = ic_cdk::api::caller();
+    members::require_role(&caller, MemberRole::Guardian)?;
+
+    SERVICE.with(|service| {
+        let mut service = service.borrow_mut();
+        service.paused = paused;
+        service.record(AuditEventKind::PauseChanged { guardian: caller, paused });
+        Ok(())
+    })
+}
+
+#[ic_cdk::query]
+fn is_dao_paused() -> bool {
+    is_paused()
+}
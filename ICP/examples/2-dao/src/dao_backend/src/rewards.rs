@@ -0,0 +1,44 @@
+use crate::types::*;
+use crate::{ledger, pause, BasicDaoService, SERVICE};
+
+impl BasicDaoService {
+    /// Accrue the voting reward to every voter of a settled proposal, paid from the
+    /// rewards pool account. Once the pool runs dry, the remaining voters get nothing.
+    pub fn accrue_voting_rewards(&mut self, proposal_id: u64) {
+        let (Some(reward), Some(pool)) = (self.system_params.voting_reward, self.system_params.rewards_pool) else {
+            return;
+        };
+        let Some(voters) = self.proposals.get(&proposal_id).map(|proposal| proposal.voters.clone()) else {
+            return;
+        };
+
+        for voter in voters {
+            if !self.accounts.debit(pool, reward) {
+                break;
+            }
+            *self.pending_rewards.entry(voter).or_default() += reward;
+        }
+    }
+}
+
+/// Move the caller's accrued voting rewards to their account
+#[ic_cdk::update]
+fn claim_rewards() -> Result<Tokens, DaoError> {
+    pause::require_not_paused()?;
+    ledger::require_internal_accounts()?;
+
+    SERVICE.with(|service| {
+        let mut service = service.borrow_mut();
+        let caller = ic_cdk::api::caller();
+        let rewards = service.pending_rewards.remove(&caller)
+            .ok_or_else(|| DaoError::from("No rewards to claim".to_string()))?;
+        service.accounts.credit(caller, rewards);
+        Ok(rewards)
+    })
+}
+
+/// Returns the voting rewards the caller can claim
+#[ic_cdk::query]
+fn get_pending_rewards() -> Tokens {
+    SERVICE.with(|service| service.borrow().pending_rewards.get(&ic_cdk::api::caller()).copied().unwrap_or_default())
+}
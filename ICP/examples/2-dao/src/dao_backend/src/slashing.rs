@@ -0,0 +1,159 @@
+use crate::types::*;
+use crate::{members, pause, BasicDaoService, SERVICE};
+
+const DEFAULT_APPEAL_WINDOW_SECONDS: u64 = 7 * 24 * 60 * 60;
+const MAX_STATEMENT_LENGTH: usize = 2_000;
+const NANOS_PER_SECOND: u64 = 1_000_000_000;
+
+impl BasicDaoService {
+    fn council_size(&self) -> usize {
+        self.members.values().filter(|roles| roles.contains(&MemberRole::Council)).count()
+    }
+
+    fn appeal_window_nanos(&self) -> u64 {
+        self.system_params.slash_appeal_window_seconds.unwrap_or(DEFAULT_APPEAL_WINDOW_SECONDS) * NANOS_PER_SECOND
+    }
+}
+
+impl MaliciousFlag {
+    /// Whether the proposer may still be slashed: the council flagged the proposal, and
+    /// the proposer can no longer appeal
+    fn is_final(&self, appeal_window_nanos: u64, now: u64) -> bool {
+        match self.flagged_at {
+            Some(flagged_at) => self.appeal.is_some() || now >= flagged_at.saturating_add(appeal_window_nanos),
+            None => false,
+        }
+    }
+}
+
+fn validate_statement(statement: &str) -> Result<(), DaoError> {
+    if statement.trim().is_empty() || statement.chars().count() > MAX_STATEMENT_LENGTH {
+        return Err(DaoError::from(format!("A statement must have 1 to {} characters", MAX_STATEMENT_LENGTH)));
+    }
+    Ok(())
+}
+
+/// Vote, as a Council member, to flag a proposal as malicious. The first vote gives the
+/// justification. The proposal is flagged once a majority of the council voted for it,
+/// after which its proposer may be slashed by a Slash proposal.
+#[ic_cdk::update]
+fn flag_malicious(proposal_id: u64, justification: String) -> Result<(), DaoError> {
+    pause::require_not_paused()?;
+    let caller = ic_cdk::api::caller();
+    members::require_role(&caller, MemberRole::Council)?;
+
+    SERVICE.with(|service| {
+        let mut service = service.borrow_mut();
+        let proposer = service.proposals.get(&proposal_id)
+            .ok_or_else(|| DaoError::from("Proposal not found".to_string()))?
+            .proposer;
+        let majority = service.council_size() / 2 + 1;
+        let now = ic_cdk::api::time();
+
+        if !service.malicious_flags.contains_key(&proposal_id) {
+            validate_statement(&justification)?;
+        }
+        let flag = service.malicious_flags.entry(proposal_id).or_insert_with(|| MaliciousFlag {
+            proposal_id,
+            proposer,
+            justification,
+            votes: vec![],
+            flagged_at: None,
+            appeal: None,
+            slashed: None,
+        });
+        if flag.flagged_at.is_some() {
+            return Err(DaoError::from(format!("Proposal {} is already flagged", proposal_id)));
+        }
+        if flag.votes.contains(&caller) {
+            return Err(DaoError::from("Already voted".to_string()));
+        }
+
+        flag.votes.push(caller);
+        if flag.votes.len() >= majority {
+            flag.flagged_at = Some(now);
+        }
+        Ok(())
+    })
+}
+
+/// Appeal the flag on one of the caller's proposals within the appeal window. The flag is
+/// lifted, and the council has to flag the proposal again by majority, after hearing the
+/// statement, for the proposer to be slashed. A proposer appeals a flag once.
+#[ic_cdk::update]
+fn appeal_malicious_flag(proposal_id: u64, statement: String) -> Result<(), DaoError> {
+    pause::require_not_paused()?;
+    validate_statement(&statement)?;
+
+    SERVICE.with(|service| {
+        let mut service = service.borrow_mut();
+        let appeal_window_nanos = service.appeal_window_nanos();
+        let now = ic_cdk::api::time();
+        let flag = service.malicious_flags.get_mut(&proposal_id)
+            .ok_or_else(|| DaoError::from("Proposal is not flagged".to_string()))?;
+        if flag.proposer != ic_cdk::api::caller() {
+            return Err(DaoError::Unauthorized);
+        }
+        if flag.appeal.is_some() {
+            return Err(DaoError::from("The flag was already appealed".to_string()));
+        }
+        let in_window = flag.flagged_at.is_some_and(|flagged_at| now < flagged_at.saturating_add(appeal_window_nanos));
+        if !in_window {
+            return Err(DaoError::from("Only a flag within its appeal window can be appealed".to_string()));
+        }
+
+        flag.appeal = Some(Appeal { statement, timestamp: now });
+        flag.votes.clear();
+        flag.flagged_at = None;
+        Ok(())
+    })
+}
+
+/// Checked on submission of Slash proposals
+pub fn validate_slash(proposal_id: u64, percent: u8) -> Result<(), String> {
+    if !(1..=100).contains(&percent) {
+        return Err("A slash takes between 1 and 100 percent of the stakes".to_string());
+    }
+    SERVICE.with(|service| match service.borrow().malicious_flags.get(&proposal_id) {
+        Some(flag) if flag.flagged_at.is_some() && flag.slashed.is_none() => Ok(()),
+        _ => Err(format!("The council has not flagged proposal {} as malicious", proposal_id)),
+    })
+}
+
+/// Execute an accepted Slash proposal: take a percentage of every stake of the proposer of
+/// a flagged proposal, once the appeal window is over, and burn it or send it to the
+/// treasury account
+pub fn slash(slash_proposal_id: u64, proposal_id: u64, percent: u8, policy: DepositPolicy) -> Result<(), String> {
+    validate_slash(proposal_id, percent)?;
+
+    SERVICE.with(|service| {
+        let mut service = service.borrow_mut();
+        let appeal_window_nanos = service.appeal_window_nanos();
+        let now = ic_cdk::api::time();
+        let flag = &service.malicious_flags[&proposal_id];
+        if !flag.is_final(appeal_window_nanos, now) {
+            return Err(format!("The flag on proposal {} can still be appealed", proposal_id));
+        }
+
+        let proposer = flag.proposer;
+        let mut amount = Tokens::default();
+        for stake in service.stakes.values_mut().filter(|stake| stake.owner == proposer) {
+            let slashed = Tokens { amount_e8s: (stake.amount.amount_e8s as u128 * percent as u128 / 100) as u64 };
+            stake.amount -= slashed;
+            amount += slashed;
+        }
+        match &policy {
+            DepositPolicy::Burn => service.total_supply -= amount,
+            DepositPolicy::ToTreasury(treasury) => service.accounts.credit(*treasury, amount),
+        }
+
+        service.malicious_flags.get_mut(&proposal_id).unwrap().slashed = Some(amount);
+        service.record(AuditEventKind::Slashed { proposal_id: slash_proposal_id, flagged_proposal_id: proposal_id, proposer, amount, policy });
+        Ok(())
+    })
+}
+
+#[ic_cdk::query]
+fn list_malicious_flags() -> Vec<MaliciousFlag> {
+    SERVICE.with(|service| service.borrow().malicious_flags.values().cloned().collect())
+}
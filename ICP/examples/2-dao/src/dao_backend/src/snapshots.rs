@@ -0,0 +1,170 @@
+use ic_cdk::export::candid::{Decode, Encode};
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+use crate::accounts::Accounts;
+use crate::types::*;
+use crate::{airdrop, execution, members, pause, BasicDaoService, SERVICE};
+
+/// Chunks stay well below the size limit of a reply
+const CHUNK_SIZE: usize = 1 << 20;
+
+/// A snapshot being imported, one chunk at a time, in any order
+struct SnapshotImport {
+    manifest: SnapshotManifest,
+    chunks: Vec<Option<Vec<u8>>>,
+}
+
+/// A snapshot encoded once by prepare_snapshot, whose chunks export_snapshot serves
+struct SnapshotExport {
+    manifest: SnapshotManifest,
+    bytes: Vec<u8>,
+}
+
+thread_local! {
+    // Not saved across upgrades; an import interrupted by one starts over
+    static IMPORT: RefCell<Option<SnapshotImport>> = const { RefCell::new(None) };
+
+    // Not saved across upgrades either; an export interrupted by one is prepared again
+    static EXPORT: RefCell<Option<SnapshotExport>> = const { RefCell::new(None) };
+}
+
+/// The whole state of the DAO, with the accounts that otherwise live in stable memory
+fn encode_snapshot(service: &BasicDaoService) -> Result<Vec<u8>, String> {
+    let mut stable = BasicDaoStableStorage::from(service);
+    stable.accounts = accounts_of(TokenClass::Governance);
+    stable.utility_accounts = Some(accounts_of(TokenClass::Utility));
+    Encode!(&VersionedStableStorage::V2(Box::new(stable))).map_err(|e| format!("Could not encode the snapshot: {}", e))
+}
+
+fn accounts_of(class: TokenClass) -> Vec<Account> {
+    Accounts::of(class).entries().into_iter().map(|(owner, tokens)| Account { owner, tokens }).collect()
+}
+
+fn manifest_of(bytes: &[u8]) -> SnapshotManifest {
+    SnapshotManifest {
+        total_bytes: bytes.len() as u64,
+        sha256: Sha256::digest(bytes).to_vec(),
+        chunk_sha256: bytes.chunks(CHUNK_SIZE).map(|chunk| Sha256::digest(chunk).to_vec()).collect(),
+    }
+}
+
+fn prepare(service: &BasicDaoService) -> Result<SnapshotManifest, String> {
+    let bytes = encode_snapshot(service)?;
+    let manifest = manifest_of(&bytes);
+    EXPORT.with(|export| *export.borrow_mut() = Some(SnapshotExport { manifest: manifest.clone(), bytes }));
+    Ok(manifest)
+}
+
+fn chunk_of(index: u64) -> Result<SnapshotChunk, String> {
+    EXPORT.with(|export| {
+        let export = export.borrow();
+        let export = export.as_ref().ok_or_else(|| "No snapshot was prepared".to_string())?;
+        let data = export.bytes.chunks(CHUNK_SIZE).nth(index as usize)
+            .ok_or_else(|| format!("The snapshot has {} chunks", export.manifest.chunk_sha256.len()))?
+            .to_vec();
+        Ok(SnapshotChunk { index, manifest: export.manifest.clone(), data })
+    })
+}
+
+/// Encode a snapshot of the whole DAO state: accounts, proposals, params and the rest,
+/// replacing the one prepared before. Returns its manifest, which tells how many chunks
+/// there are and their checksums. The snapshot is the state at the time of the call;
+/// later changes are only in the next one. Only callable by Admins.
+#[ic_cdk::update]
+fn prepare_snapshot() -> Result<SnapshotManifest, DaoError> {
+    members::require_role(&ic_cdk::api::caller(), MemberRole::Admin)?;
+    Ok(SERVICE.with(|service| prepare(&service.borrow()))?)
+}
+
+/// Returns a chunk of the snapshot made by prepare_snapshot, with its manifest. Only
+/// callable by Admins.
+#[ic_cdk::query]
+fn export_snapshot(chunk: u64) -> Result<SnapshotChunk, DaoError> {
+    members::require_role(&ic_cdk::api::caller(), MemberRole::Admin)?;
+    Ok(chunk_of(chunk)?)
+}
+
+/// Import a chunk of a snapshot made by export_snapshot, e.g. to move the DAO to a new
+/// canister. Once every chunk of the manifest is in and the whole snapshot matches its
+/// checksum, the snapshot replaces the state of the DAO, which stays paused for a
+/// Guardian to resume. Only callable by Admins while the DAO is paused. Returns whether
+/// the snapshot was applied.
+#[ic_cdk::update]
+fn import_snapshot(chunk: SnapshotChunk) -> Result<bool, DaoError> {
+    members::require_role(&ic_cdk::api::caller(), MemberRole::Admin)?;
+    if !pause::is_paused() {
+        return Err(DaoError::from("A snapshot is only imported while the DAO is paused".to_string()));
+    }
+    let Some(expected) = chunk.manifest.chunk_sha256.get(chunk.index as usize) else {
+        return Err(DaoError::from(format!("The snapshot has no chunk {}", chunk.index)));
+    };
+    if Sha256::digest(&chunk.data).to_vec() != *expected {
+        return Err(DaoError::from(format!("Chunk {} does not match its checksum", chunk.index)));
+    }
+
+    let bytes = IMPORT.with(|import| {
+        let mut import = import.borrow_mut();
+        // A chunk of another snapshot starts the import over
+        if import.as_ref().is_none_or(|import| import.manifest != chunk.manifest) {
+            *import = Some(SnapshotImport {
+                chunks: vec![None; chunk.manifest.chunk_sha256.len()],
+                manifest: chunk.manifest.clone(),
+            });
+        }
+        let pending = import.as_mut().unwrap();
+        pending.chunks[chunk.index as usize] = Some(chunk.data);
+        if pending.chunks.iter().any(Option::is_none) {
+            return None;
+        }
+        let bytes: Vec<u8> = pending.chunks.iter().flatten().flatten().copied().collect();
+        *import = None;
+        Some(bytes)
+    });
+    let Some(bytes) = bytes else {
+        return Ok(false);
+    };
+
+    if manifest_of(&bytes) != chunk.manifest {
+        return Err(DaoError::from("The snapshot does not match its checksum".to_string()));
+    }
+    let stable = Decode!(&bytes, VersionedStableStorage)
+        .map_err(|e| DaoError::from(format!("Could not decode the snapshot: {}", e)))?
+        .into_latest();
+
+    for class in [TokenClass::Governance, TokenClass::Utility] {
+        let mut accounts = Accounts::of(class);
+        for (owner, _) in accounts.entries() {
+            accounts.remove(&owner);
+        }
+    }
+    let mut service = BasicDaoService::from(stable);
+    service.paused = true;
+    SERVICE.with(|current| *current.borrow_mut() = service);
+    execution::reschedule();
+    airdrop::reschedule();
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunks_are_served_from_the_prepared_snapshot() {
+        assert!(chunk_of(0).is_err());
+
+        let mut service = BasicDaoService::default();
+        let manifest = prepare(&service).unwrap();
+        service.paused = !service.paused;
+
+        // The state changed, but the chunks are those of the snapshot prepared before it did
+        let chunk = chunk_of(0).unwrap();
+        assert_eq!(chunk.manifest, manifest);
+        assert_eq!(manifest_of(&chunk.data), manifest);
+        assert!(chunk_of(1).is_err());
+
+        let manifest = prepare(&service).unwrap();
+        assert_ne!(chunk.manifest, manifest);
+        assert_eq!(chunk_of(0).unwrap().manifest, manifest);
+    }
+}
@@ -0,0 +1,470 @@
+use ic_cdk::export::candid::{CandidType, Deserialize};
+use ic_cdk::export::Principal;
+use crate::types::{Ballot, DaoError, Proposal, ProposalAction, ProposalState, Stake, StakeState, Vote, VoteArgs};
+use crate::{staking, SERVICE};
+
+const NANOS_PER_SECOND: u64 = 1_000_000_000;
+const MAX_LIST_LIMIT: u32 = 100;
+
+// The SNS proposal statuses
+const STATUS_OPEN: i32 = 1;
+const STATUS_REJECTED: i32 = 2;
+const STATUS_ADOPTED: i32 = 3;
+const STATUS_EXECUTED: i32 = 4;
+const STATUS_FAILED: i32 = 5;
+
+// The SNS votes
+const VOTE_YES: i32 = 1;
+const VOTE_NO: i32 = 2;
+
+// The SNS governance error types
+const ERROR_UNSPECIFIED: i32 = 0;
+const ERROR_UNAVAILABLE: i32 = 1;
+const ERROR_NOT_AUTHORIZED: i32 = 2;
+const ERROR_NOT_FOUND: i32 = 3;
+const ERROR_INVALID_COMMAND: i32 = 4;
+const ERROR_RESOURCE_EXHAUSTED: i32 = 9;
+
+// The SNS neuron permission to vote, the only one this DAO knows
+const PERMISSION_VOTE: i32 = 4;
+
+#[derive(CandidType, Deserialize, Clone, Copy)]
+struct ProposalId {
+    id: u64,
+}
+
+/// Stakes are shown to SNS tooling as neurons, whose ids are the stake ids as 8 big-endian
+/// bytes
+#[derive(CandidType, Deserialize, Clone)]
+struct NeuronId {
+    id: Vec<u8>,
+}
+
+impl NeuronId {
+    fn of(stake_id: u64) -> NeuronId {
+        NeuronId { id: stake_id.to_be_bytes().to_vec() }
+    }
+
+    fn stake_id(&self) -> Option<u64> {
+        Some(u64::from_be_bytes(self.id.as_slice().try_into().ok()?))
+    }
+}
+
+#[derive(CandidType, Deserialize)]
+struct GovernanceError {
+    error_type: i32,
+    error_message: String,
+}
+
+impl GovernanceError {
+    fn new(error_type: i32, error_message: &str) -> GovernanceError {
+        GovernanceError { error_type, error_message: error_message.to_string() }
+    }
+}
+
+impl From<DaoError> for GovernanceError {
+    fn from(error: DaoError) -> GovernanceError {
+        let error_type = match error {
+            DaoError::Paused => ERROR_UNAVAILABLE,
+            DaoError::Unauthorized => ERROR_NOT_AUTHORIZED,
+            DaoError::TooManyOpenProposals { .. } | DaoError::SubmissionCooldown { .. } => ERROR_RESOURCE_EXHAUSTED,
+            DaoError::Other(_) => ERROR_UNSPECIFIED,
+        };
+        GovernanceError { error_type, error_message: format!("{:?}", error) }
+    }
+}
+
+#[derive(CandidType, Deserialize)]
+struct SnsProposal {
+    title: String,
+    summary: String,
+    url: String,
+}
+
+#[derive(CandidType, Deserialize)]
+struct SnsBallot {
+    vote: i32,
+    voting_power: u64,
+    cast_timestamp_seconds: u64,
+}
+
+#[derive(CandidType, Deserialize)]
+struct Tally {
+    timestamp_seconds: u64,
+    yes: u64,
+    no: u64,
+    total: u64,
+}
+
+#[derive(CandidType, Deserialize)]
+struct ProposalData {
+    id: Option<ProposalId>,
+    // Proposals are submitted by principals rather than neurons
+    proposer: Option<NeuronId>,
+    action: u64,
+    proposal: Option<SnsProposal>,
+    // Keyed by the voters' principals rather than neuron ids
+    ballots: Vec<(String, SnsBallot)>,
+    latest_tally: Option<Tally>,
+    reject_cost_e8s: u64,
+    proposal_creation_timestamp_seconds: u64,
+    initial_voting_period_seconds: u64,
+    wait_for_quiet_deadline_increase_seconds: u64,
+    decided_timestamp_seconds: u64,
+    executed_timestamp_seconds: u64,
+    failed_timestamp_seconds: u64,
+    failure_reason: Option<GovernanceError>,
+    is_eligible_for_rewards: bool,
+    reward_event_round: u64,
+}
+
+#[derive(CandidType, Deserialize)]
+struct ListProposals {
+    limit: u32,
+    before_proposal: Option<ProposalId>,
+    exclude_type: Vec<u64>,
+    include_status: Vec<i32>,
+    include_reward_status: Vec<i32>,
+}
+
+#[derive(CandidType, Deserialize)]
+struct ListProposalsResponse {
+    proposals: Vec<ProposalData>,
+}
+
+#[derive(CandidType, Deserialize)]
+struct GetProposal {
+    proposal_id: Option<ProposalId>,
+}
+
+#[derive(CandidType, Deserialize)]
+enum GetProposalResult {
+    Error(GovernanceError),
+    Proposal(Box<ProposalData>),
+}
+
+#[derive(CandidType, Deserialize)]
+struct GetProposalResponse {
+    result: Option<GetProposalResult>,
+}
+
+#[derive(CandidType, Deserialize)]
+struct NeuronPermission {
+    principal: Option<Principal>,
+    permission_type: Vec<i32>,
+}
+
+#[derive(CandidType, Deserialize)]
+enum DissolveState {
+    DissolveDelaySeconds(u64),
+    WhenDissolvedTimestampSeconds(u64),
+}
+
+#[derive(CandidType, Deserialize)]
+struct Followees {
+    followees: Vec<NeuronId>,
+}
+
+#[derive(CandidType, Deserialize)]
+struct Neuron {
+    id: Option<NeuronId>,
+    permissions: Vec<NeuronPermission>,
+    cached_neuron_stake_e8s: u64,
+    neuron_fees_e8s: u64,
+    created_timestamp_seconds: u64,
+    aging_since_timestamp_seconds: u64,
+    dissolve_state: Option<DissolveState>,
+    voting_power_percentage_multiplier: u64,
+    maturity_e8s_equivalent: u64,
+    followees: Vec<(u64, Followees)>,
+}
+
+#[derive(CandidType, Deserialize)]
+struct ListNeurons {
+    of_principal: Option<Principal>,
+    limit: u32,
+    start_page_at: Option<NeuronId>,
+}
+
+#[derive(CandidType, Deserialize)]
+struct ListNeuronsResponse {
+    neurons: Vec<Neuron>,
+}
+
+#[derive(CandidType, Deserialize)]
+struct NervousSystemParameters {
+    reject_cost_e8s: Option<u64>,
+    transaction_fee_e8s: Option<u64>,
+    max_dissolve_delay_seconds: Option<u64>,
+    neuron_minimum_dissolve_delay_to_vote_seconds: Option<u64>,
+    initial_voting_period_seconds: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize)]
+struct RegisterVote {
+    proposal: Option<ProposalId>,
+    vote: i32,
+}
+
+#[derive(CandidType, Deserialize)]
+struct StartDissolving {}
+
+#[derive(CandidType, Deserialize)]
+enum Operation {
+    StartDissolving(StartDissolving),
+}
+
+#[derive(CandidType, Deserialize)]
+struct Configure {
+    operation: Option<Operation>,
+}
+
+#[derive(CandidType, Deserialize)]
+struct Disburse {}
+
+#[derive(CandidType, Deserialize)]
+enum Command {
+    RegisterVote(RegisterVote),
+    Configure(Configure),
+    Disburse(Disburse),
+}
+
+#[derive(CandidType, Deserialize)]
+struct ManageNeuron {
+    // The id of the neuron
+    subaccount: Vec<u8>,
+    command: Option<Command>,
+}
+
+#[derive(CandidType, Deserialize)]
+struct EmptyResponse {}
+
+#[derive(CandidType, Deserialize)]
+struct DisburseResponse {
+    // Stakes are disbursed to internal accounts, without a ledger block
+    transfer_block_height: u64,
+}
+
+#[derive(CandidType, Deserialize)]
+enum CommandResponse {
+    Error(GovernanceError),
+    RegisterVote(EmptyResponse),
+    Configure(EmptyResponse),
+    Disburse(DisburseResponse),
+}
+
+#[derive(CandidType, Deserialize)]
+struct ManageNeuronResponse {
+    command: Option<CommandResponse>,
+}
+
+fn seconds(nanos: u64) -> u64 {
+    nanos / NANOS_PER_SECOND
+}
+
+/// The nearest SNS proposal type: the ids of the SNS native functions
+fn action_type(action: &ProposalAction) -> u64 {
+    match action {
+        ProposalAction::UpdateSystemParams(_) => 2,
+        ProposalAction::UpgradeCanister { .. } | ProposalAction::UpgradeCanisterToStagedWasm { .. } => 3,
+        ProposalAction::CallExternal(_) => 6,
+        ProposalAction::TransferTokens { .. } | ProposalAction::TransferFromTreasury { .. } => 9,
+        _ => 0,
+    }
+}
+
+fn status(state: &ProposalState) -> i32 {
+    match state {
+        ProposalState::Open => STATUS_OPEN,
+        ProposalState::Rejected | ProposalState::Cancelled | ProposalState::Vetoed | ProposalState::Expired => STATUS_REJECTED,
+        ProposalState::Accepted | ProposalState::Executing | ProposalState::RetryScheduled { .. } => STATUS_ADOPTED,
+        ProposalState::Succeeded => STATUS_EXECUTED,
+        ProposalState::Failed(_) => STATUS_FAILED,
+    }
+}
+
+fn vote_code(vote: Vote) -> i32 {
+    match vote {
+        Vote::Yes => VOTE_YES,
+        Vote::No => VOTE_NO,
+    }
+}
+
+fn proposal_data(proposal: &Proposal) -> ProposalData {
+    let voting_period = SERVICE.with(|service| {
+        service.borrow().system_params.effective_params(proposal.topic()).voting_period_seconds
+    });
+    let executed_at = proposal.execution.as_ref().map_or(0, |execution| seconds(execution.executed_at));
+    let total = proposal.voting_power_snapshot.as_ref()
+        .map_or(proposal.votes_yes.amount_e8s + proposal.votes_no.amount_e8s, |snapshot| {
+            snapshot.iter().map(|(_, power)| power.amount_e8s).sum()
+        });
+    let decided_at = match proposal.state {
+        ProposalState::Open => 0,
+        _ => proposal.accepted_at.map_or(0, seconds),
+    };
+
+    ProposalData {
+        id: Some(ProposalId { id: proposal.id }),
+        proposer: None,
+        action: action_type(&proposal.action),
+        proposal: Some(SnsProposal {
+            title: proposal.text.as_ref().map(|text| text.title.clone()).unwrap_or_default(),
+            summary: proposal.text.as_ref().map(|text| text.summary.clone()).unwrap_or_default(),
+            url: String::new(),
+        }),
+        ballots: proposal.ballots.iter().flatten()
+            .map(|ballot: &Ballot| (ballot.voter.to_text(), SnsBallot {
+                vote: vote_code(ballot.vote),
+                voting_power: ballot.counted_power().amount_e8s,
+                cast_timestamp_seconds: 0,
+            }))
+            .collect(),
+        latest_tally: Some(Tally {
+            timestamp_seconds: seconds(ic_cdk::api::time()),
+            yes: proposal.votes_yes.amount_e8s,
+            no: proposal.votes_no.amount_e8s,
+            total,
+        }),
+        reject_cost_e8s: proposal.deposit.as_ref().map_or(0, |deposit| deposit.amount.amount_e8s),
+        proposal_creation_timestamp_seconds: seconds(proposal.timestamp),
+        initial_voting_period_seconds: voting_period.unwrap_or_default(),
+        wait_for_quiet_deadline_increase_seconds: 0,
+        decided_timestamp_seconds: decided_at,
+        executed_timestamp_seconds: if proposal.state == ProposalState::Succeeded { executed_at } else { 0 },
+        failed_timestamp_seconds: if matches!(proposal.state, ProposalState::Failed(_)) { executed_at } else { 0 },
+        failure_reason: match &proposal.state {
+            ProposalState::Failed(error) => Some(GovernanceError::new(ERROR_UNSPECIFIED, error)),
+            _ => None,
+        },
+        is_eligible_for_rewards: false,
+        reward_event_round: 0,
+    }
+}
+
+fn neuron(stake: &Stake) -> Neuron {
+    Neuron {
+        id: Some(NeuronId::of(stake.id)),
+        permissions: vec![NeuronPermission { principal: Some(stake.owner), permission_type: vec![PERMISSION_VOTE] }],
+        cached_neuron_stake_e8s: stake.amount.amount_e8s,
+        neuron_fees_e8s: 0,
+        created_timestamp_seconds: seconds(stake.created_at),
+        aging_since_timestamp_seconds: seconds(stake.created_at),
+        dissolve_state: Some(match stake.state {
+            StakeState::Locked => DissolveState::DissolveDelaySeconds(stake.dissolve_delay_seconds),
+            StakeState::Dissolving { dissolves_at } => DissolveState::WhenDissolvedTimestampSeconds(seconds(dissolves_at)),
+        }),
+        voting_power_percentage_multiplier: 100,
+        maturity_e8s_equivalent: 0,
+        followees: vec![],
+    }
+}
+
+/// The SNS `list_proposals`, under another name as the DAO's own `list_proposals` keeps
+/// its. Returns up to `limit` proposals, at most 100, older than `before_proposal`, newest
+/// first. `include_reward_status` is ignored, as the DAO has no SNS rewards.
+#[ic_cdk::query]
+fn sns_list_proposals(request: ListProposals) -> ListProposalsResponse {
+    let _ = request.include_reward_status;
+    SERVICE.with(|service| {
+        let service = service.borrow();
+        let mut proposals: Vec<&Proposal> = service.proposals.values()
+            .filter(|proposal| request.before_proposal.is_none_or(|before| proposal.id < before.id))
+            .filter(|proposal| !request.exclude_type.contains(&action_type(&proposal.action)))
+            .filter(|proposal| request.include_status.is_empty() || request.include_status.contains(&status(&proposal.state)))
+            .collect();
+        proposals.sort_by_key(|proposal| std::cmp::Reverse(proposal.id));
+        ListProposalsResponse {
+            proposals: proposals.into_iter()
+                .take(request.limit.min(MAX_LIST_LIMIT) as usize)
+                .map(proposal_data)
+                .collect(),
+        }
+    })
+}
+
+/// The SNS `get_proposal`
+#[ic_cdk::query]
+fn sns_get_proposal(request: GetProposal) -> GetProposalResponse {
+    let proposal = request.proposal_id
+        .and_then(|id| SERVICE.with(|service| service.borrow().proposals.get(&id.id).cloned()));
+    let result = match proposal {
+        Some(proposal) => GetProposalResult::Proposal(Box::new(proposal_data(&proposal))),
+        None => GetProposalResult::Error(GovernanceError::new(ERROR_NOT_FOUND, "Proposal not found")),
+    };
+    GetProposalResponse { result: Some(result) }
+}
+
+/// Returns up to `limit` neurons, at most 100, from `start_page_at` on, by id
+#[ic_cdk::query]
+fn list_neurons(request: ListNeurons) -> ListNeuronsResponse {
+    let start = request.start_page_at.and_then(|id| id.stake_id()).unwrap_or_default();
+    SERVICE.with(|service| {
+        let service = service.borrow();
+        let mut stakes: Vec<&Stake> = service.stakes.values()
+            .filter(|stake| stake.id >= start)
+            .filter(|stake| request.of_principal.is_none_or(|owner| stake.owner == owner))
+            .collect();
+        stakes.sort_by_key(|stake| stake.id);
+        ListNeuronsResponse {
+            neurons: stakes.into_iter().take(request.limit.min(MAX_LIST_LIMIT) as usize).map(neuron).collect(),
+        }
+    })
+}
+
+#[ic_cdk::query]
+fn get_nervous_system_parameters(_: ()) -> NervousSystemParameters {
+    SERVICE.with(|service| {
+        let params = &service.borrow().system_params;
+        NervousSystemParameters {
+            reject_cost_e8s: Some(params.proposal_submission_deposit.amount_e8s),
+            transaction_fee_e8s: Some(params.transfer_fee.amount_e8s),
+            max_dissolve_delay_seconds: Some(staking::MAX_DISSOLVE_DELAY_SECONDS),
+            neuron_minimum_dissolve_delay_to_vote_seconds: Some(staking::MIN_DISSOLVE_DELAY_SECONDS),
+            initial_voting_period_seconds: params.proposal_expiry_seconds,
+        }
+    })
+}
+
+/// Vote with, start dissolving or disburse a neuron of the caller. Votes are cast with
+/// the caller's whole voting power, as by `vote`, whichever of their neurons is given.
+#[ic_cdk::update]
+async fn manage_neuron(request: ManageNeuron) -> ManageNeuronResponse {
+    let response = match manage(request).await {
+        Ok(response) => response,
+        Err(error) => CommandResponse::Error(error),
+    };
+    ManageNeuronResponse { command: Some(response) }
+}
+
+async fn manage(request: ManageNeuron) -> Result<CommandResponse, GovernanceError> {
+    let stake_id = NeuronId { id: request.subaccount }.stake_id()
+        .ok_or_else(|| GovernanceError::new(ERROR_NOT_FOUND, "Neuron not found"))?;
+    let owns_neuron = SERVICE.with(|service| {
+        service.borrow().stakes.get(&stake_id).is_some_and(|stake| stake.owner == ic_cdk::api::caller())
+    });
+    if !owns_neuron {
+        return Err(GovernanceError::new(ERROR_NOT_FOUND, "Neuron not found"));
+    }
+
+    match request.command {
+        Some(Command::RegisterVote(RegisterVote { proposal: Some(proposal), vote })) => {
+            let vote = match vote {
+                VOTE_YES => Vote::Yes,
+                VOTE_NO => Vote::No,
+                _ => return Err(GovernanceError::new(ERROR_INVALID_COMMAND, "Unknown vote")),
+            };
+            crate::vote(VoteArgs { proposal_id: proposal.id, vote }).await?;
+            Ok(CommandResponse::RegisterVote(EmptyResponse {}))
+        }
+        Some(Command::Configure(Configure { operation: Some(Operation::StartDissolving(_)) })) => {
+            staking::start_dissolve(stake_id)?;
+            Ok(CommandResponse::Configure(EmptyResponse {}))
+        }
+        Some(Command::Disburse(_)) => {
+            staking::disburse(stake_id)?;
+            Ok(CommandResponse::Disburse(DisburseResponse { transfer_block_height: 0 }))
+        }
+        _ => Err(GovernanceError::new(ERROR_INVALID_COMMAND, "Unsupported command")),
+    }
+}
@@ -0,0 +1,207 @@
+use crate::types::*;
+use crate::SERVICE;
+use ic_cdk::export::Principal;
+use std::collections::HashMap;
+
+const NANOS_PER_SECOND: u64 = 1_000_000_000;
+/// Stakes that dissolve within less than this carry no voting power
+pub const MIN_DISSOLVE_DELAY_SECONDS: u64 = 7 * 24 * 60 * 60;
+/// Locking tokens for this long doubles their voting power
+pub const MAX_DISSOLVE_DELAY_SECONDS: u64 = 4 * 365 * 24 * 60 * 60;
+
+impl Stake {
+    /// Seconds until the stake can be disbursed
+    pub fn remaining_delay_seconds(&self, now: u64) -> u64 {
+        match self.state {
+            StakeState::Locked => self.dissolve_delay_seconds,
+            StakeState::Dissolving { dissolves_at } => dissolves_at.saturating_sub(now) / NANOS_PER_SECOND,
+        }
+    }
+
+    /// The staked amount, with a bonus growing linearly with the remaining delay up to
+    /// 100% at MAX_DISSOLVE_DELAY_SECONDS
+    pub fn voting_power(&self, now: u64) -> Tokens {
+        let remaining = self.remaining_delay_seconds(now);
+        if remaining < MIN_DISSOLVE_DELAY_SECONDS {
+            return Tokens::default();
+        }
+        let bonus = self.amount.amount_e8s as u128 * remaining.min(MAX_DISSOLVE_DELAY_SECONDS) as u128
+            / MAX_DISSOLVE_DELAY_SECONDS as u128;
+        Tokens { amount_e8s: self.amount.amount_e8s + bonus as u64 }
+    }
+}
+
+impl crate::BasicDaoService {
+    /// The voting power of all stakes of a principal. Only governance tokens are staked, so
+    /// utility tokens never count in a tally.
+    pub fn voting_power(&self, owner: &Principal) -> Tokens {
+        let now = ic_cdk::api::time();
+        self.stakes
+            .values()
+            .filter(|stake| stake.owner == *owner)
+            .map(|stake| stake.voting_power(now))
+            .fold(Tokens::default(), |total, power| total + power)
+    }
+
+    /// The voting power of a voter on a proposal. Only staked tokens vote, as of the
+    /// proposal's snapshot, so tokens cannot be moved to another account to vote twice.
+    /// Proposals without a snapshot take the current voting power.
+    pub fn proposal_voting_power(&self, proposal_id: u64, voter: &Principal) -> Tokens {
+        self.proposals.get(&proposal_id)
+            .and_then(|proposal| proposal.snapshot_voting_power(voter))
+            .unwrap_or_else(|| self.voting_power(voter))
+    }
+
+    /// What a vote cast now with some voting power adds to the tally of a proposal, when
+    /// the vote decay weighs it
+    pub fn weighted_power(&self, proposal_id: u64, voting_power: Tokens) -> Option<Tokens> {
+        let decay = self.system_params.vote_decay?;
+        let proposal = self.proposals.get(&proposal_id)?;
+        let opens_at = proposal.voting_opens_at.unwrap_or(proposal.timestamp);
+        Some(decay.weigh(voting_power, opens_at, ic_cdk::api::time()))
+    }
+
+    /// The voting power of every staker that has some
+    pub fn voting_power_snapshot(&self) -> Vec<(Principal, Tokens)> {
+        let now = ic_cdk::api::time();
+        let mut powers: HashMap<Principal, Tokens> = HashMap::new();
+        for stake in self.stakes.values() {
+            *powers.entry(stake.owner).or_default() += stake.voting_power(now);
+        }
+        powers.into_iter().filter(|(_, power)| power.amount_e8s > 0).collect()
+    }
+}
+
+/// Lock tokens of the caller's account in a new stake. The tokens can only be taken
+/// back once the stake has dissolved, `dissolve_delay_seconds` after `start_dissolve`.
+#[ic_cdk::update]
+fn stake(amount: Tokens, dissolve_delay_seconds: u64) -> Result<u64, DaoError> {
+    crate::pause::require_not_paused()?;
+    crate::ledger::require_internal_accounts()?;
+    if amount.amount_e8s == 0 {
+        return Err(DaoError::from("Cannot stake zero tokens".to_string()));
+    }
+    if !(MIN_DISSOLVE_DELAY_SECONDS..=MAX_DISSOLVE_DELAY_SECONDS).contains(&dissolve_delay_seconds) {
+        return Err(DaoError::from(format!(
+            "Dissolve delay must be between {} and {} seconds",
+            MIN_DISSOLVE_DELAY_SECONDS, MAX_DISSOLVE_DELAY_SECONDS
+        )));
+    }
+
+    SERVICE.with(|service| {
+        let mut service = service.borrow_mut();
+        let caller = ic_cdk::api::caller();
+
+        if service.accounts.get(&caller).is_none() {
+            return Err("Caller needs an account to stake".to_string());
+        }
+        if !service.accounts.debit(caller, amount) {
+            return Err(format!("Caller's account has insufficient funds to stake {:?}", amount));
+        }
+
+        let stake_id = service.next_stake_id;
+        service.next_stake_id += 1;
+        service.stakes.insert(stake_id, Stake {
+            id: stake_id,
+            owner: caller,
+            amount,
+            dissolve_delay_seconds,
+            state: StakeState::Locked,
+            created_at: ic_cdk::api::time(),
+        });
+        Ok(stake_id)
+    }).map_err(DaoError::from)
+}
+
+/// Start dissolving one of the caller's stakes. Its voting power decreases with the
+/// remaining delay from now on.
+#[ic_cdk::update]
+pub(crate) fn start_dissolve(stake_id: u64) -> Result<Stake, DaoError> {
+    crate::pause::require_not_paused()?;
+
+    SERVICE.with(|service| {
+        let mut service = service.borrow_mut();
+        let stake = service.stakes.get_mut(&stake_id)
+            .filter(|stake| stake.owner == ic_cdk::api::caller())
+            .ok_or_else(|| "Stake not found".to_string())?;
+
+        if stake.state != StakeState::Locked {
+            return Err("Stake is already dissolving".to_string());
+        }
+        stake.state = StakeState::Dissolving {
+            dissolves_at: ic_cdk::api::time() + stake.dissolve_delay_seconds * NANOS_PER_SECOND,
+        };
+        Ok(stake.clone())
+    }).map_err(DaoError::from)
+}
+
+/// Move the tokens of a dissolved stake back to the caller's account
+#[ic_cdk::update]
+pub(crate) fn disburse(stake_id: u64) -> Result<Tokens, DaoError> {
+    crate::pause::require_not_paused()?;
+
+    SERVICE.with(|service| {
+        let mut service = service.borrow_mut();
+        let stake = service.stakes.get(&stake_id)
+            .filter(|stake| stake.owner == ic_cdk::api::caller())
+            .ok_or_else(|| "Stake not found".to_string())?;
+
+        match stake.state {
+            StakeState::Dissolving { dissolves_at } if dissolves_at <= ic_cdk::api::time() => {}
+            _ => return Err("Stake has not dissolved yet".to_string()),
+        }
+
+        let stake = service.stakes.remove(&stake_id).unwrap();
+        service.accounts.credit(stake.owner, stake.amount);
+        Ok(stake.amount)
+    }).map_err(DaoError::from)
+}
+
+#[ic_cdk::query]
+fn list_my_stakes() -> Vec<Stake> {
+    SERVICE.with(|service| {
+        let caller = ic_cdk::api::caller();
+        service.borrow().stakes.values().filter(|stake| stake.owner == caller).cloned().collect()
+    })
+}
+
+/// Returns the voting power of the caller's stakes
+#[ic_cdk::query]
+fn get_voting_power() -> Tokens {
+    SERVICE.with(|service| service.borrow().voting_power(&ic_cdk::api::caller()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stake(dissolve_delay_seconds: u64, state: StakeState) -> Stake {
+        Stake {
+            id: 1,
+            owner: Principal::anonymous(),
+            amount: Tokens { amount_e8s: 1_000 },
+            dissolve_delay_seconds,
+            state,
+            created_at: 0,
+        }
+    }
+
+    #[test]
+    fn longer_delays_carry_more_voting_power() {
+        let power = |delay: u64| stake(delay, StakeState::Locked).voting_power(0).amount_e8s;
+        assert_eq!(power(MIN_DISSOLVE_DELAY_SECONDS - 1), 0);
+        assert_eq!(power(MAX_DISSOLVE_DELAY_SECONDS / 2), 1_500);
+        assert_eq!(power(MAX_DISSOLVE_DELAY_SECONDS), 2_000);
+        assert_eq!(power(2 * MAX_DISSOLVE_DELAY_SECONDS), 2_000);
+    }
+
+    #[test]
+    fn dissolving_stakes_lose_voting_power_as_they_dissolve() {
+        let dissolves_at = MAX_DISSOLVE_DELAY_SECONDS * NANOS_PER_SECOND;
+        let stake = stake(MAX_DISSOLVE_DELAY_SECONDS, StakeState::Dissolving { dissolves_at });
+        assert_eq!(stake.voting_power(0).amount_e8s, 2_000);
+        assert_eq!(stake.voting_power(dissolves_at / 2).amount_e8s, 1_500);
+        assert_eq!(stake.voting_power(dissolves_at - NANOS_PER_SECOND).amount_e8s, 0);
+        assert_eq!(stake.remaining_delay_seconds(dissolves_at + 1), 0);
+    }
+}
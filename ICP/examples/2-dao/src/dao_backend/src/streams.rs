@@ -0,0 +1,133 @@
+use ic_cdk::export::Principal;
+use crate::types::*;
+use crate::{ledger, pause, SERVICE};
+
+/// Streams run for at most a year, so that their funds are not locked away for good
+const MAX_STREAM_DURATION_SECONDS: u64 = 365 * 24 * 60 * 60;
+const NANOS_PER_SECOND: u64 = 1_000_000_000;
+
+impl Stream {
+    /// The whole amount of the stream, set aside from the DAO's account
+    pub fn total(&self) -> Tokens {
+        self.rate_per_second * self.duration_seconds
+    }
+
+    /// What the stream has paid out or can pay out by `now`, whether withdrawn or not. A
+    /// cancelled stream stops accruing when it is cancelled.
+    pub fn accrued(&self, now: u64) -> Tokens {
+        let until = self.cancelled_at.map_or(now, |cancelled_at| cancelled_at.min(now));
+        let seconds = (until.saturating_sub(self.start_at) / NANOS_PER_SECOND).min(self.duration_seconds);
+        self.rate_per_second * seconds
+    }
+
+    /// What the recipient can withdraw at `now`
+    pub fn withdrawable(&self, now: u64) -> Tokens {
+        Tokens { amount_e8s: self.accrued(now).amount_e8s - self.withdrawn.amount_e8s }
+    }
+
+    /// What is set aside for the stream and not withdrawn yet
+    pub fn held(&self) -> Tokens {
+        let set_aside = match self.cancelled_at {
+            // The rest went back to the DAO on cancellation
+            Some(cancelled_at) => self.accrued(cancelled_at),
+            None => self.total(),
+        };
+        Tokens { amount_e8s: set_aside.amount_e8s - self.withdrawn.amount_e8s }
+    }
+}
+
+/// Checked on submission of CreateStream proposals
+pub fn validate_stream(rate_per_second: Tokens, duration_seconds: u64) -> Result<(), String> {
+    ledger::require_internal_accounts()?;
+    if rate_per_second.amount_e8s == 0 {
+        return Err("A stream must pay some tokens per second".to_string());
+    }
+    if duration_seconds == 0 || duration_seconds > MAX_STREAM_DURATION_SECONDS {
+        return Err(format!("A stream lasts between 1 and {} seconds", MAX_STREAM_DURATION_SECONDS));
+    }
+    if rate_per_second.amount_e8s.checked_mul(duration_seconds).is_none() {
+        return Err("The stream's total amount does not fit in Tokens".to_string());
+    }
+    Ok(())
+}
+
+/// Execute an accepted CreateStream proposal. The whole amount of the stream is taken
+/// from the DAO's account up front, so the stream can always pay what it accrues.
+pub fn create(proposal_id: u64, to: Principal, rate_per_second: Tokens, duration_seconds: u64) -> Result<(), String> {
+    validate_stream(rate_per_second, duration_seconds)?;
+
+    SERVICE.with(|service| {
+        let mut service = service.borrow_mut();
+        let id = service.streams.keys().max().map_or(1, |id| id + 1);
+        let stream = Stream {
+            id,
+            to,
+            rate_per_second,
+            duration_seconds,
+            start_at: ic_cdk::api::time(),
+            withdrawn: Tokens::default(),
+            cancelled_at: None,
+            created_by_proposal: proposal_id,
+        };
+        if !service.accounts.debit(ic_cdk::api::id(), stream.total()) {
+            return Err(format!("The DAO's account has insufficient funds to stream {:?}", stream.total()));
+        }
+        service.streams.insert(id, stream);
+        Ok(())
+    })
+}
+
+/// Execute an accepted CancelStream proposal. The recipient keeps what accrued until now,
+/// to withdraw as before, and the rest goes back to the DAO's account.
+pub fn cancel(stream_id: u64) -> Result<(), String> {
+    SERVICE.with(|service| {
+        let mut service = service.borrow_mut();
+        let now = ic_cdk::api::time();
+        let stream = service.streams.get_mut(&stream_id)
+            .ok_or_else(|| "Stream not found".to_string())?;
+        if stream.cancelled_at.is_some() {
+            return Err(format!("Stream {} is already cancelled", stream_id));
+        }
+
+        let unaccrued = Tokens { amount_e8s: stream.total().amount_e8s - stream.accrued(now).amount_e8s };
+        stream.cancelled_at = Some(now);
+        service.accounts.credit(ic_cdk::api::id(), unaccrued);
+        Ok(())
+    })
+}
+
+/// Withdraw what a stream paying the caller has accrued so far
+#[ic_cdk::update]
+fn withdraw_stream(stream_id: u64) -> Result<Tokens, DaoError> {
+    pause::require_not_paused()?;
+    ledger::require_internal_accounts()?;
+
+    SERVICE.with(|service| {
+        let mut service = service.borrow_mut();
+        let caller = ic_cdk::api::caller();
+        let stream = service.streams.get_mut(&stream_id)
+            .ok_or_else(|| DaoError::from("Stream not found".to_string()))?;
+        if stream.to != caller {
+            return Err(DaoError::Unauthorized);
+        }
+
+        let amount = stream.withdrawable(ic_cdk::api::time());
+        stream.withdrawn += amount;
+        service.accounts.credit(caller, amount);
+        service.record(AuditEventKind::StreamWithdrawal { stream_id, to: caller, amount });
+        Ok(amount)
+    })
+}
+
+#[ic_cdk::query]
+fn list_streams() -> Vec<Stream> {
+    SERVICE.with(|service| service.borrow().streams.values().cloned().collect())
+}
+
+/// Returns what the recipient of a stream can withdraw now
+#[ic_cdk::query]
+fn get_stream_withdrawable(stream_id: u64) -> Option<Tokens> {
+    SERVICE.with(|service| {
+        service.borrow().streams.get(&stream_id).map(|stream| stream.withdrawable(ic_cdk::api::time()))
+    })
+}
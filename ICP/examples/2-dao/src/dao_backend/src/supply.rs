@@ -0,0 +1,84 @@
+use ic_cdk::export::candid::Nat;
+use ic_cdk::export::Principal;
+use crate::types::*;
+use crate::accounts::Accounts;
+use crate::{ledger, BasicDaoService, SERVICE};
+
+/// All internal tokens: account balances, stakes, held proposal deposits, committee budgets
+/// and the tokens set aside for streams
+pub fn circulating(service: &BasicDaoService) -> Tokens {
+    let accounts = service.accounts.entries().into_iter().map(|(_, tokens)| tokens);
+    let stakes = service.stakes.values().map(|stake| stake.amount);
+    let deposits = service.proposals.values()
+        .filter_map(|proposal| proposal.deposit.as_ref())
+        .filter(|deposit| deposit.state == DepositState::Held)
+        .map(|deposit| deposit.amount);
+    let budgets = service.committees.values().map(|committee| committee.budget);
+    let streams = service.streams.values().map(|stream| stream.held());
+    accounts.chain(stakes).chain(deposits).chain(budgets).chain(streams).fold(Tokens::default(), |total, tokens| total + tokens)
+}
+
+/// All utility tokens, which are only held in accounts
+pub fn circulating_utility() -> Tokens {
+    Accounts::of(TokenClass::Utility).entries().into_iter()
+        .fold(Tokens::default(), |total, (_, tokens)| total + tokens)
+}
+
+/// Execute an accepted Mint proposal. With a token ledger, the DAO canister must be the
+/// minting account of the ledger, so its transfers mint governance tokens. Utility tokens
+/// are always internal.
+pub async fn mint(proposal_id: u64, to: Principal, amount: Tokens, class: TokenClass) -> Result<(), String> {
+    let total_supply = match ledger::token_ledger().filter(|_| class == TokenClass::Governance) {
+        Some(token_ledger) => {
+            let account = IcrcAccount { owner: to, subaccount: None };
+            ledger::icrc1_transfer(token_ledger, account, Nat::from(amount.amount_e8s), proposal_id).await
+                .map_err(|error| format!("Mint failed: {}", error))?;
+            ledger::total_supply(token_ledger).await.ok()
+        }
+        None => SERVICE.with(|service| {
+            let mut service = service.borrow_mut();
+            Accounts::of(class).credit(to, amount);
+            *service.supply_mut(class) += amount;
+            Some(*service.supply_mut(class))
+        }),
+    };
+
+    SERVICE.with(|service| {
+        service.borrow_mut().record(AuditEventKind::Minted { proposal_id, to, amount, total_supply, class: Some(class) })
+    });
+    Ok(())
+}
+
+/// Execute an accepted Burn proposal. Tokens on a ledger can only be burned by their
+/// holder, so this takes internal accounts.
+pub fn burn(proposal_id: u64, from: Principal, amount: Tokens) -> Result<(), String> {
+    ledger::require_internal_accounts()?;
+
+    SERVICE.with(|service| {
+        let mut service = service.borrow_mut();
+        if !service.accounts.debit(from, amount) {
+            return Err(format!("The account has insufficient funds to burn {:?}", amount));
+        }
+        service.total_supply -= amount;
+        let total_supply = Some(service.total_supply);
+        service.record(AuditEventKind::Burned { proposal_id, from, amount, total_supply });
+        Ok(())
+    })
+}
+
+/// Returns the total supply of the internal governance tokens
+#[ic_cdk::query]
+fn get_total_supply() -> Tokens {
+    SERVICE.with(|service| service.borrow().total_supply)
+}
+
+#[ic_cdk::query]
+fn get_utility_supply() -> Tokens {
+    SERVICE.with(|service| service.borrow().utility_supply)
+}
+
+/// Returns the transfer fees of a class of tokens taken so far
+#[ic_cdk::query]
+fn get_fees_collected(class: TokenClass) -> Tokens {
+    SERVICE.with(|service| service.borrow().fees_collected(class))
+}
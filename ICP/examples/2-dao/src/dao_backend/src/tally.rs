@@ -0,0 +1,48 @@
+use crate::types::*;
+use crate::{expiry, SERVICE};
+
+const NANOS_PER_SECOND: u64 = 1_000_000_000;
+
+fn percent_of(part: Tokens, whole: Tokens) -> f64 {
+    part.amount_e8s as f64 * 100.0 / whole.amount_e8s as f64
+}
+
+/// Returns the tally of a proposal as the DAO counts it, so that frontends need not count
+/// it again: the votes cast, their share of the voting power of the snapshot, how far Yes
+/// is from the vote threshold and how long voting stays open
+#[ic_cdk::query]
+fn get_tally(proposal_id: u64) -> Option<Tally> {
+    SERVICE.with(|service| {
+        let service = service.borrow();
+        let proposal = service.proposals.get(&proposal_id)?;
+        let vote_threshold = service.system_params.effective_params(proposal.topic()).vote_threshold;
+        let now = ic_cdk::api::time();
+
+        // Proposals submitted with a token ledger before ledger balances were snapshot have
+        // none, and the ledger's holders cannot be added up
+        let snapshot_power = proposal.voting_power_snapshot.as_ref()
+            .map(|snapshot| snapshot.iter().fold(Tokens::default(), |total, (_, power)| total + *power))
+            .filter(|total| total.amount_e8s > 0);
+        let cast = proposal.votes_yes + proposal.votes_no;
+        let expires_at = expiry::expires_at(&service.system_params, proposal);
+
+        Some(Tally {
+            proposal_id,
+            state: proposal.state.clone(),
+            votes_yes: proposal.votes_yes,
+            votes_no: proposal.votes_no,
+            not_voted: snapshot_power.map(|total| Tokens { amount_e8s: total.amount_e8s.saturating_sub(cast.amount_e8s) }),
+            snapshot_power,
+            yes_percent: snapshot_power.map(|total| percent_of(proposal.votes_yes, total)),
+            no_percent: snapshot_power.map(|total| percent_of(proposal.votes_no, total)),
+            turnout_percent: snapshot_power.map(|total| percent_of(cast, total)),
+            vote_threshold,
+            threshold_percent: percent_of(proposal.votes_yes, vote_threshold),
+            yes_needed: Tokens { amount_e8s: vote_threshold.amount_e8s.saturating_sub(proposal.votes_yes.amount_e8s) },
+            seconds_remaining: expires_at
+                .filter(|_| proposal.state == ProposalState::Open)
+                .map(|expires_at| expires_at.saturating_sub(now) / NANOS_PER_SECOND),
+            would_pass: proposal.votes_yes >= vote_threshold,
+        })
+    })
+}
@@ -0,0 +1,131 @@
+use ic_cdk::export::candid::Nat;
+use ic_cdk::export::Principal;
+use crate::types::*;
+use crate::{members, pause, SERVICE};
+
+const MAX_TEMPLATES: usize = 100;
+const MAX_NAME_LENGTH: usize = 100;
+
+/// The recipient and amount of the actions whose recipient and amount a template may
+/// leave to each submission
+fn recipient_and_amount(action: &ProposalAction) -> (Option<Principal>, Option<Nat>) {
+    match action {
+        ProposalAction::TransferTokens { to, amount, .. } | ProposalAction::Mint { to, amount, .. } =>
+            (Some(*to), Some(Nat::from(amount.amount_e8s))),
+        ProposalAction::TransferFromTreasury { to, amount } => (Some(to.owner), Some(amount.clone())),
+        ProposalAction::FundCommittee { amount, .. } => (None, Some(Nat::from(amount.amount_e8s))),
+        _ => (None, None),
+    }
+}
+
+/// The template's action with the overrides of a submission
+fn apply(action: &ProposalAction, overrides: &TemplateOverrides) -> Result<ProposalAction, String> {
+    let (to, amount) = recipient_and_amount(action);
+    if overrides.to.is_some() && to.is_none() {
+        return Err("The template's action has no recipient to override".to_string());
+    }
+    if overrides.amount.is_some() && amount.is_none() {
+        return Err("The template's action has no amount to override".to_string());
+    }
+
+    let mut action = action.clone();
+    match &mut action {
+        ProposalAction::TransferTokens { to, amount, .. } | ProposalAction::Mint { to, amount, .. } => {
+            *to = overrides.to.unwrap_or(*to);
+            *amount = overrides.amount.unwrap_or(*amount);
+        }
+        ProposalAction::TransferFromTreasury { to, amount } => {
+            if let Some(owner) = overrides.to {
+                *to = IcrcAccount { owner, subaccount: None };
+            }
+            if let Some(overridden) = overrides.amount {
+                *amount = Nat::from(overridden.amount_e8s);
+            }
+        }
+        ProposalAction::FundCommittee { amount, .. } => *amount = overrides.amount.unwrap_or(*amount),
+        _ => {}
+    }
+    Ok(action)
+}
+
+/// Whether an action stays within the template's constraints
+fn check_constraints(params: &TemplateParams, action: &ProposalAction) -> Result<(), String> {
+    let (to, amount) = recipient_and_amount(action);
+    if let (Some(max_amount), Some(amount)) = (params.max_amount, amount) {
+        if amount > max_amount.amount_e8s {
+            return Err(format!("The template allows amounts of at most {:?}", max_amount));
+        }
+    }
+    if let Some(to) = to {
+        if !params.recipients.is_empty() && !params.recipients.contains(&to) {
+            return Err(format!("The template does not allow {} as a recipient", to));
+        }
+    }
+    Ok(())
+}
+
+/// Add a template for a recurring proposal, such as a monthly budget transfer or a
+/// parameter bump. Submissions from the template change at most the recipient, the
+/// amount and the text of its action, within its params. Only callable by Admins.
+#[ic_cdk::update]
+fn create_template(name: String, action: ProposalAction, params: TemplateParams) -> Result<u64, DaoError> {
+    pause::require_not_paused()?;
+    let caller = ic_cdk::api::caller();
+    members::require_role(&caller, MemberRole::Admin)?;
+    if name.trim().is_empty() || name.chars().count() > MAX_NAME_LENGTH {
+        return Err(DaoError::from(format!("A template's name must have 1 to {} characters", MAX_NAME_LENGTH)));
+    }
+    action.validate()?;
+    action.resolve_topic(params.topic)?;
+    if let Some(text) = &params.text {
+        text.validate()?;
+    }
+    check_constraints(&params, &action)?;
+
+    SERVICE.with(|service| {
+        let mut service = service.borrow_mut();
+        if service.templates.len() >= MAX_TEMPLATES {
+            return Err(format!("The DAO keeps at most {} templates", MAX_TEMPLATES));
+        }
+        let id = service.next_template_id;
+        service.next_template_id += 1;
+        service.templates.insert(id, ProposalTemplate {
+            id,
+            name,
+            action,
+            params,
+            created_by: caller,
+            created_at: ic_cdk::api::time(),
+        });
+        Ok(id)
+    }).map_err(DaoError::from)
+}
+
+/// Only callable by Admins
+#[ic_cdk::update]
+fn delete_template(template_id: u64) -> Result<(), DaoError> {
+    pause::require_not_paused()?;
+    members::require_role(&ic_cdk::api::caller(), MemberRole::Admin)?;
+
+    SERVICE.with(|service| match service.borrow_mut().templates.remove(&template_id) {
+        Some(_) => Ok(()),
+        None => Err(DaoError::from("Template not found".to_string())),
+    })
+}
+
+/// Submit a proposal from a template, with the given overrides, as submit_proposal would
+#[ic_cdk::update]
+async fn submit_from_template(template_id: u64, overrides: TemplateOverrides) -> Result<u64, DaoError> {
+    let template = SERVICE.with(|service| service.borrow().templates.get(&template_id).cloned())
+        .ok_or_else(|| DaoError::from("Template not found".to_string()))?;
+    let action = apply(&template.action, &overrides)?;
+    check_constraints(&template.params, &action)?;
+
+    let text = overrides.text.or(template.params.text);
+    crate::submit_proposal(action, template.params.topic, text).await
+}
+
+#[ic_cdk::query]
+fn list_templates() -> Vec<ProposalTemplate> {
+    SERVICE.with(|service| service.borrow().templates.values().cloned().collect())
+}
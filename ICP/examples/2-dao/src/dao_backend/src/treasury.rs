@@ -0,0 +1,88 @@
+use ic_cdk::export::candid::Nat;
+use ic_cdk::export::Principal;
+use crate::types::*;
+use crate::{ledger, SERVICE};
+
+/// How long the cached treasury balance is served before the heartbeat refreshes it
+const BALANCE_REFRESH_INTERVAL_NANOS: u64 = 10 * 60 * 1_000_000_000;
+/// Oldest treasury transactions are dropped beyond this many
+const MAX_TREASURY_TRANSACTIONS: usize = 1_000;
+
+fn ledger() -> Result<Principal, String> {
+    SERVICE.with(|service| service.borrow().system_params.treasury_ledger)
+        .ok_or_else(|| "No treasury ledger is set".to_string())
+}
+
+fn dao_account() -> IcrcAccount {
+    IcrcAccount { owner: ic_cdk::api::id(), subaccount: None }
+}
+
+/// Transfer ledger funds of the DAO canister with `icrc1_transfer`, recording the
+/// transfer in the treasury history whether it succeeds or not
+pub async fn transfer_from_treasury(proposal_id: u64, to: IcrcAccount, amount: Nat) -> Result<(), String> {
+    let ledger = ledger()?;
+    // The proposal id is the memo, so the transfers can be told apart on the ledger
+    let result = ledger::icrc1_transfer(ledger, to.clone(), amount.clone(), proposal_id).await
+        .map_err(|error| format!("Treasury transfer failed: {}", error));
+
+    SERVICE.with(|service| {
+        let mut service = service.borrow_mut();
+        let transactions = &mut service.treasury_transactions;
+        transactions.push(TreasuryTransaction {
+            proposal_id,
+            to,
+            amount,
+            timestamp: ic_cdk::api::time(),
+            block_index: result.as_ref().ok().cloned(),
+            error: result.as_ref().err().cloned(),
+        });
+        if transactions.len() > MAX_TREASURY_TRANSACTIONS {
+            transactions.remove(0);
+        }
+        // The balance changed, so it is read again by the next heartbeat
+        service.treasury_balance = None;
+    });
+    result.map(|_| ())
+}
+
+/// Read the ledger balance of the DAO canister again when the cached one is stale
+pub async fn refresh_balance() {
+    let Ok(ledger) = ledger() else {
+        return;
+    };
+    let now = ic_cdk::api::time();
+    let is_stale = SERVICE.with(|service| {
+        let mut service = service.borrow_mut();
+        let stale = !service.refreshing_treasury_balance
+            && service.treasury_balance.as_ref()
+                .is_none_or(|balance| now >= balance.updated_at + BALANCE_REFRESH_INTERVAL_NANOS);
+        if stale {
+            service.refreshing_treasury_balance = true;
+        }
+        stale
+    });
+    if !is_stale {
+        return;
+    }
+
+    let result: Result<(Nat,), _> = ic_cdk::call(ledger, "icrc1_balance_of", (dao_account(),)).await;
+    SERVICE.with(|service| {
+        let mut service = service.borrow_mut();
+        service.refreshing_treasury_balance = false;
+        if let Ok((amount,)) = result {
+            service.treasury_balance = Some(TreasuryBalance { amount, updated_at: ic_cdk::api::time() });
+        }
+    });
+}
+
+/// Returns the ledger balance of the DAO canister, as last read by the heartbeat
+#[ic_cdk::query]
+fn get_treasury_balance() -> Option<TreasuryBalance> {
+    SERVICE.with(|service| service.borrow().treasury_balance.clone())
+}
+
+/// Returns the treasury transfers made by proposals, newest first
+#[ic_cdk::query]
+fn get_treasury_transactions() -> Vec<TreasuryTransaction> {
+    SERVICE.with(|service| service.borrow().treasury_transactions.iter().rev().cloned().collect())
+}
@@ -1,7 +1,8 @@
 use ic_cdk::export::{
-    candid::{CandidType, Deserialize},
+    candid::{CandidType, Deserialize, Nat},
     Principal,
 };
+use ic_cdk::api::management_canister::main::CanisterSettings;
 use std::ops::{Add, AddAssign, SubAssign, Mul};
 
 
@@ -10,6 +11,152 @@ pub struct BasicDaoStableStorage {
     pub accounts: Vec<Account>,
     pub proposals: Vec<Proposal>,
     pub system_params: SystemParams,
+    pub stakes: Option<Vec<Stake>>,
+    pub treasury_transactions: Option<Vec<TreasuryTransaction>>,
+    // Computed from the balances when missing
+    pub total_supply: Option<Tokens>,
+    pub audit_log: Option<Vec<AuditEvent>>,
+    pub paused: Option<bool>,
+    pub comments: Option<Vec<(u64, ProposalThread)>>,
+    pub allowances: Option<Vec<Allowance>>,
+    // Set up from the guardian and council system params when missing
+    pub members: Option<Vec<Member>>,
+    pub airdrops: Option<Vec<Airdrop>>,
+    pub pending_rewards: Option<Vec<(Principal, Tokens)>>,
+    pub follows: Option<Vec<Follow>>,
+    pub revisions: Option<Vec<(u64, Vec<ProposalRevision>)>>,
+    pub staged_wasms: Option<Vec<StagedWasm>>,
+    pub canister_upgrades: Option<Vec<CanisterUpgrade>>,
+    pub governed_canisters: Option<Vec<GovernedCanister>>,
+    pub committees: Option<Vec<Committee>>,
+    pub committee_proposals: Option<Vec<CommitteeProposal>>,
+    pub subscriptions: Option<Vec<Subscription>>,
+    pub streams: Option<Vec<Stream>>,
+    pub malicious_flags: Option<Vec<MaliciousFlag>>,
+    pub templates: Option<Vec<ProposalTemplate>>,
+    pub fees_collected: Option<Tokens>,
+    pub utility_fees_collected: Option<Tokens>,
+    // The ids of the next proposal, stake, committee and template
+    pub next_proposal_id: Option<u64>,
+    pub next_stake_id: Option<u64>,
+    pub next_committee_id: Option<u64>,
+    pub next_template_id: Option<u64>,
+    // Balances of utility tokens to start with; the accounts survive upgrades on their own
+    pub utility_accounts: Option<Vec<Account>>,
+    // Computed from the balances when missing
+    pub utility_supply: Option<Tokens>,
+    // The principals whose token ledger balances are read into the snapshots of proposals
+    pub ledger_voters: Option<Vec<Principal>>,
+    // Where the next dust sweep of each class of tokens goes on
+    pub dust_sweep_cursors: Option<Vec<(TokenClass, Principal)>>,
+    // The notifications not sent yet
+    pub outbox: Option<Vec<crate::notifications::PendingNotification>>,
+}
+
+/// The state written to stable memory in `pre_upgrade`, tagged with the version of its
+/// layout. Fields added to BasicDaoStableStorage must be optional, so earlier saves still
+/// decode; a layout that cannot be decoded from the previous one gets a new variant,
+/// converted in `into_latest`.
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub enum VersionedStableStorage {
+    V1(Box<BasicDaoStableStorageV1>),
+    V2(Box<BasicDaoStableStorage>),
+}
+
+impl VersionedStableStorage {
+    pub fn into_latest(self) -> BasicDaoStableStorage {
+        match self {
+            VersionedStableStorage::V1(storage) => BasicDaoStableStorage {
+                accounts: storage.accounts,
+                proposals: storage.proposals.into_iter().map(Proposal::from).collect(),
+                system_params: storage.system_params,
+                stakes: storage.stakes,
+                treasury_transactions: storage.treasury_transactions,
+                total_supply: None,
+                audit_log: None,
+                paused: None,
+                comments: None,
+                allowances: None,
+                members: None,
+                airdrops: None,
+                pending_rewards: None,
+                follows: None,
+                revisions: None,
+                staged_wasms: None,
+                canister_upgrades: None,
+                governed_canisters: None,
+                committees: None,
+                committee_proposals: None,
+                subscriptions: None,
+                streams: None,
+                malicious_flags: None,
+                templates: None,
+                fees_collected: None,
+                utility_fees_collected: None,
+                next_proposal_id: None,
+                next_stake_id: None,
+                next_committee_id: None,
+                next_template_id: None,
+                utility_accounts: None,
+                utility_supply: None,
+                ledger_voters: None,
+                dust_sweep_cursors: None,
+                outbox: None,
+            },
+            VersionedStableStorage::V2(storage) => *storage,
+        }
+    }
+}
+
+/// The layout of V1, whose proposals could only make raw canister calls
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct BasicDaoStableStorageV1 {
+    pub accounts: Vec<Account>,
+    pub proposals: Vec<ProposalV1>,
+    pub system_params: SystemParams,
+    pub stakes: Option<Vec<Stake>>,
+    pub treasury_transactions: Option<Vec<TreasuryTransaction>>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct ProposalV1 {
+    pub id: u64,
+    pub timestamp: u64,
+    pub proposer: Principal,
+    pub payload: ProposalPayload,
+    pub state: ProposalState,
+    pub votes_yes: Tokens,
+    pub votes_no: Tokens,
+    pub voters: Vec<Principal>,
+    pub deposit: Option<ProposalDeposit>,
+}
+
+impl From<ProposalV1> for Proposal {
+    fn from(proposal: ProposalV1) -> Proposal {
+        Proposal {
+            id: proposal.id,
+            timestamp: proposal.timestamp,
+            proposer: proposal.proposer,
+            action: ProposalAction::CallExternal(proposal.payload),
+            state: proposal.state,
+            votes_yes: proposal.votes_yes,
+            votes_no: proposal.votes_no,
+            voters: proposal.voters,
+            deposit: proposal.deposit,
+            voting_power_snapshot: None,
+            execution_failures: None,
+            execution_attempts: None,
+            accepted_at: None,
+            veto: None,
+            ballots: None,
+            topic: None,
+            text: None,
+            version: None,
+            voting_opens_at: None,
+            execution: None,
+            execution_lease: None,
+        }
+    }
 }
 
 #[derive(Clone, Copy, Debug, Default, CandidType, Deserialize, PartialEq, PartialOrd)]
@@ -17,30 +164,39 @@ pub struct Tokens {
     pub amount_e8s: u64,
 }
 
+impl Tokens {
+    /// The sum of two amounts, None when it does not fit
+    pub fn checked_add(self, other: Self) -> Option<Self> {
+        self.amount_e8s.checked_add(other.amount_e8s).map(|amount_e8s| Tokens { amount_e8s })
+    }
+}
+
+// Release builds do not check for overflows, so the operators do it themselves: an
+// overflowing amount traps, rolling the call back, rather than wrapping around
 impl Add for Tokens {
     type Output = Self;
 
     fn add(self, other: Self) -> Self {
-        Tokens { amount_e8s: self.amount_e8s + other.amount_e8s }
+        self.checked_add(other).expect("token amount overflow")
     }
 }
 
 impl AddAssign for Tokens {
     fn add_assign(&mut self, other: Self) {
-        self.amount_e8s += other.amount_e8s;
+        *self = *self + other;
     }
 }
 
 impl SubAssign for Tokens {
     fn sub_assign(&mut self, other: Self) {
-        self.amount_e8s -= other.amount_e8s;
+        self.amount_e8s = self.amount_e8s.checked_sub(other.amount_e8s).expect("token amount underflow");
     }
 }
 
 impl Mul<u64> for Tokens {
     type Output = Tokens;
     fn mul(self, rhs: u64) -> Self {
-        Tokens { amount_e8s: self.amount_e8s * rhs }
+        Tokens { amount_e8s: self.amount_e8s.checked_mul(rhs).expect("token amount overflow") }
     }
 }
 
@@ -51,25 +207,455 @@ pub enum ProposalState {
     Accepted,
     Rejected,
     Executing,// The proposal is currently being executed
+    RetryScheduled { retry_at: u64 }, // The execution failed and is tried again at retry_at
     Succeeded, // The proposal has been successfully executed
     Failed(String),// A failure occurred while executing the proposal
+    Cancelled, // The proposer withdrew the proposal before any vote
+    Vetoed, // The council vetoed the accepted proposal before its execution
+    Expired, // The proposal was neither accepted nor rejected within the expiry
 }
 
-/// A proposal is a proposition to execute an arbitrary canister call
+/// A proposal is a proposition to execute an action
 ///
 /// Token holders can vote to either accept the proposal and execute the given
-/// canister call, or vote to reject the proposal and not execute the canister
-/// call.
+/// action, or vote to reject the proposal and not execute the action.
 #[derive(Clone, Debug, CandidType, Deserialize)]
 pub struct Proposal {
     pub id: u64,
     pub timestamp: u64,
     pub proposer: Principal,
-    pub payload: ProposalPayload,
+    pub action: ProposalAction,
     pub state: ProposalState,
     pub votes_yes: Tokens,
     pub votes_no: Tokens,
     pub voters: Vec<Principal>,
+    // Proposals submitted before deposits were tracked have none
+    pub deposit: Option<ProposalDeposit>,
+    // The voting power of every staker when the proposal was submitted, or with a token
+    // ledger the balance of every registered ledger voter. Proposals submitted before
+    // snapshots, or before ledger balances were snapshot, have none.
+    pub voting_power_snapshot: Option<Vec<(Principal, Tokens)>>,
+    // Failed attempts to execute the proposal, oldest first
+    pub execution_failures: Option<Vec<ExecutionFailure>>,
+    // Attempts made since the proposal was accepted, or since the last RetryExecution
+    pub execution_attempts: Option<u32>,
+    pub accepted_at: Option<u64>,
+    pub veto: Option<Veto>,
+    // The ballots of the voters. Proposals voted on before ballots were kept only have
+    // the voters.
+    pub ballots: Option<Vec<Ballot>>,
+    // Proposals submitted before topics were stored have the topic of their action
+    pub topic: Option<Topic>,
+    pub text: Option<ProposalText>,
+    // Starts at 1, and increases with every amendment
+    pub version: Option<u32>,
+    // Proposals can be amended but not voted on until then
+    pub voting_opens_at: Option<u64>,
+    // The outcome of the latest attempt to execute the proposal
+    pub execution: Option<ProposalExecutionRecord>,
+    // Held while the proposal is Executing
+    pub execution_lease: Option<ExecutionLease>,
+}
+
+/// What a proposal is about, in the proposer's words
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct ProposalText {
+    pub title: String,
+    pub summary: String,
+}
+
+const MAX_TITLE_LENGTH: usize = 200;
+const MAX_SUMMARY_LENGTH: usize = 10_000;
+
+impl ProposalText {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.title.trim().is_empty() || self.title.chars().count() > MAX_TITLE_LENGTH {
+            return Err(format!("A title must have 1 to {} characters", MAX_TITLE_LENGTH));
+        }
+        if self.summary.chars().count() > MAX_SUMMARY_LENGTH {
+            return Err(format!("A summary must have at most {} characters", MAX_SUMMARY_LENGTH));
+        }
+        Ok(())
+    }
+}
+
+/// A version of a proposal's text and action
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct ProposalRevision {
+    pub version: u32,
+    pub text: Option<ProposalText>,
+    pub action: ProposalAction,
+    pub timestamp: u64,
+}
+
+/// The changes of an amendment, fields left out are kept
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct ProposalAmendment {
+    pub text: Option<ProposalText>,
+    pub action: Option<ProposalAction>,
+}
+
+/// Why and by whom of the council an accepted proposal was vetoed
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct Veto {
+    pub vetoed_by: Principal,
+    pub justification: String,
+    pub timestamp: u64,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct ExecutionFailure {
+    pub attempt: u32,
+    pub timestamp: u64,
+    pub error: String,
+}
+
+/// The claim of an execution on an Executing proposal. Only the execution holding the
+/// lease applies its outcome. A proposal whose execution has not ended by `expires_at` is
+/// put back among the Accepted proposals by the next heartbeat.
+#[derive(Clone, Copy, Debug, CandidType, Deserialize, PartialEq, Eq)]
+pub struct ExecutionLease {
+    pub acquired_at: u64,
+    pub expires_at: u64,
+}
+
+/// What an attempt to execute a proposal returned
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct ProposalExecutionRecord {
+    pub executed_at: u64,
+    // The reply to the call of a CallExternal proposal, cut to its first 4 KiB
+    pub reply: Option<Vec<u8>>,
+    pub reply_truncated: bool,
+    // The drop of the DAO's cycle balance over the execution, which also counts whatever
+    // else ran between its awaits
+    pub cycles_used: u128,
+    pub error: Option<String>,
+}
+
+impl Proposal {
+    /// An Open proposal to transfer a token, for tests
+    #[cfg(test)]
+    pub fn for_test(id: u64, proposer: Principal) -> Proposal {
+        Proposal {
+            id,
+            timestamp: 0,
+            proposer,
+            action: ProposalAction::TransferTokens { to: proposer, amount: Tokens { amount_e8s: 1 }, class: None },
+            state: ProposalState::Open,
+            votes_yes: Tokens::default(),
+            votes_no: Tokens::default(),
+            voters: vec![],
+            deposit: None,
+            voting_power_snapshot: None,
+            execution_failures: None,
+            execution_attempts: None,
+            accepted_at: None,
+            veto: None,
+            ballots: None,
+            topic: None,
+            text: None,
+            version: Some(1),
+            voting_opens_at: None,
+            execution: None,
+            execution_lease: None,
+        }
+    }
+
+    /// The voting power of a voter in the snapshot, if the proposal has one
+    pub fn snapshot_voting_power(&self, voter: &Principal) -> Option<Tokens> {
+        let snapshot = self.voting_power_snapshot.as_ref()?;
+        Some(snapshot.iter().find(|(owner, _)| owner == voter).map(|(_, power)| *power).unwrap_or_default())
+    }
+
+    pub fn topic(&self) -> Topic {
+        self.topic.unwrap_or_else(|| self.action.topic())
+    }
+
+    pub fn ballot_of(&self, voter: &Principal) -> Option<&Ballot> {
+        self.ballots.as_ref()?.iter().find(|ballot| ballot.voter == *voter)
+    }
+
+    /// Add a ballot to the tally
+    pub fn cast(&mut self, ballot: Ballot) {
+        match ballot.vote {
+            Vote::Yes => self.votes_yes += ballot.counted_power(),
+            Vote::No => self.votes_no += ballot.counted_power(),
+        }
+        if !self.voters.contains(&ballot.voter) {
+            self.voters.push(ballot.voter);
+        }
+        self.ballots.get_or_insert_with(Vec::new).push(ballot);
+    }
+
+    /// Take a ballot back out of the tally, keeping its voter among the voters
+    pub fn retract(&mut self, ballot: &Ballot) {
+        match ballot.vote {
+            Vote::Yes => self.votes_yes -= ballot.counted_power(),
+            Vote::No => self.votes_no -= ballot.counted_power(),
+        }
+        if let Some(ballots) = self.ballots.as_mut() {
+            ballots.retain(|cast| cast.voter != ballot.voter);
+        }
+    }
+}
+
+/// The tally of a proposal. The shares are percentages of the voting power of the
+/// proposal's snapshot, unknown for proposals submitted with a token ledger before ledger
+/// balances were snapshot.
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct Tally {
+    pub proposal_id: u64,
+    pub state: ProposalState,
+    pub votes_yes: Tokens,
+    pub votes_no: Tokens,
+    // The voting power of the snapshot that was not cast; the DAO has no abstain vote
+    pub not_voted: Option<Tokens>,
+    pub snapshot_power: Option<Tokens>,
+    pub yes_percent: Option<f64>,
+    pub no_percent: Option<f64>,
+    pub turnout_percent: Option<f64>,
+    pub vote_threshold: Tokens,
+    // Yes votes as a percentage of the vote threshold, which accepts the proposal at 100
+    pub threshold_percent: f64,
+    pub yes_needed: Tokens,
+    // Until an Open proposal expires, never when its topic has no voting period
+    pub seconds_remaining: Option<u64>,
+    // Whether the proposal would pass were voting to end now: Yes reached the threshold.
+    // An Open proposal expires, failing, at the end of its voting period.
+    pub would_pass: bool,
+}
+
+/// A vote on a proposal, with the voting power it carried
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct Ballot {
+    pub voter: Principal,
+    pub vote: Vote,
+    pub voting_power: Tokens,
+    // Cast automatically, the same way as a followee of the voter
+    pub followed: bool,
+    // What the vote adds to the tally, when the vote decay weighed it down
+    pub weighted_power: Option<Tokens>,
+}
+
+impl Ballot {
+    pub fn counted_power(&self) -> Tokens {
+        self.weighted_power.unwrap_or(self.voting_power)
+    }
+}
+
+/// The submission deposit taken from the proposer, refunded once the proposal is accepted
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct ProposalDeposit {
+    pub amount: Tokens,
+    pub state: DepositState,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq)]
+pub enum DepositState {
+    Held,
+    Refunded,
+    // Kept by the DAO as set by the rejected or expired deposit policy
+    Forfeited(DepositPolicy),
+}
+
+/// What happens to the deposit of a rejected proposal
+#[derive(Clone, Debug, Default, CandidType, Deserialize, PartialEq)]
+pub enum DepositPolicy {
+    #[default]
+    Burn,
+    ToTreasury(Principal),
+}
+
+/// What happens to the deposit of an expired proposal
+#[derive(Clone, Debug, Default, CandidType, Deserialize, PartialEq)]
+pub enum ExpiredDepositPolicy {
+    #[default]
+    Refund,
+    ToTreasury(Principal),
+}
+
+/// What an accepted proposal does. Every action but CallExternal has typed arguments,
+/// so voters can read what they vote on.
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub enum ProposalAction {
+    /// Transfer tokens from the DAO's own account
+    TransferTokens { to: Principal, amount: Tokens, class: Option<TokenClass> },
+    UpdateSystemParams(Box<UpdateSystemParamsPayload>),
+    /// Upgrade a canister the DAO controls
+    UpgradeCanister { canister_id: Principal, wasm_module: Vec<u8>, arg: Vec<u8> },
+    /// Upgrade a canister the DAO controls to a wasm staged with `upload_wasm_chunk`, by
+    /// its SHA-256 hash
+    UpgradeCanisterToStagedWasm { canister_id: Principal, wasm_hash: Vec<u8>, arg: Vec<u8> },
+    /// Add a canister the DAO controls to the registry of governed canisters
+    RegisterCanister { canister_id: Principal, name: String },
+    UnregisterCanister { canister_id: Principal },
+    /// Change a governed canister through the management canister
+    ManageCanister { canister_id: Principal, operation: CanisterOperation },
+    /// Set up a committee with a budget from the DAO's account
+    CreateCommittee(CreateCommitteePayload),
+    /// Add tokens from the DAO's account to a committee's budget
+    FundCommittee { committee_id: u64, amount: Tokens },
+    /// Return the rest of a committee's budget to the DAO's account
+    DissolveCommittee { committee_id: u64 },
+    /// Transfer ledger funds held by the DAO canister with `icrc1_transfer`
+    TransferFromTreasury { to: IcrcAccount, amount: Nat },
+    /// An arbitrary canister call with Candid encoded args
+    CallExternal(ProposalPayload),
+    /// Create new tokens in an account, or on the token ledger
+    Mint { to: Principal, amount: Tokens, class: Option<TokenClass> },
+    /// Destroy tokens of an account
+    Burn { from: Principal, amount: Tokens },
+    /// Execute a Failed proposal again, with a new round of attempts
+    RetryExecution { proposal_id: u64 },
+    /// Give a role to a principal in the member registry
+    AddMember { principal: Principal, role: MemberRole },
+    /// Take a role from a principal in the member registry
+    RemoveMember { principal: Principal, role: MemberRole },
+    /// Give the same amount of tokens from the DAO's own account to each recipient
+    Airdrop { amount: Tokens, recipients: AirdropRecipients },
+    /// Pay tokens from the DAO's account to a recipient by the second, for some seconds
+    CreateStream { to: Principal, rate_per_second: Tokens, duration_seconds: u64 },
+    /// Stop a stream, returning what it has not accrued yet to the DAO's account
+    CancelStream { stream_id: u64 },
+    /// Take a percentage of the stakes of the proposer of a proposal the council flagged as
+    /// malicious, to burn or send to a treasury account
+    Slash { proposal_id: u64, percent: u8, policy: DepositPolicy },
+    /// Create and publish an official event, organized by the DAO, on the events canister
+    CreateEvent { name: String, start_time: u64, end_time: u64, max_participants: Option<u32> },
+    /// Cancel an event of the events canister that the DAO organizes or administers
+    CancelEvent { event_id: u64 },
+    /// Hand the admin role of the events canister, which the DAO holds, to a principal
+    SetEventsAdmin { admin: Principal },
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub enum AirdropRecipients {
+    List(Vec<Principal>),
+    // The accounts with a balance above this when the proposal executes
+    HoldersAbove(Tokens),
+}
+
+/// An airdrop being distributed. Its tokens are already taken from the DAO's account.
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct Airdrop {
+    pub proposal_id: u64,
+    pub amount: Tokens,
+    pub recipients: Vec<Principal>,
+    // How many recipients were credited so far, in order
+    pub distributed: u64,
+}
+
+impl ProposalAction {
+    /// Checked on submission, so that proposals which cannot execute are not voted on
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            ProposalAction::UpdateSystemParams(payload) => payload.validate(),
+            ProposalAction::Airdrop { recipients: AirdropRecipients::List(recipients), .. }
+                if recipients.len() > crate::airdrop::MAX_RECIPIENTS => {
+                Err(format!("An airdrop has at most {} recipients", crate::airdrop::MAX_RECIPIENTS))
+            }
+            ProposalAction::UpgradeCanisterToStagedWasm { wasm_hash, .. } => crate::wasm::require_staged(wasm_hash),
+            ProposalAction::RegisterCanister { .. }
+            | ProposalAction::UnregisterCanister { .. }
+            | ProposalAction::ManageCanister { .. } => crate::canisters::validate(self),
+            ProposalAction::CreateCommittee(payload) => {
+                crate::committees::validate_committee(&payload.name, &payload.members, payload.vote_threshold)
+            }
+            ProposalAction::CallExternal(payload) => crate::heartbeat::require_allowed_call(payload),
+            ProposalAction::CreateStream { rate_per_second, duration_seconds, .. } => {
+                crate::streams::validate_stream(*rate_per_second, *duration_seconds)
+            }
+            ProposalAction::Slash { proposal_id, percent, .. } => crate::slashing::validate_slash(*proposal_id, *percent),
+            ProposalAction::CreateEvent { .. }
+            | ProposalAction::CancelEvent { .. }
+            | ProposalAction::SetEventsAdmin { .. } => crate::events_canister::validate(self),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The discussion of a proposal, kept apart from the proposal itself
+#[derive(Clone, Debug, Default, CandidType, Deserialize)]
+pub struct ProposalThread {
+    pub comments: Vec<ProposalComment>,
+    // The comment pinned by the proposer
+    pub pinned: Option<u64>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct ProposalComment {
+    pub id: u64,
+    pub author: Principal,
+    pub text: String,
+    pub timestamp: u64,
+}
+
+#[derive(Clone, Debug, Default, CandidType, Deserialize)]
+pub struct CommentsPage {
+    pub pinned: Option<ProposalComment>,
+    pub comments: Vec<ProposalComment>,
+    pub total: u64,
+}
+
+/// What a proposal is about, which members can follow others on
+#[derive(Clone, Copy, Debug, CandidType, Deserialize, PartialEq, Eq, Hash)]
+pub enum Topic {
+    Treasury,
+    Governance,
+    Technical,
+    // The events of the events canister, and what proposers choose for CallExternal proposals
+    Community,
+}
+
+impl ProposalAction {
+    /// The topic of a proposal of this action. Only CallExternal proposals, whose effect
+    /// cannot be told, may be given another topic than the action's.
+    pub fn resolve_topic(&self, requested: Option<Topic>) -> Result<Topic, String> {
+        match (requested, self) {
+            (Some(topic), ProposalAction::CallExternal(_)) => Ok(topic),
+            (Some(topic), _) if topic != self.topic() => {
+                Err(format!("The proposal's action has the topic {:?}", self.topic()))
+            }
+            _ => Ok(self.topic()),
+        }
+    }
+
+    pub fn topic(&self) -> Topic {
+        match self {
+            ProposalAction::TransferTokens { .. }
+            | ProposalAction::TransferFromTreasury { .. }
+            | ProposalAction::Mint { .. }
+            | ProposalAction::Burn { .. }
+            | ProposalAction::Airdrop { .. }
+            | ProposalAction::CreateCommittee(_)
+            | ProposalAction::FundCommittee { .. }
+            | ProposalAction::DissolveCommittee { .. }
+            | ProposalAction::CreateStream { .. }
+            | ProposalAction::CancelStream { .. } => Topic::Treasury,
+            ProposalAction::UpdateSystemParams(_)
+            | ProposalAction::AddMember { .. }
+            | ProposalAction::RemoveMember { .. }
+            | ProposalAction::Slash { .. } => Topic::Governance,
+            ProposalAction::UpgradeCanister { .. }
+            | ProposalAction::UpgradeCanisterToStagedWasm { .. }
+            | ProposalAction::RegisterCanister { .. }
+            | ProposalAction::UnregisterCanister { .. }
+            | ProposalAction::ManageCanister { .. }
+            | ProposalAction::CallExternal(_)
+            | ProposalAction::RetryExecution { .. }
+            | ProposalAction::SetEventsAdmin { .. } => Topic::Technical,
+            ProposalAction::CreateEvent { .. } | ProposalAction::CancelEvent { .. } => Topic::Community,
+        }
+    }
+}
+
+/// A follower's votes are cast like those of its followee, on one topic or, without a
+/// topic, on the topics the follower follows nobody else on
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct Follow {
+    pub follower: Principal,
+    pub topic: Option<Topic>,
+    pub followee: Principal,
 }
 
 /// The data needed to call a given method on a given canister with given args
@@ -80,7 +666,7 @@ pub struct ProposalPayload {
     pub message: Vec<u8>,
 }
 
-#[derive(Clone, Debug, CandidType, Deserialize)]
+#[derive(Clone, Copy, Debug, CandidType, Deserialize, PartialEq)]
 pub enum Vote {
     Yes,
     No,
@@ -92,10 +678,381 @@ pub struct Account {
     pub tokens: Tokens,
 }
 
+/// Tokens locked for voting power. A stake stays Locked until its owner starts
+/// dissolving it, and can be disbursed once the dissolve delay has passed.
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct Stake {
+    pub id: u64,
+    pub owner: Principal,
+    pub amount: Tokens,
+    pub dissolve_delay_seconds: u64,
+    pub state: StakeState,
+    pub created_at: u64,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq)]
+pub enum StakeState {
+    Locked,
+    Dissolving { dissolves_at: u64 },
+}
+
+/// An account on an ICRC-1 ledger
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct IcrcAccount {
+    pub owner: Principal,
+    pub subaccount: Option<Vec<u8>>,
+}
+
+/// The ledger balance of the DAO canister, in the ledger's smallest unit
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct TreasuryBalance {
+    pub amount: Nat,
+    pub updated_at: u64,
+}
+
+/// A transfer from the treasury made by a proposal. Failed transfers have an error and
+/// no block index.
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct TreasuryTransaction {
+    pub proposal_id: u64,
+    pub to: IcrcAccount,
+    pub amount: Nat,
+    pub timestamp: u64,
+    pub block_index: Option<Nat>,
+    pub error: Option<String>,
+}
+
+/// A committee of members spending a budget allocated by the DAO, with a smaller vote
+/// threshold than the DAO's
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct CreateCommitteePayload {
+    pub name: String,
+    pub members: Vec<Principal>,
+    // How many members must vote Yes on a committee proposal
+    pub vote_threshold: u32,
+    pub budget: Tokens,
+    // The only accounts the committee may transfer to, any when not set
+    pub recipients: Option<Vec<Principal>>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct Committee {
+    pub id: u64,
+    pub name: String,
+    pub members: Vec<Principal>,
+    pub vote_threshold: u32,
+    // What is left of the budget
+    pub budget: Tokens,
+    pub spent: Tokens,
+    pub recipients: Option<Vec<Principal>>,
+    pub created_by_proposal: u64,
+}
+
+/// A transfer from a committee's budget, voted on by the committee's members
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct CommitteeProposal {
+    pub id: u64,
+    pub committee_id: u64,
+    pub proposer: Principal,
+    pub to: Principal,
+    pub amount: Tokens,
+    pub summary: String,
+    // Open, then Succeeded, Failed or Rejected
+    pub state: ProposalState,
+    pub votes_yes: Vec<Principal>,
+    pub votes_no: Vec<Principal>,
+    pub timestamp: u64,
+}
+
+/// How a committee used its budget, for the parent DAO
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct CommitteeReport {
+    pub committee_id: u64,
+    pub budget: Tokens,
+    pub spent: Tokens,
+    pub open_proposals: u64,
+    pub executed_proposals: u64,
+    pub rejected_proposals: u64,
+}
+
+/// The council's vote to flag a proposal as malicious, so its proposer can be slashed
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct MaliciousFlag {
+    pub proposal_id: u64,
+    pub proposer: Principal,
+    pub justification: String,
+    // The Council members who voted for the flag
+    pub votes: Vec<Principal>,
+    // When a majority of the council voted for the flag
+    pub flagged_at: Option<u64>,
+    pub appeal: Option<Appeal>,
+    // The tokens taken by the Slash proposal
+    pub slashed: Option<Tokens>,
+}
+
+/// The proposer's answer to a flag, which the council hears before flagging again
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct Appeal {
+    pub statement: String,
+    pub timestamp: u64,
+}
+
+/// The checksums of a snapshot of the DAO state, carried by each of its chunks
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq)]
+pub struct SnapshotManifest {
+    pub total_bytes: u64,
+    // Of the whole snapshot
+    pub sha256: Vec<u8>,
+    // Of each chunk, in order
+    pub chunk_sha256: Vec<Vec<u8>>,
+}
+
+/// A part of a snapshot: the Candid-encoded state, split in chunks of at most 1 MiB
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct SnapshotChunk {
+    pub index: u64,
+    pub manifest: SnapshotManifest,
+    pub data: Vec<u8>,
+}
+
+/// A recurring proposal, submitted again and again with a few values changed
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct ProposalTemplate {
+    pub id: u64,
+    pub name: String,
+    // The action submitted, unless overridden
+    pub action: ProposalAction,
+    pub params: TemplateParams,
+    pub created_by: Principal,
+    pub created_at: u64,
+}
+
+/// The defaults and constraints of the proposals submitted from a template
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct TemplateParams {
+    pub topic: Option<Topic>,
+    // The text of submissions that give none
+    pub text: Option<ProposalText>,
+    // The most a submission may transfer or mint
+    pub max_amount: Option<Tokens>,
+    // The only recipients a submission may pay, any when empty
+    pub recipients: Vec<Principal>,
+}
+
+/// What a submission from a template changes in its action
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct TemplateOverrides {
+    pub to: Option<Principal>,
+    pub amount: Option<Tokens>,
+    pub text: Option<ProposalText>,
+}
+
+/// Tokens paid to a recipient by the second, from an amount set aside when it was created.
+/// The recipient withdraws what accrued whenever they like.
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct Stream {
+    pub id: u64,
+    pub to: Principal,
+    pub rate_per_second: Tokens,
+    pub duration_seconds: u64,
+    pub start_at: u64,
+    pub withdrawn: Tokens,
+    pub cancelled_at: Option<u64>,
+    pub created_by_proposal: u64,
+}
+
+/// A canister notified of the state changes of proposals of its topics, all when empty
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct Subscription {
+    pub canister_id: Principal,
+    pub topics: Vec<Topic>,
+}
+
+/// A vote of a voter's history. Votes cast before ballots were recorded have none.
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct VoteRecord {
+    pub proposal_id: u64,
+    pub ballot: Option<Ballot>,
+    pub proposal_state: ProposalState,
+}
+
+/// Sent to the subscribers when a proposal is Accepted, Succeeded or Failed
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct ProposalNotification {
+    pub proposal_id: u64,
+    pub topic: Topic,
+    pub state: ProposalState,
+    pub timestamp: u64,
+}
+
+/// A canister the DAO controls and governs with ManageCanister proposals
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct GovernedCanister {
+    pub canister_id: Principal,
+    pub name: String,
+    pub registered_at: u64,
+}
+
+/// A change of a governed canister made through the management canister
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub enum CanisterOperation {
+    AddController(Principal),
+    RemoveController(Principal),
+    Start,
+    Stop,
+    // Only the settings that are set change
+    UpdateSettings(CanisterSettings),
+}
+
+/// A wasm uploaded in chunks, which `UpgradeCanisterToStagedWasm` proposals reference by
+/// its SHA-256 hash
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct StagedWasm {
+    pub hash: Vec<u8>,
+    pub module: Vec<u8>,
+    pub uploader: Principal,
+    pub staged_at: u64,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct StagedWasmInfo {
+    pub hash: Vec<u8>,
+    pub size: u64,
+    pub uploader: Principal,
+    pub staged_at: u64,
+}
+
+/// An upgrade to a staged wasm made by a proposal. Failed upgrades have an error.
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct CanisterUpgrade {
+    pub proposal_id: u64,
+    pub canister_id: Principal,
+    pub wasm_hash: Vec<u8>,
+    pub timestamp: u64,
+    pub error: Option<String>,
+}
+
+/// A change of the DAO's state worth keeping a record of
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct AuditEvent {
+    pub timestamp: u64,
+    pub kind: AuditEventKind,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub enum AuditEventKind {
+    // The total supply after the change, unknown when the token ledger could not be read
+    Minted { proposal_id: u64, to: Principal, amount: Tokens, total_supply: Option<Tokens>, class: Option<TokenClass> },
+    Burned { proposal_id: u64, from: Principal, amount: Tokens, total_supply: Option<Tokens> },
+    // The params before and after an update, by a proposal or by the DAO canister itself
+    SystemParamsUpdated { proposal_id: Option<u64>, previous: Box<SystemParams>, updated: Box<SystemParams> },
+    PauseChanged { guardian: Principal, paused: bool },
+    MemberAdded { proposal_id: u64, principal: Principal, role: MemberRole },
+    MemberRemoved { proposal_id: u64, principal: Principal, role: MemberRole },
+    CanisterRegistered { proposal_id: u64, canister_id: Principal },
+    CanisterUnregistered { proposal_id: u64, canister_id: Principal },
+    CanisterManaged { proposal_id: u64, canister_id: Principal, operation: CanisterOperation },
+    // A transfer from a committee's budget, by a committee proposal
+    CommitteeTransfer { committee_id: u64, proposal_id: u64, to: Principal, amount: Tokens },
+    StreamWithdrawal { stream_id: u64, to: Principal, amount: Tokens },
+    Slashed { proposal_id: u64, flagged_proposal_id: u64, proposer: Principal, amount: Tokens, policy: DepositPolicy },
+    // What became of the deposit, if the proposal had one
+    ProposalExpired { proposal_id: u64, deposit: Option<DepositState> },
+    // What was sent to `to`, net of the fee
+    AccountClosed { owner: Principal, to: Principal, amount: Tokens, class: TokenClass },
+    DustSwept { treasury: Principal, accounts: u64, amount: Tokens, class: TokenClass },
+}
+
+/// What a principal may do in the DAO
+#[derive(Clone, Copy, Debug, CandidType, Deserialize, PartialEq, Eq)]
+pub enum MemberRole {
+    // Submits proposals
+    Member,
+    // Vetoes accepted proposals
+    Council,
+    // Pauses the DAO
+    Guardian,
+    // Sets proposal states by hand
+    Admin,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct Member {
+    pub principal: Principal,
+    pub roles: Vec<MemberRole>,
+}
+
+/// How often the heartbeat and timers ran since the last upgrade
+#[derive(Clone, Debug, Default, CandidType, Deserialize)]
+pub struct ExecutionStats {
+    pub heartbeats: u64,
+    pub last_heartbeat_at: u64,
+    pub timer_runs: u64,
+    pub executions_succeeded: u64,
+    pub executions_failed: u64,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct Metrics {
+    pub cycle_balance: u128,
+    pub heap_memory_bytes: u64,
+    pub stable_memory_bytes: u64,
+    pub accounts: u64,
+    pub open_proposals: u64,
+    pub accepted_proposals: u64,
+    pub failed_proposals: u64,
+    pub stats: ExecutionStats,
+}
+
+/// The errors of the update methods
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub enum DaoError {
+    // The guardian has paused the DAO
+    Paused,
+    Unauthorized,
+    // The proposer has as many Open proposals as a proposer may have
+    TooManyOpenProposals { limit: u32 },
+    // The proposer submitted a proposal too recently to submit another before retry_at
+    SubmissionCooldown { retry_at: u64 },
+    Other(String),
+}
+
+impl From<String> for DaoError {
+    fn from(message: String) -> DaoError {
+        DaoError::Other(message)
+    }
+}
+
+/// The tokens a spender may transfer from the owner's account
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct Allowance {
+    pub owner: Principal,
+    pub spender: Principal,
+    pub amount: Tokens,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct TransferFromArgs {
+    pub from: Principal,
+    pub to: Principal,
+    pub amount: Tokens,
+}
+
 #[derive(Clone, Debug, CandidType, Deserialize)]
 pub struct TransferArgs {
     pub to: Principal,
     pub amount: Tokens,
+    // The governance tokens when not set
+    pub class: Option<TokenClass>,
+}
+
+/// The classes of internal tokens. Only governance tokens can be staked, and so vote;
+/// utility tokens only pay, within the DAO's community.
+#[derive(Clone, Copy, Debug, Default, CandidType, Deserialize, PartialEq, Eq, Hash)]
+pub enum TokenClass {
+    #[default]
+    Governance,
+    Utility,
 }
 
 #[derive(Clone, Debug, CandidType, Deserialize)]
@@ -110,8 +1067,316 @@ pub struct SystemParams {
     pub proposal_vote_threshold: Tokens, // The amount of tokens needed to vote "yes" to accept, or "no" to reject, a proposal
     // The amount of tokens that will be temporarily deducted from the account of
     // a user that submits a proposal. If the proposal is Accepted, this deposit is returned,
-    // otherwise it is lost. This prevents users from submitting superfluous proposals.
+    // otherwise it is burned or sent to the treasury, as the rejected deposit policy says.
+    // This prevents users from submitting superfluous proposals.
     pub proposal_submission_deposit: Tokens,
+    // Burn when not set
+    pub rejected_deposit_policy: Option<DepositPolicy>,
+    // The ICRC-1 ledger holding the treasury funds of the DAO canister
+    pub treasury_ledger: Option<Principal>,
+    // The ICRC-1 ledger of the governance token. When set, ledger balances vote and the
+    // internal accounts are not used.
+    pub token_ledger: Option<Principal>,
+    // How many times a proposal execution is attempted before the proposal Fails, 3 when
+    // not set
+    pub max_execution_attempts: Option<u32>,
+    // The delay before the first retry of a failed execution, doubled on every further
+    // retry. 60 seconds when not set.
+    pub execution_retry_delay_seconds: Option<u64>,
+    // The initial Guardian and Council members. Only read when the member registry is
+    // set up, at init or from a state saved without one; the registry holds the roles.
+    pub guardian: Option<Principal>,
+    pub council: Option<Vec<Principal>>,
+    // How long accepted proposals wait for a veto when there is a council, 1 day when
+    // not set
+    pub veto_window_seconds: Option<u64>,
+    // Accrued to every voter of a proposal when it is accepted or rejected, from the
+    // account of the rewards pool. No rewards without both.
+    pub voting_reward: Option<Tokens>,
+    pub rewards_pool: Option<Principal>,
+    // Vote thresholds of the topics that need another than proposal_vote_threshold
+    pub topic_vote_thresholds: Option<Vec<(Topic, Tokens)>>,
+    // How long new proposals can be amended before voting opens, 0 when not set
+    pub review_window_seconds: Option<u64>,
+    // Limits on proposal submission, unlimited when not set
+    pub max_open_proposals_per_proposer: Option<u32>,
+    pub min_submission_interval_seconds: Option<u64>,
+    // Weighs votes down the later they are cast, so the outcome is not swung at the last
+    // moment. Votes carry their full voting power when not set.
+    pub vote_decay: Option<VoteDecay>,
+    // Open proposals expire once voting has been open this long, never when not set
+    pub proposal_expiry_seconds: Option<u64>,
+    // What happens to the deposits of expired proposals, refunded when not set
+    pub expired_deposit_policy: Option<ExpiredDepositPolicy>,
+    // The fee of utility token transfers, transfer_fee when not set
+    pub utility_transfer_fee: Option<Tokens>,
+    // The only methods CallExternal proposals may call. When not set, they may call any
+    // method of any canister but the management canister.
+    pub call_allowlist: Option<Vec<CallTarget>>,
+    // Overrides of the params above for the proposals of some topics
+    pub topic_params: Option<Vec<TopicParams>>,
+    // Voting power from a membership NFT collection instead of stakes, for the proposals
+    // of some topics
+    pub nft_voting: Option<NftVoting>,
+    // How long the proposer of a flagged proposal can appeal before being slashed, 7 days
+    // when not set
+    pub slash_appeal_window_seconds: Option<u64>,
+    // Which accounts sweep_dust consolidates, and where to
+    pub dust_policy: Option<DustPolicy>,
+    // The events canister that CreateEvent, CancelEvent and SetEventsAdmin proposals call
+    pub events_canister: Option<Principal>,
+    // Where transfer fees go, burned when not set
+    pub fee_policy: Option<DepositPolicy>,
+}
+
+/// Accounts holding less than `threshold` are dust, swept into the `treasury` account
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct DustPolicy {
+    pub threshold: Tokens,
+    pub treasury: Principal,
+}
+
+/// The proposals of the topics, or of all topics when empty, are voted on by the holders
+/// of a DIP-721 NFT collection, as of the submission. Each NFT carries `votes_per_nft`,
+/// times the natural number of its `weight_key` metadata trait when this is set and the
+/// NFT has it.
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct NftVoting {
+    pub collection: Principal,
+    pub votes_per_nft: Tokens,
+    pub weight_key: Option<String>,
+    pub topics: Vec<Topic>,
+}
+
+/// The params of the proposals of a topic that differ from the DAO-wide ones. Those not
+/// set fall back to the DAO-wide params.
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct TopicParams {
+    pub topic: Topic,
+    // How long voting stays open before the proposal expires, proposal_expiry_seconds
+    // when not set
+    pub voting_period_seconds: Option<u64>,
+    // topic_vote_thresholds, or proposal_vote_threshold, when not set
+    pub vote_threshold: Option<Tokens>,
+    pub submission_deposit: Option<Tokens>,
+}
+
+/// The params the proposals of a topic are held to, once the overrides are resolved
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct EffectiveParams {
+    // Never expire when not set
+    pub voting_period_seconds: Option<u64>,
+    pub vote_threshold: Tokens,
+    pub submission_deposit: Tokens,
+}
+
+/// A method of a canister that CallExternal proposals may call
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq, Eq)]
+pub struct CallTarget {
+    pub canister_id: Principal,
+    pub method: String,
+}
+
+/// Votes cast within `full_weight_seconds` of voting opening carry their full voting power.
+/// Over the next `decay_seconds`, up to the deadline, their weight decreases linearly down
+/// to `min_weight_percent`, which later votes carry.
+#[derive(Clone, Copy, Debug, CandidType, Deserialize)]
+pub struct VoteDecay {
+    pub full_weight_seconds: u64,
+    pub decay_seconds: u64,
+    pub min_weight_percent: u8,
+}
+
+impl VoteDecay {
+    /// The weighted voting power of a vote cast at `now` on a proposal whose voting
+    /// opened at `opens_at`
+    pub fn weigh(&self, voting_power: Tokens, opens_at: u64, now: u64) -> Tokens {
+        let elapsed = now.saturating_sub(opens_at) / 1_000_000_000;
+        let decayed = elapsed.saturating_sub(self.full_weight_seconds).min(self.decay_seconds);
+        // In basis points, for a smoother decay than by whole percents
+        let min_weight = self.min_weight_percent as u128 * 100;
+        let weight = 10_000 - (10_000 - min_weight) * decayed as u128 / self.decay_seconds.max(1) as u128;
+        let amount_e8s = voting_power.amount_e8s as u128 * weight / 10_000;
+        Tokens { amount_e8s: amount_e8s as u64 }
+    }
+}
+
+impl SystemParams {
+    pub fn transfer_fee_of(&self, class: TokenClass) -> Tokens {
+        match class {
+            TokenClass::Governance => self.transfer_fee,
+            TokenClass::Utility => self.utility_transfer_fee.unwrap_or(self.transfer_fee),
+        }
+    }
+
+    /// The amount of tokens needed to accept or reject a proposal of a topic, without the
+    /// topic params
+    fn vote_threshold(&self, topic: Topic) -> Tokens {
+        self.topic_vote_thresholds.iter().flatten()
+            .find(|(threshold_topic, _)| *threshold_topic == topic)
+            .map_or(self.proposal_vote_threshold, |(_, threshold)| *threshold)
+    }
+
+    /// The params of the proposals of a topic: those of its topic params, falling back to
+    /// the DAO-wide ones
+    pub fn effective_params(&self, topic: Topic) -> EffectiveParams {
+        let overrides = self.topic_params.iter().flatten().find(|params| params.topic == topic);
+        EffectiveParams {
+            voting_period_seconds: overrides.and_then(|params| params.voting_period_seconds)
+                .or(self.proposal_expiry_seconds),
+            vote_threshold: overrides.and_then(|params| params.vote_threshold)
+                .unwrap_or_else(|| self.vote_threshold(topic)),
+            submission_deposit: overrides.and_then(|params| params.submission_deposit)
+                .unwrap_or(self.proposal_submission_deposit),
+        }
+    }
+
+    pub fn apply(&mut self, payload: UpdateSystemParamsPayload) {
+        if let Some(transfer_fee) = payload.transfer_fee {
+            self.transfer_fee = transfer_fee;
+        }
+        if let Some(proposal_vote_threshold) = payload.proposal_vote_threshold {
+            self.proposal_vote_threshold = proposal_vote_threshold;
+        }
+        if let Some(proposal_submission_deposit) = payload.proposal_submission_deposit {
+            self.proposal_submission_deposit = proposal_submission_deposit;
+        }
+        if let Some(rejected_deposit_policy) = payload.rejected_deposit_policy {
+            self.rejected_deposit_policy = Some(rejected_deposit_policy);
+        }
+        if let Some(treasury_ledger) = payload.treasury_ledger {
+            self.treasury_ledger = Some(treasury_ledger);
+        }
+        if let Some(token_ledger) = payload.token_ledger {
+            self.token_ledger = Some(token_ledger);
+        }
+        if let Some(max_execution_attempts) = payload.max_execution_attempts {
+            self.max_execution_attempts = Some(max_execution_attempts);
+        }
+        if let Some(execution_retry_delay_seconds) = payload.execution_retry_delay_seconds {
+            self.execution_retry_delay_seconds = Some(execution_retry_delay_seconds);
+        }
+        if let Some(veto_window_seconds) = payload.veto_window_seconds {
+            self.veto_window_seconds = Some(veto_window_seconds);
+        }
+        if let Some(voting_reward) = payload.voting_reward {
+            self.voting_reward = Some(voting_reward);
+        }
+        if let Some(rewards_pool) = payload.rewards_pool {
+            self.rewards_pool = Some(rewards_pool);
+        }
+        if let Some(topic_vote_thresholds) = payload.topic_vote_thresholds {
+            self.topic_vote_thresholds = Some(topic_vote_thresholds);
+        }
+        if let Some(review_window_seconds) = payload.review_window_seconds {
+            self.review_window_seconds = Some(review_window_seconds);
+        }
+        if let Some(max_open_proposals_per_proposer) = payload.max_open_proposals_per_proposer {
+            self.max_open_proposals_per_proposer = Some(max_open_proposals_per_proposer);
+        }
+        if let Some(min_submission_interval_seconds) = payload.min_submission_interval_seconds {
+            self.min_submission_interval_seconds = Some(min_submission_interval_seconds);
+        }
+        if let Some(vote_decay) = payload.vote_decay {
+            self.vote_decay = Some(vote_decay);
+        }
+        if let Some(proposal_expiry_seconds) = payload.proposal_expiry_seconds {
+            self.proposal_expiry_seconds = Some(proposal_expiry_seconds);
+        }
+        if let Some(expired_deposit_policy) = payload.expired_deposit_policy {
+            self.expired_deposit_policy = Some(expired_deposit_policy);
+        }
+        if let Some(utility_transfer_fee) = payload.utility_transfer_fee {
+            self.utility_transfer_fee = Some(utility_transfer_fee);
+        }
+        if let Some(call_allowlist) = payload.call_allowlist {
+            self.call_allowlist = Some(call_allowlist);
+        }
+        if let Some(topic_params) = payload.topic_params {
+            self.topic_params = Some(topic_params);
+        }
+        if let Some(nft_voting) = payload.nft_voting {
+            self.nft_voting = Some(nft_voting);
+        }
+        if let Some(slash_appeal_window_seconds) = payload.slash_appeal_window_seconds {
+            self.slash_appeal_window_seconds = Some(slash_appeal_window_seconds);
+        }
+        if let Some(dust_policy) = payload.dust_policy {
+            self.dust_policy = Some(dust_policy);
+        }
+        if let Some(events_canister) = payload.events_canister {
+            self.events_canister = Some(events_canister);
+        }
+        if let Some(fee_policy) = payload.fee_policy {
+            self.fee_policy = Some(fee_policy);
+        }
+    }
+}
+
+/// Bounds of the params that an UpdateSystemParams proposal may set
+const MAX_EXECUTION_ATTEMPTS: u32 = 10;
+const MAX_EXECUTION_RETRY_DELAY_SECONDS: u64 = 7 * 24 * 60 * 60;
+const MAX_VETO_WINDOW_SECONDS: u64 = 30 * 24 * 60 * 60;
+const MAX_REVIEW_WINDOW_SECONDS: u64 = 7 * 24 * 60 * 60;
+const MIN_PROPOSAL_EXPIRY_SECONDS: u64 = 24 * 60 * 60;
+const MAX_CALL_TARGETS: usize = 100;
+
+impl UpdateSystemParamsPayload {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.proposal_vote_threshold.is_some_and(|threshold| threshold.amount_e8s == 0) {
+            return Err("The proposal vote threshold must be above zero".to_string());
+        }
+        if self.topic_vote_thresholds.iter().flatten().any(|(_, threshold)| threshold.amount_e8s == 0) {
+            return Err("The topic vote thresholds must be above zero".to_string());
+        }
+        if self.max_open_proposals_per_proposer == Some(0) {
+            return Err("The max open proposals per proposer must be above zero".to_string());
+        }
+        if let Some(decay) = self.vote_decay {
+            if decay.decay_seconds == 0 || decay.min_weight_percent > 100 {
+                return Err("The vote decay must last some seconds, down to a weight of at most 100%".to_string());
+            }
+        }
+        if self.proposal_expiry_seconds.is_some_and(|expiry| expiry < MIN_PROPOSAL_EXPIRY_SECONDS) {
+            return Err(format!("The proposal expiry must be at least {} seconds", MIN_PROPOSAL_EXPIRY_SECONDS));
+        }
+        for (i, params) in self.topic_params.iter().flatten().enumerate() {
+            if self.topic_params.iter().flatten().skip(i + 1).any(|other| other.topic == params.topic) {
+                return Err(format!("The topic params of {:?} are given more than once", params.topic));
+            }
+            if params.vote_threshold.is_some_and(|threshold| threshold.amount_e8s == 0) {
+                return Err("The topic vote thresholds must be above zero".to_string());
+            }
+            if params.voting_period_seconds.is_some_and(|period| period < MIN_PROPOSAL_EXPIRY_SECONDS) {
+                return Err(format!("The voting periods must be at least {} seconds", MIN_PROPOSAL_EXPIRY_SECONDS));
+            }
+        }
+        if self.nft_voting.as_ref().is_some_and(|nft| nft.votes_per_nft.amount_e8s == 0) {
+            return Err("Each NFT must carry some votes".to_string());
+        }
+        if self.dust_policy.as_ref().is_some_and(|policy| policy.threshold.amount_e8s == 0) {
+            return Err("The dust threshold must be above zero".to_string());
+        }
+        if self.call_allowlist.as_ref().is_some_and(|targets| targets.len() > MAX_CALL_TARGETS) {
+            return Err(format!("The call allowlist has at most {} targets", MAX_CALL_TARGETS));
+        }
+        if self.max_execution_attempts.is_some_and(|attempts| !(1..=MAX_EXECUTION_ATTEMPTS).contains(&attempts)) {
+            return Err(format!("The max execution attempts must be between 1 and {}", MAX_EXECUTION_ATTEMPTS));
+        }
+        if self.review_window_seconds.is_some_and(|window| window > MAX_REVIEW_WINDOW_SECONDS) {
+            return Err(format!("The review window must be at most {} seconds", MAX_REVIEW_WINDOW_SECONDS));
+        }
+        if self.veto_window_seconds.is_some_and(|window| window > MAX_VETO_WINDOW_SECONDS) {
+            return Err(format!("The veto window must be at most {} seconds", MAX_VETO_WINDOW_SECONDS));
+        }
+        if self.execution_retry_delay_seconds.is_some_and(|delay| delay > MAX_EXECUTION_RETRY_DELAY_SECONDS) {
+            return Err(format!(
+                "The execution retry delay must be at most {} seconds",
+                MAX_EXECUTION_RETRY_DELAY_SECONDS
+            ));
+        }
+        Ok(())
+    }
 }
 
 #[derive(Clone, Debug, CandidType, Deserialize)]
@@ -119,7 +1384,109 @@ pub struct UpdateSystemParamsPayload  {
     pub transfer_fee: Option<Tokens>,
     pub proposal_vote_threshold: Option<Tokens>,
     pub proposal_submission_deposit: Option<Tokens>,
+    pub rejected_deposit_policy: Option<DepositPolicy>,
+    pub treasury_ledger: Option<Principal>,
+    pub token_ledger: Option<Principal>,
+    pub max_execution_attempts: Option<u32>,
+    pub execution_retry_delay_seconds: Option<u64>,
+    pub veto_window_seconds: Option<u64>,
+    pub voting_reward: Option<Tokens>,
+    pub rewards_pool: Option<Principal>,
+    pub topic_vote_thresholds: Option<Vec<(Topic, Tokens)>>,
+    pub review_window_seconds: Option<u64>,
+    pub max_open_proposals_per_proposer: Option<u32>,
+    pub min_submission_interval_seconds: Option<u64>,
+    pub vote_decay: Option<VoteDecay>,
+    pub proposal_expiry_seconds: Option<u64>,
+    pub expired_deposit_policy: Option<ExpiredDepositPolicy>,
+    pub utility_transfer_fee: Option<Tokens>,
+    pub call_allowlist: Option<Vec<CallTarget>>,
+    pub topic_params: Option<Vec<TopicParams>>,
+    pub nft_voting: Option<NftVoting>,
+    pub slash_appeal_window_seconds: Option<u64>,
+    pub dust_policy: Option<DustPolicy>,
+    pub events_canister: Option<Principal>,
+    pub fee_policy: Option<DepositPolicy>,
 }
 
 
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(amount_e8s: u64) -> Tokens {
+        Tokens { amount_e8s }
+    }
+
+    #[test]
+    #[should_panic(expected = "token amount overflow")]
+    fn token_sums_that_overflow_trap() {
+        assert_eq!(tokens(u64::MAX - 1).checked_add(tokens(2)), None);
+        let _ = tokens(u64::MAX - 1) + tokens(2);
+    }
+
+    #[test]
+    #[should_panic(expected = "token amount underflow")]
+    fn token_differences_below_zero_trap() {
+        let mut balance = tokens(1);
+        balance -= tokens(2);
+    }
+
+    #[test]
+    fn v1_states_are_migrated_to_the_latest_layout() {
+        let proposer = Principal::anonymous();
+        let v1 = BasicDaoStableStorageV1 {
+            accounts: vec![Account { owner: proposer, tokens: tokens(5) }],
+            proposals: vec![ProposalV1 {
+                id: 3,
+                timestamp: 0,
+                proposer,
+                payload: ProposalPayload { canister_id: proposer, method: "poke".to_string(), message: vec![] },
+                state: ProposalState::Accepted,
+                votes_yes: tokens(5),
+                votes_no: tokens(0),
+                voters: vec![proposer],
+                deposit: None,
+            }],
+            system_params: SystemParams::default(),
+            stakes: None,
+            treasury_transactions: None,
+        };
+
+        let latest = VersionedStableStorage::V1(Box::new(v1)).into_latest();
+        assert_eq!(latest.accounts.len(), 1);
+        let proposal = &latest.proposals[0];
+        assert_eq!((proposal.id, proposal.state.clone()), (3, ProposalState::Accepted));
+        assert!(matches!(&proposal.action, ProposalAction::CallExternal(payload) if payload.method == "poke"));
+        assert_eq!(proposal.topic(), Topic::Technical);
+        assert!(proposal.ballots.is_none() && proposal.voting_power_snapshot.is_none());
+        assert_eq!((latest.next_proposal_id, latest.ledger_voters.is_none()), (None, true));
+    }
+
+    #[test]
+    fn retracted_ballots_leave_the_tally() {
+        let voter = Principal::anonymous();
+        let mut proposal = Proposal::for_test(1, voter);
+        let ballot = Ballot { voter, vote: Vote::Yes, voting_power: tokens(10), followed: false, weighted_power: Some(tokens(4)) };
+        proposal.cast(ballot.clone());
+        assert_eq!((proposal.votes_yes, proposal.votes_no), (tokens(4), tokens(0)));
+
+        proposal.retract(&ballot);
+        proposal.cast(Ballot { vote: Vote::No, ..ballot });
+        assert_eq!((proposal.votes_yes, proposal.votes_no), (tokens(0), tokens(4)));
+        assert_eq!(proposal.voters, vec![voter]);
+        assert_eq!(proposal.ballots.as_ref().map(Vec::len), Some(1));
+    }
+
+    #[test]
+    fn late_votes_decay_down_to_the_minimum_weight() {
+        let decay = VoteDecay { full_weight_seconds: 100, decay_seconds: 1_000, min_weight_percent: 20 };
+        let at = |seconds: u64| decay.weigh(tokens(1_000), 0, seconds * 1_000_000_000);
+        assert_eq!(at(100), tokens(1_000));
+        assert_eq!(at(600), tokens(600));
+        assert_eq!(at(1_100), tokens(200));
+        assert_eq!(at(10_000), tokens(200));
+    }
+}
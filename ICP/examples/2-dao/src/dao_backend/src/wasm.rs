@@ -0,0 +1,232 @@
+use ic_cdk::api::management_canister::main::{install_code, CanisterInstallMode, InstallCodeArgument};
+use ic_cdk::export::Principal;
+use sha2::{Digest, Sha256};
+use crate::types::*;
+use crate::{members, pause, BasicDaoService, SERVICE};
+
+/// `install_code` takes the whole module in a single call, so a staged wasm is no larger
+/// than a call may be
+pub const MAX_WASM_BYTES: usize = 2 * 1024 * 1024;
+/// Staged wasms are kept until an upgrade installs them or they are unstaged, so only this
+/// many can wait
+const MAX_STAGED_WASMS: usize = 10;
+/// Uploads in progress are kept in heap memory, so all of them together are no larger
+const MAX_UPLOAD_BYTES: usize = 4 * MAX_WASM_BYTES;
+/// Oldest canister upgrades are dropped beyond this many
+const MAX_CANISTER_UPGRADES: usize = 1_000;
+
+/// Error for an `UpgradeCanisterToStagedWasm` proposal whose wasm is not staged
+pub fn require_staged(wasm_hash: &[u8]) -> Result<(), String> {
+    SERVICE.with(|service| {
+        match service.borrow().staged_wasms.contains_key(wasm_hash) {
+            true => Ok(()),
+            false => Err(format!("No wasm is staged with the hash {}", hex(wasm_hash))),
+        }
+    })
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Whether a proposal other than `except` may still upgrade a canister to the wasm
+fn is_referenced(service: &BasicDaoService, wasm_hash: &[u8], except: Option<u64>) -> bool {
+    service.proposals.values().any(|proposal| {
+        Some(proposal.id) != except
+            && matches!(proposal.state, ProposalState::Open | ProposalState::Accepted | ProposalState::Executing | ProposalState::RetryScheduled { .. })
+            && matches!(&proposal.action, ProposalAction::UpgradeCanisterToStagedWasm { wasm_hash: hash, .. } if hash == wasm_hash)
+    })
+}
+
+fn append_chunk(service: &mut BasicDaoService, caller: Principal, chunk: Vec<u8>) -> Result<u64, String> {
+    let uploading: usize = service.wasm_uploads.values().map(Vec::len).sum();
+    if uploading + chunk.len() > MAX_UPLOAD_BYTES {
+        return Err("Too many wasms are being uploaded, try again later".to_string());
+    }
+    let upload = service.wasm_uploads.entry(caller).or_default();
+    if upload.len() + chunk.len() > MAX_WASM_BYTES {
+        return Err(format!("A wasm has at most {} bytes", MAX_WASM_BYTES));
+    }
+    upload.extend(chunk);
+    Ok(upload.len() as u64)
+}
+
+/// Append a chunk to the wasm the caller is uploading. Returns how many bytes were
+/// uploaded so far. Uploads in progress are not kept across upgrades. Only callable by
+/// Members.
+#[ic_cdk::update]
+fn upload_wasm_chunk(chunk: Vec<u8>) -> Result<u64, DaoError> {
+    pause::require_not_paused()?;
+    let caller = ic_cdk::api::caller();
+    members::require_role(&caller, MemberRole::Member)?;
+
+    SERVICE.with(|service| append_chunk(&mut service.borrow_mut(), caller, chunk))
+        .map_err(DaoError::from)
+}
+
+/// Stage the wasm the caller uploaded, so proposals can reference it by its SHA-256 hash.
+/// Returns the hash. Only callable by Members.
+#[ic_cdk::update]
+fn stage_wasm() -> Result<Vec<u8>, DaoError> {
+    pause::require_not_paused()?;
+    let caller = ic_cdk::api::caller();
+    members::require_role(&caller, MemberRole::Member)?;
+
+    SERVICE.with(|service| {
+        let mut service = service.borrow_mut();
+        if service.staged_wasms.len() >= MAX_STAGED_WASMS {
+            return Err(format!("At most {} wasms can be staged at once", MAX_STAGED_WASMS));
+        }
+        let module = service.wasm_uploads.remove(&caller)
+            .filter(|module| !module.is_empty())
+            .ok_or_else(|| "Upload the wasm with upload_wasm_chunk first".to_string())?;
+
+        let hash = Sha256::digest(&module).to_vec();
+        service.staged_wasms.entry(hash.clone()).or_insert(StagedWasm {
+            hash: hash.clone(),
+            module,
+            uploader: caller,
+            staged_at: ic_cdk::api::time(),
+        });
+        Ok(hash)
+    }).map_err(DaoError::from)
+}
+
+/// Drop the wasm the caller is uploading
+#[ic_cdk::update]
+fn clear_wasm_upload() {
+    SERVICE.with(|service| service.borrow_mut().wasm_uploads.remove(&ic_cdk::api::caller()));
+}
+
+fn unstage(service: &mut BasicDaoService, caller: Principal, wasm_hash: &[u8]) -> Result<(), DaoError> {
+    let wasm = service.staged_wasms.get(wasm_hash)
+        .ok_or_else(|| DaoError::from(format!("No wasm is staged with the hash {}", hex(wasm_hash))))?;
+    if wasm.uploader != caller && !service.has_role(&caller, MemberRole::Guardian) {
+        return Err(DaoError::Unauthorized);
+    }
+    if is_referenced(service, wasm_hash, None) {
+        return Err(DaoError::from("A proposal may still upgrade a canister to the wasm".to_string()));
+    }
+    service.staged_wasms.remove(wasm_hash);
+    Ok(())
+}
+
+/// Drop a staged wasm no proposal may still upgrade a canister to, freeing its slot.
+/// Only callable by its uploader or a Guardian.
+#[ic_cdk::update]
+fn unstage_wasm(wasm_hash: Vec<u8>) -> Result<(), DaoError> {
+    pause::require_not_paused()?;
+    let caller = ic_cdk::api::caller();
+    SERVICE.with(|service| unstage(&mut service.borrow_mut(), caller, &wasm_hash))
+}
+
+/// Upgrade a canister the DAO controls to a staged wasm, recording the result. The wasm
+/// stays staged for the other proposals referencing it, and for retries if the upgrade
+/// fails.
+pub async fn upgrade_to_staged_wasm(proposal_id: u64, canister_id: Principal, wasm_hash: Vec<u8>, arg: Vec<u8>) -> Result<(), String> {
+    let wasm_module = SERVICE.with(|service| {
+        service.borrow().staged_wasms.get(&wasm_hash).map(|wasm| wasm.module.clone())
+    }).ok_or_else(|| format!("No wasm is staged with the hash {}", hex(&wasm_hash)))?;
+
+    let result = install_code(InstallCodeArgument {
+        mode: CanisterInstallMode::Upgrade,
+        canister_id,
+        wasm_module,
+        arg,
+    }).await
+        .map_err(|(code, msg)| {
+            format!(
+                "Proposal execution failed: \
+                upgrade of canister: {}, rejection code: {:?}, message: {}",
+                canister_id, code, msg
+            )
+        });
+
+    SERVICE.with(|service| {
+        let mut service = service.borrow_mut();
+        let upgrades = &mut service.canister_upgrades;
+        upgrades.push(CanisterUpgrade {
+            proposal_id,
+            canister_id,
+            wasm_hash: wasm_hash.clone(),
+            timestamp: ic_cdk::api::time(),
+            error: result.as_ref().err().cloned(),
+        });
+        if upgrades.len() > MAX_CANISTER_UPGRADES {
+            upgrades.remove(0);
+        }
+
+        if result.is_ok() && !is_referenced(&service, &wasm_hash, Some(proposal_id)) {
+            service.staged_wasms.remove(&wasm_hash);
+        }
+    });
+    result
+}
+
+/// Lists the staged wasms, without their modules
+#[ic_cdk::query]
+fn list_staged_wasms() -> Vec<StagedWasmInfo> {
+    SERVICE.with(|service| {
+        service.borrow().staged_wasms.values()
+            .map(|wasm| StagedWasmInfo {
+                hash: wasm.hash.clone(),
+                size: wasm.module.len() as u64,
+                uploader: wasm.uploader,
+                staged_at: wasm.staged_at,
+            })
+            .collect()
+    })
+}
+
+/// Returns the upgrades made by proposals to staged wasms, newest first
+#[ic_cdk::query]
+fn get_canister_upgrades() -> Vec<CanisterUpgrade> {
+    SERVICE.with(|service| service.borrow().canister_upgrades.iter().rev().cloned().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn principal(n: u8) -> Principal {
+        Principal::from_slice(&[n; 29])
+    }
+
+    #[test]
+    fn uploads_are_capped_in_total() {
+        let mut service = BasicDaoService::default();
+        let wasm = vec![0; MAX_WASM_BYTES];
+        for n in 0..4 {
+            assert_eq!(append_chunk(&mut service, principal(n), wasm.clone()), Ok(MAX_WASM_BYTES as u64));
+        }
+        assert!(append_chunk(&mut service, principal(4), vec![0]).is_err());
+
+        service.wasm_uploads.remove(&principal(0));
+        assert_eq!(append_chunk(&mut service, principal(4), vec![0]), Ok(1));
+    }
+
+    #[test]
+    fn only_the_uploader_or_a_guardian_unstages_an_unreferenced_wasm() {
+        let (uploader, guardian, stranger) = (principal(1), principal(2), principal(3));
+        let hash = vec![7; 32];
+        let mut service = BasicDaoService::default();
+        service.members.insert(guardian, vec![MemberRole::Guardian]);
+        let stage = |service: &mut BasicDaoService| {
+            service.staged_wasms.insert(hash.clone(), StagedWasm { hash: hash.clone(), module: vec![0], uploader, staged_at: 0 });
+        };
+        stage(&mut service);
+
+        let mut proposal = Proposal::for_test(1, uploader);
+        proposal.action = ProposalAction::UpgradeCanisterToStagedWasm { canister_id: uploader, wasm_hash: hash.clone(), arg: vec![] };
+        service.proposals.insert(1, proposal);
+        assert!(unstage(&mut service, uploader, &hash).is_err());
+
+        service.proposals.get_mut(&1).unwrap().state = ProposalState::Rejected;
+        assert!(matches!(unstage(&mut service, stranger, &hash), Err(DaoError::Unauthorized)));
+        assert!(unstage(&mut service, uploader, &hash).is_ok());
+        assert!(service.staged_wasms.is_empty());
+
+        stage(&mut service);
+        assert!(unstage(&mut service, guardian, &hash).is_ok());
+    }
+}
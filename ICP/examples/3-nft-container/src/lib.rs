@@ -115,6 +115,8 @@ fn transfer_from(from: Principal, to: Principal, token_id: u64) -> Result {
             Err(Error::Unauthorized)
         } else if nft.owner != from {
             Err(Error::Other)
+        } else if !nft.is_transferable() {
+            Err(Error::Unauthorized)
         } else {
             nft.approved = None;
             nft.owner = to;
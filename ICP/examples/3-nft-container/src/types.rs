@@ -73,6 +73,16 @@ pub enum MetadataVal {
     Nat64Content(u64),
 }
 
+impl Nft {
+    // Tokens minted with a `transferable` entry of 0, like proofs of attendance, stay
+    // with the principal they were minted to
+    pub fn is_transferable(&self) -> bool {
+        !self.metadata.iter().any(|part| {
+            matches!(part.key_val_data.get("transferable"), Some(MetadataVal::Nat8Content(0)))
+        })
+    }
+}
+
 impl State {
     pub fn next_txid(&mut self) -> u128 {
         let txid = self.txid;